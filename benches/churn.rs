@@ -0,0 +1,170 @@
+// Latency of rule insertions and removals under concurrent classification, with and without
+// `RVHClassifier::set_low_latency`. Run with `cargo bench --bench churn`.
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rvh::adaptive::AdaptivePolicy;
+use rvh::fields;
+use rvh::prelude::*;
+
+const RULES: u32 = 10_000;
+const UPDATES: u32 = 20_000;
+const READERS: usize = 3;
+// updates between two calls to `maintain` in low latency mode
+const MAINTAIN_EVERY: u32 = 100;
+
+#[derive(Debug, Clone)]
+struct BenchRule {
+    fields: Vec<Field>,
+    masks: Vec<Mask>,
+    priority: Priority,
+}
+
+impl Rule for BenchRule {
+    fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+    fn masks(&self) -> &[Mask] {
+        &self.masks
+    }
+    fn priority(&self) -> Priority {
+        self.priority
+    }
+}
+
+impl PartialEq for BenchRule {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority
+    }
+}
+
+struct BenchPacket(Vec<Field>);
+
+impl Packet for BenchPacket {
+    fn fields(&self) -> &[Field] {
+        &self.0
+    }
+}
+
+// Scatters `i` over all bits, so that rules and packets are spread over tables and buckets.
+fn mix(i: u32) -> u32 {
+    let x = i.wrapping_mul(0x9e37_79b9);
+    x ^ (x >> 15)
+}
+
+fn rule(i: u32) -> BenchRule {
+    let x = mix(i);
+    let (dport, dport_mask) = fields::port((x >> 3) as u16 % 1024);
+    BenchRule {
+        fields: vec![x, x.rotate_left(11), 0, dport, 6],
+        masks: vec![
+            fields::prefix_mask(8 + x % 25),
+            fields::prefix_mask(8 + (x >> 8) % 25),
+            0,
+            dport_mask,
+            fields::prefix_mask(8),
+        ],
+        priority: i + 1,
+    }
+}
+
+fn packet(i: u32) -> BenchPacket {
+    let x = mix(i);
+    BenchPacket(vec![x, x.rotate_left(11), 40000, (x >> 3) % 1024, 6])
+}
+
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    sorted[(sorted.len() - 1) * p / 100]
+}
+
+fn report(name: &str, mut latencies: Vec<Duration>) {
+    latencies.sort_unstable();
+    println!(
+        "  {:<8} p50 {:>10?}  p99 {:>10?}  max {:>10?}",
+        name,
+        percentile(&latencies, 50),
+        percentile(&latencies, 99),
+        latencies.last().unwrap(),
+    );
+}
+
+fn run(low_latency: bool) {
+    let mut classifier = RVHClassifier::<BenchRule>::five_tuple();
+    classifier.set_adaptive(Some(AdaptivePolicy::default()));
+    classifier.set_low_latency(low_latency);
+    let mut ids: VecDeque<RuleId> = (0..RULES)
+        .filter_map(|i| classifier.add_rule(rule(i)).ok())
+        .collect();
+
+    let classifier = Arc::new(RwLock::new(classifier));
+    let stop = Arc::new(AtomicBool::new(false));
+    let classified = Arc::new(AtomicUsize::new(0));
+    let readers: Vec<_> = (0..READERS)
+        .map(|reader| {
+            let (classifier, stop, classified) =
+                (classifier.clone(), stop.clone(), classified.clone());
+            thread::spawn(move || {
+                let mut i = reader as u32;
+                while !stop.load(Ordering::Relaxed) {
+                    let p = packet(i % (2 * RULES));
+                    std::hint::black_box(classifier.read().unwrap().classify(&p).is_some());
+                    classified.fetch_add(1, Ordering::Relaxed);
+                    i = i.wrapping_add(READERS as u32);
+                }
+            })
+        })
+        .collect();
+
+    let mut adds = Vec::with_capacity(UPDATES as usize);
+    let mut removes = Vec::with_capacity(UPDATES as usize);
+    let mut maintenance = Vec::new();
+    let start = Instant::now();
+    for i in RULES..RULES + UPDATES {
+        let id = ids.pop_front().unwrap();
+        let t = Instant::now();
+        let removed = classifier.write().unwrap().remove(id);
+        removes.push(t.elapsed());
+        assert!(removed.is_ok());
+
+        let t = Instant::now();
+        let added = classifier.write().unwrap().add_rule(rule(i));
+        adds.push(t.elapsed());
+        ids.extend(added.ok());
+
+        if low_latency && i % MAINTAIN_EVERY == 0 {
+            let t = Instant::now();
+            classifier
+                .write()
+                .unwrap()
+                .maintain(MAINTAIN_EVERY as usize);
+            maintenance.push(t.elapsed());
+        }
+    }
+    let elapsed = start.elapsed();
+
+    stop.store(true, Ordering::Relaxed);
+    for reader in readers {
+        reader.join().unwrap();
+    }
+
+    println!(
+        "low latency {}: {:.0} updates/s, {:.0} classifications/s, {} tables",
+        low_latency,
+        (2 * UPDATES) as f64 / elapsed.as_secs_f64(),
+        classified.load(Ordering::Relaxed) as f64 / elapsed.as_secs_f64(),
+        classifier.read().unwrap().table_count(),
+    );
+    report("add", adds);
+    report("remove", removes);
+    if !maintenance.is_empty() {
+        report("maintain", maintenance);
+    }
+}
+
+fn main() {
+    run(false);
+    run(true);
+}
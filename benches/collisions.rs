@@ -0,0 +1,171 @@
+// Hash collisions within the buckets of 100k synthetic 5-tuple rules, for the old XOR hash,
+// the hashers of the crate truncated to 32 bits and the full 64 bit keys. Run with
+// `cargo bench --bench collisions`.
+use std::hash::{BuildHasher, Hasher};
+use std::time::Instant;
+
+use rvh::fields;
+use rvh::hash::{MixBuildHasher, SipBuildHasher};
+use rvh::prelude::*;
+use rvh::presets;
+
+const RULES: u32 = 100_000;
+
+#[derive(Debug, Clone)]
+struct BenchRule {
+    fields: Vec<Field>,
+    masks: Vec<Mask>,
+    priority: Priority,
+}
+
+impl Rule for BenchRule {
+    fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+    fn masks(&self) -> &[Mask] {
+        &self.masks
+    }
+    fn priority(&self) -> Priority {
+        self.priority
+    }
+}
+
+impl PartialEq for BenchRule {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority
+    }
+}
+
+// Scatters `i` over all bits.
+fn mix(i: u32) -> u32 {
+    let x = i.wrapping_mul(0x9e37_79b9);
+    x ^ (x >> 15)
+}
+
+// Addresses of a few hundred subnets with hosts counting up, as in firewall rule sets.
+fn rule(i: u32) -> BenchRule {
+    let x = mix(i);
+    let src = 0x0a00_0000 | (x % 512) << 8 | (i % 256);
+    let dst = 0xc0a8_0000 | ((x >> 9) % 256) << 8 | ((i / 256) % 256);
+    let (dport, dport_mask) = fields::port((i % 64) as u16);
+    BenchRule {
+        fields: vec![src, dst, 0, dport, 6],
+        masks: vec![
+            fields::prefix_mask(24 + x % 9),
+            fields::prefix_mask(24 + (x >> 4) % 9),
+            0,
+            dport_mask,
+            fields::prefix_mask(8),
+        ],
+        priority: i + 1,
+    }
+}
+
+// XOR of the words with an alternating bit, the hash the tables used to have.
+#[derive(Default)]
+struct XorHasher {
+    hash: u32,
+    bit: u32,
+    seeded: bool,
+}
+
+impl Hasher for XorHasher {
+    // other integers and byte strings are folded in as words
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(4) {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.write_u32(u32::from_ne_bytes(word));
+        }
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        // the first word is the seed of the table, which is 0
+        if !self.seeded {
+            self.seeded = true;
+            self.bit = 1;
+            return;
+        }
+        self.hash ^= self.bit | i;
+        self.bit ^= 1;
+    }
+
+    fn finish(&self) -> u64 {
+        u64::from(self.hash)
+    }
+}
+
+#[derive(Clone, Default)]
+struct Xor;
+
+impl BuildHasher for Xor {
+    type Hasher = XorHasher;
+
+    fn build_hasher(&self) -> XorHasher {
+        XorHasher::default()
+    }
+}
+
+// Keeps the lower 32 bits of the hashes of `S`.
+#[derive(Clone, Default)]
+struct Truncated<S>(S);
+
+struct TruncatedHasher<H>(H);
+
+impl<H: Hasher> Hasher for TruncatedHasher<H> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.write(bytes)
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.0.write_u32(i)
+    }
+
+    fn finish(&self) -> u64 {
+        u64::from(self.0.finish() as u32)
+    }
+}
+
+impl<S: BuildHasher> BuildHasher for Truncated<S> {
+    type Hasher = TruncatedHasher<S::Hasher>;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        TruncatedHasher(self.0.build_hasher())
+    }
+}
+
+fn run<S: BuildHasher + Clone>(name: &str, hasher: S) {
+    let start = Instant::now();
+    let mut classifier = RVHClassifier::<BenchRule, Field, (), S>::with_hasher(
+        presets::five_tuple().into_iter(),
+        hasher,
+    );
+    for i in 0..RULES {
+        classifier.add_rule(rule(i)).unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    let stats: Vec<_> = (0..classifier.table_count())
+        .filter_map(|index| classifier.table_stats(index))
+        .collect();
+    let collisions: usize = stats.iter().map(|s| s.collisions).sum();
+    let buckets: usize = stats.iter().map(|s| s.buckets).sum();
+    let largest = stats.iter().map(|s| s.largest_bucket).max().unwrap_or(0);
+    println!(
+        "{:<12} {:>7} collisions ({:.3}%), {:>6} buckets, largest bucket {:>4}, built in {:?}",
+        name,
+        collisions,
+        100.0 * collisions as f64 / RULES as f64,
+        buckets,
+        largest,
+        elapsed,
+    );
+}
+
+fn main() {
+    run("xor", Xor);
+    run("mix 32 bit", Truncated(MixBuildHasher));
+    run("sip 32 bit", Truncated(SipBuildHasher::random()));
+    run("mix", MixBuildHasher);
+    run("sip", SipBuildHasher::random());
+}
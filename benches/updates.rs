@@ -0,0 +1,107 @@
+// Throughput of insertions and removals without concurrent readers, for tuple space search
+// with many tables where every update used to sort all tables. Rules are removed highest
+// priority first, so that the highest priority of their table changes on every removal. The
+// initial load is timed with `add_rule` and `add_rules`. Run with
+// `cargo bench --bench updates`.
+use std::time::Instant;
+
+use rvh::fields;
+use rvh::prelude::*;
+
+const RULES: u32 = 20_000;
+const ROUNDS: u32 = 5;
+const LOAD: u32 = 500_000;
+
+#[derive(Debug, Clone)]
+struct BenchRule {
+    fields: Vec<Field>,
+    masks: Vec<Mask>,
+    priority: Priority,
+}
+
+impl Rule for BenchRule {
+    fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+    fn masks(&self) -> &[Mask] {
+        &self.masks
+    }
+    fn priority(&self) -> Priority {
+        self.priority
+    }
+}
+
+impl PartialEq for BenchRule {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority
+    }
+}
+
+// Scatters `i` over all bits, so that rules are spread over tables and buckets.
+fn mix(i: u32) -> u32 {
+    let x = i.wrapping_mul(0x9e37_79b9);
+    x ^ (x >> 15)
+}
+
+// Up to 33 * 33 prefix length combinations, each getting its own table.
+fn rule(i: u32) -> BenchRule {
+    let x = mix(i);
+    BenchRule {
+        fields: vec![x, x.rotate_left(11)],
+        masks: vec![
+            fields::prefix_mask(x % 33),
+            fields::prefix_mask((x >> 8) % 33),
+        ],
+        priority: i + 1,
+    }
+}
+
+fn load(bulk: bool) {
+    let mut classifier = RVHClassifier::<BenchRule>::new(std::iter::empty());
+    classifier.set_auto_tables(true);
+
+    let rules = (0..LOAD).map(rule);
+    let start = Instant::now();
+    if bulk {
+        classifier.add_rules(rules);
+    } else {
+        for rule in rules {
+            let _ = classifier.add_rule(rule);
+        }
+    }
+
+    println!(
+        "load {} rules, bulk {}: {:?}",
+        classifier.len(),
+        bulk,
+        start.elapsed()
+    );
+}
+
+fn main() {
+    load(false);
+    load(true);
+
+    let mut classifier = RVHClassifier::<BenchRule>::new(std::iter::empty());
+    classifier.set_auto_tables(true);
+
+    let start = Instant::now();
+    let mut updates = 0;
+    for _ in 0..ROUNDS {
+        let ids: Vec<RuleId> = (0..RULES)
+            .filter_map(|i| classifier.add_rule(rule(i)).ok())
+            .collect();
+        for id in ids.iter().rev() {
+            assert!(classifier.remove(*id).is_ok());
+        }
+        updates += 2 * ids.len();
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{} tables: {:.0} updates/s, {:?} per update",
+        classifier.table_count(),
+        updates as f64 / elapsed.as_secs_f64(),
+        elapsed / updates as u32,
+    );
+}
@@ -0,0 +1,98 @@
+// `#[derive(Rule)]` for structs marking their fields, masks and priority, f.e.
+//
+//     #[derive(PartialEq, Rule)]
+//     struct MyRule {
+//         #[rvh(field)]
+//         fields: Vec<Field>,
+//         #[rvh(mask)]
+//         masks: [Mask; 5],
+//         #[rvh(priority)]
+//         priority: Priority,
+//     }
+//
+// Fields and masks may be anything dereferencing to a slice. Rules with wider fields name
+// their type on the struct, f.e. `#[rvh(field_type = u128)]`. Re-exported by rvh with the
+// `derive` feature.
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Member, Type};
+
+#[proc_macro_derive(Rule, attributes(rvh))]
+pub fn derive_rule(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2, Error> {
+    let mut field_type: Type = syn::parse_quote!(::rvh::types::Field);
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("rvh")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("field_type") {
+                field_type = meta.value()?.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("expected `field_type = <type>`"))
+            }
+        })?;
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "Rule can only be derived for structs",
+            ))
+        }
+    };
+
+    // the member marked with each of `field`, `mask` and `priority`
+    let mut marked: [Option<Member>; 3] = [None, None, None];
+    let names = ["field", "mask", "priority"];
+    for (index, field) in fields.iter().enumerate() {
+        let member = match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(index.into()),
+        };
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("rvh")) {
+            attr.parse_nested_meta(|meta| {
+                let Some(i) = names.iter().position(|n| meta.path.is_ident(n)) else {
+                    return Err(meta.error("expected `field`, `mask` or `priority`"));
+                };
+                if marked[i].is_some() {
+                    return Err(meta.error(format!("more than one `{}`", names[i])));
+                }
+                marked[i] = Some(member.clone());
+                Ok(())
+            })?;
+        }
+    }
+    if let Some(i) = marked.iter().position(Option::is_none) {
+        return Err(Error::new(
+            Span::call_site(),
+            format!("missing a field marked with `#[rvh({})]`", names[i]),
+        ));
+    }
+    let [field, mask, priority] = marked.map(Option::unwrap);
+
+    let ident = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::rvh::types::Rule<#field_type> for #ident #type_generics
+            #where_clause
+        {
+            fn priority(&self) -> ::rvh::types::Priority {
+                self.#priority
+            }
+            fn masks(&self) -> &[#field_type] {
+                &self.#mask
+            }
+            fn fields(&self) -> &[#field_type] {
+                &self.#field
+            }
+        }
+    })
+}
@@ -0,0 +1,96 @@
+use crate::types::Range;
+
+// Thresholds for adapting the tables of a classifier to its rules as they are added and
+// removed, similar to TupleMerge, see `RVHClassifier::set_adaptive`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AdaptivePolicy {
+    // a table is split once one of its buckets holds more rules
+    pub max_bucket: usize,
+    // neighboring tables are merged once they hold at most this many rules together
+    pub merge_below: usize,
+    // tables are not split beyond this number
+    pub max_tables: usize,
+}
+
+impl Default for AdaptivePolicy {
+    fn default() -> Self {
+        Self {
+            max_bucket: 8,
+            merge_below: 4,
+            max_tables: 64,
+        }
+    }
+}
+
+// Dimension and prefix length at which to split a table with `ranges`, so that the
+// `prefix_lengths` of its rules are divided as evenly as possible. Of equally even cuts the
+// last one is taken, so that the upper table hashes more bits. None if all rules have the
+// same prefix lengths within the ranges.
+pub(crate) fn choose_cut(
+    ranges: &[Range],
+    prefix_lengths: &[impl AsRef<[u32]>],
+) -> Option<(usize, u32)> {
+    let mut best: Option<(usize, u32, usize)> = None;
+
+    for (dim, &(low, high)) in ranges.iter().enumerate() {
+        for cut in low + 1..high {
+            let below = prefix_lengths
+                .iter()
+                .filter(|lengths| lengths.as_ref().get(dim).is_some_and(|len| *len < cut))
+                .count();
+            let smaller = below.min(prefix_lengths.len() - below);
+            if smaller > 0 && best.is_none_or(|(_, _, s)| smaller >= s) {
+                best = Some((dim, cut, smaller));
+            }
+        }
+    }
+
+    best.map(|(dim, cut, _)| (dim, cut))
+}
+
+// Union of two tables' ranges, if they only differ in one dimension where they are adjacent.
+pub(crate) fn merged(a: &[Range], b: &[Range]) -> Option<Vec<Range>> {
+    if a.len() != b.len() {
+        return None;
+    }
+
+    let mut differing = a.iter().zip(b).enumerate().filter(|(_, (x, y))| x != y);
+    let (dim, (&(a_low, a_high), &(b_low, b_high))) = differing.next()?;
+    if differing.next().is_some() || (a_high != b_low && b_high != a_low) {
+        return None;
+    }
+
+    let mut ranges = a.to_vec();
+    ranges[dim] = (a_low.min(b_low), a_high.max(b_high));
+    Some(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cut_divides_rules_evenly() {
+        let lengths = vec![vec![1, 8], vec![2, 8], vec![3, 16], vec![3, 24]];
+        assert_eq!(choose_cut(&[(0, 4), (0, 33)], &lengths), Some((1, 16)));
+        assert_eq!(choose_cut(&[(0, 4)], &lengths), Some((0, 3)));
+        assert_eq!(
+            choose_cut(&[(3, 4), (8, 9)], &[vec![3, 8], vec![3, 8]]),
+            None
+        );
+        assert_eq!(choose_cut(&[(0, 4)], &[vec![3], vec![3]]), None);
+    }
+
+    #[test]
+    fn test_only_neighbors_are_merged() {
+        assert_eq!(
+            merged(&[(0, 4), (8, 9)], &[(4, 6), (8, 9)]),
+            Some(vec![(0, 6), (8, 9)])
+        );
+        assert_eq!(merged(&[(4, 6)], &[(0, 4)]), Some(vec![(0, 6)]));
+        assert!(merged(&[(0, 4), (8, 9)], &[(4, 6), (9, 10)]).is_none());
+        assert!(merged(&[(0, 4)], &[(5, 6)]).is_none());
+        assert!(merged(&[(0, 4)], &[(0, 4)]).is_none());
+    }
+}
@@ -0,0 +1,403 @@
+use crate::classifier::RVHClassifier;
+use crate::error::RvhError;
+use crate::hash::MixBuildHasher;
+use crate::telemetry::TableStats;
+use crate::types::{ActionRule, FieldType, Range, Rule};
+
+// Properties of a rule set relevant for choosing the tables of a classifier, see `analyze`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleSetReport {
+    pub rules: usize,
+    // number of rules per prefix length for each dimension, indexed by the prefix length
+    pub prefix_lengths: Vec<Vec<usize>>,
+    // number of rules with prefix length 0 for each dimension
+    pub wildcards: Vec<usize>,
+    // the largest number of other rules a single rule overlaps with, i.e. that match at least
+    // one packet it matches as well
+    pub max_overlap: usize,
+    pub mean_overlap: f64,
+    // split with at most the requested number of tables, valid according to `split::analyze`
+    pub suggested_split: Vec<Vec<Range>>,
+}
+
+impl RuleSetReport {
+    // Share of rules with a wildcard in `dimension`.
+    pub fn wildcard_frequency(&self, dimension: usize) -> f64 {
+        if self.rules == 0 {
+            return 0.0;
+        }
+        self.wildcards.get(dimension).copied().unwrap_or(0) as f64 / self.rules as f64
+    }
+}
+
+// Analyzes the rules for fields of the given `widths`. The overlap degree compares every pair
+// of rules and is meant for offline use.
+pub fn analyze<'a, R, F>(
+    rules: impl IntoIterator<Item = &'a R>,
+    widths: &[u32],
+    max_tables: usize,
+) -> RuleSetReport
+where
+    R: Rule<F> + 'a,
+    F: FieldType,
+{
+    let rules: Vec<&R> = rules.into_iter().collect();
+
+    let lengths: Vec<Vec<u32>> = rules
+        .iter()
+        .map(|r| {
+            widths
+                .iter()
+                .enumerate()
+                .map(|(dim, width)| r.masks().get(dim).map_or(0, |m| m.count_ones().min(*width)))
+                .collect()
+        })
+        .collect();
+
+    let mut prefix_lengths: Vec<Vec<usize>> =
+        widths.iter().map(|w| vec![0; *w as usize + 1]).collect();
+    for rule in lengths.iter() {
+        for (dim, len) in rule.iter().enumerate() {
+            prefix_lengths[dim][*len as usize] += 1;
+        }
+    }
+    let wildcards = prefix_lengths.iter().map(|counts| counts[0]).collect();
+
+    let mut overlaps = vec![0; rules.len()];
+    for (i, a) in rules.iter().enumerate() {
+        for (j, b) in rules.iter().enumerate().skip(i + 1) {
+            if overlap(*a, *b) {
+                overlaps[i] += 1;
+                overlaps[j] += 1;
+            }
+        }
+    }
+    let mean_overlap = if rules.is_empty() {
+        0.0
+    } else {
+        overlaps.iter().sum::<usize>() as f64 / rules.len() as f64
+    };
+
+    RuleSetReport {
+        rules: rules.len(),
+        suggested_split: suggest_split(&prefix_lengths, max_tables),
+        prefix_lengths,
+        wildcards,
+        max_overlap: overlaps.into_iter().max().unwrap_or(0),
+        mean_overlap,
+    }
+}
+
+// Two rules overlap if in every dimension the fields agree on the bits both masks cover.
+pub(crate) fn overlap<R: Rule<F>, F: FieldType>(a: &R, b: &R) -> bool {
+    a.fields()
+        .iter()
+        .zip(a.masks())
+        .zip(b.fields().iter().zip(b.masks()))
+        .all(|((fa, ma), (fb, mb))| (*fa ^ *fb) & *ma & *mb == F::ZERO)
+}
+
+// How far the regions of two conflicting rules overlap, see `analyze_conflicts`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    // some packets match both rules
+    Partial,
+    // the higher priority rule covers the lower one, which then never decides a packet
+    Shadowing,
+    // the lower priority rule covers the higher one, which is an exception to it
+    Generalization,
+}
+
+impl Severity {
+    pub fn is_full(&self) -> bool {
+        *self != Severity::Partial
+    }
+}
+
+// Two overlapping rules with different actions, as indices into the analyzed rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conflict {
+    // of rules with the same priority, the first one
+    pub higher: usize,
+    pub lower: usize,
+    pub severity: Severity,
+}
+
+// Finds every pair of overlapping rules with different actions, the classic source of firewall
+// misconfiguration. Shadowed rules are likely mistakes, partial overlaps and generalizations
+// may be intended. Compares every pair of rules and is meant for offline use, like `analyze`.
+pub fn analyze_conflicts<'a, R, F>(rules: impl IntoIterator<Item = &'a R>) -> Vec<Conflict>
+where
+    R: ActionRule<F> + 'a,
+    R::Action: PartialEq,
+    F: FieldType,
+{
+    let rules: Vec<&R> = rules.into_iter().collect();
+    let mut conflicts = Vec::new();
+    for (i, a) in rules.iter().enumerate() {
+        for (j, b) in rules.iter().enumerate().skip(i + 1) {
+            if a.action() == b.action() || !overlap(*a, *b) {
+                continue;
+            }
+
+            let (higher, lower) = if b.priority() > a.priority() {
+                (j, i)
+            } else {
+                (i, j)
+            };
+            let severity = if covers(rules[higher], rules[lower]) {
+                Severity::Shadowing
+            } else if covers(rules[lower], rules[higher]) {
+                Severity::Generalization
+            } else {
+                Severity::Partial
+            };
+            conflicts.push(Conflict {
+                higher,
+                lower,
+                severity,
+            });
+        }
+    }
+
+    conflicts
+}
+
+// Whether every packet matching `b` matches `a` as well, dimensions a rule does not have match
+// anything.
+fn covers<R: Rule<F>, F: FieldType>(a: &R, b: &R) -> bool {
+    a.fields()
+        .iter()
+        .zip(a.masks())
+        .enumerate()
+        .all(|(dim, (fa, ma))| {
+            let fb = b.fields().get(dim).copied().unwrap_or(F::ZERO);
+            let mb = b.masks().get(dim).copied().unwrap_or(F::ZERO);
+            *ma & mb == *ma && (*fa ^ fb) & *ma == F::ZERO
+        })
+}
+
+// Repeatedly halves every table along the dimension whose rules are divided most evenly by a
+// single cut of the prefix lengths, as long as the number of tables stays within `max_tables`.
+fn suggest_split(prefix_lengths: &[Vec<usize>], max_tables: usize) -> Vec<Vec<Range>> {
+    let mut split: Vec<Vec<Range>> = vec![prefix_lengths
+        .iter()
+        .map(|counts| (0, counts.len() as u32))
+        .collect()];
+    let mut cuts: Vec<(usize, u32, usize)> = prefix_lengths
+        .iter()
+        .enumerate()
+        .filter_map(|(dim, counts)| {
+            let total: usize = counts.iter().sum();
+            (1..counts.len())
+                .map(|cut| {
+                    let below: usize = counts[..cut].iter().sum();
+                    (cut as u32, below, total - below)
+                })
+                .filter(|(_, below, above)| *below > 0 && *above > 0)
+                .min_by_key(|(_, below, above)| (*below as isize - *above as isize).abs())
+                .map(|(cut, below, above)| (dim, cut, below.min(above)))
+        })
+        .collect();
+    // most even cut first
+    cuts.sort_by_key(|(_, _, smaller)| std::cmp::Reverse(*smaller));
+
+    for (dim, cut, _) in cuts {
+        if split.len() * 2 > max_tables {
+            break;
+        }
+
+        split = split
+            .into_iter()
+            .flat_map(|ranges| {
+                let (low, high) = ranges[dim];
+                let mut lower = ranges.clone();
+                lower[dim] = (low, cut);
+                let mut upper = ranges;
+                upper[dim] = (cut, high);
+                vec![lower, upper]
+            })
+            .collect();
+    }
+
+    split
+}
+
+// Bucket usage the tables of a proposed split would have with a sample rule set, see
+// `simulate_split`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitSimulation {
+    // per range vector, in the order of the split
+    pub tables: Vec<TableStats>,
+    // rules no table accepts
+    pub unmatched: usize,
+    // rules rejected for other reasons, f.e. a duplicate priority within a table
+    pub rejected: usize,
+}
+
+impl SplitSimulation {
+    // Longest bucket of all tables, the worst case number of rules compared per table probe.
+    pub fn largest_bucket(&self) -> usize {
+        self.tables
+            .iter()
+            .map(|t| t.largest_bucket)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Mean number of rules per non-empty bucket over all tables.
+    pub fn mean_bucket(&self) -> f64 {
+        let buckets: usize = self.tables.iter().map(|t| t.buckets).sum();
+        if buckets == 0 {
+            return 0.0;
+        }
+        self.tables.iter().map(|t| t.rules).sum::<usize>() as f64 / buckets as f64
+    }
+}
+
+// Inserts the sample rules into the tables of `split` without building a classifier for
+// use, so that candidate splits can be compared offline. The tables hash with
+// `MixBuildHasher`, so the result is the same on every run.
+pub fn simulate_split<'a, R, F>(
+    split: &[Vec<Range>],
+    rules: impl IntoIterator<Item = &'a R>,
+) -> SplitSimulation
+where
+    R: Rule<F> + Clone + 'a,
+    F: FieldType,
+{
+    let mut classifier =
+        RVHClassifier::<R, F, (), _>::with_hasher(split.iter().cloned(), MixBuildHasher);
+    let (mut unmatched, mut rejected) = (0, 0);
+    for result in classifier.add_rules(rules.into_iter().cloned()) {
+        match result {
+            Ok(_) => {}
+            Err(RvhError::NoMatchingTable) => unmatched += 1,
+            Err(_) => rejected += 1,
+        }
+    }
+
+    SplitSimulation {
+        tables: (0..split.len())
+            .filter_map(|index| classifier.table_stats(index))
+            .collect(),
+        unmatched,
+        rejected,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields;
+    use crate::split;
+    use crate::types::mocks::MockRule;
+
+    #[test]
+    fn test_report_of_small_rule_set() {
+        let rules = [
+            MockRule::new(vec![0b1, 0], vec![0b1, 0], 1),
+            MockRule::new(vec![0b11, 0], vec![0b11, 0], 2),
+            MockRule::new(vec![0b101, 0b1], vec![0b111, 0b1111], 3),
+            MockRule::new(vec![0b110, 0b1], vec![0b111, 0b1111], 4),
+        ];
+        let report = analyze(rules.iter(), &[4, 4], 4);
+
+        assert_eq!(report.rules, 4);
+        assert_eq!(
+            report.prefix_lengths,
+            vec![vec![0, 1, 1, 2, 0], vec![2, 0, 0, 0, 2]]
+        );
+        assert_eq!(report.wildcards, vec![0, 2]);
+        assert_eq!(report.wildcard_frequency(1), 0.5);
+        // the first rule overlaps with the second and the third one
+        assert_eq!(report.max_overlap, 2);
+        assert_eq!(report.mean_overlap, 1.0);
+
+        assert_eq!(
+            report.suggested_split,
+            vec![
+                vec![(0, 3), (0, 1)],
+                vec![(0, 3), (1, 5)],
+                vec![(3, 5), (0, 1)],
+                vec![(3, 5), (1, 5)],
+            ]
+        );
+        assert!(split::analyze(&report.suggested_split, &[4, 4]).is_valid());
+        assert_eq!(analyze(rules.iter(), &[4, 4], 3).suggested_split.len(), 2);
+
+        let empty = analyze(Vec::<MockRule>::new().iter(), &[4], 8);
+        assert_eq!(empty.suggested_split, vec![vec![(0, 5)]]);
+        assert_eq!(empty.wildcard_frequency(0), 0.0);
+    }
+
+    #[test]
+    fn test_simulation_compares_splits() {
+        let rules: Vec<MockRule> = (0..16)
+            .map(|i| MockRule::new(vec![i], vec![fields::prefix_mask(1 + i % 4)], i + 1))
+            .collect();
+
+        // all rules hash by their lowest bit in a single table
+        let coarse = simulate_split(&[vec![(1, 5)]], rules.iter());
+        assert_eq!(coarse.tables.len(), 1);
+        assert_eq!(coarse.tables[0].rules, 16);
+        assert_eq!(coarse.tables[0].buckets, 2);
+        assert_eq!(coarse.largest_bucket(), 8);
+        assert_eq!(coarse.mean_bucket(), 8.0);
+
+        let fine = simulate_split(
+            &(1..5).map(|len| vec![(len, len + 1)]).collect::<Vec<_>>(),
+            rules.iter(),
+        );
+        assert_eq!(fine.tables.iter().map(|t| t.rules).sum::<usize>(), 16);
+        assert!(fine.largest_bucket() < coarse.largest_bucket());
+        assert_eq!(fine.tables[0].collision_rate(), 0.0);
+
+        let partial = simulate_split(&[vec![(1, 3)]], rules.iter());
+        assert_eq!((partial.unmatched, partial.rejected), (8, 0));
+    }
+
+    #[test]
+    fn test_conflicts_by_severity() {
+        use crate::rules::FiveTupleRule;
+        use std::net::Ipv4Addr;
+
+        let net = |b, len| (Ipv4Addr::new(10, b, 0, 0), len);
+        let rule = |priority, (addr, len), action| {
+            FiveTupleRule::new(priority)
+                .src_prefix(addr, len)
+                .with_action(action)
+        };
+        let rules = [
+            rule(10, net(0, 8), "accept"),
+            // never matches, the rule above covers it
+            rule(5, net(1, 16), "drop"),
+            // an exception to the rule below
+            rule(20, net(2, 16), "drop").dst_port(22),
+            rule(3, net(0, 8), "accept").dst_port(22),
+            rule(1, net(0, 16), "log").protocol(6),
+            rule(15, net(0, 8), "drop").src_port(53),
+        ];
+
+        let conflicts = analyze_conflicts(rules.iter());
+        let found: Vec<_> = conflicts
+            .iter()
+            .map(|c| (c.higher, c.lower, c.severity))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (0, 1, Severity::Shadowing),
+                (2, 0, Severity::Generalization),
+                (0, 4, Severity::Shadowing),
+                (5, 0, Severity::Generalization),
+                (1, 3, Severity::Partial),
+                (2, 3, Severity::Generalization),
+                (3, 4, Severity::Partial),
+                (5, 3, Severity::Partial),
+                (5, 4, Severity::Partial),
+            ]
+        );
+        assert!(conflicts[1].severity.is_full() && !conflicts[4].severity.is_full());
+    }
+}
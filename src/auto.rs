@@ -0,0 +1,232 @@
+use std::hash::BuildHasher;
+
+use crate::classifier::RVHClassifier;
+use crate::error::RvhError;
+use crate::linear::LinearClassifier;
+use crate::range_vector_hash_map;
+use crate::table::RVHTable;
+use crate::types::*;
+
+// Operations shared by the classifiers of this crate, so that backends can be swapped, see
+// `AutoClassifier`.
+pub trait Classifier<R: Rule<F>, F: FieldType = Field> {
+    fn insert(&mut self, rule: R) -> Result<(), RvhError>;
+    fn remove_rule(&mut self, rule: &R) -> Result<(), RvhError>;
+    fn classify(&self, p: &impl Packet<F>) -> Option<&R>;
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<R: Rule<F>, F: FieldType, M, S: BuildHasher + Clone> Classifier<R, F>
+    for RVHClassifier<R, F, M, S>
+{
+    fn insert(&mut self, rule: R) -> Result<(), RvhError> {
+        self.add_rule(rule).map(|_| ())
+    }
+
+    fn remove_rule(&mut self, rule: &R) -> Result<(), RvhError> {
+        RVHClassifier::remove_rule(self, rule)
+    }
+
+    fn classify(&self, p: &impl Packet<F>) -> Option<&R> {
+        RVHClassifier::classify(self, p)
+    }
+
+    fn len(&self) -> usize {
+        RVHClassifier::len(self)
+    }
+}
+
+impl<R: Rule<F>, F: FieldType> Classifier<R, F> for RVHTable<R, F> {
+    fn insert(&mut self, rule: R) -> Result<(), RvhError> {
+        RVHTable::insert(self, rule).map(|_| ())
+    }
+
+    fn remove_rule(&mut self, rule: &R) -> Result<(), RvhError> {
+        self.remove(rule).map(|_| ())
+    }
+
+    fn classify(&self, p: &impl Packet<F>) -> Option<&R> {
+        RVHTable::classify(self, p)
+    }
+
+    fn len(&self) -> usize {
+        RVHTable::len(self)
+    }
+}
+
+fn is_valid<R: Rule<F>, F: FieldType>(rule: &R) -> bool {
+    rule.fields().len() == rule.masks().len() && range_vector_hash_map::invalid_mask(rule).is_none()
+}
+
+// The data structure an `AutoClassifier` picked for its rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    // a `LinearClassifier`, for a handful of rules
+    Linear,
+    // a single `RVHTable`, for rules that all have the same prefix lengths
+    Table,
+    // an `RVHClassifier` with a split inferred from the rules
+    Tables,
+}
+
+#[derive(Debug, Clone)]
+enum Inner<R: Rule<F>, F: FieldType> {
+    Linear(LinearClassifier<R>),
+    Table(RVHTable<R, F>),
+    Tables(RVHClassifier<R, F>),
+}
+
+// Picks the cheapest backend for a rule set when it is built, so that small or uniform rule
+// sets do not pay for probing several tables. A rule the single table does not accept moves
+// all rules into an `RVHClassifier`, the backend is not changed otherwise.
+#[derive(Debug, Clone)]
+pub struct AutoClassifier<R: Rule<F>, F: FieldType = Field> {
+    inner: Inner<R, F>,
+}
+
+impl<R: Rule<F>, F: FieldType> AutoClassifier<R, F> {
+    // rule sets up to this size are scanned linearly
+    pub const LINEAR_MAX_RULES: usize = 16;
+
+    // Rules that are rejected, f.e. for a duplicate priority, are dropped.
+    pub fn build(rules: Vec<R>) -> Self {
+        let prefix_lengths =
+            |r: &R| -> Vec<u32> { r.masks().iter().map(|m| m.count_ones()).collect() };
+
+        let inner = if rules.len() <= Self::LINEAR_MAX_RULES {
+            Inner::Linear(LinearClassifier::new())
+        } else if rules
+            .windows(2)
+            .all(|w| prefix_lengths(&w[0]) == prefix_lengths(&w[1]))
+        {
+            let ranges = prefix_lengths(&rules[0])
+                .into_iter()
+                .map(|len| (len, len + 1))
+                .collect();
+            Inner::Table(RVHTable::new(ranges))
+        } else {
+            return Self {
+                inner: Inner::Tables(RVHClassifier::from_rules(rules)),
+            };
+        };
+
+        let mut classifier = Self { inner };
+        for rule in rules {
+            let _ = classifier.insert(rule);
+        }
+        classifier
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self.inner {
+            Inner::Linear(_) => Backend::Linear,
+            Inner::Table(_) => Backend::Table,
+            Inner::Tables(_) => Backend::Tables,
+        }
+    }
+}
+
+impl<R: Rule<F>, F: FieldType> Classifier<R, F> for AutoClassifier<R, F> {
+    fn insert(&mut self, rule: R) -> Result<(), RvhError> {
+        let table = match &mut self.inner {
+            Inner::Linear(c) => return c.insert(rule),
+            Inner::Tables(c) => return Classifier::insert(c, rule),
+            // invalid rules are rejected by the table as well
+            Inner::Table(c) if c.accepts(&rule) || !is_valid(&rule) => {
+                return Classifier::insert(c, rule)
+            }
+            Inner::Table(c) => std::mem::replace(c, RVHTable::new(Vec::new())),
+        };
+
+        let mut tables = RVHClassifier::from_rules(table.into_rules().collect());
+        let result = Classifier::insert(&mut tables, rule);
+        self.inner = Inner::Tables(tables);
+        result
+    }
+
+    fn remove_rule(&mut self, rule: &R) -> Result<(), RvhError> {
+        match &mut self.inner {
+            Inner::Linear(c) => c.remove_rule(rule),
+            Inner::Table(c) => Classifier::remove_rule(c, rule),
+            Inner::Tables(c) => Classifier::remove_rule(c, rule),
+        }
+    }
+
+    fn classify(&self, p: &impl Packet<F>) -> Option<&R> {
+        match &self.inner {
+            Inner::Linear(c) => c.classify(p),
+            Inner::Table(c) => c.classify(p),
+            Inner::Tables(c) => c.classify(p),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match &self.inner {
+            Inner::Linear(c) => Classifier::len(c),
+            Inner::Table(c) => c.len(),
+            Inner::Tables(c) => c.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::mocks::{MockPacket, MockRule};
+
+    fn exact(value: Field, priority: Priority) -> MockRule {
+        MockRule::new(vec![value, 0], vec![0xff, 0], priority)
+    }
+
+    #[test]
+    fn test_backend_follows_the_rules() {
+        let few = AutoClassifier::build(vec![exact(1, 1), exact(2, 2)]);
+        assert_eq!(few.backend(), Backend::Linear);
+        assert_eq!(
+            few.classify(&MockPacket::new(vec![2, 7]))
+                .unwrap()
+                .priority(),
+            2
+        );
+
+        let mut uniform = AutoClassifier::build((1..=100).map(|i| exact(i, i)).collect());
+        assert_eq!(uniform.backend(), Backend::Table);
+        assert_eq!(uniform.len(), 100);
+        let p = MockPacket::new(vec![0x1_0042, 9]);
+        assert_eq!(uniform.classify(&p).unwrap().priority(), 0x42);
+
+        // a rule with other prefix lengths moves all rules into several tables
+        assert!(uniform
+            .insert(MockRule::new(vec![0x42, 0], vec![0xf, 0], 200))
+            .is_ok());
+        assert_eq!(uniform.backend(), Backend::Tables);
+        assert_eq!(uniform.len(), 101);
+        assert_eq!(uniform.classify(&p).unwrap().priority(), 200);
+
+        assert!(uniform.remove_rule(&exact(0x42, 200)).is_ok());
+        assert_eq!(uniform.classify(&p).unwrap().priority(), 0x42);
+    }
+
+    #[test]
+    fn test_linear_backend_checks_rules() {
+        let mut linear = AutoClassifier::build(vec![exact(1, 1)]);
+        assert_eq!(linear.insert(exact(3, 1)), Err(RvhError::DuplicatePriority));
+        assert_eq!(
+            linear.insert(MockRule::new(vec![1], vec![0b10], 2)),
+            Err(RvhError::InvalidMask { dimension: 0 })
+        );
+        assert!(linear
+            .insert(MockRule::new(vec![1, 0], vec![0x1, 0], 5))
+            .is_ok());
+
+        let p = MockPacket::new(vec![1, 0]);
+        assert_eq!(linear.classify(&p).unwrap().priority(), 5);
+        assert!(linear.remove_rule(&exact(0, 5)).is_ok());
+        assert_eq!(linear.classify(&p).unwrap().priority(), 1);
+        assert_eq!(linear.remove_rule(&exact(0, 5)), Err(RvhError::NotFound));
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::BTreeMap;
+
+use crate::types::Priority;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PriorityBand {
+    name: String,
+    low: Priority,
+    high: Priority,
+}
+
+impl PriorityBand {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn low(&self) -> Priority {
+        self.low
+    }
+
+    // inclusive, so that a band may extend up to `Priority::MAX`
+    pub fn high(&self) -> Priority {
+        self.high
+    }
+
+    pub fn contains(&self, priority: Priority) -> bool {
+        priority >= self.low && priority <= self.high
+    }
+
+    fn overlaps(&self, low: Priority, high: Priority) -> bool {
+        low <= self.high && high >= self.low
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct PriorityBands {
+    bands: Vec<PriorityBand>,
+}
+
+impl PriorityBands {
+    pub const fn new() -> Self {
+        Self { bands: Vec::new() }
+    }
+
+    pub fn declare(&mut self, name: String, low: Priority, high: Priority) -> bool {
+        if low > high
+            || self
+                .bands
+                .iter()
+                .any(|b| b.name == name || b.overlaps(low, high))
+        {
+            return false;
+        }
+
+        self.bands.push(PriorityBand { name, low, high });
+        self.bands.sort_by_key(|b| b.low);
+        true
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PriorityBand> {
+        self.bands.iter().find(|b| b.name == name)
+    }
+
+    pub fn band_of(&self, priority: Priority) -> Option<&PriorityBand> {
+        self.bands.iter().find(|b| b.contains(priority))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PriorityBand> {
+        self.bands.iter()
+    }
+}
+
+// Hands out the priorities `low..=high`, lowest first, see `RVHClassifier::add_rule_with`.
+// Released priorities are handed out again before new ones, and released priorities at the
+// top of the used range are given back to it, so long-lived users do not run out of
+// priorities. Priority 0 is never handed out, rules with it never match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PriorityAllocator {
+    band: Option<String>,
+    low: Priority,
+    high: Priority,
+    // all priorities below `next` were handed out, except for the released ones
+    next: u64,
+    // released or skipped priorities below `next`, as disjoint ranges `low => high` that are
+    // never adjacent and never end right below `next`
+    gaps: BTreeMap<Priority, Priority>,
+}
+
+impl PriorityAllocator {
+    pub fn new(low: Priority, high: Priority) -> Self {
+        let low = low.max(1);
+        Self {
+            band: None,
+            low,
+            high,
+            next: low as u64,
+            gaps: BTreeMap::new(),
+        }
+    }
+
+    // Allocator for the priorities of a band, rules are added to the band.
+    pub fn for_band(band: &PriorityBand) -> Self {
+        Self {
+            band: Some(band.name().to_owned()),
+            ..Self::new(band.low(), band.high())
+        }
+    }
+
+    pub fn band(&self) -> Option<&str> {
+        self.band.as_deref()
+    }
+
+    // None once all priorities are in use.
+    pub fn allocate(&mut self) -> Option<Priority> {
+        if let Some((low, high)) = self.gaps.pop_first() {
+            if low < high {
+                self.gaps.insert(low + 1, high);
+            }
+            return Some(low);
+        }
+
+        if self.next > self.high as u64 {
+            return None;
+        }
+        self.next += 1;
+        Some((self.next - 1) as Priority)
+    }
+
+    // Fails if the priority is not handed out.
+    pub fn release(&mut self, priority: Priority) -> bool {
+        if !self.is_allocated(priority) {
+            return false;
+        }
+
+        let (mut low, mut high) = (priority, priority);
+        if let Some((&l, &h)) = self.gaps.range(..priority).next_back() {
+            if h == priority - 1 {
+                self.gaps.remove(&l);
+                low = l;
+            }
+        }
+        if let Some(h) = priority.checked_add(1).and_then(|p| self.gaps.remove(&p)) {
+            high = h;
+        }
+
+        if high as u64 + 1 == self.next {
+            self.next = low as u64;
+        } else {
+            self.gaps.insert(low, high);
+        }
+        true
+    }
+
+    // Marks a priority as used without handing it out, f.e. for rules added with a fixed
+    // priority. Fails if it is outside of the range or already in use.
+    pub fn reserve(&mut self, priority: Priority) -> bool {
+        if priority < self.low || priority > self.high || self.is_allocated(priority) {
+            return false;
+        }
+
+        match self.gap_of(priority) {
+            Some((low, high)) => {
+                self.gaps.remove(&low);
+                if low < priority {
+                    self.gaps.insert(low, priority - 1);
+                }
+                if priority < high {
+                    self.gaps.insert(priority + 1, high);
+                }
+            }
+            None => {
+                if (priority as u64) > self.next {
+                    self.gaps.insert(self.next as Priority, priority - 1);
+                }
+                self.next = priority as u64 + 1;
+            }
+        }
+        true
+    }
+
+    pub fn is_allocated(&self, priority: Priority) -> bool {
+        priority >= self.low && (priority as u64) < self.next && self.gap_of(priority).is_none()
+    }
+
+    // Number of priorities that can still be handed out.
+    pub fn available(&self) -> u64 {
+        let released: u64 = self
+            .gaps
+            .iter()
+            .map(|(&low, &high)| (high - low) as u64 + 1)
+            .sum();
+        (self.high as u64 + 1).saturating_sub(self.next) + released
+    }
+
+    fn gap_of(&self, priority: Priority) -> Option<(Priority, Priority)> {
+        self.gaps
+            .range(..=priority)
+            .next_back()
+            .filter(|(_, &high)| priority <= high)
+            .map(|(&low, &high)| (low, high))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocator_reuses_released_priorities() {
+        let mut allocator = PriorityAllocator::new(0, 4);
+        assert_eq!(allocator.available(), 4);
+        assert_eq!(allocator.allocate(), Some(1));
+        assert_eq!(allocator.allocate(), Some(2));
+        assert_eq!(allocator.allocate(), Some(3));
+
+        assert!(allocator.release(2));
+        assert!(!allocator.release(2));
+        assert!(!allocator.release(4));
+        assert_eq!(allocator.allocate(), Some(2));
+
+        // released priorities at the top are compacted
+        assert!(allocator.release(2));
+        assert!(allocator.release(3));
+        assert_eq!(allocator.available(), 3);
+        assert_eq!(allocator.allocate(), Some(2));
+
+        assert!(allocator.reserve(4));
+        assert!(!allocator.reserve(4));
+        assert!(!allocator.reserve(5));
+        assert_eq!(allocator.allocate(), Some(3));
+        assert_eq!(allocator.allocate(), None);
+        assert_eq!(allocator.available(), 0);
+
+        let mut full = PriorityAllocator::new(Priority::MAX, Priority::MAX);
+        assert_eq!(full.allocate(), Some(Priority::MAX));
+        assert_eq!(full.allocate(), None);
+        assert!(full.release(Priority::MAX));
+        assert_eq!(full.available(), 1);
+    }
+
+    #[test]
+    fn test_allocator_reserves_far_ahead() {
+        let mut bands = PriorityBands::default();
+        assert!(bands.declare("system".into(), 1_000_000, Priority::MAX));
+        let mut allocator = PriorityAllocator::for_band(bands.get("system").unwrap());
+
+        // the skipped priorities are kept as a single range
+        assert!(allocator.reserve(Priority::MAX));
+        assert!(allocator.reserve(1_000_002));
+        assert_eq!(allocator.available(), Priority::MAX as u64 - 1_000_000 - 1);
+        assert_eq!(allocator.allocate(), Some(1_000_000));
+        assert_eq!(allocator.allocate(), Some(1_000_001));
+        assert_eq!(allocator.allocate(), Some(1_000_003));
+        assert!(!allocator.is_allocated(1_000_004));
+
+        // released priorities next to the gap join it, up to the top of the used range
+        assert!(allocator.release(1_000_002));
+        assert!(allocator.release(1_000_003));
+        assert!(allocator.release(Priority::MAX));
+        assert_eq!(allocator.allocate(), Some(1_000_002));
+        assert_eq!(allocator.available(), Priority::MAX as u64 - 1_000_002);
+    }
+
+    #[test]
+    fn test_declare_rejects_overlapping_bands() {
+        let mut bands = PriorityBands::default();
+
+        assert!(bands.declare("system".into(), 1_000_000, Priority::MAX));
+        assert!(bands.declare("user".into(), 0, 999_999));
+
+        assert!(!bands.declare("other".into(), 999_999, 1_000_000));
+        assert!(!bands.declare("user".into(), 5, 4));
+        assert!(!bands.declare("user".into(), 5, 6));
+
+        let names: Vec<_> = bands.iter().map(|b| b.name()).collect();
+        assert_eq!(names, vec!["user", "system"]);
+    }
+
+    #[test]
+    fn test_band_lookup() {
+        let mut bands = PriorityBands::default();
+        assert!(bands.declare("system".into(), 100, 200));
+
+        assert_eq!(bands.band_of(100).map(|b| b.name()), Some("system"));
+        assert_eq!(bands.band_of(200).map(|b| b.name()), Some("system"));
+        assert!(bands.band_of(201).is_none());
+        assert_eq!(bands.get("system").unwrap().low(), 100);
+        assert!(bands.get("user").is_none());
+    }
+}
@@ -0,0 +1,158 @@
+// Classifies the packets of a capture against a rule file and reports how often each rule
+// matched, to check a rule set offline:
+//
+//     rvh <rule file> <pcap file>
+//
+// See `ruleset::parse` for the format of the rule file.
+use std::collections::BTreeMap;
+use std::process::ExitCode;
+use std::{env, fs};
+
+use rvh::parse::{self, ParsedPacket};
+use rvh::types::ActionRule;
+use rvh::RVHClassifier;
+
+mod pcap;
+mod ruleset;
+
+use pcap::{LinkType, Pcap};
+use ruleset::FileRule;
+
+// Outcome of a replay.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Replay {
+    // by line of the rule in the rule file
+    hits: BTreeMap<usize, u64>,
+    unmatched: u64,
+    // by reason, f.e. "protocol 1 has no ports"
+    unparseable: BTreeMap<String, u64>,
+}
+
+fn replay(
+    rvh: &RVHClassifier<FileRule, u128>,
+    capture: Pcap<'_>,
+) -> Result<Replay, pcap::PcapError> {
+    let link_type = capture.link_type;
+    let mut replay = Replay::default();
+    for record in capture {
+        let parsed: Result<ParsedPacket, _> = match link_type {
+            LinkType::Ethernet => parse::parse(record?),
+            LinkType::Raw => parse::parse_ip(record?),
+        };
+        match parsed {
+            Ok(packet) => match rvh.decide(&packet) {
+                Some(decision) => *replay.hits.entry(decision.action).or_default() += 1,
+                None => replay.unmatched += 1,
+            },
+            Err(e) => *replay.unparseable.entry(e.to_string()).or_default() += 1,
+        }
+    }
+
+    Ok(replay)
+}
+
+fn run(rules_path: &str, pcap_path: &str) -> Result<(), String> {
+    let text = fs::read_to_string(rules_path).map_err(|e| format!("{}: {}", rules_path, e))?;
+    let report = ruleset::parse(&text);
+    for unsupported in report.unsupported.iter() {
+        eprintln!("{}: skipped {}", rules_path, unsupported);
+    }
+
+    let mut rvh = RVHClassifier::<FileRule, u128>::ipv6_five_tuple();
+    rvh.set_auto_tables(true);
+    for (rule, result) in report.rules.iter().zip(rvh.add_rules(report.rules.clone())) {
+        if let Err(e) = result {
+            eprintln!(
+                "{}:{}: rule not installed: {}",
+                rules_path,
+                *rule.action(),
+                e
+            );
+        }
+    }
+
+    let bytes = fs::read(pcap_path).map_err(|e| format!("{}: {}", pcap_path, e))?;
+    let capture = Pcap::new(&bytes).map_err(|e| format!("{}: {}", pcap_path, e))?;
+    let replay = replay(&rvh, capture).map_err(|e| format!("{}: {}", pcap_path, e))?;
+
+    let lines: Vec<&str> = text.lines().collect();
+    for rule in report.rules.iter() {
+        let hits = replay.hits.get(rule.action()).copied().unwrap_or(0);
+        println!(
+            "{:>10}  {}: {}",
+            hits,
+            *rule.action(),
+            lines[*rule.action() - 1].trim()
+        );
+    }
+    println!("{:>10}  unmatched", replay.unmatched);
+    for (reason, count) in replay.unparseable.iter() {
+        println!("{:>10}  unparseable: {}", count, reason);
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} <rule file> <pcap file>", args[0]);
+        return ExitCode::from(2);
+    }
+
+    match run(&args[1], &args[2]) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_counts_every_packet() {
+        let report = ruleset::parse("dport 443 proto tcp\nsrc 10.0.0.0/8\n");
+        let mut rvh = RVHClassifier::<FileRule, u128>::ipv6_five_tuple();
+        for rule in report.rules {
+            assert!(rvh.add_rule(rule).is_ok());
+        }
+
+        let packet = |src: [u8; 4], dport: u16, protocol: u8| {
+            let mut ip = vec![0x45, 0, 0, 24, 0, 0, 0, 0, 64, protocol, 0, 0];
+            ip.extend(src);
+            ip.extend([192, 168, 0, 1]);
+            ip.extend(1234u16.to_be_bytes());
+            ip.extend(dport.to_be_bytes());
+            ip
+        };
+        let records = [
+            packet([10, 1, 1, 1], 443, 6),
+            packet([10, 1, 1, 1], 80, 6),
+            packet([11, 1, 1, 1], 443, 6),
+            packet([11, 1, 1, 1], 443, 17),
+            packet([11, 1, 1, 1], 0, 1),
+        ];
+        let mut bytes = Vec::new();
+        bytes.extend(0xa1b2_c3d4u32.to_le_bytes());
+        bytes.extend([2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0]);
+        bytes.extend(101u32.to_le_bytes());
+        for record in records.iter() {
+            bytes.extend([0; 8]);
+            bytes.extend((record.len() as u32).to_le_bytes());
+            bytes.extend((record.len() as u32).to_le_bytes());
+            bytes.extend(record);
+        }
+
+        let replay = replay(&rvh, Pcap::new(&bytes).unwrap()).unwrap();
+        assert_eq!(replay.hits, BTreeMap::from([(1, 2), (2, 1)]));
+        assert_eq!(replay.unmatched, 1);
+        assert_eq!(
+            replay.unparseable,
+            BTreeMap::from([("protocol 1 has no ports".to_string(), 1)])
+        );
+    }
+}
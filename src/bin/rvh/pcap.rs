@@ -0,0 +1,153 @@
+use std::fmt;
+
+// Link types of the captures that can be replayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkType {
+    Ethernet,
+    // IP packets without link layer header
+    Raw,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PcapError {
+    // not a pcap file, pcapng is not supported either
+    BadMagic(u32),
+    UnsupportedLinkType(u32),
+    // the file ends within a header or record
+    Truncated,
+}
+
+impl fmt::Display for PcapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PcapError::BadMagic(magic) => write!(f, "not a pcap file (magic {:#010x})", magic),
+            PcapError::UnsupportedLinkType(t) => write!(f, "unsupported link type {}", t),
+            PcapError::Truncated => write!(f, "truncated capture"),
+        }
+    }
+}
+
+// Records of a classic pcap file in either byte order, with micro- or nanosecond timestamps.
+#[derive(Debug)]
+pub struct Pcap<'a> {
+    pub link_type: LinkType,
+    swapped: bool,
+    rest: &'a [u8],
+}
+
+impl<'a> Pcap<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, PcapError> {
+        let header = bytes.get(..24).ok_or(PcapError::Truncated)?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let swapped = match magic {
+            0xa1b2_c3d4 | 0xa1b2_3c4d => false,
+            0xd4c3_b2a1 | 0x4d3c_b2a1 => true,
+            _ => return Err(PcapError::BadMagic(magic)),
+        };
+
+        let mut pcap = Self {
+            link_type: LinkType::Ethernet,
+            swapped,
+            rest: bytes,
+        };
+        pcap.link_type = match pcap.u32_at(20) {
+            1 => LinkType::Ethernet,
+            101 => LinkType::Raw,
+            t => return Err(PcapError::UnsupportedLinkType(t)),
+        };
+        pcap.rest = &bytes[24..];
+        Ok(pcap)
+    }
+
+    fn u32_at(&self, at: usize) -> u32 {
+        let b = [
+            self.rest[at],
+            self.rest[at + 1],
+            self.rest[at + 2],
+            self.rest[at + 3],
+        ];
+        if self.swapped {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    }
+}
+
+impl<'a> Iterator for Pcap<'a> {
+    type Item = Result<&'a [u8], PcapError>;
+
+    // The captured bytes of the next record, which may be cut off at the snap length.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        if self.rest.len() < 16 {
+            self.rest = &[];
+            return Some(Err(PcapError::Truncated));
+        }
+
+        let len = self.u32_at(8) as usize;
+        let Some(data) = self.rest.get(16..16 + len) else {
+            self.rest = &[];
+            return Some(Err(PcapError::Truncated));
+        };
+        self.rest = &self.rest[16 + len..];
+        Some(Ok(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture(link_type: u32, records: &[&[u8]]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(0xa1b2_c3d4u32.to_le_bytes());
+        bytes.extend([2, 0, 4, 0]);
+        bytes.extend([0; 8]);
+        bytes.extend(65535u32.to_le_bytes());
+        bytes.extend(link_type.to_le_bytes());
+        for record in records {
+            bytes.extend([0; 8]);
+            bytes.extend((record.len() as u32).to_le_bytes());
+            bytes.extend((record.len() as u32).to_le_bytes());
+            bytes.extend(*record);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_records_are_read_in_order() {
+        let bytes = capture(1, &[b"first", b"", b"third"]);
+        let pcap = Pcap::new(&bytes).unwrap();
+        assert_eq!(pcap.link_type, LinkType::Ethernet);
+        let records: Vec<_> = pcap.collect();
+        assert_eq!(
+            records,
+            vec![Ok(&b"first"[..]), Ok(&b""[..]), Ok(&b"third"[..])]
+        );
+
+        // big endian captures
+        let mut swapped = bytes.clone();
+        swapped[..4].reverse();
+        swapped[20..24].reverse();
+        for at in [24, 45, 61] {
+            swapped[at + 8..at + 12].reverse();
+        }
+        let records: Vec<_> = Pcap::new(&swapped).unwrap().collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2], Ok(&b"third"[..]));
+
+        let records: Vec<_> = Pcap::new(&bytes[..bytes.len() - 1]).unwrap().collect();
+        assert_eq!(records.last(), Some(&Err(PcapError::Truncated)));
+        assert_eq!(
+            Pcap::new(&capture(105, &[])).unwrap_err(),
+            PcapError::UnsupportedLinkType(105)
+        );
+        assert!(matches!(
+            Pcap::new(&[0; 24]).unwrap_err(),
+            PcapError::BadMagic(0)
+        ));
+    }
+}
@@ -0,0 +1,70 @@
+use rvh::import::ImportReport;
+use rvh::rules::Ipv6FiveTupleRule;
+
+// A rule of the rule file, its action is its position in the file.
+pub type FileRule = Ipv6FiveTupleRule<usize>;
+
+// Parses one rule per line in the syntax of `Ipv6FiveTupleRule::from_str`, f.e.
+// `src 10.0.0.0/8 dport 22 proto tcp`. Rules without `prio` are prioritized by position, the
+// first rule has the highest priority. `#` starts a comment. IPv4 networks are matched as
+// IPv4-mapped addresses, as `rvh::parse` extracts them.
+pub fn parse(text: &str) -> ImportReport<FileRule> {
+    let lines: Vec<(usize, &str)> = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split('#').next().unwrap().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect();
+
+    let mut report = ImportReport::default();
+    for (i, (line, text)) in lines.iter().enumerate() {
+        let priority = (lines.len() - i) as u32;
+        match Ipv6FiveTupleRule::parse_with_priority(text, priority) {
+            Ok(rule) => report.rules.push(rule.with_action(*line)),
+            Err(e) => report.skip(*line, e.construct, *text),
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rvh::types::{ActionRule, Rule};
+
+    #[test]
+    fn test_rules_are_prioritized_by_position() {
+        let report = parse(
+            "# management\n\
+             src 10.0.0.0/8 dport 22 proto tcp\n\
+             \n\
+             dst 2001:db8::/32 proto udp # dns\n\
+             dport 80 ttl 3\n\
+             src 10.0.0.0/33\n\
+             sport\n\
+             proto 17\n",
+        );
+        assert_eq!(report.rules.len(), 3);
+        let priorities: Vec<_> = report.rules.iter().map(|r| r.priority()).collect();
+        assert_eq!(priorities, vec![6, 5, 1]);
+        assert_eq!(
+            report.rules.iter().map(|r| *r.action()).collect::<Vec<_>>(),
+            vec![2, 4, 8]
+        );
+
+        let expected = Ipv6FiveTupleRule::new(6)
+            .src_prefix("::ffff:10.0.0.0".parse().unwrap(), 104)
+            .dst_port(22)
+            .protocol(6)
+            .with_action(2);
+        assert_eq!(report.rules[0], expected);
+
+        let skipped: Vec<_> = report
+            .unsupported
+            .iter()
+            .map(|u| u.construct.as_str())
+            .collect();
+        assert_eq!(skipped, vec!["ttl", "src 10.0.0.0/33", "sport"]);
+    }
+}
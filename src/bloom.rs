@@ -0,0 +1,97 @@
+// Counting Bloom filter over the bucket hashes of a table, so that a lookup whose bucket does
+// not exist is mostly answered without touching the buckets. Counters saturate instead of
+// wrapping, a saturated counter is never decremented again and only adds false positives.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct BucketFilter {
+    counters: Vec<u8>,
+    // buckets added and not removed again
+    len: usize,
+}
+
+// counters per bucket, about 3% false positives with `PROBES` probes
+const COUNTERS_PER_BUCKET: usize = 8;
+const PROBES: u64 = 3;
+const MIN_COUNTERS: usize = 64;
+
+impl BucketFilter {
+    pub fn new(buckets: impl ExactSizeIterator<Item = u64>) -> Self {
+        let size = (buckets.len() * COUNTERS_PER_BUCKET)
+            .next_power_of_two()
+            .max(MIN_COUNTERS);
+        let mut filter = Self {
+            counters: vec![0; size],
+            len: 0,
+        };
+        for hash in buckets {
+            filter.insert(hash);
+        }
+
+        filter
+    }
+
+    // False if there is no bucket with this hash.
+    #[inline]
+    pub fn may_contain(&self, hash: u64) -> bool {
+        self.probes(hash).all(|i| self.counters[i] != 0)
+    }
+
+    // Whether the filter got too full and should be built again for more buckets.
+    pub fn insert(&mut self, hash: u64) -> bool {
+        for i in self.probes(hash) {
+            self.counters[i] = self.counters[i].saturating_add(1);
+        }
+        self.len += 1;
+        self.len * COUNTERS_PER_BUCKET > self.counters.len()
+    }
+
+    pub fn remove(&mut self, hash: u64) {
+        for i in self.probes(hash) {
+            if self.counters[i] != u8::MAX {
+                self.counters[i] -= 1;
+            }
+        }
+        self.len -= 1;
+    }
+
+    pub fn heap_bytes(&self) -> usize {
+        self.counters.capacity()
+    }
+
+    // Double hashing with the halves of the bucket hash, which is well mixed already.
+    #[inline]
+    fn probes(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let mask = self.counters.len() as u64 - 1;
+        let step = (hash >> 32) | 1;
+        (0..PROBES).map(move |i| (hash.wrapping_add(i.wrapping_mul(step)) & mask) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_removed_buckets_are_forgotten() {
+        let hash = |i: u32| {
+            u64::from(i)
+                .wrapping_mul(0x9e37_79b9_7f4a_7c15)
+                .rotate_left(17)
+        };
+        let mut filter = BucketFilter::new((0..100).map(hash));
+        assert!((0..100).all(|i| filter.may_contain(hash(i))));
+        let false_positives = (100..10_100)
+            .filter(|i| filter.may_contain(hash(*i)))
+            .count();
+        assert!(false_positives < 500);
+
+        for i in 0..50 {
+            filter.remove(hash(i));
+        }
+        assert!((50..100).all(|i| filter.may_contain(hash(i))));
+        assert!((0..50).filter(|i| filter.may_contain(hash(*i))).count() < 10);
+
+        // the filter asks to be built again once it is too full
+        assert!((100..10_000).any(|i| filter.insert(hash(i))));
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+
+use crate::classifier::RVHClassifier;
+use crate::frozen::FrozenRVHClassifier;
+use crate::types::*;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// FNV-1a is used instead of `DefaultHasher`, whose output may change between Rust releases,
+// because the hash is meant to be persisted across restarts.
+fn fnv1a(hash: u64, word: u32) -> u64 {
+    word.to_le_bytes()
+        .iter()
+        .fold(hash, |h, b| (h ^ u64::from(*b)).wrapping_mul(FNV_PRIME))
+}
+
+fn finalize(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
+
+fn hash_words(words: impl Iterator<Item = u32>) -> u64 {
+    finalize(words.fold(FNV_OFFSET, fnv1a))
+}
+
+fn words<F: FieldType>(value: F) -> impl Iterator<Item = u32> {
+    (0..F::WORDS).map(move |i| value.word(i))
+}
+
+// Content hash of a split and a rule set. Neither the order of the range vectors nor the order
+// of the rules affect the result.
+pub fn rule_set_hash<'a, R: Rule<F> + 'a, F: FieldType>(
+    split: &[Vec<Range>],
+    rules: impl IntoIterator<Item = &'a R>,
+) -> u64 {
+    let split_hash =
+        split
+            .iter()
+            .map(|ranges| {
+                hash_words(
+                    std::iter::once(ranges.len() as u32).chain(ranges.iter().flat_map(
+                        |&(low, high)| std::iter::once(low).chain(std::iter::once(high)),
+                    )),
+                )
+            })
+            .fold(0u64, u64::wrapping_add);
+
+    let rules_hash = rules
+        .into_iter()
+        .map(|r| {
+            hash_words(
+                [
+                    r.priority(),
+                    r.fields().len() as u32,
+                    r.masks().len() as u32,
+                ]
+                .iter()
+                .copied()
+                .chain(r.fields().iter().flat_map(|f| words(*f)))
+                .chain(r.masks().iter().flat_map(|m| words(*m))),
+            )
+        })
+        .fold(0u64, u64::wrapping_add);
+
+    finalize(split_hash ^ finalize(rules_hash))
+}
+
+// Storage for frozen classifiers keyed by `rule_set_hash`. Implementations may persist the
+// classifier, e.g. on disk, to skip building it again after a restart.
+pub trait FreezeCache<R: Rule<F>, F: FieldType = Field> {
+    fn load(&mut self, hash: u64) -> Option<FrozenRVHClassifier<R, F>>;
+    fn store(&mut self, hash: u64, frozen: &FrozenRVHClassifier<R, F>);
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryFreezeCache<R: Rule<F>, F: FieldType = Field> {
+    entries: HashMap<u64, FrozenRVHClassifier<R, F>>,
+}
+
+impl<R: Rule<F>, F: FieldType> MemoryFreezeCache<R, F> {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<R: Rule<F>, F: FieldType> Default for MemoryFreezeCache<R, F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: Rule<F> + Clone, F: FieldType> FreezeCache<R, F> for MemoryFreezeCache<R, F> {
+    fn load(&mut self, hash: u64) -> Option<FrozenRVHClassifier<R, F>> {
+        self.entries.get(&hash).cloned()
+    }
+
+    fn store(&mut self, hash: u64, frozen: &FrozenRVHClassifier<R, F>) {
+        self.entries.insert(hash, frozen.clone());
+    }
+}
+
+impl<R: Rule<F>, F: FieldType> FrozenRVHClassifier<R, F> {
+    // Returns the cached classifier for this split and rule set if there is one, otherwise
+    // builds, freezes and caches it.
+    pub fn build_cached(
+        split: Vec<Vec<Range>>,
+        rules: Vec<R>,
+        cache: &mut impl FreezeCache<R, F>,
+    ) -> Self {
+        let hash = rule_set_hash(&split, &rules);
+        if let Some(frozen) = cache.load(hash) {
+            return frozen;
+        }
+
+        let mut classifier = RVHClassifier::new(split.into_iter());
+        for rule in rules {
+            // rejected rules are left out
+            let _ = classifier.add_rule(rule);
+        }
+
+        let frozen = classifier.freeze();
+        cache.store(hash, &frozen);
+        frozen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::mocks::{MockPacket, MockRule};
+
+    fn split() -> Vec<Vec<Range>> {
+        vec![vec![(0, 3)], vec![(3, 6)]]
+    }
+
+    fn rules() -> Vec<MockRule> {
+        vec![
+            MockRule::new(vec![0b1], vec![0b1], 1),
+            MockRule::new(vec![0b101], vec![0b111], 2),
+        ]
+    }
+
+    #[test]
+    fn test_rule_set_hash_is_order_independent() {
+        let mut reversed_rules = rules();
+        reversed_rules.reverse();
+        let mut reversed_split = split();
+        reversed_split.reverse();
+
+        assert_eq!(
+            rule_set_hash(&split(), &rules()),
+            rule_set_hash(&reversed_split, &reversed_rules)
+        );
+
+        let mut changed = rules();
+        changed[0] = MockRule::new(vec![0b0], vec![0b1], 1);
+        assert_ne!(
+            rule_set_hash(&split(), &rules()),
+            rule_set_hash(&split(), &changed)
+        );
+        assert_ne!(
+            rule_set_hash(&split(), &rules()),
+            rule_set_hash(&[vec![(0, 6)]], &rules())
+        );
+    }
+
+    #[test]
+    fn test_classifier_hash_matches_hash_of_its_input() {
+        let mut rvh = RVHClassifier::new(split().into_iter());
+        for r in rules() {
+            rvh.add_rule(r).unwrap();
+        }
+
+        assert_eq!(rvh.rule_set_hash(), rule_set_hash(&split(), &rules()));
+    }
+
+    #[test]
+    fn test_build_cached_reuses_cached_classifier() {
+        let mut cache = MemoryFreezeCache::new();
+
+        let frozen = FrozenRVHClassifier::build_cached(split(), rules(), &mut cache);
+        assert_eq!(cache.len(), 1);
+
+        let cached = FrozenRVHClassifier::build_cached(split(), rules(), &mut cache);
+        assert_eq!(cache.len(), 1);
+
+        let p = MockPacket::new(vec![0b101]);
+        assert_eq!(frozen.classify(&p).unwrap().priority(), 2);
+        assert_eq!(cached.classify(&p).unwrap().priority(), 2);
+
+        FrozenRVHClassifier::build_cached(split(), rules()[..1].to_vec(), &mut cache);
+        assert_eq!(cache.len(), 2);
+    }
+}
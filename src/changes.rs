@@ -0,0 +1,69 @@
+use crate::types::RuleId;
+
+// Rules installed and removed since a generation, see `RVHClassifier::changes_since`. Changed
+// rules are reported as removed and added under the same id, so removals should be applied
+// first. Rules added and removed again since the generation may be reported as removed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Changes<'a, R> {
+    // generation of the classifier, to be passed to the next call
+    pub generation: u64,
+    pub added: Vec<(RuleId, &'a R)>,
+    pub removed: Vec<RuleId>,
+}
+
+// Ids of removed rules with the generation they were removed in, starting with `since`.
+#[derive(Debug, Clone)]
+pub(crate) struct ChangeLog {
+    since: u64,
+    removed: Vec<(u64, RuleId)>,
+}
+
+impl ChangeLog {
+    pub fn new(since: u64) -> Self {
+        Self {
+            since,
+            removed: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, generation: u64, id: RuleId) {
+        self.removed.push((generation, id));
+    }
+
+    // None if removals since `generation` were not recorded or already forgotten.
+    pub fn removed_since(&self, generation: u64) -> Option<Vec<RuleId>> {
+        if generation < self.since {
+            return None;
+        }
+
+        let start = self.removed.partition_point(|(g, _)| *g <= generation);
+        Some(self.removed[start..].iter().map(|(_, id)| *id).collect())
+    }
+
+    pub fn forget(&mut self, generation: u64) {
+        let end = self.removed.partition_point(|(g, _)| *g <= generation);
+        self.removed.drain(..end);
+        self.since = self.since.max(generation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forgotten_removals_are_not_reported() {
+        let mut log = ChangeLog::new(2);
+        log.record(3, RuleId(0));
+        log.record(5, RuleId(1));
+
+        assert!(log.removed_since(1).is_none());
+        assert_eq!(log.removed_since(2), Some(vec![RuleId(0), RuleId(1)]));
+        assert_eq!(log.removed_since(3), Some(vec![RuleId(1)]));
+
+        log.forget(4);
+        assert!(log.removed_since(3).is_none());
+        assert_eq!(log.removed_since(4), Some(vec![RuleId(1)]));
+        assert_eq!(log.removed_since(5), Some(vec![]));
+    }
+}
@@ -0,0 +1,144 @@
+use std::net::Ipv4Addr;
+
+use crate::fields::{self, PORT_WIDTH};
+use crate::import::ImportReport;
+use crate::rules::FiveTupleRule;
+
+// A rule of a ClassBench filter set, its action is the 1-based line of its filter. Filters
+// with port ranges expand into several rules sharing the line.
+pub type ClassBenchRule = FiveTupleRule<usize>;
+
+// Reads a ClassBench `_filters` file, one filter per line, f.e.
+// `@10.0.0.0/8	192.168.1.0/24	0 : 65535	1024 : 65535	0x06/0xFF	0x0000/0x0000`.
+// Port ranges are expanded into prefixes, the cross product of both ports making up the rules
+// of a filter. The first filter has the highest priority and every rule gets a priority of its
+// own. Filters matching on TCP flags, the optional last column, are skipped.
+pub fn parse(text: &str) -> ImportReport<ClassBenchRule> {
+    let mut report = ImportReport::default();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match filter(line) {
+            Ok(rules) => report
+                .rules
+                .extend(rules.into_iter().map(|r| r.with_action(i + 1))),
+            Err(construct) => report.skip(i + 1, construct, line),
+        }
+    }
+
+    // rules of later filters are placed below the earlier ones
+    let count = report.rules.len();
+    report.rules = report
+        .rules
+        .into_iter()
+        .enumerate()
+        .map(|(i, rule)| rule.with_priority((count - i) as u32))
+        .collect();
+    report
+}
+
+// Fails with the column that could not be read.
+fn filter(line: &str) -> Result<Vec<FiveTupleRule>, String> {
+    let columns: Vec<&str> = line
+        .strip_prefix('@')
+        .ok_or("@")?
+        .split_whitespace()
+        .collect();
+    if columns.len() < 9 || columns.len() > 10 || columns[3] != ":" || columns[6] != ":" {
+        return Err(line.to_string());
+    }
+
+    let (src, src_len) = net(columns[0]).ok_or(columns[0])?;
+    let (dst, dst_len) = net(columns[1]).ok_or(columns[1])?;
+    let src_ports = ports(columns[2], columns[4]).ok_or(columns[2])?;
+    let dst_ports = ports(columns[5], columns[7]).ok_or(columns[5])?;
+    let (protocol, protocol_mask) = value_mask(columns[8]).ok_or(columns[8])?;
+    if let Some(flags) = columns.get(9) {
+        match value_mask(flags) {
+            Some((_, 0)) => {}
+            _ => return Err("flags".into()),
+        }
+    }
+
+    let mut rule = FiveTupleRule::new(0)
+        .src_prefix(src, src_len)
+        .dst_prefix(dst, dst_len);
+    match protocol_mask {
+        0 => {}
+        0xff if protocol <= 0xff => rule = rule.protocol(protocol as u8),
+        _ => return Err(columns[8].into()),
+    }
+
+    let mut rules = Vec::with_capacity(src_ports.len() * dst_ports.len());
+    for &(src_port, src_port_len) in src_ports.iter() {
+        for &(dst_port, dst_port_len) in dst_ports.iter() {
+            rules.push(
+                rule.clone()
+                    .src_port_prefix(src_port as u16, src_port_len)
+                    .dst_port_prefix(dst_port as u16, dst_port_len),
+            );
+        }
+    }
+    Ok(rules)
+}
+
+fn net(value: &str) -> Option<(Ipv4Addr, u32)> {
+    let (addr, len) = value.split_once('/')?;
+    let len = len.parse().ok().filter(|len| *len <= 32)?;
+    Some((addr.parse().ok()?, len))
+}
+
+fn ports(lo: &str, hi: &str) -> Option<Vec<(u32, u32)>> {
+    let (lo, hi): (u16, u16) = (lo.parse().ok()?, hi.parse().ok()?);
+    (lo <= hi).then(|| fields::range_prefixes(lo.into(), hi.into(), PORT_WIDTH))
+}
+
+// f.e. `0x06/0xFF`
+fn value_mask(value: &str) -> Option<(u32, u32)> {
+    let hex = |s: &str| u32::from_str_radix(s.strip_prefix("0x")?, 16).ok();
+    let (value, mask) = value.split_once('/')?;
+    Some((hex(value)?, hex(mask)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::FiveTuple;
+    use crate::types::{ActionRule, Rule};
+    use crate::RVHClassifier;
+
+    #[test]
+    fn test_filters_expand_port_ranges() {
+        let report = parse(
+            "@10.0.0.0/8\t192.168.1.0/24\t0 : 65535\t1024 : 65535\t0x06/0xFF\t0x0000/0x0000\n\
+             @10.0.0.0/8\t0.0.0.0/0\t53 : 53\t0 : 65535\t0x11/0xFF\t0x0000/0x0000\n\
+             @0.0.0.0/0\t0.0.0.0/0\t0 : 65535\t0 : 65535\t0x06/0xFF\t0x0200/0x0200\n\
+             @0.0.0.0/0\t0.0.0.0/0\t100 : 50\t0 : 65535\t0x00/0x00\n\
+             @0.0.0.0/0\t0.0.0.0/0\t0 : 65535\t0 : 65535\t0x00/0x00\n",
+        );
+        let lines: Vec<_> = report.rules.iter().map(|r| *r.action()).collect();
+        assert_eq!(lines, vec![1, 1, 1, 1, 1, 1, 2, 5]);
+        let priorities: Vec<_> = report.rules.iter().map(|r| r.priority()).collect();
+        assert_eq!(priorities, (1..=8).rev().collect::<Vec<_>>());
+        let skipped: Vec<_> = report
+            .unsupported
+            .iter()
+            .map(|u| (u.line, u.construct.as_str()))
+            .collect();
+        assert_eq!(skipped, vec![(3, "flags"), (4, "100")]);
+
+        let mut rvh = RVHClassifier::<ClassBenchRule>::five_tuple();
+        for rule in report.rules {
+            assert!(rvh.add_rule(rule).is_ok());
+        }
+        let src = Ipv4Addr::new(10, 1, 2, 3);
+        let p = FiveTuple::new(src, Ipv4Addr::new(192, 168, 1, 7), 40000, 8080, 6);
+        assert_eq!(*rvh.classify(&p).unwrap().action(), 1);
+        let p = FiveTuple::new(src, Ipv4Addr::new(192, 168, 1, 7), 40000, 1023, 6);
+        assert_eq!(*rvh.classify(&p).unwrap().action(), 5);
+        let p = FiveTuple::new(src, Ipv4Addr::new(8, 8, 8, 8), 53, 1023, 17);
+        assert_eq!(*rvh.classify(&p).unwrap().action(), 2);
+    }
+}
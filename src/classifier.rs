@@ -1,236 +1,3748 @@
-use crate::range_vector_hash_map::RVHashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::hash::BuildHasher;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::adaptive::{self, AdaptivePolicy};
+use crate::analysis;
+use crate::bands::{PriorityAllocator, PriorityBand, PriorityBands};
+use crate::changes::{ChangeLog, Changes};
+use crate::dimensions::{self, Dimension};
+use crate::error::RvhError;
+use crate::fields;
+use crate::frozen::FrozenRVHClassifier;
+use crate::hash::SipBuildHasher;
+#[cfg(feature = "rate-limit")]
+use crate::police::{Policed, RateLimit, TokenBucket};
+use crate::prefilter::Prefilter;
+use crate::presets;
+use crate::range_vector_hash_map::{self, RVHashMap};
+use crate::rebuild::Rebuild;
+use crate::split::{self, SplitReport};
+use crate::telemetry::{
+    CollisionReport, Counter, LatencySampler, MemoryUsage, RejectionStats, TableStats,
+};
 use crate::types::*;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetedMatch<'a, R> {
+    pub rule: Option<&'a R>,
+    // false if the budget ran out before all relevant tables were searched, in which case
+    // `rule` is only the best match found so far
+    pub exact: bool,
+    pub probes: usize,
+}
+
+// Outcome of `RVHClassifier::classify_try`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryMatch<'a, R, E> {
+    Matched(&'a R),
+    Missed,
+    // the fields could not be extracted, no rule was looked at
+    Unparseable(E),
+}
+
+impl<'a, R, E> TryMatch<'a, R, E> {
+    pub fn rule(&self) -> Option<&'a R> {
+        match self {
+            TryMatch::Matched(rule) => Some(rule),
+            _ => None,
+        }
+    }
+}
+
+// What happened to a table in `RVHClassifier::classify_explain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    Disabled,
+    // its highest priority is below the priority of a match found before
+    Skipped,
+    // the packet has no bucket in the table
+    NoBucket,
+    // `compared` rules of the bucket were compared with the packet, one per run of rules with
+    // the same key, `matched` is the priority of the matching one if it is the best so far
+    Bucket {
+        hash: u64,
+        compared: usize,
+        matched: Option<Priority>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableProbe {
+    pub table: usize,
+    pub highest_priority: Priority,
+    pub probe: Probe,
+}
+
+// Outcome of `RVHClassifier::classify_explain`, the tables in probe order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation<'a, R> {
+    pub rule: Option<&'a R>,
+    // rejected by the prefilter, no table was probed
+    pub prefiltered: bool,
+    pub tables: Vec<TableProbe>,
+}
+
+impl<R> Explanation<'_, R> {
+    // Rules compared in all tables.
+    pub fn compared(&self) -> usize {
+        self.tables
+            .iter()
+            .map(|t| match t.probe {
+                Probe::Bucket { compared, .. } => compared,
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+impl<R> fmt::Display for Explanation<'_, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.prefiltered {
+            return writeln!(f, "rejected by the prefilter");
+        }
+        for t in self.tables.iter() {
+            write!(
+                f,
+                "table {} (highest priority {}): ",
+                t.table, t.highest_priority
+            )?;
+            match t.probe {
+                Probe::Disabled => writeln!(f, "disabled")?,
+                Probe::Skipped => writeln!(f, "skipped")?,
+                Probe::NoBucket => writeln!(f, "no bucket")?,
+                Probe::Bucket {
+                    hash,
+                    compared,
+                    matched,
+                } => {
+                    write!(f, "bucket {:#018x}, {} compared, ", hash, compared)?;
+                    match matched {
+                        Some(priority) => writeln!(f, "matched priority {}", priority)?,
+                        None => writeln!(f, "no match")?,
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// The action of the matching rule, see `RVHClassifier::decide`. There is no lookup cache in
+// front of the tables, every decision comes from probing them, so there is no flag telling
+// whether a decision was cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision<A> {
+    pub action: A,
+    pub rule: RuleId,
+}
+
+// `M` is the type of the optional metadata attached to rules through `set_meta`. It is kept
+// apart from the rules, so it does not get in the way of classification. `S` builds the
+// hashers of the tables, see `with_hasher`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RVHClassifier<R: Rule<F>, F: FieldType = Field, M = (), S = SipBuildHasher> {
+    #[cfg_attr(
+        feature = "serde",
+        serde(bound(
+            serialize = "RVHashMap<R, F, S>: serde::Serialize",
+            deserialize = "RVHashMap<R, F, S>: serde::Deserialize<'de>"
+        ))
+    )]
+    hash_maps: Vec<RVHashMap<R, F, S>>,
+    bands: PriorityBands,
+    // rejections are not persisted, a restored classifier starts a new window
+    #[cfg_attr(feature = "serde", serde(skip, default = "RejectionStats::new"))]
+    rejections: RejectionStats,
+    dimensions: Vec<Dimension>,
+    next_id: u64,
+    // one entry per installed rule, ids are never reused so a stale id can not refer to
+    // another rule
+    slots: BTreeMap<RuleId, Slot<M>>,
+    // incremented on every insertion and removal
+    generation: u64,
+    // removals since change tracking was started, see `track_changes`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    changes: Option<ChangeLog>,
+    // create a table for rules no table accepts, see `set_auto_tables`
+    auto_tables: bool,
+    adaptive: Option<AdaptivePolicy>,
+    // defer adapting the tables to `maintain`, see `set_low_latency`
+    low_latency: bool,
+    // keep the rules of each table in one pool, see `set_pooled_storage`
+    pooled: bool,
+    // keep a Bloom filter of the buckets of each table, see `set_table_filters`
+    table_filters: bool,
+    // positions of the tables, most hits first, see `set_hit_ordering`, kept in line with
+    // `hash_maps` when tables are moved
+    #[cfg_attr(feature = "serde", serde(skip))]
+    hit_order: Option<Vec<usize>>,
+    // rejects packets before the tables are probed, see `set_prefilter`
+    prefilter: Option<Prefilter>,
+    // indices of tables changed since the last `maintain`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pending: BTreeSet<usize>,
+    // tables are out of probe order until `finalize`, see `add_rules`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    bulk: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    miss_hook: Option<Hook<MissFn<F>>>,
+    // per dimension, see `set_transform`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    transforms: Vec<Option<Hook<TransformFn<F>>>>,
+    // split of a classifier created by `empty` whose tables were not created yet. It is not
+    // serialized, such a classifier is restored without tables.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    lazy_split: Option<&'static [&'static [Range]]>,
+    // dimensions hashed by dictionary codes, see `set_dictionary`
+    dictionaries: BTreeSet<usize>,
+    // shared by all tables
+    hasher: S,
+}
+
+// Callback registered with the classifier, see `set_miss_hook` and `set_transform`.
+struct Hook<T: ?Sized>(Arc<T>);
+
+// called with the fields of packets no rule matched
+type MissFn<F> = dyn Fn(&[F]) + Send + Sync;
+// maps a packet field to the value rules are matched against
+type TransformFn<F> = dyn Fn(F) -> F + Send + Sync;
+
+impl<T: ?Sized> Clone for Hook<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for Hook<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Hook")
+    }
+}
+
+// Fields of a packet after applying the transforms of the classifier, borrowed if there are
+// none.
+pub(crate) struct Transformed<'a, F: Clone>(Cow<'a, [F]>);
+
+impl<F: FieldType> Packet<F> for Transformed<'_, F> {
+    fn fields(&self) -> &[F] {
+        &self.0
+    }
+}
+
+// Where an installed rule is stored, and its metadata.
 #[derive(Debug, Clone)]
-pub struct RVHClassifier<R: Rule> {
-    hash_maps: Vec<RVHashMap<R>>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Slot<M> {
+    // index of the table
+    table: usize,
+    bucket: u64,
+    priority: Priority,
+    // prefix length of every dimension, counted once when the rule is placed
+    prefixes: Box<[u32]>,
+    // generation the rule was placed in
+    generation: u64,
+    meta: Option<M>,
+    #[cfg(feature = "rate-limit")]
+    limit: Option<TokenBucket>,
+}
+
+impl<R: Rule<F>, F: FieldType> RVHClassifier<R, F> {
+    pub fn new(ranges: impl Iterator<Item = Vec<Range>>) -> Self {
+        Self::with_metadata(ranges)
+    }
+
+    // Derives the split from the prefix lengths of the rules, see `analysis::analyze`, and
+    // inserts them. At most `INFERRED_TABLES` tables are created, each dividing the rules as
+    // evenly as possible. Rules that are rejected, f.e. for a duplicate priority, are counted
+    // in `rejections`.
+    pub fn from_rules(rules: Vec<R>) -> Self {
+        const INFERRED_TABLES: usize = 16;
+
+        let dimensions = rules.iter().map(|r| r.masks().len()).max().unwrap_or(0);
+        let report = analysis::analyze(rules.iter(), &vec![F::BITS; dimensions], INFERRED_TABLES);
+
+        let mut classifier = Self::new(report.suggested_split.into_iter());
+        classifier.add_rules(rules);
+        classifier
+    }
+}
+
+impl<R: Rule> RVHClassifier<R> {
+    // Classifier for IPv4 5-tuples, see `presets::five_tuple`. Rules and packets use the
+    // dimensions `presets::SRC_IP` to `presets::PROTOCOL`. Source and destination prefix
+    // lengths are split into the buckets 0..8, 8..16, 16..24 and 24..=32, giving 16 tables,
+    // while ports (0..=16 bits) and the protocol (0..=8 bits) are accepted with any prefix
+    // length in every table.
+    pub fn five_tuple() -> Self {
+        Self::default()
+    }
+}
+
+impl<R: Rule<u128>> RVHClassifier<R, u128> {
+    // Classifier for IPv6 5-tuples with `u128` fields, see `presets::ipv6_five_tuple`. Uses
+    // the same dimensions as `five_tuple`, addresses are split into prefix lengths 0..32,
+    // 32..48, 48..64 and 64..=128.
+    pub fn ipv6_five_tuple() -> Self {
+        let mut classifier = Self::new(presets::ipv6_five_tuple().into_iter());
+        classifier.dimensions = presets::ipv6_five_tuple_dimensions();
+        classifier
+    }
+}
+
+impl<R: Rule<F>, F: FieldType, M, S> RVHClassifier<R, F, M, S> {
+    // Classifier that can be created in a const context, f.e. for a `static`. Nothing is
+    // allocated until the tables of `split` are created on the first insertion. Random keys
+    // can not be drawn in a const context, keys passed to `SipBuildHasher::with_keys` should
+    // be kept secret.
+    pub const fn empty(split: &'static [&'static [Range]], hasher: S) -> Self {
+        Self {
+            hash_maps: Vec::new(),
+            bands: PriorityBands::new(),
+            rejections: RejectionStats::unstarted(),
+            dimensions: Vec::new(),
+            next_id: 0,
+            slots: BTreeMap::new(),
+            generation: 0,
+            changes: None,
+            auto_tables: false,
+            adaptive: None,
+            dictionaries: BTreeSet::new(),
+            low_latency: false,
+            pooled: false,
+            table_filters: false,
+            hit_order: None,
+            prefilter: None,
+            pending: BTreeSet::new(),
+            bulk: false,
+            miss_hook: None,
+            transforms: Vec::new(),
+            lazy_split: Some(split),
+            hasher,
+        }
+    }
 }
 
-impl<R: Rule> RVHClassifier<R> {
-    pub fn new(ranges: impl Iterator<Item = Vec<Range>>) -> Self {
-        let mut hash_maps = Vec::new();
-        for range in ranges {
-            hash_maps.push(RVHashMap::new(range));
+impl<R: Rule<F>, F: FieldType, M, S: BuildHasher + Clone> RVHClassifier<R, F, M, S> {
+    // Same as `new` for a classifier attaching metadata of type `M` to its rules.
+    pub fn with_metadata(ranges: impl Iterator<Item = Vec<Range>>) -> Self
+    where
+        S: Default,
+    {
+        Self::with_hasher(ranges, S::default())
+    }
+
+    // Same as `with_metadata`, with the tables hashing the masked fields with hashers built by
+    // `hasher`, f.e. `MixBuildHasher` for deterministic and faster, but predictable, hashing.
+    pub fn with_hasher(ranges: impl Iterator<Item = Vec<Range>>, hasher: S) -> Self {
+        Self {
+            hash_maps: Self::tables(ranges, &hasher),
+            bands: PriorityBands::new(),
+            rejections: RejectionStats::new(),
+            dimensions: Vec::new(),
+            next_id: 0,
+            slots: BTreeMap::new(),
+            generation: 0,
+            changes: None,
+            auto_tables: false,
+            adaptive: None,
+            dictionaries: BTreeSet::new(),
+            low_latency: false,
+            pooled: false,
+            table_filters: false,
+            hit_order: None,
+            prefilter: None,
+            pending: BTreeSet::new(),
+            bulk: false,
+            miss_hook: None,
+            transforms: Vec::new(),
+            lazy_split: None,
+            hasher,
+        }
+    }
+
+    fn tables(ranges: impl Iterator<Item = Vec<Range>>, hasher: &S) -> Vec<RVHashMap<R, F, S>> {
+        ranges
+            .enumerate()
+            .map(|(index, range)| {
+                let mut hm = RVHashMap::with_hasher(range, hasher.clone());
+                hm.index = index;
+                hm
+            })
+            .collect()
+    }
+
+    // Creates the tables of a classifier created by `empty`.
+    fn init_tables(&mut self) {
+        if let Some(split) = self.lazy_split.take() {
+            let ranges = split.iter().map(|ranges| ranges.to_vec());
+            self.hash_maps = Self::tables(ranges, &self.hasher);
+        }
+    }
+
+    pub fn add_rule(&mut self, rule: R) -> Result<RuleId, RvhError> {
+        // priorities inside a band are reserved for `add_rule_in_band`
+        let result = if self.bands.band_of(rule.priority()).is_some() {
+            Err(RvhError::PriorityBand)
+        } else {
+            self.place_rule(rule)
+        };
+
+        self.record(result)
+    }
+
+    // Adds many rules at once, f.e. when loading a rule set. Tables are neither sorted nor
+    // adapted after every rule but once at the end, see `finalize`. Returns the result of
+    // `add_rule` for every rule.
+    pub fn add_rules(
+        &mut self,
+        rules: impl IntoIterator<Item = R>,
+    ) -> Vec<Result<RuleId, RvhError>> {
+        self.bulk = true;
+        let results = rules.into_iter().map(|rule| self.add_rule(rule)).collect();
+        self.finalize();
+        results
+    }
+
+    // Restores the probe order of the tables and adapts all changed tables, also those whose
+    // adaptation was deferred by low latency updates, see `set_low_latency`.
+    pub fn finalize(&mut self) {
+        self.bulk = false;
+        self.sort_hash_maps();
+        self.maintain(usize::MAX);
+    }
+
+    pub fn add_rule_in_band(&mut self, band: &str, rule: R) -> Result<RuleId, RvhError> {
+        let result = match self.bands.get(band) {
+            Some(b) if b.contains(rule.priority()) => self.place_rule(rule),
+            _ => Err(RvhError::PriorityBand),
+        };
+
+        self.record(result)
+    }
+
+    // Adds a rule with a priority handed out by `allocator`, `make` builds the rule for that
+    // priority. Priorities of installed rules are skipped and stay allocated. Rules of an
+    // allocator created by `PriorityAllocator::for_band` are added to its band. The priority
+    // is released again if the rule is rejected.
+    pub fn add_rule_with(
+        &mut self,
+        allocator: &mut PriorityAllocator,
+        make: impl FnOnce(Priority) -> R,
+    ) -> Result<RuleId, RvhError> {
+        let priority = loop {
+            match allocator.allocate() {
+                Some(p)
+                    if self
+                        .hash_maps
+                        .iter()
+                        .any(|hm| hm.priorities.contains_key(&p)) => {}
+                Some(p) => break p,
+                None => return self.record(Err(RvhError::PrioritiesExhausted)),
+            }
+        };
+
+        let rule = make(priority);
+        let result = match allocator.band() {
+            Some(band) => self.add_rule_in_band(band, rule),
+            None => self.add_rule(rule),
+        };
+
+        if result.is_err() {
+            allocator.release(priority);
+        }
+        result
+    }
+
+    // Rejected insertions since the start of the current window.
+    pub fn rejections(&self) -> &RejectionStats {
+        &self.rejections
+    }
+
+    // Returns the rejections of the current window and starts a new one.
+    pub fn take_rejections(&mut self) -> RejectionStats {
+        std::mem::replace(&mut self.rejections, RejectionStats::new())
+    }
+
+    fn record(&mut self, result: Result<RuleId, RvhError>) -> Result<RuleId, RvhError> {
+        if let Some(reason) = result.as_ref().err().and_then(RvhError::rejection_reason) {
+            self.rejections.record(reason);
+        }
+
+        result
+    }
+
+    // Attaches `meta` to the rule, replacing previous metadata. Fails if the rule is not
+    // installed. The metadata is dropped together with the rule.
+    pub fn set_meta(&mut self, id: RuleId, meta: M) -> bool {
+        match self.slots.get_mut(&id) {
+            Some(slot) => {
+                slot.meta = Some(meta);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get_meta(&self, id: RuleId) -> Option<&M> {
+        self.slots.get(&id).and_then(|slot| slot.meta.as_ref())
+    }
+
+    // Polices the matches of the rule with a token bucket, see `classify_and_count`. The bucket
+    // starts full, None removes the limit. Fails if the rule is not installed.
+    #[cfg(feature = "rate-limit")]
+    pub fn set_rate_limit(&mut self, id: RuleId, limit: Option<RateLimit>) -> bool {
+        match self.slots.get_mut(&id) {
+            Some(slot) => {
+                slot.limit = limit.map(TokenBucket::new);
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[cfg(feature = "rate-limit")]
+    pub fn rate_limit(&self, id: RuleId) -> Option<RateLimit> {
+        self.slots.get(&id)?.limit.as_ref().map(TokenBucket::limit)
+    }
+
+    pub fn get_meta_mut(&mut self, id: RuleId) -> Option<&mut M> {
+        self.slots.get_mut(&id).and_then(|slot| slot.meta.as_mut())
+    }
+
+    pub fn take_meta(&mut self, id: RuleId) -> Option<M> {
+        self.slots.get_mut(&id).and_then(|slot| slot.meta.take())
+    }
+
+    pub fn get(&self, id: RuleId) -> Option<&R> {
+        let (table, index) = self.locate(id)?;
+        let bucket = self.slots[&id].bucket;
+        Some(self.hash_maps[table].hash_map.get(bucket)?.get(index))
+    }
+
+    // Prefix length of every dimension of the rule, as counted when it was placed.
+    pub fn prefix_lengths(&self, id: RuleId) -> Option<&[u32]> {
+        self.slots.get(&id).map(|slot| &*slot.prefixes)
+    }
+
+    // Mutable access to a rule. The rule is moved to the table matching its new prefix lengths
+    // when the returned guard is dropped, see `RuleMut`. Rules are cloned on the first mutable
+    // access to be able to revert a rejected change.
+    pub fn get_mut(&mut self, id: RuleId) -> Option<RuleMut<'_, R, F, M, S>>
+    where
+        R: Clone,
+    {
+        let (table, index) = self.locate(id)?;
+        let bucket = self.slots[&id].bucket;
+        self.hash_maps[table].hash_map.get(bucket)?;
+
+        Some(RuleMut {
+            classifier: self,
+            id,
+            table,
+            bucket,
+            index,
+            original: None,
+        })
+    }
+
+    pub fn remove(&mut self, id: RuleId) -> Result<R, RvhError> {
+        let (table, index) = self.locate(id).ok_or(RvhError::NotFound)?;
+        let slot = self.forget(id).unwrap();
+        let (_, rule) = self.hash_maps[table].take(slot.bucket, index, slot.priority);
+        if let Some(prefilter) = self.prefilter.as_mut() {
+            prefilter.remove(rule.fields(), rule.masks());
+        }
+
+        if self.low_latency {
+            self.defer(table);
+        } else {
+            self.adapt(table, |c| c.merge_if_sparse(table));
+        }
+        Ok(rule)
+    }
+
+    // Removes the rule with priority `prio`. Priorities are only unique per table, if several
+    // tables have a rule with it the one checked first by `classify` is removed.
+    pub fn remove_by_priority(&mut self, prio: Priority) -> Option<R> {
+        let id = self
+            .hash_maps
+            .iter()
+            .find_map(|hm| hm.priorities.get(&prio).copied())?;
+        self.remove(id).ok()
+    }
+
+    // The rule with priority `prio`, from the table checked first by `classify` if several
+    // tables have a rule with it.
+    pub fn get_by_priority(&self, prio: Priority) -> Option<&R> {
+        let id = self
+            .hash_maps
+            .iter()
+            .find_map(|hm| hm.priorities.get(&prio).copied())?;
+        self.get(id)
+    }
+
+    // The rule with exactly these fields and masks, bits of the fields outside of the masks are
+    // ignored. Of several such rules the one with the highest priority is returned.
+    pub fn find(&self, fields: &[F], masks: &[F]) -> Option<&R> {
+        self.hash_maps.iter().find_map(|hm| hm.find(fields, masks))
+    }
+
+    // Position of the table of a rule in `hash_maps` and of the rule within its bucket.
+    fn locate(&self, id: RuleId) -> Option<(usize, usize)> {
+        let slot = self.slots.get(&id)?;
+        let table = self
+            .hash_maps
+            .iter()
+            .position(|hm| hm.index == slot.table)?;
+        let index = self.hash_maps[table].position(slot.bucket, slot.priority)?;
+        Some((table, index))
+    }
+
+    // Reserves the priorities `low..=high` for rules added through `add_rule_in_band`.
+    // Fails if the range overlaps another band or rules are already installed within it.
+    pub fn declare_band(&mut self, name: impl Into<String>, low: Priority, high: Priority) -> bool {
+        if low <= high
+            && self
+                .hash_maps
+                .iter()
+                .any(|hm| hm.priorities.range(low..=high).next().is_some())
+        {
+            return false;
+        }
+
+        self.bands.declare(name.into(), low, high)
+    }
+
+    pub fn band(&self, name: &str) -> Option<&PriorityBand> {
+        self.bands.get(name)
+    }
+
+    pub fn band_of(&self, priority: Priority) -> Option<&PriorityBand> {
+        self.bands.band_of(priority)
+    }
+
+    pub fn bands(&self) -> impl Iterator<Item = &PriorityBand> {
+        self.bands.iter()
+    }
+
+    // Names the dimensions for diagnostics. Fails if a range of a table does not fit the width
+    // of its dimension.
+    pub fn set_dimensions(&mut self, dimensions: Vec<Dimension>) -> bool {
+        self.init_tables();
+        let split = self.split();
+        let widths: Vec<_> = dimensions.iter().map(|d| d.width()).collect();
+        if !fields::invalid_ranges(&split, &widths).is_empty() {
+            return false;
+        }
+
+        self.dimensions = dimensions;
+        true
+    }
+
+    pub fn dimensions(&self) -> &[Dimension] {
+        &self.dimensions
+    }
+
+    // Reports prefix lengths no table or more than one table accepts, see `split::analyze`.
+    // Without dimensions the largest prefix length of each dimension in the split is taken as
+    // its width.
+    pub fn check_split(&self) -> SplitReport {
+        let split = self.split();
+        let widths: Vec<_> = if self.dimensions.is_empty() {
+            let dims = split.iter().map(Vec::len).max().unwrap_or(0);
+            (0..dims)
+                .map(|dim| {
+                    split
+                        .iter()
+                        .filter_map(|ranges| ranges.get(dim))
+                        .map(|&(_, high)| high.saturating_sub(1))
+                        .max()
+                        .unwrap_or(0)
+                })
+                .collect()
+        } else {
+            self.dimensions.iter().map(|d| d.width()).collect()
+        };
+
+        split::analyze(&split, &widths)
+    }
+
+    // Range vectors of the tables in the order of the split the classifier was created with.
+    fn split(&self) -> Vec<Vec<Range>> {
+        match self.lazy_split {
+            Some(split) => split.iter().map(|ranges| ranges.to_vec()).collect(),
+            None => {
+                let mut hash_maps: Vec<_> = self.hash_maps.iter().collect();
+                hash_maps.sort_by_key(|hm| hm.index);
+                hash_maps.iter().map(|hm| hm.ranges.clone()).collect()
+            }
+        }
+    }
+
+    pub(crate) fn can_insert_rule(&self, rule: &R) -> bool {
+        rule.fields().len() == rule.masks().len()
+            && range_vector_hash_map::invalid_mask(rule).is_none()
+            && self
+                .hash_maps
+                .iter()
+                .find(|hm| hm.can_insert(rule))
+                .map_or(self.auto_tables, |hm| {
+                    !hm.priorities.contains_key(&rule.priority())
+                })
+    }
+
+    pub(crate) fn contains_rule(&self, rule: &R) -> bool {
+        self.hash_maps.iter().any(|hm| hm.contains(rule))
+    }
+
+    pub(crate) fn insert_rule(&mut self, rule: R) -> bool {
+        self.place_rule(rule).is_ok()
+    }
+
+    // Inserts a rule under an id assigned by another classifier, see `Rebuild`.
+    pub(crate) fn insert_rule_with_id(&mut self, id: RuleId, rule: R) -> bool {
+        self.next_id = self.next_id.max(id.0 + 1);
+        self.place_rule_with_id(id, rule).is_ok()
+    }
+
+    fn place_rule(&mut self, rule: R) -> Result<RuleId, RvhError> {
+        let id = RuleId(self.next_id);
+        self.place_rule_with_id(id, rule)?;
+        self.next_id += 1;
+        Ok(id)
+    }
+
+    fn place_rule_with_id(&mut self, id: RuleId, mut rule: R) -> Result<RuleId, RvhError> {
+        self.init_tables();
+        if rule.fields().len() != rule.masks().len() {
+            return Err(RvhError::ArityMismatch {
+                fields: rule.fields().len(),
+                masks: rule.masks().len(),
+            });
+        }
+        if let Some(dimension) = range_vector_hash_map::invalid_mask(&rule) {
+            return Err(RvhError::InvalidMask { dimension });
+        }
+        range_vector_hash_map::normalize(&mut rule);
+        let prefixes = range_vector_hash_map::prefix_lengths(rule.masks());
+
+        // the first table accepting the prefix lengths is the only one
+        let position = match self
+            .hash_maps
+            .iter()
+            .position(|hm| hm.accepts_prefixes(&prefixes))
+        {
+            Some(position) => position,
+            None if self.auto_tables => {
+                let ranges = prefixes.iter().map(|len| (*len, len + 1));
+                let mut hm = self.new_table(ranges.collect());
+                hm.index = self.hash_maps.len();
+                self.hash_maps.push(hm);
+                self.hash_maps.len() - 1
+            }
+            None => return Err(RvhError::NoMatchingTable),
+        };
+        let hm = &mut self.hash_maps[position];
+        let priority = rule.priority();
+        let bucket = hm.insert(id, rule)?;
+
+        self.generation += 1;
+        let slot = Slot {
+            table: hm.index,
+            bucket,
+            priority,
+            prefixes,
+            generation: self.generation,
+            meta: None,
+            #[cfg(feature = "rate-limit")]
+            limit: None,
+        };
+        self.slots.insert(id, slot);
+        self.admit(id);
+        if self.bulk {
+            self.pending.insert(self.hash_maps[position].index);
+        } else if self.low_latency {
+            self.defer(position);
+        } else {
+            self.adapt(position, |c| c.split_if_crowded(position, bucket));
+        }
+        Ok(id)
+    }
+
+    pub fn remove_rule(&mut self, rule: &R) -> Result<(), RvhError> {
+        for position in 0..self.hash_maps.len() {
+            if let Some(id) = self.hash_maps[position].remove(rule) {
+                self.forget(id);
+                if let Some(prefilter) = self.prefilter.as_mut() {
+                    prefilter.remove(rule.fields(), rule.masks());
+                }
+                if self.low_latency {
+                    self.defer(position);
+                } else {
+                    self.adapt(position, |c| c.merge_if_sparse(position));
+                }
+                return Ok(());
+            }
+        }
+        Err(RvhError::NotFound)
+    }
+
+    pub fn classify(&self, p: &impl Packet<F>) -> Option<&R> {
+        let q = &self.transform(p);
+        let best_match = self.best_match(q, |hm| hm.check_match(q)).map(|(_, r)| r);
+        if best_match.is_none() {
+            self.missed(p);
+        }
+
+        best_match
+    }
+
+    // Same as `classify` for packets whose fields may not be extracted. Unparseable packets
+    // are told apart from misses and do not call the miss hook.
+    pub fn classify_try<P: TryPacket<F>>(&self, p: &P) -> TryMatch<'_, R, P::Error> {
+        match p.try_packet() {
+            Ok(packet) => self
+                .classify(&packet)
+                .map_or(TryMatch::Missed, TryMatch::Matched),
+            Err(e) => TryMatch::Unparseable(e),
+        }
+    }
+
+    // Same as `classify`, and takes `cost` tokens from the bucket of the matching rule, see
+    // `set_rate_limit`, refilled up to `now`. Matches of rules without a limit never exceed
+    // it.
+    #[cfg(feature = "rate-limit")]
+    pub fn classify_and_count(
+        &mut self,
+        p: &impl Packet<F>,
+        cost: u64,
+        now: Instant,
+    ) -> Option<Policed<'_, R>> {
+        let id = self.classify_id(p)?;
+        let exceeded = match self.slots.get_mut(&id).unwrap().limit.as_mut() {
+            Some(bucket) => !bucket.take(cost, now),
+            None => false,
+        };
+        Some(Policed {
+            rule: self.get(id).unwrap(),
+            id,
+            exceeded,
+        })
+    }
+
+    // Same as `classify`, returning the id of the matching rule.
+    pub(crate) fn classify_id(&self, p: &impl Packet<F>) -> Option<RuleId> {
+        let best_match = self.lookup_id(p);
+        if best_match.is_none() {
+            self.missed(p);
+        }
+
+        best_match
+    }
+
+    // Same as `classify_id` without calling the miss hook, f.e. for probes.
+    pub(crate) fn lookup_id(&self, p: &impl Packet<F>) -> Option<RuleId> {
+        let q = &self.transform(p);
+        self.best_match(q, |hm| hm.check_match(q))
+            .map(|(hm, rule)| hm.priorities[&rule.priority()])
+    }
+
+    // Registers `hook` to be called with the fields of every packet `classify`, `decide` and
+    // their variants find no rule for, f.e. to log or punt such packets in one place. The
+    // hook is not serialized and not carried over by `freeze`.
+    pub fn set_miss_hook(&mut self, hook: impl Fn(&[F]) + Send + Sync + 'static) {
+        self.miss_hook = Some(Hook(Arc::new(hook)));
+    }
+
+    pub fn clear_miss_hook(&mut self) {
+        self.miss_hook = None;
+    }
+
+    fn missed(&self, p: &impl Packet<F>) {
+        if let Some(hook) = &self.miss_hook {
+            (hook.0)(p.fields());
+        }
+    }
+
+    // Registers a transform for a dimension, which maps the field of every classified packet
+    // before it is matched, f.e. a VLAN to a zone, so that rules can be written against the
+    // derived value. Rules are not transformed. The transforms are not serialized and not
+    // carried over by `freeze`.
+    pub fn set_transform(
+        &mut self,
+        dimension: usize,
+        transform: impl Fn(F) -> F + Send + Sync + 'static,
+    ) {
+        if self.transforms.len() <= dimension {
+            self.transforms.resize(dimension + 1, None);
+        }
+        self.transforms[dimension] = Some(Hook(Arc::new(transform)));
+    }
+
+    pub fn clear_transform(&mut self, dimension: usize) {
+        if let Some(transform) = self.transforms.get_mut(dimension) {
+            *transform = None;
+        }
+        while let Some(None) = self.transforms.last() {
+            self.transforms.pop();
+        }
+    }
+
+    pub(crate) fn transform<'a>(&self, p: &'a impl Packet<F>) -> Transformed<'a, F> {
+        if self.transforms.is_empty() {
+            return Transformed(Cow::Borrowed(p.fields()));
+        }
+
+        let fields =
+            p.fields()
+                .iter()
+                .enumerate()
+                .map(|(dim, &f)| match self.transforms.get(dim) {
+                    Some(Some(transform)) => (transform.0)(f),
+                    _ => f,
+                });
+        Transformed(Cow::Owned(fields.collect()))
+    }
+
+    // Returns the first matching rule in table order without comparing priorities, for rule
+    // sets known to be disjoint where only a single rule can match anyway.
+    pub fn classify_first(&self, p: &impl Packet<F>) -> Option<&R> {
+        let q = &self.transform(p);
+        let may_match = self.may_match(q);
+        let first_match = self.active_tables().filter(|_| may_match).find_map(|hm| {
+            hm.candidates(q)
+                .find(|r| r.priority() > 0 && range_vector_hash_map::rule_matches(*r, q))
+        });
+        if first_match.is_none() {
+            self.missed(p);
+        }
+
+        first_match
+    }
+
+    // Every rule matching the packet, highest priority first. All tables are searched.
+    pub fn classify_all(&self, p: &impl Packet<F>) -> impl Iterator<Item = &R> {
+        let q = &self.transform(p);
+        let may_match = self.may_match(q);
+        let mut matches: Vec<_> = self
+            .active_tables()
+            .filter(|_| may_match)
+            .flat_map(|hm| hm.candidates(q))
+            .filter(|r| r.priority() > 0 && range_vector_hash_map::rule_matches(*r, q))
+            .collect();
+        matches.sort_unstable_by_key(|r| std::cmp::Reverse(r.priority()));
+        matches.into_iter()
+    }
+
+    // Same as `classify`, but every few classifications the time taken is recorded in
+    // `sampler` under the table of the matching rule.
+    pub fn classify_sampled(&self, p: &impl Packet<F>, sampler: &mut LatencySampler) -> Option<&R> {
+        if !sampler.should_sample() {
+            return self.classify(p);
+        }
+
+        let start = Instant::now();
+        let q = &self.transform(p);
+        let best_match = self.best_match(q, |hm| hm.check_match(q));
+        sampler.record(best_match.map(|(hm, _)| hm.index), start.elapsed());
+        if best_match.is_none() {
+            self.missed(p);
+        }
+
+        best_match.map(|(_, r)| r)
+    }
+
+    // Same as `classify` without calling the miss hook or counting hits, for lookups that are
+    // not traffic, f.e. simulations.
+    pub(crate) fn lookup(&self, p: &impl Packet<F>) -> Option<&R> {
+        self.lookup_where(p, |_| true)
+    }
+
+    // Same as `lookup` as if only the rules for which `accept` returns true were installed.
+    pub(crate) fn lookup_where(
+        &self,
+        p: &impl Packet<F>,
+        accept: impl Fn(&R) -> bool,
+    ) -> Option<&R> {
+        let q = &self.transform(p);
+        self.probe(q, |hm| hm.check_match_where(q, &accept))
+            .map(|(_, r)| r)
+    }
+
+    // The best matching rule and its table, counted for `set_hit_ordering`.
+    fn best_match<'a>(
+        &'a self,
+        q: &impl Packet<F>,
+        check: impl Fn(&'a RVHashMap<R, F, S>) -> Option<&'a R>,
+    ) -> Option<(&'a RVHashMap<R, F, S>, &'a R)> {
+        let best_match = self.probe(q, check);
+        if let (Some((hm, _)), Some(_)) = (best_match, &self.hit_order) {
+            hm.hits.bump();
+        }
+        best_match
+    }
+
+    fn probe<'a>(
+        &'a self,
+        q: &impl Packet<F>,
+        check: impl Fn(&'a RVHashMap<R, F, S>) -> Option<&'a R>,
+    ) -> Option<(&'a RVHashMap<R, F, S>, &'a R)> {
+        if !self.may_match(q) {
+            return None;
+        }
+        // an order from before tables were added or removed is not used
+        let hit_order = self.hit_order.as_ref();
+        if let Some(order) = hit_order.filter(|order| order.len() == self.hash_maps.len()) {
+            return self.probe_by_hits(order, check);
+        }
+
+        let mut highest_matching_priority = 0;
+        let mut best_match = None;
+
+        for hm in self.active_tables() {
+            if hm.highest_priority() < highest_matching_priority {
+                break;
+            }
+
+            if let Some(matching_rule) = check(hm) {
+                if matching_rule.priority() > highest_matching_priority {
+                    highest_matching_priority = matching_rule.priority();
+                    best_match = Some((hm, matching_rule));
+                }
+            }
+        }
+
+        best_match
+    }
+
+    // Same as `probe` with the tables probed in `order`. Tables that can not have a better
+    // match are skipped, but as they are not sorted by priority all of them are looked at.
+    fn probe_by_hits<'a>(
+        &'a self,
+        order: &[usize],
+        check: impl Fn(&'a RVHashMap<R, F, S>) -> Option<&'a R>,
+    ) -> Option<(&'a RVHashMap<R, F, S>, &'a R)> {
+        let mut highest_matching_priority = 0;
+        let mut best_match = None;
+
+        for hm in order.iter().map(|&position| &self.hash_maps[position]) {
+            if !hm.enabled || hm.highest_priority() < highest_matching_priority {
+                continue;
+            }
+
+            if let Some(matching_rule) = check(hm) {
+                if matching_rule.priority() > highest_matching_priority {
+                    highest_matching_priority = matching_rule.priority();
+                    best_match = Some((hm, matching_rule));
+                }
+            }
+        }
+
+        best_match
+    }
+
+    // Classification with bounded work: every table lookup and every rule compared within a
+    // bucket counts as one probe, the search stops once `max_probes` are used up.
+    pub fn classify_with_budget(
+        &self,
+        p: &impl Packet<F>,
+        max_probes: usize,
+    ) -> BudgetedMatch<'_, R> {
+        let q = &self.transform(p);
+        let mut budget = max_probes;
+        let mut highest_matching_priority = 0;
+        let mut best_match = None;
+        let mut exact = true;
+        let may_match = self.may_match(q);
+
+        for hm in self.active_tables().filter(|_| may_match) {
+            if hm.highest_priority() < highest_matching_priority {
+                break;
+            }
+
+            if budget == 0 {
+                exact = false;
+                break;
+            }
+            budget -= 1;
+
+            let (matching_rule, complete) = hm.check_match_bounded(q, &mut budget);
+            if let Some(matching_rule) = matching_rule {
+                if matching_rule.priority() > highest_matching_priority {
+                    highest_matching_priority = matching_rule.priority();
+                    best_match = Some(matching_rule);
+                }
+            }
+
+            if !complete {
+                exact = false;
+                break;
+            }
+        }
+
+        // a truncated search is not a definite miss
+        if best_match.is_none() && exact {
+            self.missed(p);
+        }
+
+        BudgetedMatch {
+            rule: best_match,
+            exact,
+            probes: max_probes - budget,
+        }
+    }
+
+    // Same as `classify`, along with what was done to find the rule, f.e. to find out why a
+    // packet does not match a rule it is expected to. Tables are always probed by priority,
+    // neither the miss hook nor the hit counts of `set_hit_ordering` are touched.
+    pub fn classify_explain(&self, p: &impl Packet<F>) -> Explanation<'_, R> {
+        let q = &self.transform(p);
+        if !self.may_match(q) {
+            return Explanation {
+                rule: None,
+                prefiltered: true,
+                tables: Vec::new(),
+            };
+        }
+
+        let mut best_match: Option<&R> = None;
+        let mut tables = Vec::with_capacity(self.hash_maps.len());
+        for hm in self.hash_maps.iter() {
+            let best_prio = best_match.map_or(0, |r| r.priority());
+            let probe = if !hm.enabled {
+                Probe::Disabled
+            } else if hm.highest_priority() < best_prio {
+                Probe::Skipped
+            } else {
+                match hm.check_match_traced(q, best_prio) {
+                    (None, _, _) => Probe::NoBucket,
+                    (Some(hash), matched, compared) => {
+                        if matched.is_some() {
+                            best_match = matched;
+                        }
+                        Probe::Bucket {
+                            hash,
+                            compared,
+                            matched: matched.map(|r| r.priority()),
+                        }
+                    }
+                }
+            };
+            tables.push(TableProbe {
+                table: hm.index,
+                highest_priority: hm.highest_priority(),
+                probe,
+            });
+        }
+
+        Explanation {
+            rule: best_match,
+            prefiltered: false,
+            tables,
+        }
+    }
+
+    // Converts the classifier into an immutable, compacted representation. Rule metadata is
+    // not carried over.
+    pub fn freeze(mut self) -> FrozenRVHClassifier<R, F, S> {
+        self.init_tables();
+        FrozenRVHClassifier::from_hash_maps(
+            self.hash_maps,
+            self.bands,
+            self.dimensions,
+            self.dictionaries.into_iter().collect(),
+            self.hasher,
+        )
+    }
+
+    // The tables in probe order.
+    pub(crate) fn into_tables(mut self) -> Vec<RVHashMap<R, F, S>> {
+        self.init_tables();
+        self.hash_maps
+    }
+
+    pub(crate) fn from_parts(
+        split: Vec<Vec<Range>>,
+        bands: PriorityBands,
+        rules: Vec<R>,
+        hasher: S,
+    ) -> Self {
+        let mut classifier = Self::with_hasher(split.into_iter(), hasher);
+        classifier.bands = bands;
+        for rule in rules {
+            let inserted = classifier.insert_rule(rule);
+            debug_assert!(inserted);
+        }
+
+        classifier
+    }
+
+    // Content hash of the split and the installed rules, see `cache::rule_set_hash`.
+    pub fn rule_set_hash(&self) -> u64 {
+        crate::cache::rule_set_hash(&self.split(), self.iter())
+    }
+
+    // Reads every table, bucket and rule once, so their memory is faulted in before the
+    // classifier takes traffic. Returns the number of rules visited.
+    pub fn prewarm(&self) -> usize {
+        let mut visited = 0;
+        for hm in self.hash_maps.iter() {
+            std::hint::black_box(&hm.masks);
+            for rule in hm.hash_map.rules() {
+                range_vector_hash_map::touch(rule);
+                visited += 1;
+            }
+        }
+
+        visited
+    }
+
+    // Same as `prewarm`, additionally classifies the packets of `sample` to warm up the paths
+    // real traffic takes, without calling the miss hook or counting hits.
+    pub fn prewarm_with<P: Packet<F>>(&self, sample: &[P]) -> usize {
+        for p in sample {
+            std::hint::black_box(self.lookup(p));
+        }
+
+        self.prewarm()
+    }
+
+    pub fn table_count(&self) -> usize {
+        self.lazy_split.map_or(self.hash_maps.len(), <[_]>::len)
+    }
+
+    // Tables are indexed by the position of their range vector in the split the classifier
+    // was created with.
+    pub fn table_ranges(&self, index: usize) -> Option<&[Range]> {
+        match self.lazy_split {
+            Some(split) => split.get(index).copied(),
+            None => self.table(index).map(|hm| hm.ranges.as_slice()),
+        }
+    }
+
+    // The rules stored in a table, None if there is no table with this index.
+    pub fn iter_table(&self, index: usize) -> Option<impl Iterator<Item = &R>> {
+        if index >= self.table_count() {
+            return None;
+        }
+
+        let rules = self
+            .table(index)
+            .into_iter()
+            .flat_map(|hm| hm.hash_map.rules());
+        Some(rules)
+    }
+
+    // Same as `iter_table`, kept for existing callers.
+    pub fn rules_in_table(&self, index: usize) -> Option<impl Iterator<Item = &R>> {
+        self.iter_table(index)
+    }
+
+    pub fn table_stats(&self, index: usize) -> Option<TableStats> {
+        if index >= self.table_count() {
+            return None;
+        }
+
+        Some(self.table(index).map_or_else(
+            || TableStats {
+                index,
+                ..TableStats::default()
+            },
+            RVHashMap::stats,
+        ))
+    }
+
+    // Stats of every table by index, f.e. to spot tables whose buckets grow long or that
+    // hold most of the rules, which call for another split.
+    pub fn stats(&self) -> Vec<TableStats> {
+        (0..self.table_count())
+            .filter_map(|index| self.table_stats(index))
+            .collect()
+    }
+
+    // Collisions of every table by index, with up to `worst` of its most crowded buckets.
+    pub fn collisions(&self, worst: usize) -> Vec<CollisionReport<F>> {
+        (0..self.table_count())
+            .map(|index| match self.table(index) {
+                Some(hm) => hm.collisions(worst),
+                None => CollisionReport {
+                    index,
+                    histogram: Vec::new(),
+                    worst: Vec::new(),
+                },
+            })
+            .collect()
+    }
+
+    // Makes room for `additional` more rules before a bulk load, f.e. `add_rules`. The room is
+    // shared by the tables as the installed rules are, evenly if there are none yet.
+    pub fn reserve(&mut self, additional: usize) {
+        self.init_tables();
+        let installed = self.len();
+        let tables = self.hash_maps.len().max(1);
+        for hm in self.hash_maps.iter_mut() {
+            let share = match installed {
+                0 => additional.div_ceil(tables),
+                _ => (additional * hm.len()).div_ceil(installed),
+            };
+            hm.reserve(share);
+        }
+
+        let capacity = installed + additional;
+        if let Some(prefilter) = self.prefilter.take_if(|p| p.capacity() < capacity) {
+            self.prefilter = Some(self.build_prefilter(prefilter.emptied(capacity)));
+        }
+    }
+
+    // Releases memory no longer needed after many rules were removed, see `memory_usage`.
+    pub fn shrink_to_fit(&mut self) {
+        for hm in self.hash_maps.iter_mut() {
+            hm.shrink_to_fit();
+        }
+        if let Some(prefilter) = self.prefilter.take() {
+            self.prefilter = Some(self.build_prefilter(prefilter.emptied(self.len())));
+        }
+    }
+
+    // Estimated heap memory of the tables and of the bookkeeping of the rules, see
+    // `MemoryUsage`.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let slot_bytes = self
+            .slots
+            .values()
+            .map(|slot| slot.prefixes.len() * mem::size_of::<u32>())
+            .sum::<usize>()
+            + self.slots.len() * mem::size_of::<(RuleId, Slot<M>)>();
+
+        MemoryUsage {
+            tables: self.hash_maps.iter().map(RVHashMap::memory).collect(),
+            rules: self.len(),
+            slot_bytes,
+            prefilter_bytes: self.prefilter.as_ref().map_or(0, Prefilter::heap_bytes),
+        }
+    }
+
+    // Rehashes the rules of one table with another hash function, f.e. when `table_stats`
+    // shows many collisions. The other tables are left untouched. Fails if there is no table
+    // with this index.
+    pub fn reseed_table(&mut self, index: usize, seed: u32) -> bool {
+        self.init_tables();
+        let hm = match self.hash_maps.iter_mut().find(|hm| hm.index == index) {
+            Some(hm) => hm,
+            None => return false,
+        };
+        hm.reseed(seed);
+        Self::refresh_buckets(hm, &mut self.slots);
+
+        true
+    }
+
+    // Hashes the masked values of `dimension` by small codes instead of the values themselves,
+    // for dimensions with few distinct values such as the protocol. Lookups of values no rule
+    // uses miss the table without probing a bucket. All tables are rehashed, as are tables
+    // created later on.
+    pub fn set_dictionary(&mut self, dimension: usize, enabled: bool) {
+        self.init_tables();
+        if enabled {
+            self.dictionaries.insert(dimension);
+        } else {
+            self.dictionaries.remove(&dimension);
+        }
+
+        for hm in self.hash_maps.iter_mut() {
+            hm.set_dictionary(dimension, enabled);
+            Self::refresh_buckets(hm, &mut self.slots);
+        }
+    }
+
+    pub fn dictionary_dimensions(&self) -> impl Iterator<Item = usize> + '_ {
+        self.dictionaries.iter().copied()
+    }
+
+    // Updates the buckets of the slots of the rules in `hm` after it was rehashed.
+    fn refresh_buckets(hm: &RVHashMap<R, F, S>, slots: &mut BTreeMap<RuleId, Slot<M>>) {
+        for (bucket, rules) in hm.hash_map.iter() {
+            for rule in rules.iter() {
+                let id = hm.priorities[&rule.priority()];
+                slots.get_mut(&id).unwrap().bucket = bucket;
+            }
+        }
+    }
+
+    // Instead of rejecting rules whose prefix lengths no table accepts, creates a table for
+    // exactly their prefix lengths, as in tuple space search. The new table is appended to the
+    // split. Too many such tables slow down classification, see `check_split` and `rebuild`.
+    pub fn set_auto_tables(&mut self, enabled: bool) {
+        self.auto_tables = enabled;
+    }
+
+    // Splits tables with long buckets and merges sparse neighboring tables as rules are added
+    // and removed, following `policy`. Only enabled tables are adapted, indices of tables may
+    // change. None keeps the current tables.
+    pub fn set_adaptive(&mut self, policy: Option<AdaptivePolicy>) {
+        self.adaptive = policy;
+    }
+
+    // Bounds the work of every insertion and removal, for rule sets with thousands of updates
+    // per second. Only the changed table is moved to its place in probe order instead of
+    // sorting all tables, and adapting the tables, see `set_adaptive`, is deferred to
+    // `maintain`. Turning it off adapts all changed tables at once.
+    pub fn set_low_latency(&mut self, enabled: bool) {
+        self.low_latency = enabled;
+        if !enabled {
+            self.maintain(usize::MAX);
+        }
+    }
+
+    // Stores the rules and buckets of every table in one contiguous pool instead of a vector
+    // per bucket, for rule sets with hundreds of thousands of rules where allocating the
+    // buckets fragments memory. Lookups follow one more index, and the runs of a bucket are
+    // not compared several at a time with the `simd` feature. Moves all installed rules.
+    pub fn set_pooled_storage(&mut self, enabled: bool) {
+        self.init_tables();
+        self.pooled = enabled;
+        for hm in self.hash_maps.iter_mut() {
+            hm.hash_map.set_pooled(enabled);
+        }
+    }
+
+    // Checks a Bloom filter of the buckets of a table before looking up the bucket of a
+    // packet, for classifiers with many tables where most lookups find no bucket. Lookups
+    // still hash the packet once per table.
+    pub fn set_table_filters(&mut self, enabled: bool) {
+        self.init_tables();
+        self.table_filters = enabled;
+        for hm in self.hash_maps.iter_mut() {
+            hm.set_filter(enabled);
+        }
+    }
+
+    // Counts the best matches of every table, and probes the tables with the most matches first
+    // once `reorder_by_hits` was called, for traffic where a few tables match most packets.
+    // Matches are the same as in priority order, but every table is looked at, if only to
+    // compare its highest priority. Turning it off probes by priority again.
+    pub fn set_hit_ordering(&mut self, enabled: bool) {
+        self.hit_order = enabled.then(|| (0..self.hash_maps.len()).collect());
+        for hm in self.hash_maps.iter_mut() {
+            hm.hits = Counter::default();
+        }
+    }
+
+    // Orders the tables by their matches since the last call, f.e. from a timer. Older
+    // matches count half as much each time, so that the order follows changes in traffic.
+    pub fn reorder_by_hits(&mut self) {
+        let Some(order) = self.hit_order.as_mut() else {
+            return;
+        };
+        let hash_maps = &mut self.hash_maps;
+        *order = (0..hash_maps.len()).collect();
+        // ties stay in priority order
+        order.sort_by_key(|&position| std::cmp::Reverse(hash_maps[position].hits.get()));
+        for hm in hash_maps.iter_mut() {
+            hm.hits.halve();
+        }
+    }
+
+    // Best matches counted for a table, see `set_hit_ordering`.
+    pub fn table_hits(&self, index: usize) -> Option<u64> {
+        self.table(index).map(|hm| hm.hits.get())
+    }
+
+    // Rejects packets by the first `prefix_len` bits of the field of `dimension` before any
+    // table is probed, f.e. the destination /16, if no rule has that prefix. Meant for
+    // deployments where most traffic hits the default action. Rules matching a shorter prefix
+    // of the dimension turn the filter off while they are installed. The filter is not carried
+    // over by `freeze`.
+    pub fn set_prefilter(&mut self, dimension: usize, prefix_len: u32) {
+        self.prefilter =
+            Some(self.build_prefilter(Prefilter::new(dimension, prefix_len, self.len())));
+    }
+
+    pub fn clear_prefilter(&mut self) {
+        self.prefilter = None;
+    }
+
+    // Adds all installed rules to `prefilter`, doubling its capacity until they fit.
+    fn build_prefilter(&self, mut prefilter: Prefilter) -> Prefilter {
+        loop {
+            if self.iter().all(|r| prefilter.insert(r.fields(), r.masks())) {
+                return prefilter;
+            }
+            prefilter = prefilter.emptied(prefilter.capacity() * 2);
+        }
+    }
+
+    // Adds a placed rule to the pre-filter, which is built again if it is full.
+    fn admit(&mut self, id: RuleId) {
+        let Some(mut prefilter) = self.prefilter.take() else {
+            return;
+        };
+        let rule = self.get(id).unwrap();
+        if !prefilter.insert(rule.fields(), rule.masks()) {
+            prefilter = self.build_prefilter(prefilter.emptied(prefilter.capacity() * 2));
+        }
+        self.prefilter = Some(prefilter);
+    }
+
+    // False if the pre-filter rules out every rule for the packet.
+    fn may_match(&self, q: &impl Packet<F>) -> bool {
+        self.prefilter
+            .as_ref()
+            .is_none_or(|prefilter| prefilter.may_match(q.fields()))
+    }
+
+    // Adapts up to `max_tables` of the tables changed while updates were low latency, f.e.
+    // from a timer between bursts of updates. Returns the number of changed tables left.
+    pub fn maintain(&mut self, max_tables: usize) -> usize {
+        for _ in 0..max_tables {
+            let Some(index) = self.pending.pop_first() else {
+                break;
+            };
+            // indices may have changed by adapting other tables, merged tables are gone
+            let Some(position) = self.hash_maps.iter().position(|hm| hm.index == index) else {
+                continue;
+            };
+
+            let largest = self.hash_maps[position]
+                .hash_map
+                .iter()
+                .max_by_key(|(_, bucket)| bucket.len())
+                .map(|(hash, _)| hash);
+            self.adapt(position, |c| {
+                let tables = c.hash_maps.len();
+                if let Some(bucket) = largest {
+                    c.split_if_crowded(position, bucket);
+                }
+                if c.hash_maps.len() == tables {
+                    c.merge_if_sparse(position);
+                }
+            });
+        }
+
+        self.pending.len()
+    }
+
+    // Remembers the table at `position` for `maintain` and restores the probe order.
+    fn defer(&mut self, position: usize) {
+        self.pending.insert(self.hash_maps[position].index);
+        self.reposition(position);
+    }
+
+    // Runs `adapt` after the table at `position` changed and restores the probe order. All
+    // tables are only sorted again if `adapt` split or merged tables, which leaves at most two
+    // tables out of order, so the sort is linear.
+    fn adapt(&mut self, position: usize, adapt: impl FnOnce(&mut Self)) {
+        let tables = self.hash_maps.len();
+        adapt(self);
+        if self.hash_maps.len() == tables {
+            self.reposition(position);
+        } else {
+            self.sort_hash_maps();
+        }
+    }
+
+    // Moves the table at `position`, whose highest priority changed, to its place in probe
+    // order. All other tables have to be in order. Only the tables between its old and new
+    // place are moved, which are typically none or a few.
+    fn reposition(&mut self, position: usize) {
+        self.reorder_tables(|hash_maps| {
+            let key =
+                |hm: &RVHashMap<R, F, S>| std::cmp::Reverse((hm.enabled, hm.highest_priority()));
+            let moved = key(&hash_maps[position]);
+
+            // only the tables it passes are shifted
+            let (before, after) = hash_maps.split_at(position);
+            let to = before.partition_point(|other| key(other) <= moved);
+            if to < position {
+                hash_maps[to..=position].rotate_right(1);
+            } else {
+                let to = position + after[1..].partition_point(|other| key(other) <= moved);
+                hash_maps[position..=to].rotate_left(1);
+            }
+        });
+    }
+
+    // Moves tables within `hash_maps`, the order of `set_hit_ordering` follows the tables.
+    fn reorder_tables(&mut self, reorder: impl FnOnce(&mut [RVHashMap<R, F, S>])) {
+        let hash_maps = &self.hash_maps;
+        let indices: Option<Vec<usize>> = self
+            .hit_order
+            .as_ref()
+            .filter(|order| order.len() == hash_maps.len())
+            .map(|order| {
+                order
+                    .iter()
+                    .map(|&position| hash_maps[position].index)
+                    .collect()
+            });
+        reorder(&mut self.hash_maps);
+
+        if let (Some(indices), Some(order)) = (indices, self.hit_order.as_mut()) {
+            let mut positions = vec![0; self.hash_maps.len()];
+            for (position, hm) in self.hash_maps.iter().enumerate() {
+                positions[hm.index] = position;
+            }
+            *order = indices.into_iter().map(|index| positions[index]).collect();
+        }
+    }
+
+    // Splits the table at `position` in `hash_maps` if `bucket` grew too long.
+    fn split_if_crowded(&mut self, position: usize, bucket: u64) {
+        let Some(policy) = self.adaptive else {
+            return;
+        };
+        let hm = &self.hash_maps[position];
+        if !hm.enabled
+            || hm.hash_map.get(bucket).map_or(0, |b| b.len()) <= policy.max_bucket
+            || self.hash_maps.len() >= policy.max_tables
+        {
+            return;
+        }
+
+        let prefix_lengths: Vec<&[u32]> = hm
+            .hash_map
+            .rules()
+            .map(|r| &*self.slots[&hm.priorities[&r.priority()]].prefixes)
+            .collect();
+        let Some((dim, cut)) = adaptive::choose_cut(&hm.ranges, &prefix_lengths) else {
+            return;
+        };
+
+        let mut lower = hm.ranges.clone();
+        lower[dim].1 = cut;
+        let mut upper = hm.ranges.clone();
+        upper[dim].0 = cut;
+
+        let old = self.replace_table(position, lower);
+        let mut hm = self.new_table(upper);
+        hm.index = self.hash_maps.len();
+        self.hash_maps.push(hm);
+        self.move_rules(old, &[position, self.hash_maps.len() - 1]);
+    }
+
+    // Merges the table at `position` in `hash_maps` with a neighbor if both are sparse.
+    fn merge_if_sparse(&mut self, position: usize) {
+        let Some(policy) = self.adaptive else {
+            return;
+        };
+        let limit = policy.merge_below.min(policy.max_bucket);
+        let hm = &self.hash_maps[position];
+        if !hm.enabled || hm.len() > limit {
+            return;
+        }
+
+        let neighbor = self.hash_maps.iter().enumerate().find_map(|(other, o)| {
+            let ranges = adaptive::merged(&hm.ranges, &o.ranges)?;
+            let disjoint = o.priorities.keys().all(|p| !hm.priorities.contains_key(p));
+            (o.enabled && hm.len() + o.len() <= limit && disjoint).then_some((other, ranges))
+        });
+        let Some((other, ranges)) = neighbor else {
+            return;
+        };
+
+        let old = self.hash_maps.remove(other);
+        let position = if other < position {
+            position - 1
+        } else {
+            position
+        };
+        // the last index takes over the free one, so that indices stay positions in the split
+        let last = self.hash_maps.len();
+        if old.index != last {
+            for hm in self.hash_maps.iter_mut().filter(|hm| hm.index == last) {
+                hm.index = old.index;
+            }
+            for slot in self.slots.values_mut().filter(|slot| slot.table == last) {
+                slot.table = old.index;
+            }
+        }
+
+        let replaced = self.replace_table(position, ranges);
+        self.move_rules(replaced, &[position]);
+        self.move_rules(old, &[position]);
+    }
+
+    // Replaces the table at `position` by an empty one with the same index and returns it.
+    fn replace_table(&mut self, position: usize, ranges: Vec<Range>) -> RVHashMap<R, F, S> {
+        let mut hm = self.new_table(ranges);
+        hm.index = self.hash_maps[position].index;
+        std::mem::replace(&mut self.hash_maps[position], hm)
+    }
+
+    // Empty table hashing the dimensions with a dictionary by their codes, without an index.
+    fn new_table(&self, ranges: Vec<Range>) -> RVHashMap<R, F, S> {
+        let mut hm = RVHashMap::with_hasher(ranges, self.hasher.clone());
+        for dimension in self.dictionaries.iter() {
+            hm.set_dictionary(*dimension, true);
+        }
+        hm.hash_map.set_pooled(self.pooled);
+        hm.set_filter(self.table_filters);
+        hm
+    }
+
+    // Inserts the rules of a replaced table into the tables at `positions`, keeping their ids.
+    fn move_rules(&mut self, old: RVHashMap<R, F, S>, positions: &[usize]) {
+        for rule in old.hash_map.into_rules() {
+            let id = old.priorities[&rule.priority()];
+            let prefixes = &self.slots[&id].prefixes;
+            let position = *positions
+                .iter()
+                .find(|p| self.hash_maps[**p].accepts_prefixes(prefixes))
+                .unwrap();
+            let hm = &mut self.hash_maps[position];
+            let bucket = hm.insert(id, rule).unwrap();
+
+            let slot = self.slots.get_mut(&id).unwrap();
+            slot.table = hm.index;
+            slot.bucket = bucket;
+        }
+    }
+
+    // Disabled tables keep their rules, but the rules do not match until the table is enabled
+    // again, f.e. to try out another split next to the current one. Fails if there is no table
+    // with this index.
+    pub fn set_table_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        self.init_tables();
+        let Some(position) = self.hash_maps.iter().position(|hm| hm.index == index) else {
+            return false;
+        };
+
+        self.hash_maps[position].enabled = enabled;
+        self.reposition(position);
+        true
+    }
+
+    pub fn is_table_enabled(&self, index: usize) -> Option<bool> {
+        match self.lazy_split {
+            Some(split) => (index < split.len()).then_some(true),
+            None => self.table(index).map(|hm| hm.enabled),
+        }
+    }
+
+    // Enabled tables in probe order.
+    fn active_tables(&self) -> impl Iterator<Item = &RVHashMap<R, F, S>> {
+        self.hash_maps.iter().take_while(|hm| hm.enabled)
+    }
+
+    fn table(&self, index: usize) -> Option<&RVHashMap<R, F, S>> {
+        self.hash_maps.iter().find(|hm| hm.index == index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    // Removes all rules and their metadata. The tables, bands and dimensions are kept, ids of
+    // removed rules are not handed out again.
+    pub fn clear(&mut self) {
+        for hm in self.hash_maps.iter_mut() {
+            hm.clear();
+        }
+        if let Some(prefilter) = self.prefilter.as_mut() {
+            prefilter.clear();
+        }
+
+        self.generation += 1;
+        if let Some(log) = self.changes.as_mut() {
+            for id in self.slots.keys() {
+                log.record(self.generation, *id);
+            }
+        }
+        self.slots.clear();
+    }
+
+    // Drops the slot of a removed rule and records the removal.
+    fn forget(&mut self, id: RuleId) -> Option<Slot<M>> {
+        let slot = self.slots.remove(&id)?;
+        self.generation += 1;
+        if let Some(log) = self.changes.as_mut() {
+            log.record(self.generation, id);
+        }
+
+        Some(slot)
+    }
+
+    // Number of insertions and removals so far.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Starts recording removed rules for `changes_since`, changes before the current
+    // generation are not available.
+    pub fn track_changes(&mut self) {
+        if self.changes.is_none() {
+            self.changes = Some(ChangeLog::new(self.generation));
+        }
+    }
+
+    // Rules added and removed since `generation`, None if changes are not tracked since then.
+    // Finding the added rules visits every installed rule, but only the changes are returned.
+    pub fn changes_since(&self, generation: u64) -> Option<Changes<'_, R>> {
+        let removed = self.changes.as_ref()?.removed_since(generation)?;
+        let added = self
+            .slots
+            .iter()
+            .filter(|(_, slot)| slot.generation > generation)
+            .filter_map(|(id, _)| Some((*id, self.get(*id)?)))
+            .collect();
+
+        Some(Changes {
+            generation: self.generation,
+            added,
+            removed,
+        })
+    }
+
+    // Drops the recorded removals up to `generation`, once every consumer has seen them.
+    pub fn forget_changes(&mut self, generation: u64) {
+        if let Some(log) = self.changes.as_mut() {
+            log.forget(generation.min(self.generation));
+        }
+    }
+
+    // All installed rules, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &R> {
+        self.hash_maps.iter().flat_map(|hm| hm.hash_map.rules())
+    }
+
+    // All installed rules with their ids, in the order of the ids.
+    pub(crate) fn iter_ids(&self) -> impl Iterator<Item = (RuleId, &R)> {
+        self.slots
+            .keys()
+            .filter_map(move |id| Some((*id, self.get(*id)?)))
+    }
+
+    // All installed rules, highest priority first.
+    pub fn iter_by_priority(&self) -> impl Iterator<Item = &R> {
+        let mut rules: Vec<_> = self.iter().collect();
+        rules.sort_unstable_by_key(|r| std::cmp::Reverse(r.priority()));
+        rules.into_iter()
+    }
+
+    // Disabled tables go last, so that classification can stop at the first one.
+    fn sort_hash_maps(&mut self) {
+        self.reorder_tables(|hash_maps| {
+            hash_maps.sort_by_key(|hm| std::cmp::Reverse((hm.enabled, hm.highest_priority())))
+        });
+    }
+}
+
+impl<R: ActionRule<F>, F: FieldType, M, S: BuildHasher + Clone> RVHClassifier<R, F, M, S> {
+    // Classifies the packet and resolves the action and id of the matching rule in one call.
+    pub fn decide(&self, p: &impl Packet<F>) -> Option<Decision<R::Action>> {
+        let q = &self.transform(p);
+        let Some((hm, rule)) = self.best_match(q, |hm| hm.check_match(q)) else {
+            self.missed(p);
+            return None;
+        };
+
+        Some(Decision {
+            action: rule.action().clone(),
+            rule: hm.priorities[&rule.priority()],
+        })
+    }
+}
+
+impl<R: Rule<F> + Clone, F: FieldType, M, S: BuildHasher + Clone> RVHClassifier<R, F, M, S> {
+    // Like `classify` but returns a copy of the matching rule, which is not tied to the
+    // lifetime of the classifier and can thus be sent to other threads or tasks.
+    pub fn classify_owned(&self, p: &impl Packet<F>) -> Option<R> {
+        self.classify(p).cloned()
+    }
+
+    // Starts building a copy of this classifier with a different split, see `Rebuild`. Rules
+    // keep their ids.
+    pub fn start_rebuild(&self, split: Vec<Vec<Range>>) -> Rebuild<R, F, M, S> {
+        let mut target = Self::with_hasher(split.into_iter(), self.hasher.clone());
+        target.bands = self.bands.clone();
+        target.dimensions = self.dimensions.clone();
+        target.next_id = self.next_id;
+        target.auto_tables = self.auto_tables;
+        target.adaptive = self.adaptive;
+        target.low_latency = self.low_latency;
+        target.set_pooled_storage(self.pooled);
+        target.set_table_filters(self.table_filters);
+        target.set_hit_ordering(self.hit_order.is_some());
+        target.prefilter = self.prefilter.as_ref().map(|p| p.emptied(p.capacity()));
+        for dimension in self.dictionaries.iter() {
+            target.set_dictionary(*dimension, true);
+        }
+
+        let rules = self
+            .hash_maps
+            .iter()
+            .flat_map(|hm| {
+                hm.hash_map
+                    .rules()
+                    .map(move |r| (hm.priorities[&r.priority()], r.clone()))
+            })
+            .collect();
+
+        Rebuild::new(target, rules)
+    }
+
+    // Replaces this classifier with the result of a rebuild, moving over the metadata of the
+    // rules. Hands the rebuild back if it is not complete yet or rejected some of the rules.
+    pub fn cutover(&mut self, rebuild: Rebuild<R, F, M, S>) -> Result<(), Rebuild<R, F, M, S>> {
+        let mut target = rebuild.into_target()?;
+
+        // rules changed during the rebuild are reported as removed and added again
+        let generation = self.generation + 1;
+        let changed: BTreeSet<RuleId> = target
+            .slots
+            .keys()
+            .copied()
+            .filter(|id| target.get(*id) != self.get(*id))
+            .collect();
+        for (id, slot) in target.slots.iter_mut() {
+            let old = self.slots.remove(id);
+            slot.generation = match &old {
+                Some(old) if !changed.contains(id) => old.generation,
+                _ => generation,
+            };
+            if let Some(old) = old {
+                slot.meta = old.meta;
+                #[cfg(feature = "rate-limit")]
+                {
+                    slot.limit = old.limit;
+                }
+            }
+        }
+        if let Some(log) = self.changes.as_mut() {
+            for id in self.slots.keys().chain(changed.iter()) {
+                log.record(generation, *id);
+            }
+        }
+
+        target.generation = generation;
+        target.changes = self.changes.take();
+        *self = target;
+        Ok(())
+    }
+
+    // Rebuilds the classifier with a different split in one go.
+    pub fn rebuild(&mut self, split: Vec<Vec<Range>>) -> Result<(), Rebuild<R, F, M, S>> {
+        let mut rebuild = self.start_rebuild(split);
+        rebuild.step(usize::MAX);
+        self.cutover(rebuild)
+    }
+}
+
+// Mutable access to an installed rule, see `RVHClassifier::get_mut`. If the fields, masks or
+// priority were changed, the rule is placed again under the same id when the guard is dropped.
+// A changed rule that is rejected, f.e. because its priority is already in use, is reverted to
+// the rule as it was before, `finish` reports the rejection.
+pub struct RuleMut<
+    'a,
+    R: Rule<F> + Clone,
+    F: FieldType = Field,
+    M = (),
+    S: BuildHasher + Clone = SipBuildHasher,
+> {
+    classifier: &'a mut RVHClassifier<R, F, M, S>,
+    id: RuleId,
+    // position of the table in `hash_maps` and of the rule in its bucket
+    table: usize,
+    bucket: u64,
+    index: usize,
+    // the rule as placed, taken on the first mutable access, None once placed again
+    original: Option<R>,
+}
+
+impl<R: Rule<F> + Clone, F: FieldType, M, S: BuildHasher + Clone> RuleMut<'_, R, F, M, S> {
+    pub fn id(&self) -> RuleId {
+        self.id
+    }
+
+    // Same as dropping the guard, but reports whether a changed rule could be placed.
+    pub fn finish(mut self) -> Result<(), RvhError> {
+        self.place()
+    }
+
+    fn place(&mut self) -> Result<(), RvhError> {
+        let Some(original) = self.original.take() else {
+            return Ok(());
+        };
+
+        let rule: &R = self;
+        if rule.priority() == original.priority()
+            && rule.fields() == original.fields()
+            && rule.masks() == original.masks()
+        {
+            return Ok(());
+        }
+
+        let classifier = &mut *self.classifier;
+        let (_, rule) =
+            classifier.hash_maps[self.table].take(self.bucket, self.index, original.priority());
+        let slot = classifier.forget(self.id);
+        if let Some(prefilter) = classifier.prefilter.as_mut() {
+            prefilter.remove(original.fields(), original.masks());
+        }
+        // the highest priority of the table may have dropped, wherever the rule goes
+        classifier.reposition(self.table);
+
+        // the rule may not leave or enter a band
+        let band = |p| classifier.bands.band_of(p).map(|b| b.name().to_owned());
+        let result = if band(rule.priority()) != band(original.priority()) {
+            Err(RvhError::PriorityBand)
+        } else {
+            classifier.place_rule_with_id(self.id, rule)
+        };
+        let result = classifier.record(result).map(|_| ());
+        if result.is_err() {
+            classifier
+                .place_rule_with_id(self.id, original)
+                .expect("the rule as it was before is placed again");
+        }
+
+        if let Some(slot) = slot {
+            let placed = classifier.slots.get_mut(&self.id).unwrap();
+            placed.meta = slot.meta;
+            #[cfg(feature = "rate-limit")]
+            {
+                placed.limit = slot.limit;
+            }
+        }
+        result
+    }
+}
+
+impl<R: Rule<F> + Clone, F: FieldType, M, S: BuildHasher + Clone> Deref
+    for RuleMut<'_, R, F, M, S>
+{
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        let bucket = self.classifier.hash_maps[self.table]
+            .hash_map
+            .get(self.bucket);
+        bucket.unwrap().get(self.index)
+    }
+}
+
+impl<R: Rule<F> + Clone, F: FieldType, M, S: BuildHasher + Clone> DerefMut
+    for RuleMut<'_, R, F, M, S>
+{
+    fn deref_mut(&mut self) -> &mut R {
+        if self.original.is_none() {
+            self.original = Some((**self).clone());
+        }
+        self.classifier.hash_maps[self.table]
+            .hash_map
+            .rule_mut(self.bucket, self.index)
+    }
+}
+
+impl<R: Rule<F> + Clone, F: FieldType, M, S: BuildHasher + Clone> Drop for RuleMut<'_, R, F, M, S> {
+    fn drop(&mut self) {
+        // a rejected change is reverted, `finish` reports it
+        let _ = self.place();
+    }
+}
+
+impl<R: Rule, M, S: BuildHasher + Clone + Default> Default for RVHClassifier<R, Field, M, S> {
+    // same split as `five_tuple`
+    fn default() -> Self {
+        let mut classifier = Self::with_metadata(presets::five_tuple().into_iter());
+        classifier.dimensions = presets::five_tuple_dimensions();
+        classifier
+    }
+}
+
+// Lists the tables in probe order with their index and ranges.
+impl<R: Rule<F>, F: FieldType, M, S: BuildHasher + Clone> fmt::Display
+    for RVHClassifier<R, F, M, S>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for hm in self.hash_maps.iter() {
+            write!(f, "table {}:", hm.index)?;
+            for (dim, (low, high)) in hm.ranges.iter().enumerate() {
+                write!(
+                    f,
+                    " {} {}..{}",
+                    dimensions::label(&self.dimensions, dim),
+                    low,
+                    high
+                )?;
+            }
+            write!(
+                f,
+                " ({} rules, highest priority {})",
+                hm.priorities.len(),
+                hm.highest_priority()
+            )?;
+            if !hm.enabled {
+                write!(f, " disabled")?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::mocks::{MockBuildHasher, MockPacket, MockRule};
+
+    #[test]
+    fn test_insertions_keep_correct_order_of_hash_tables() {
+        let mut rvh = RVHClassifier::<MockRule>::new(
+            vec![
+                vec![(3, 6), (0, 3)],
+                vec![(0, 3), (3, 6)],
+                vec![(0, 3), (0, 3)],
+                vec![(3, 6), (3, 6)],
+            ]
+            .into_iter(),
+        );
+
+        let r11 = MockRule::new(vec![0b1, 0b10], vec![0b11, 0b1], 1);
+        assert!(rvh.add_rule(r11).is_ok());
+
+        let r21 = MockRule::new(vec![0b1, 0b10], vec![0b111, 0b1], 3);
+        assert!(rvh.add_rule(r21).is_ok());
+
+        assert_eq!(rvh.hash_maps[0].highest_priority(), 3);
+        assert_eq!(rvh.hash_maps[1].highest_priority(), 1);
+
+        let r31 = MockRule::new(vec![0b1, 0b10], vec![0b11, 0b111], 5);
+        assert!(rvh.add_rule(r31).is_ok());
+
+        assert_eq!(rvh.hash_maps[0].highest_priority(), 5);
+
+        let r41 = MockRule::new(vec![0b1, 0b10], vec![0b111, 0b11_111], 7);
+        assert!(rvh.add_rule(r41).is_ok());
+
+        assert_eq!(rvh.hash_maps[0].highest_priority(), 7);
+        assert_eq!(rvh.hash_maps[1].highest_priority(), 5);
+        assert_eq!(rvh.hash_maps[2].highest_priority(), 3);
+        assert_eq!(rvh.hash_maps[3].highest_priority(), 1);
+    }
+
+    #[test]
+    fn test_removals_keep_correct_order_of_hash_tables() {
+        let mut rvh = RVHClassifier::<MockRule>::new(
+            vec![
+                vec![(3, 6), (0, 3)],
+                vec![(0, 3), (3, 6)],
+                vec![(0, 3), (0, 3)],
+                vec![(3, 6), (3, 6)],
+            ]
+            .into_iter(),
+        );
+
+        let r11 = MockRule::new(vec![0b1, 0b10], vec![0b11, 0b1], 1);
+        assert!(rvh.add_rule(r11.clone()).is_ok());
+
+        let r21 = MockRule::new(vec![0b1, 0b10], vec![0b111, 0b1], 3);
+        assert!(rvh.add_rule(r21.clone()).is_ok());
+
+        let r31 = MockRule::new(vec![0b1, 0b10], vec![0b11, 0b111], 5);
+        assert!(rvh.add_rule(r31.clone()).is_ok());
+
+        let r41 = MockRule::new(vec![0b1, 0b10], vec![0b111, 0b11_111], 7);
+        assert!(rvh.add_rule(r41.clone()).is_ok());
+
+        assert!(rvh.remove_rule(&r31).is_ok());
+        assert_eq!(rvh.hash_maps[0].highest_priority(), 7);
+        assert_eq!(rvh.hash_maps[1].highest_priority(), 3);
+
+        assert!(rvh.remove_rule(&r41).is_ok());
+        assert_eq!(rvh.hash_maps[0].highest_priority(), 3);
+        assert_eq!(rvh.hash_maps[1].highest_priority(), 1);
+    }
+
+    #[test]
+    fn test_add_rule_inserts_rule_into_correct_table() {
+        let mut rvh = RVHClassifier::<MockRule>::new(
+            vec![
+                vec![(0, 3), (0, 3)],
+                vec![(3, 6), (0, 3)],
+                vec![(0, 3), (3, 6)],
+                vec![(3, 6), (3, 6)],
+            ]
+            .into_iter(),
+        );
+
+        let r11 = MockRule::new(vec![0b1, 0b10], vec![0b11, 0b1], 1);
+        let r12 = MockRule::new(vec![0b1, 0b10], vec![0b1, 0b11], 2);
+        assert!(rvh.add_rule(r11).is_ok());
+        assert!(rvh.add_rule(r12).is_ok());
+
+        let r21 = MockRule::new(vec![0b1, 0b10], vec![0b111, 0b1], 3);
+        let r22 = MockRule::new(vec![0b1, 0b10], vec![0b11_111, 0b11], 4);
+        assert!(rvh.add_rule(r21).is_ok());
+        assert!(rvh.add_rule(r22).is_ok());
+
+        let r31 = MockRule::new(vec![0b1, 0b10], vec![0b11, 0b111], 5);
+        let r32 = MockRule::new(vec![0b1, 0b10], vec![0b1, 0b11_111], 6);
+        assert!(rvh.add_rule(r31).is_ok());
+        assert!(rvh.add_rule(r32).is_ok());
+
+        let r41 = MockRule::new(vec![0b1, 0b10], vec![0b111, 0b11_111], 7);
+        let r42 = MockRule::new(vec![0b1, 0b10], vec![0b11_111, 0b111], 8);
+        assert!(rvh.add_rule(r41).is_ok());
+        assert!(rvh.add_rule(r42).is_ok());
+
+        let prios: Vec<_> = rvh.hash_maps[0].priorities.keys().collect();
+        assert_eq!(prios, vec![&7, &8]);
+
+        let prios: Vec<_> = rvh.hash_maps[1].priorities.keys().collect();
+        assert_eq!(prios, vec![&5, &6]);
+
+        let prios: Vec<_> = rvh.hash_maps[2].priorities.keys().collect();
+        assert_eq!(prios, vec![&3, &4]);
+
+        let prios: Vec<_> = rvh.hash_maps[3].priorities.keys().collect();
+        assert_eq!(prios, vec![&1, &2]);
+    }
+
+    #[test]
+    fn test_classifier_classifies_correctly() {
+        let mut rvh = RVHClassifier::<MockRule>::new(
+            vec![vec![(0, 3)], vec![(3, 6)], vec![(6, 9)]].into_iter(),
+        );
+
+        let r11 = MockRule::new(vec![0b11], vec![0b11], 1);
+        let r12 = MockRule::new(vec![0b1], vec![0b1], 3);
+        rvh.add_rule(r11).unwrap();
+        rvh.add_rule(r12).unwrap();
+
+        let r21 = MockRule::new(vec![0b100], vec![0b111], 4);
+        let r22 = MockRule::new(vec![0b101], vec![0b1_1111], 2);
+        rvh.add_rule(r21).unwrap();
+        rvh.add_rule(r22).unwrap();
+
+        let r31 = MockRule::new(vec![0b11_1001], vec![0b11_1111], 6);
+        let r32 = MockRule::new(vec![0b11_1100], vec![0b1111_1111], 5);
+        rvh.add_rule(r31).unwrap();
+        rvh.add_rule(r32).unwrap();
+
+        let p31 = MockPacket::new(vec![0b11_1001]); // matches r12, r31
+        let p32 = MockPacket::new(vec![0b11_1100]); // matches r21, r32
+
+        let p12 = MockPacket::new(vec![0b00_101]); // matches r22, r12
+        let p21 = MockPacket::new(vec![0b00_100]); // matches r21
+
+        let p_none = MockPacket::new(vec![0b0]);
+
+        assert_eq!(rvh.classify(&p31).expect("should match").priority(), 6);
+        assert_eq!(rvh.classify(&p32).expect("should match").priority(), 5);
+        assert_eq!(rvh.classify(&p12).expect("should match").priority(), 3);
+        assert_eq!(rvh.classify(&p21).expect("should match").priority(), 4);
+        assert!(rvh.classify(&p_none).is_none());
+    }
+
+    #[test]
+    fn test_bands_are_enforced_on_insertion() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3)], vec![(3, 6)]].into_iter());
+
+        assert!(rvh.add_rule(MockRule::new(vec![0b1], vec![0b1], 5)).is_ok());
+        assert!(!rvh.declare_band("user", 0, 99));
+
+        assert!(rvh.declare_band("system", 1_000_000, Priority::MAX));
+        assert!(rvh.declare_band("user", 10, 999_999));
+
+        let system = MockRule::new(vec![0b11], vec![0b111], 1_000_001);
+        let user = MockRule::new(vec![0b10], vec![0b11], 500);
+
+        assert!(rvh.add_rule(system.clone()).is_err());
+        assert!(rvh.add_rule_in_band("user", system.clone()).is_err());
+        assert!(rvh.add_rule_in_band("system", system).is_ok());
+
+        assert!(rvh.add_rule(user.clone()).is_err());
+        assert!(rvh.add_rule_in_band("unknown", user.clone()).is_err());
+        assert!(rvh.add_rule_in_band("user", user).is_ok());
+
+        assert_eq!(rvh.band_of(2_000_000).unwrap().name(), "system");
+        assert!(rvh.band_of(5).is_none());
+        assert_eq!(rvh.band("user").unwrap().high(), 999_999);
+        assert_eq!(rvh.bands().count(), 2);
+    }
+
+    #[test]
+    fn test_classify_owned_result_outlives_the_classifier() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3)]].into_iter());
+        rvh.add_rule(MockRule::new(vec![0b1], vec![0b1], 3))
+            .unwrap();
+
+        let p = MockPacket::new(vec![0b11]);
+        let owned = rvh.classify_owned(&p);
+        drop(rvh);
+
+        let handle = std::thread::spawn(move || owned.map(|r| r.priority()));
+        assert_eq!(handle.join().unwrap(), Some(3));
+    }
+
+    #[test]
+    fn test_classify_with_budget_reports_truncation() {
+        let mut rvh = RVHClassifier::<MockRule>::new(
+            vec![vec![(0, 3)], vec![(3, 6)], vec![(6, 9)]].into_iter(),
+        );
+
+        rvh.add_rule(MockRule::new(vec![0b1], vec![0b1], 3))
+            .unwrap();
+        rvh.add_rule(MockRule::new(vec![0b11_1001], vec![0b11_1111], 6))
+            .unwrap();
+        rvh.add_rule(MockRule::new(vec![0b1001], vec![0b1111], 2))
+            .unwrap();
+
+        let p = MockPacket::new(vec![0b11_1001]);
+
+        // the table with priority 6 is probed first, the rest is cut off by priority
+        let m = rvh.classify_with_budget(&p, 10);
+        assert!(m.exact);
+        assert_eq!(m.rule.unwrap().priority(), 6);
+        assert_eq!(m.probes, 2);
+
+        let m = rvh.classify_with_budget(&p, 1);
+        assert!(!m.exact);
+        assert!(m.rule.is_none());
+
+        let m = rvh.classify_with_budget(&p, 0);
+        assert!(!m.exact);
+        assert_eq!(m.probes, 0);
+
+        // misses have to search every table, including the non-matching rule in the
+        // bucket of the first table
+        let miss = MockPacket::new(vec![0b10]);
+        let m = rvh.classify_with_budget(&miss, 10);
+        assert!(m.exact);
+        assert!(m.rule.is_none());
+        assert_eq!(m.probes, 4);
+        assert!(!rvh.classify_with_budget(&miss, 3).exact);
+    }
+
+    #[test]
+    fn test_rejections_are_counted_per_reason() {
+        use crate::telemetry::RejectionReason;
+
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3)]].into_iter());
+        assert!(rvh.declare_band("system", 100, 200));
+
+        let r = |fields, masks, priority| MockRule::new(fields, masks, priority);
+        assert!(rvh.add_rule(r(vec![0b1], vec![0b1], 1)).is_ok());
+        assert_eq!(
+            rvh.add_rule(r(vec![0b1], vec![0b11], 1)),
+            Err(RvhError::DuplicatePriority)
+        );
+        assert_eq!(
+            rvh.add_rule(r(vec![0b1], vec![0b1111], 2)),
+            Err(RvhError::NoMatchingTable)
+        );
+        assert_eq!(
+            rvh.add_rule(r(vec![0b1], vec![0b1], 150)),
+            Err(RvhError::PriorityBand)
+        );
+        assert_eq!(
+            rvh.add_rule_in_band("system", r(vec![0b1], vec![0b1], 3)),
+            Err(RvhError::PriorityBand)
+        );
+        assert_eq!(
+            rvh.add_rule_in_band("user", r(vec![0b1], vec![0b1], 3)),
+            Err(RvhError::PriorityBand)
+        );
+        assert_eq!(
+            rvh.add_rule(r(vec![0b1, 0b1], vec![0b1], 4)),
+            Err(RvhError::ArityMismatch {
+                fields: 2,
+                masks: 1
+            })
+        );
+        assert_eq!(
+            rvh.remove_rule(&r(vec![0b1], vec![0b1], 5)),
+            Err(RvhError::NotFound)
+        );
+
+        let stats = rvh.rejections();
+        assert_eq!(stats.count(RejectionReason::DuplicatePriority), 1);
+        assert_eq!(stats.count(RejectionReason::NoMatchingTable), 1);
+        assert_eq!(stats.count(RejectionReason::Validation), 4);
+
+        let window = rvh.take_rejections();
+        assert_eq!(window.total(), 6);
+        assert_eq!(rvh.rejections().total(), 0);
+        assert!(rvh.rejections().window_start() >= window.window_start());
+    }
+
+    #[test]
+    fn test_metadata_follows_rule_lifecycle() {
+        let mut rvh =
+            RVHClassifier::<MockRule, Field, u64>::with_metadata(vec![vec![(0, 3)]].into_iter());
+
+        let r1 = MockRule::new(vec![0b1], vec![0b1], 1);
+        let id1 = rvh.add_rule(r1.clone()).unwrap();
+        let id2 = rvh
+            .add_rule(MockRule::new(vec![0b0], vec![0b1], 2))
+            .unwrap();
+        assert_ne!(id1, id2);
+
+        assert!(rvh.get_meta(id1).is_none());
+        assert!(rvh.set_meta(id1, 10));
+        *rvh.get_meta_mut(id1).unwrap() += 1;
+        assert_eq!(rvh.get_meta(id1), Some(&11));
+        assert!(rvh.get_meta(id2).is_none());
+
+        assert!(rvh.remove_rule(&r1).is_ok());
+        assert!(rvh.get_meta(id1).is_none());
+        assert!(!rvh.set_meta(id1, 1));
+
+        // ids are not reused
+        let id3 = rvh.add_rule(r1).unwrap();
+        assert_ne!(id1, id3);
+        assert!(rvh.set_meta(id3, 3));
+        assert_eq!(rvh.take_meta(id3), Some(3));
+        assert!(rvh.get_meta(id3).is_none());
+    }
+
+    #[test]
+    fn test_default_classifies_five_tuples() {
+        use crate::extract::FiveTuple;
+        use crate::fields;
+        use std::net::Ipv4Addr;
+
+        let mut rvh = RVHClassifier::<MockRule>::default();
+        assert_eq!(rvh.hash_maps.len(), 16);
+
+        let (src, src_mask) = fields::ipv4_prefix(Ipv4Addr::new(10, 1, 0, 0), 16);
+        let (dst, dst_mask) = fields::ipv4_prefix(Ipv4Addr::new(192, 168, 1, 7), 32);
+        let (dport, dport_mask) = fields::port(443);
+        let (proto, proto_mask) = fields::protocol(6);
+        let rule = MockRule::new(
+            vec![src, dst, 0, dport, proto],
+            vec![src_mask, dst_mask, 0, dport_mask, proto_mask],
+            1,
+        );
+        assert!(rvh.add_rule(rule).is_ok());
+
+        let p = FiveTuple::new(
+            Ipv4Addr::new(10, 1, 2, 3),
+            Ipv4Addr::new(192, 168, 1, 7),
+            51000,
+            443,
+            6,
+        );
+        assert_eq!(rvh.classify(&p).unwrap().priority(), 1);
+
+        let other = FiveTuple::new(
+            Ipv4Addr::new(10, 2, 2, 3),
+            Ipv4Addr::new(192, 168, 1, 7),
+            51000,
+            443,
+            6,
+        );
+        assert!(rvh.classify(&other).is_none());
+
+        assert!(RVHClassifier::<MockRule>::five_tuple()
+            .add_rule(MockRule::new(vec![src], vec![src_mask], 1))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_display_uses_dimension_names() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3), (3, 9)]].into_iter());
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0, 0], vec![0, 0b111], 1))
+            .is_ok());
+        assert_eq!(
+            rvh.to_string(),
+            "table 0: field 0 0..3 field 1 3..9 (1 rules, highest priority 1)\n"
+        );
+
+        assert!(!rvh.set_dimensions(vec![Dimension::new("a", 8), Dimension::new("b", 4)]));
+        assert!(rvh.set_dimensions(vec![Dimension::new("a", 8), Dimension::new("b", 8)]));
+        assert_eq!(
+            rvh.to_string(),
+            "table 0: a 0..3 b 3..9 (1 rules, highest priority 1)\n"
+        );
+
+        let five_tuple = RVHClassifier::<MockRule>::five_tuple();
+        assert_eq!(
+            five_tuple.dimensions()[presets::DST_PORT].name(),
+            "dst_port"
+        );
+    }
+
+    #[test]
+    fn test_wide_fields() {
+        let mut rvh = RVHClassifier::<MockRule<u128>, u128>::new(
+            vec![vec![(0, 65)], vec![(65, 129)]].into_iter(),
+        );
+
+        let low_bits = (1u128 << 100) - 1;
+        let field = 0xdead_beef_u128 << 64 | 0x1234;
+        assert!(rvh
+            .add_rule(MockRule::wide(vec![field & low_bits], vec![low_bits], 2))
+            .is_ok());
+        assert!(rvh
+            .add_rule(MockRule::wide(vec![0x1234], vec![0xffff], 1))
+            .is_ok());
+        assert_eq!(rvh.hash_maps[0].ranges, vec![(65, 129)]);
+
+        let p = MockPacket::wide(vec![field | 1u128 << 120]);
+        assert_eq!(rvh.classify(&p).unwrap().priority(), 2);
+        let p = MockPacket::wide(vec![0x1234 | 1u128 << 90]);
+        assert_eq!(rvh.classify(&p).unwrap().priority(), 1);
+
+        let frozen = rvh.freeze();
+        assert_eq!(frozen.classify(&p).unwrap().priority(), 1);
+    }
+
+    #[test]
+    fn test_iter_table_uses_split_order() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3)], vec![(3, 6)]].into_iter());
+        let r1 = MockRule::new(vec![0b1], vec![0b1], 1);
+        let r2 = MockRule::new(vec![0b1], vec![0b111], 2);
+        let r3 = MockRule::new(vec![0b11], vec![0b11], 3);
+        assert!(rvh.add_rule(r1).is_ok());
+        assert!(rvh.add_rule(r2).is_ok());
+        assert!(rvh.add_rule(r3).is_ok());
+
+        assert_eq!(rvh.table_count(), 2);
+        assert_eq!(rvh.table_ranges(1), Some(&[(3, 6)][..]));
+        let mut prios: Vec<_> = rvh.iter_table(0).unwrap().map(|r| r.priority()).collect();
+        prios.sort_unstable();
+        assert_eq!(prios, vec![1, 3]);
+        assert_eq!(
+            rvh.iter_table(1)
+                .unwrap()
+                .map(|r| r.priority())
+                .collect::<Vec<_>>(),
+            vec![2]
+        );
+        assert!(rvh.iter_table(2).is_none());
+
+        let prios: Vec<_> = rvh.iter_by_priority().map(|r| r.priority()).collect();
+        assert_eq!(prios, vec![3, 2, 1]);
+        assert_eq!(rvh.iter().count(), 3);
+        assert_eq!(rvh.len(), 3);
+
+        // indices survive freezing
+        let mut thawed = rvh.freeze().thaw();
+        assert_eq!(thawed.table_ranges(1), Some(&[(3, 6)][..]));
+        assert_eq!(thawed.iter_table(1).unwrap().count(), 1);
+
+        thawed.clear();
+        assert!(thawed.is_empty());
+        assert_eq!(thawed.table_count(), 2);
+        assert_eq!(thawed.iter_table(0).unwrap().count(), 0);
+        assert!(thawed.classify(&MockPacket::new(vec![0b11])).is_none());
+        let r4 = MockRule::new(vec![0b11], vec![0b11], 3);
+        assert!(thawed.add_rule(r4.clone()).is_ok());
+        assert_eq!(thawed.len(), 1);
+        assert_eq!(thawed.add_rule(r4), Err(RvhError::DuplicatePriority));
+    }
+
+    #[test]
+    fn test_classify_sampled_attributes_latency_to_tables() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3)], vec![(3, 6)]].into_iter());
+        assert!(rvh.add_rule(MockRule::new(vec![0b1], vec![0b1], 1)).is_ok());
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b101], vec![0b111], 2))
+            .is_ok());
+
+        let mut sampler = LatencySampler::new(1, 16);
+        let p = MockPacket::new(vec![0b101]);
+        assert_eq!(
+            rvh.classify_sampled(&p, &mut sampler).unwrap().priority(),
+            2
+        );
+        assert!(rvh
+            .classify_sampled(&MockPacket::new(vec![0b0]), &mut sampler)
+            .is_none());
+
+        assert_eq!(sampler.table(1).unwrap().len(), 1);
+        assert!(sampler.table(0).is_none());
+        assert_eq!(sampler.misses().len(), 1);
+
+        let mut sparse = LatencySampler::new(3, 16);
+        for _ in 0..7 {
+            rvh.classify_sampled(&p, &mut sparse);
+        }
+        assert_eq!(sparse.table(1).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_empty_classifier_can_be_static() {
+        use std::sync::Mutex;
+
+        static SPLIT: &[&[Range]] = &[&[(0, 3)], &[(3, 6)]];
+        static RVH: Mutex<RVHClassifier<MockRule>> =
+            Mutex::new(RVHClassifier::empty(SPLIT, SipBuildHasher::with_keys(1, 2)));
+
+        let mut rvh = RVH.lock().unwrap();
+        assert!(rvh.hash_maps.is_empty());
+        assert_eq!(rvh.table_count(), 2);
+        assert_eq!(rvh.table_ranges(1), Some(&[(3, 6)][..]));
+        assert_eq!(rvh.iter_table(1).unwrap().count(), 0);
+        assert!(rvh.classify(&MockPacket::new(vec![0b1])).is_none());
+        let hash = rvh.rule_set_hash();
+
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b101], vec![0b111], 2))
+            .is_ok());
+        assert_eq!(rvh.hash_maps.len(), 2);
+        assert_eq!(rvh.iter_table(1).unwrap().count(), 1);
+        assert_eq!(
+            rvh.classify(&MockPacket::new(vec![0b101]))
+                .unwrap()
+                .priority(),
+            2
+        );
+
+        assert!(rvh.remove(RuleId(0)).is_ok());
+        assert_eq!(rvh.rule_set_hash(), hash);
+    }
+
+    #[test]
+    fn test_check_split_reports_gaps_and_overlaps() {
+        let rvh = RVHClassifier::<MockRule>::new(
+            vec![
+                vec![(0, 3), (0, 9)],
+                vec![(3, 6), (0, 4)],
+                vec![(5, 9), (4, 9)],
+            ]
+            .into_iter(),
+        );
+        let report = rvh.check_split();
+        assert_eq!(
+            report.gaps,
+            vec![vec![(6, 9), (0, 4)], vec![(3, 5), (4, 9)]]
+        );
+        assert_eq!(report.overlaps.len(), 0);
+
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 6)], vec![(4, 9)]].into_iter());
+        assert_eq!(rvh.check_split().overlaps[0].ranges, vec![(4, 6)]);
+        assert!(rvh.set_dimensions(vec![Dimension::new("a", 10)]));
+        assert_eq!(rvh.check_split().gaps, vec![vec![(9, 11)]]);
+
+        assert!(RVHClassifier::<MockRule>::five_tuple()
+            .check_split()
+            .is_valid());
+    }
+
+    #[test]
+    fn test_rules_are_stored_in_canonical_form() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 9), (0, 9)]].into_iter());
+
+        let id = rvh
+            .add_rule(MockRule::new(vec![0b1101, 0b11], vec![0b11, 0], 1))
+            .unwrap();
+        assert_eq!(rvh.get(id).unwrap().fields(), &[0b01, 0][..]);
+        assert_eq!(
+            rvh.classify(&MockPacket::new(vec![0b1001, 0b10]))
+                .unwrap()
+                .priority(),
+            1
+        );
+
+        assert_eq!(
+            rvh.add_rule(MockRule::new(vec![0, 0], vec![0b1, 0b110], 2)),
+            Err(RvhError::InvalidMask { dimension: 1 })
+        );
+        assert_eq!(rvh.rejections().validation, 1);
+    }
+
+    #[test]
+    fn test_reseed_table_keeps_rules_reachable() {
+        let mut rvh = RVHClassifier::<MockRule, Field, (), MockBuildHasher>::with_hasher(
+            vec![vec![(0, 3), (0, 3)], vec![(3, 4), (3, 4)]].into_iter(),
+            MockBuildHasher::default(),
+        );
+        let id = rvh
+            .add_rule(MockRule::new(vec![0b10, 0b100], vec![0b111, 0b111], 1))
+            .unwrap();
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b100, 0b10], vec![0b111, 0b111], 2))
+            .is_ok());
+        assert_eq!(rvh.table_stats(1).unwrap().collisions, 1);
+        assert_eq!(rvh.table_stats(0), Some(TableStats::default()));
+        assert!(rvh.table_stats(2).is_none());
+
+        assert!(rvh.reseed_table(1, 2));
+        assert!(!rvh.reseed_table(2, 2));
+        assert_eq!(rvh.table_stats(1).unwrap().collisions, 0);
+
+        assert_eq!(rvh.get(id).unwrap().priority(), 1);
+        let p = MockPacket::new(vec![0b100, 0b10]);
+        assert_eq!(rvh.classify(&p).unwrap().priority(), 2);
+        assert_eq!(rvh.freeze().classify(&p).unwrap().priority(), 2);
+    }
+
+    #[test]
+    fn test_stats_cover_every_table() {
+        let split = || vec![vec![(0, 4)], vec![(4, 9)], vec![(9, 12)]].into_iter();
+        let mut rvh = RVHClassifier::<MockRule>::new(split());
+        assert_eq!(rvh.stats().len(), 3);
+        assert!(rvh
+            .stats()
+            .iter()
+            .all(|t| t.rules == 0 && t.load_factor() == 0.0));
+
+        for i in 0..100u32 {
+            let len = 4 + i % 5;
+            let rule = MockRule::new(vec![i % 8], vec![fields::prefix_mask(len)], i + 10);
+            assert!(rvh.add_rule(rule).is_ok());
+        }
+        let stats = rvh.stats();
+        assert_eq!(
+            stats.iter().map(|t| t.index).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        let table = stats[1];
+        assert_eq!((table.rules, table.buckets), (100, 8));
+        assert_eq!((table.lowest_priority, table.highest_priority), (10, 109));
+        assert_eq!(table.mean_bucket(), 12.5);
+        assert_eq!(table.largest_bucket, 13);
+        assert!(table.load_factor() > 0.0 && table.load_factor() <= 1.0);
+        assert_eq!(
+            stats[2],
+            TableStats {
+                index: 2,
+                ..TableStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_memory_usage_grows_with_the_rules() {
+        let split = || vec![vec![(0, 4)], vec![(4, 9)]].into_iter();
+        let mut rvh = RVHClassifier::<MockRule>::new(split());
+        let empty = rvh.memory_usage();
+        assert_eq!(empty.tables.len(), 2);
+        assert_eq!(empty.total_bytes(), 0);
+
+        for i in 0..200u32 {
+            let len = 4 + i % 5;
+            let rule = MockRule::new(vec![i % 64], vec![fields::prefix_mask(len)], i + 1);
+            assert!(rvh.add_rule(rule).is_ok());
         }
+        let usage = rvh.memory_usage();
+        assert_eq!(usage.rules, 200);
+        let table = usage.largest_table().unwrap();
+        assert_eq!(table.rules, 200);
+        assert_eq!(table.buckets, rvh.table_stats(table.index).unwrap().buckets);
+        assert!(table.rule_bytes >= 200 * std::mem::size_of::<MockRule>());
+        assert!(table.key_bytes > 0 && table.bucket_bytes > 0);
+        assert!(usage.slot_bytes >= 200 * std::mem::size_of::<u32>());
+        assert_eq!(usage.prefilter_bytes, 0);
 
-        Self { hash_maps }
+        // pooled buckets are counted as well
+        rvh.set_pooled_storage(true);
+        let pooled = rvh.memory_usage();
+        let pooled_table = pooled.largest_table().unwrap();
+        assert_eq!(pooled_table.buckets, table.buckets);
+        assert!(pooled_table.rule_bytes >= 200 * std::mem::size_of::<MockRule>());
+        assert!(pooled_table.bucket_bytes >= 3 * 200 * std::mem::size_of::<u32>());
+        rvh.set_prefilter(0, 4);
+        assert!(rvh.memory_usage().total_bytes() > pooled.total_bytes());
     }
 
-    pub fn add_rule(&mut self, rule: R) -> bool {
-        for hm in self.hash_maps.iter_mut() {
-            if hm.can_insert(&rule) {
-                if hm.insert(rule) {
-                    self.sort_hash_maps();
-                    return true;
-                }
+    #[test]
+    fn test_shrink_to_fit_releases_removed_rules() {
+        for pooled in [false, true] {
+            let split = vec![vec![(0, 4)], vec![(4, 9)]];
+            let mut rvh = RVHClassifier::<MockRule>::new(split.into_iter());
+            rvh.set_pooled_storage(pooled);
+            rvh.set_prefilter(0, 8);
+            rvh.reserve(1000);
+            assert!(rvh.memory_usage().tables.iter().all(|t| t.key_bytes > 0));
 
-                // this only happens if the priority of `rule` is not unique
-                break;
+            let mut ids = Vec::new();
+            for i in 0..1000u32 {
+                let len = 4 + i % 5;
+                let rule = MockRule::new(vec![i % 300], vec![fields::prefix_mask(len)], i + 1);
+                ids.push(rvh.add_rule(rule).unwrap());
             }
+            for id in ids.iter().filter(|id| id.0 % 50 != 0) {
+                assert!(rvh.remove(*id).is_ok());
+            }
+
+            let packets: Vec<_> = (0..512).map(|a| MockPacket::new(vec![a])).collect();
+            let matches: Vec<_> = packets.iter().map(|p| rvh.classify_id(p)).collect();
+            let before = rvh.memory_usage();
+            rvh.shrink_to_fit();
+            let after = rvh.memory_usage();
+            assert!(after.total_bytes() * 4 < before.total_bytes());
+            assert!(after.prefilter_bytes < before.prefilter_bytes);
+            assert!(packets
+                .iter()
+                .zip(matches)
+                .all(|(p, m)| rvh.classify_id(p) == m));
         }
-        false
     }
 
-    pub fn remove_rule(&mut self, rule: &R) -> bool {
-        for hm in self.hash_maps.iter_mut() {
-            if hm.remove(&rule) {
-                self.sort_hash_maps();
-                return true;
+    #[test]
+    fn test_decide_returns_action_and_rule_id() {
+        #[derive(Debug, PartialEq)]
+        struct Acl(MockRule, &'static str);
+
+        impl Rule for Acl {
+            fn priority(&self) -> Priority {
+                self.0.priority()
+            }
+            fn masks(&self) -> &[Mask] {
+                self.0.masks()
+            }
+            fn fields(&self) -> &[Field] {
+                self.0.fields()
             }
         }
-        false
-    }
 
-    pub fn classify(&self, p: &impl Packet) -> Option<&R> {
-        let mut highest_matching_priority = 0;
-        let mut best_match = None;
+        impl ActionRule for Acl {
+            type Action = &'static str;
 
-        for hm in self.hash_maps.iter() {
-            if hm.highest_priority() < highest_matching_priority {
-                break;
+            fn action(&self) -> &&'static str {
+                &self.1
             }
+        }
 
-            if let Some(matching_rule) = hm.check_match(p) {
-                if matching_rule.priority() > highest_matching_priority {
-                    highest_matching_priority = matching_rule.priority();
-                    best_match = Some(matching_rule);
-                }
+        let mut rvh = RVHClassifier::<Acl>::new(vec![vec![(0, 3)], vec![(3, 6)]].into_iter());
+        assert!(rvh
+            .add_rule(Acl(MockRule::new(vec![0b1], vec![0b1], 1), "allow"))
+            .is_ok());
+        let id = rvh
+            .add_rule(Acl(MockRule::new(vec![0b101], vec![0b111], 2), "drop"))
+            .unwrap();
+
+        let decision = rvh.decide(&MockPacket::new(vec![0b101])).unwrap();
+        assert_eq!(
+            decision,
+            Decision {
+                action: "drop",
+                rule: id
             }
+        );
+        assert_eq!(
+            rvh.decide(&MockPacket::new(vec![0b11])).unwrap().action,
+            "allow"
+        );
+        assert!(rvh.decide(&MockPacket::new(vec![0b10])).is_none());
+    }
+
+    #[test]
+    fn test_add_rule_with_allocated_priorities() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3)], vec![(3, 6)]].into_iter());
+        assert!(rvh.declare_band("user", 10, 12));
+        assert!(rvh.add_rule(MockRule::new(vec![0b1], vec![0b1], 1)).is_ok());
+
+        let mut allocator = PriorityAllocator::new(1, 3);
+        let id = rvh
+            .add_rule_with(&mut allocator, |p| {
+                MockRule::new(vec![0b101], vec![0b111], p)
+            })
+            .unwrap();
+        // 1 is taken by the first rule
+        assert_eq!(rvh.get(id).unwrap().priority(), 2);
+
+        let rejected = rvh.add_rule_with(&mut allocator, |p| {
+            MockRule::new(vec![0b1], vec![0b1111111], p)
+        });
+        assert_eq!(rejected, Err(RvhError::NoMatchingTable));
+        assert!(!allocator.is_allocated(3));
+
+        let mut band = PriorityAllocator::for_band(rvh.band("user").unwrap());
+        let make = |p| MockRule::new(vec![0b11], vec![0b11], p);
+        let ids: Vec<_> = (0..3)
+            .map(|_| rvh.add_rule_with(&mut band, make).unwrap())
+            .collect();
+        let priorities: Vec<_> = ids
+            .iter()
+            .map(|id| rvh.get(*id).unwrap().priority())
+            .collect();
+        assert_eq!(priorities, vec![10, 11, 12]);
+        assert_eq!(
+            rvh.add_rule_with(&mut band, make),
+            Err(RvhError::PrioritiesExhausted)
+        );
+        assert_eq!(rvh.rejections().validation, 1);
+
+        let removed = rvh.remove(ids[1]).unwrap();
+        assert!(band.release(removed.priority()));
+        assert!(rvh.add_rule_with(&mut band, make).is_ok());
+    }
+
+    #[test]
+    fn test_prewarm_visits_every_rule() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3)], vec![(3, 6)]].into_iter());
+        assert_eq!(rvh.prewarm(), 0);
+
+        assert!(rvh.add_rule(MockRule::new(vec![0b1], vec![0b1], 1)).is_ok());
+        assert!(rvh.add_rule(MockRule::new(vec![0b0], vec![0b1], 2)).is_ok());
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b101], vec![0b111], 3))
+            .is_ok());
+        assert_eq!(rvh.prewarm(), 3);
+
+        let sample = vec![MockPacket::new(vec![0b101]), MockPacket::new(vec![0b10])];
+        assert_eq!(rvh.prewarm_with(&sample), 3);
+        assert_eq!(rvh.freeze().prewarm_with(&sample), 3);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip_keeps_tables_ids_and_metadata() {
+        let mut rvh = RVHClassifier::<MockRule, Field, String>::with_metadata(
+            vec![vec![(0, 3)], vec![(3, 6)]].into_iter(),
+        );
+        assert!(rvh.declare_band("system", 100, 200));
+        assert!(rvh.set_dimensions(vec![Dimension::new("port", 16)]));
+        let id = rvh
+            .add_rule(MockRule::new(vec![0b101], vec![0b111], 2))
+            .unwrap();
+        assert!(rvh.add_rule(MockRule::new(vec![0b1], vec![0b1], 1)).is_ok());
+        assert!(rvh.set_meta(id, "allow ssh".to_owned()));
+
+        let json = serde_json::to_string(&rvh).unwrap();
+        let mut restored: RVHClassifier<MockRule, Field, String> =
+            serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.to_string(), rvh.to_string());
+        assert_eq!(restored.get_meta(id).map(String::as_str), Some("allow ssh"));
+        assert_eq!(restored.band("system").unwrap().high(), 200);
+        assert_eq!(
+            restored
+                .classify(&MockPacket::new(vec![0b101]))
+                .unwrap()
+                .priority(),
+            2
+        );
+
+        // new ids do not collide with restored ones
+        let next = restored
+            .add_rule(MockRule::new(vec![0b0], vec![0b1], 3))
+            .unwrap();
+        assert_ne!(next, id);
+        assert!(restored.remove(id).is_ok());
+    }
+
+    #[test]
+    fn test_rules_are_managed_by_id() {
+        let mut rvh = RVHClassifier::<MockRule, Field, u64>::with_metadata(
+            vec![vec![(0, 3)], vec![(3, 6)]].into_iter(),
+        );
+        let id1 = rvh
+            .add_rule(MockRule::new(vec![0b1], vec![0b1], 1))
+            .unwrap();
+        let id2 = rvh
+            .add_rule(MockRule::new(vec![0b10], vec![0b11], 2))
+            .unwrap();
+        assert!(rvh.set_meta(id1, 7));
+        assert_eq!(rvh.get(id2).unwrap().priority(), 2);
+        assert_eq!(rvh.prefix_lengths(id2), Some(&[2][..]));
+
+        // unchanged rules stay where they are
+        assert_eq!(rvh.get_mut(id2).unwrap().id(), id2);
+        assert_eq!(rvh.get(id2).unwrap().priority(), 2);
+
+        // the changed rule moves to the second table and keeps its id and metadata
+        *rvh.get_mut(id1).unwrap() = MockRule::new(vec![0b101], vec![0b111], 5);
+        assert_eq!(rvh.get(id1).unwrap().priority(), 5);
+        assert_eq!(rvh.get_meta(id1), Some(&7));
+        assert_eq!(rvh.prefix_lengths(id1), Some(&[3][..]));
+        assert_eq!(rvh.iter_table(1).unwrap().count(), 1);
+        assert_eq!(
+            rvh.classify(&MockPacket::new(vec![0b101]))
+                .unwrap()
+                .priority(),
+            5
+        );
+
+        // a rejected change reverts the rule
+        assert!(rvh.set_meta(id2, 3));
+        let mut r = rvh.get_mut(id2).unwrap();
+        *r = MockRule::new(vec![0b1], vec![0b111], 5);
+        assert_eq!(r.finish(), Err(RvhError::DuplicatePriority));
+        assert_eq!(rvh.get(id2).unwrap().priority(), 2);
+        assert_eq!(rvh.get_meta(id2), Some(&3));
+        // also when the guard is dropped
+        *rvh.get_mut(id2).unwrap() = MockRule::new(vec![0b1], vec![0b111], 5);
+        let p = MockPacket::new(vec![0b10]);
+        assert_eq!(rvh.classify(&p).unwrap().priority(), 2);
+        assert_eq!(rvh.remove(id2).unwrap().priority(), 2);
+
+        assert_eq!(rvh.remove(id1).unwrap().priority(), 5);
+        assert_eq!(rvh.remove(id1), Err(RvhError::NotFound));
+        assert!(rvh.get_mut(id1).is_none());
+        assert!(rvh.prefix_lengths(id1).is_none());
+        assert_eq!(rvh.iter().count(), 0);
+        assert!(rvh.classify(&MockPacket::new(vec![0b101])).is_none());
+    }
+
+    #[test]
+    fn test_changes_since_generation() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3)], vec![(3, 6)]].into_iter());
+        let a = rvh
+            .add_rule(MockRule::new(vec![0b1], vec![0b1], 1))
+            .unwrap();
+        assert!(rvh.changes_since(0).is_none());
+
+        rvh.track_changes();
+        let start = rvh.generation();
+        let b = rvh
+            .add_rule(MockRule::new(vec![0b10], vec![0b11], 2))
+            .unwrap();
+        let synced = rvh.changes_since(start).unwrap();
+        assert_eq!(synced.added.len(), 1);
+        assert_eq!(synced.added[0].0, b);
+        assert!(synced.removed.is_empty());
+        let synced = synced.generation;
+
+        assert!(rvh.remove(a).is_ok());
+        *rvh.get_mut(b).unwrap() = MockRule::new(vec![0b10], vec![0b11], 3);
+        let c = rvh
+            .add_rule(MockRule::new(vec![0b101], vec![0b111], 4))
+            .unwrap();
+        let changes = rvh.changes_since(synced).unwrap();
+        let added: Vec<_> = changes
+            .added
+            .iter()
+            .map(|(id, r)| (*id, r.priority()))
+            .collect();
+        assert_eq!(added, vec![(b, 3), (c, 4)]);
+        assert_eq!(changes.removed, vec![a, b]);
+        assert_eq!(changes.generation, rvh.generation());
+
+        rvh.forget_changes(synced + 1);
+        assert!(rvh.changes_since(synced).is_none());
+        assert_eq!(rvh.changes_since(synced + 1).unwrap().removed, vec![b]);
+
+        // a rebuild only reports the rules it changed
+        let generation = rvh.generation();
+        assert!(rvh.rebuild(vec![vec![(0, 6)]]).is_ok());
+        let changes = rvh.changes_since(generation).unwrap();
+        assert!(changes.added.is_empty() && changes.removed.is_empty());
+
+        rvh.clear();
+        assert_eq!(rvh.changes_since(generation).unwrap().removed, vec![b, c]);
+    }
+
+    #[test]
+    fn test_lookup_by_priority_and_key() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3)], vec![(3, 6)]].into_iter());
+        assert!(rvh.add_rule(MockRule::new(vec![0b1], vec![0b1], 3)).is_ok());
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b101], vec![0b111], 3))
+            .is_ok());
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b101], vec![0b111], 7))
+            .is_ok());
+
+        assert_eq!(rvh.get_by_priority(3).unwrap().fields(), &[0b101]);
+        assert!(rvh.get_by_priority(4).is_none());
+
+        assert_eq!(rvh.find(&[0b1101], &[0b111]).unwrap().priority(), 7);
+        assert_eq!(rvh.find(&[0b1], &[0b1]).unwrap().priority(), 3);
+        assert!(rvh.find(&[0b1], &[0b11]).is_none());
+        assert!(rvh.find(&[0b1], &[0b1, 0b1]).is_none());
+        assert!(rvh.find(&[0b101], &[0b101]).is_none());
+    }
+
+    #[test]
+    fn test_classify_all_returns_every_match() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3)], vec![(3, 6)]].into_iter());
+        for (fields, masks, priority) in [
+            (0b1, 0b1, 2),
+            (0b0, 0b0, 0),
+            (0b101, 0b111, 1),
+            (0b101, 0b1111, 5),
+            (0b110, 0b111, 9),
+        ] {
+            assert!(rvh
+                .add_rule(MockRule::new(vec![fields], vec![masks], priority))
+                .is_ok());
         }
 
-        best_match
+        let p = MockPacket::new(vec![0b101]);
+        let priorities: Vec<_> = rvh.classify_all(&p).map(|r| r.priority()).collect();
+        assert_eq!(priorities, vec![5, 2, 1]);
+        assert_eq!(rvh.classify(&p).unwrap().priority(), 5);
+        assert_eq!(rvh.classify_all(&MockPacket::new(vec![0b10])).count(), 0);
     }
 
-    fn sort_hash_maps(&mut self) {
-        self.hash_maps
-            .sort_by(|a, b| b.highest_priority().cmp(&a.highest_priority()));
+    #[test]
+    fn test_classify_first_ignores_priorities() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3)], vec![(3, 6)]].into_iter());
+        assert!(rvh.add_rule(MockRule::new(vec![0b1], vec![0b1], 3)).is_ok());
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b101], vec![0b111], 2))
+            .is_ok());
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b110], vec![0b111], 9))
+            .is_ok());
+
+        // the second table is searched first since it holds the highest priority
+        let p = MockPacket::new(vec![0b101]);
+        assert_eq!(rvh.classify_first(&p).unwrap().priority(), 2);
+        assert_eq!(rvh.classify(&p).unwrap().priority(), 3);
+        assert!(rvh.classify_first(&MockPacket::new(vec![0b10])).is_none());
     }
-}
 
-impl<R: Rule> Default for RVHClassifier<R> {
-    fn default() -> Self {
-        panic!("Not implemented!");
-        // TODO this should return the standard split for 5-Tuples
-        // Self::new(vec![vec![], vec![], vec![], vec![]].into_iter())
+    #[test]
+    fn test_disabled_tables_do_not_match() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3)], vec![(3, 6)]].into_iter());
+        assert!(rvh.add_rule(MockRule::new(vec![0b1], vec![0b1], 3)).is_ok());
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b101], vec![0b111], 9))
+            .is_ok());
+
+        let p = MockPacket::new(vec![0b101]);
+        assert!(rvh.set_table_enabled(1, false));
+        assert!(!rvh.set_table_enabled(2, false));
+        assert_eq!(rvh.is_table_enabled(1), Some(false));
+        assert_eq!(rvh.is_table_enabled(0), Some(true));
+        assert_eq!(rvh.classify(&p).unwrap().priority(), 3);
+        assert_eq!(rvh.classify_all(&p).count(), 1);
+        assert!(rvh.to_string().contains("disabled"));
+
+        // rules can still be added to and removed from a disabled table
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b100], vec![0b111], 5))
+            .is_ok());
+        assert_eq!(rvh.len(), 3);
+
+        let frozen = rvh.clone().freeze();
+        assert_eq!(frozen.classify(&p).unwrap().priority(), 3);
+        let mut thawed = frozen.thaw();
+        assert_eq!(thawed.is_table_enabled(1), Some(false));
+
+        assert!(thawed.set_table_enabled(1, true));
+        assert_eq!(thawed.classify(&p).unwrap().priority(), 9);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::types::mocks::{MockPacket, MockRule};
+    #[test]
+    fn test_auto_tables_accept_any_prefix_lengths() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3), (0, 3)]].into_iter());
+        let rule = MockRule::new(vec![0b101, 0b1], vec![0b111, 0b1], 2);
+        assert_eq!(rvh.add_rule(rule.clone()), Err(RvhError::NoMatchingTable));
+
+        rvh.set_auto_tables(true);
+        assert!(rvh.add_rule(rule).is_ok());
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b100, 0b0], vec![0b111, 0b1], 3))
+            .is_ok());
+        assert_eq!(rvh.table_ranges(1), Some(&[(3, 4), (1, 2)][..]));
+        assert_eq!(
+            rvh.classify(&MockPacket::new(vec![0b1101, 0b11]))
+                .unwrap()
+                .priority(),
+            2
+        );
+
+        // the first table is still used for the prefix lengths it accepts
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b1, 0b1], vec![0b11, 0b1], 4))
+            .is_ok());
+        assert_eq!(rvh.table_count(), 2);
+    }
 
     #[test]
-    fn test_insertions_keep_correct_order_of_hash_tables() {
-        let mut rvh = RVHClassifier::<MockRule>::new(
-            vec![
-                vec![(3, 6), (0, 3)],
-                vec![(0, 3), (3, 6)],
-                vec![(0, 3), (0, 3)],
-                vec![(3, 6), (3, 6)],
-            ]
-            .into_iter(),
+    fn test_from_rules_infers_the_split() {
+        let rules: Vec<_> = (1..=8)
+            .map(|i| {
+                let len = if i % 2 == 0 { 4 } else { 28 };
+                MockRule::new(vec![i, 0], vec![fields::prefix_mask(len), 0], i)
+            })
+            .chain(vec![MockRule::new(vec![1, 0], vec![1, 0], 8)])
+            .collect();
+        let rvh = RVHClassifier::from_rules(rules);
+
+        assert_eq!(rvh.table_count(), 2);
+        assert!(rvh.check_split().is_valid());
+        assert_eq!(rvh.len(), 8);
+        assert_eq!(rvh.rejections().duplicate_priority, 1);
+        for index in 0..2 {
+            assert_eq!(rvh.iter_table(index).unwrap().count(), 4);
+        }
+        assert_eq!(
+            rvh.classify(&MockPacket::new(vec![3, 9]))
+                .unwrap()
+                .priority(),
+            3
         );
+    }
 
-        let r11 = MockRule::new(vec![0b1, 0b10], vec![0b11, 0b1], 1);
-        assert!(rvh.add_rule(r11));
+    #[test]
+    fn test_miss_hook_sees_unmatched_packets() {
+        use std::sync::Mutex;
 
-        let r21 = MockRule::new(vec![0b1, 0b10], vec![0b111, 0b1], 3);
-        assert!(rvh.add_rule(r21));
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3)]].into_iter());
+        assert!(rvh.add_rule(MockRule::new(vec![0b1], vec![0b1], 1)).is_ok());
 
-        assert_eq!(rvh.hash_maps[0].highest_priority(), 3);
-        assert_eq!(rvh.hash_maps[1].highest_priority(), 1);
+        let missed = Arc::new(Mutex::new(Vec::new()));
+        let log = missed.clone();
+        rvh.set_miss_hook(move |fields| log.lock().unwrap().push(fields.to_vec()));
 
-        let r31 = MockRule::new(vec![0b1, 0b10], vec![0b11, 0b111], 5);
-        assert!(rvh.add_rule(r31));
+        assert!(rvh.classify(&MockPacket::new(vec![0b1])).is_some());
+        assert!(rvh.classify(&MockPacket::new(vec![0b10])).is_none());
+        assert!(rvh.classify_first(&MockPacket::new(vec![0b100])).is_none());
+        assert!(
+            !rvh.classify_with_budget(&MockPacket::new(vec![0b110]), 0)
+                .exact
+        );
+        assert_eq!(*missed.lock().unwrap(), vec![vec![0b10], vec![0b100]]);
 
-        assert_eq!(rvh.hash_maps[0].highest_priority(), 5);
+        rvh.clear_miss_hook();
+        assert!(rvh.classify(&MockPacket::new(vec![0b10])).is_none());
+        assert_eq!(missed.lock().unwrap().len(), 2);
+    }
 
-        let r41 = MockRule::new(vec![0b1, 0b10], vec![0b111, 0b11_111], 7);
-        assert!(rvh.add_rule(r41));
+    #[test]
+    fn test_transforms_map_packet_fields() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 33), (0, 33)]].into_iter());
+        // zone 1 in the second dimension
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b1, 1], vec![0b1, Mask::MAX], 1))
+            .is_ok());
 
-        assert_eq!(rvh.hash_maps[0].highest_priority(), 7);
-        assert_eq!(rvh.hash_maps[1].highest_priority(), 5);
-        assert_eq!(rvh.hash_maps[2].highest_priority(), 3);
-        assert_eq!(rvh.hash_maps[3].highest_priority(), 1);
+        let p = MockPacket::new(vec![0b1, 10]);
+        assert!(rvh.classify(&p).is_none());
+
+        // VLANs 10 to 19 are zone 1
+        rvh.set_transform(1, |vlan| vlan / 10);
+        assert_eq!(rvh.classify(&p).unwrap().priority(), 1);
+        assert_eq!(rvh.classify_all(&p).count(), 1);
+        assert!(rvh.classify(&MockPacket::new(vec![0b1, 20])).is_none());
+
+        rvh.clear_transform(1);
+        assert!(rvh.classify(&p).is_none());
     }
 
     #[test]
-    fn test_removals_keep_correct_order_of_hash_tables() {
-        let mut rvh = RVHClassifier::<MockRule>::new(
-            vec![
-                vec![(3, 6), (0, 3)],
-                vec![(0, 3), (3, 6)],
-                vec![(0, 3), (0, 3)],
-                vec![(3, 6), (3, 6)],
-            ]
-            .into_iter(),
+    fn test_adaptive_tables_follow_the_rules() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 9)]].into_iter());
+        rvh.set_adaptive(Some(AdaptivePolicy {
+            max_bucket: 2,
+            merge_below: 2,
+            max_tables: 4,
+        }));
+
+        // rules with a short prefix share the bucket of the table mask
+        let mut ids = Vec::new();
+        for (len, priority) in [(1, 1), (1, 2), (5, 3)] {
+            let rule = MockRule::new(vec![0b1_0001], vec![fields::prefix_mask(len)], priority);
+            ids.push(rvh.add_rule(rule).unwrap());
+        }
+        assert_eq!(rvh.table_count(), 2);
+        assert!(rvh.check_split().is_valid());
+        assert_eq!(rvh.table_ranges(0), Some(&[(0, 5)][..]));
+        assert_eq!(rvh.table_ranges(1), Some(&[(5, 9)][..]));
+        for id in ids.iter() {
+            assert!(rvh.get(*id).is_some());
+        }
+        assert_eq!(
+            rvh.classify(&MockPacket::new(vec![0b1_0001]))
+                .unwrap()
+                .priority(),
+            3
         );
 
-        let r11 = MockRule::new(vec![0b1, 0b10], vec![0b11, 0b1], 1);
-        assert!(rvh.add_rule(r11.clone()));
+        assert!(rvh.remove(ids[0]).is_ok());
+        assert_eq!(rvh.table_count(), 1);
+        assert_eq!(rvh.table_ranges(0), Some(&[(0, 9)][..]));
+        assert!(rvh.get(ids[2]).is_some());
+        assert_eq!(rvh.iter_table(0).unwrap().count(), 2);
+        assert_eq!(rvh.rules_in_table(0).unwrap().count(), 2);
+    }
 
-        let r21 = MockRule::new(vec![0b1, 0b10], vec![0b111, 0b1], 3);
-        assert!(rvh.add_rule(r21.clone()));
+    #[test]
+    fn test_low_latency_defers_adapting_tables() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 9)]].into_iter());
+        rvh.set_adaptive(Some(AdaptivePolicy {
+            max_bucket: 2,
+            merge_below: 2,
+            max_tables: 4,
+        }));
+        rvh.set_low_latency(true);
 
-        let r31 = MockRule::new(vec![0b1, 0b10], vec![0b11, 0b111], 5);
-        assert!(rvh.add_rule(r31.clone()));
+        let p = MockPacket::new(vec![0b1_0001]);
+        let mut ids = Vec::new();
+        for (len, priority) in [(1, 1), (1, 2), (5, 3)] {
+            let rule = MockRule::new(vec![0b1_0001], vec![fields::prefix_mask(len)], priority);
+            ids.push(rvh.add_rule(rule).unwrap());
+            assert_eq!(rvh.classify(&p).unwrap().priority(), priority);
+        }
+        assert_eq!(rvh.table_count(), 1);
 
-        let r41 = MockRule::new(vec![0b1, 0b10], vec![0b111, 0b11_111], 7);
-        assert!(rvh.add_rule(r41.clone()));
+        assert_eq!(rvh.maintain(1), 0);
+        assert_eq!(rvh.table_count(), 2);
+        assert_eq!(rvh.classify(&p).unwrap().priority(), 3);
 
-        assert!(rvh.remove_rule(&r31));
-        assert_eq!(rvh.hash_maps[0].highest_priority(), 7);
-        assert_eq!(rvh.hash_maps[1].highest_priority(), 3);
+        // the lower table now holds the best match
+        assert!(rvh.remove(ids[2]).is_ok());
+        assert!(rvh.add_rule(MockRule::new(vec![0b1], vec![0b1], 4)).is_ok());
+        assert_eq!(rvh.classify(&p).unwrap().priority(), 4);
+        assert!(rvh.remove(ids[0]).is_ok());
+        assert_eq!(rvh.table_count(), 2);
 
-        assert!(rvh.remove_rule(&r41));
-        assert_eq!(rvh.hash_maps[0].highest_priority(), 3);
-        assert_eq!(rvh.hash_maps[1].highest_priority(), 1);
+        rvh.set_low_latency(false);
+        assert_eq!(rvh.table_count(), 1);
+        assert_eq!(rvh.classify(&p).unwrap().priority(), 4);
     }
 
     #[test]
-    fn test_add_rule_inserts_rule_into_correct_table() {
+    fn test_dictionary_dimensions_classify_the_same() {
         let mut rvh = RVHClassifier::<MockRule>::new(
-            vec![
-                vec![(0, 3), (0, 3)],
-                vec![(3, 6), (0, 3)],
-                vec![(0, 3), (3, 6)],
-                vec![(3, 6), (3, 6)],
-            ]
-            .into_iter(),
+            vec![vec![(0, 33), (8, 9)], vec![(0, 33), (0, 8)]].into_iter(),
         );
+        let tcp = MockRule::new(vec![1, 6], vec![0xff, 0xff], 1);
+        let udp = MockRule::new(vec![1, 17], vec![0xff, 0xff], 2);
+        let id = rvh.add_rule(tcp.clone()).unwrap();
+        assert!(rvh.add_rule(udp).is_ok());
+        assert!(rvh
+            .add_rule(MockRule::new(vec![2, 0], vec![0xff, 0], 3))
+            .is_ok());
 
-        let r11 = MockRule::new(vec![0b1, 0b10], vec![0b11, 0b1], 1);
-        let r12 = MockRule::new(vec![0b1, 0b10], vec![0b1, 0b11], 2);
-        assert!(rvh.add_rule(r11));
-        assert!(rvh.add_rule(r12));
+        rvh.set_dictionary(1, true);
+        assert_eq!(rvh.dictionary_dimensions().collect::<Vec<_>>(), vec![1]);
+        let classify = |rvh: &RVHClassifier<MockRule>, fields: Vec<Field>| {
+            rvh.classify(&MockPacket::new(fields)).map(|r| r.priority())
+        };
+        assert_eq!(classify(&rvh, vec![1, 6]), Some(1));
+        assert_eq!(classify(&rvh, vec![1, 17]), Some(2));
+        assert_eq!(classify(&rvh, vec![1, 47]), None);
+        assert_eq!(classify(&rvh, vec![2, 47]), Some(3));
 
-        let r21 = MockRule::new(vec![0b1, 0b10], vec![0b111, 0b1], 3);
-        let r22 = MockRule::new(vec![0b1, 0b10], vec![0b11_111, 0b11], 4);
-        assert!(rvh.add_rule(r21));
-        assert!(rvh.add_rule(r22));
+        // codes of removed values are handed out again
+        assert!(rvh.remove(id).is_ok());
+        assert_eq!(classify(&rvh, vec![1, 6]), None);
+        assert!(rvh
+            .add_rule(MockRule::new(vec![1, 47], vec![0xff, 0xff], 4))
+            .is_ok());
+        assert_eq!(classify(&rvh, vec![1, 47]), Some(4));
+        assert!(rvh.find(tcp.fields(), tcp.masks()).is_none());
 
-        let r31 = MockRule::new(vec![0b1, 0b10], vec![0b11, 0b111], 5);
-        let r32 = MockRule::new(vec![0b1, 0b10], vec![0b1, 0b11_111], 6);
-        assert!(rvh.add_rule(r31));
-        assert!(rvh.add_rule(r32));
+        let frozen = rvh.clone().freeze();
+        assert_eq!(
+            frozen
+                .classify(&MockPacket::new(vec![1, 47]))
+                .unwrap()
+                .priority(),
+            4
+        );
+        assert_eq!(frozen.classify(&MockPacket::new(vec![1, 6])), None);
+        let thawed = frozen.thaw();
+        assert_eq!(thawed.dictionary_dimensions().count(), 1);
+        assert_eq!(classify(&thawed, vec![1, 17]), Some(2));
 
-        let r41 = MockRule::new(vec![0b1, 0b10], vec![0b111, 0b11_111], 7);
-        let r42 = MockRule::new(vec![0b1, 0b10], vec![0b11_111, 0b111], 8);
-        assert!(rvh.add_rule(r41));
-        assert!(rvh.add_rule(r42));
+        rvh.set_dictionary(1, false);
+        assert_eq!(classify(&rvh, vec![1, 17]), Some(2));
+        assert_eq!(classify(&rvh, vec![2, 47]), Some(3));
+    }
 
-        let prios: Vec<_> = rvh.hash_maps[0].priorities.iter().collect();
-        assert_eq!(prios, vec![&7, &8]);
+    #[cfg(feature = "rate-limit")]
+    #[test]
+    fn test_rate_limits_police_matches() {
+        use std::time::Duration;
 
-        let prios: Vec<_> = rvh.hash_maps[1].priorities.iter().collect();
-        assert_eq!(prios, vec![&5, &6]);
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 9)]].into_iter());
+        let limited = rvh.add_rule(MockRule::new(vec![1], vec![0xff], 2)).unwrap();
+        let free = rvh.add_rule(MockRule::new(vec![0], vec![0], 1)).unwrap();
+        let limit = RateLimit {
+            rate: 1,
+            burst: 1500,
+        };
+        assert!(rvh.set_rate_limit(limited, Some(limit)));
+        assert_eq!(rvh.rate_limit(limited), Some(limit));
+        assert_eq!(rvh.rate_limit(free), None);
 
-        let prios: Vec<_> = rvh.hash_maps[2].priorities.iter().collect();
-        assert_eq!(prios, vec![&3, &4]);
+        let now = Instant::now();
+        let p = MockPacket::new(vec![1]);
+        let m = rvh.classify_and_count(&p, 1000, now).unwrap();
+        assert_eq!((m.id, m.exceeded), (limited, false));
+        assert!(rvh.classify_and_count(&p, 1000, now).unwrap().exceeded);
+        assert!(!rvh.classify_and_count(&p, 500, now).unwrap().exceeded);
 
-        let prios: Vec<_> = rvh.hash_maps[3].priorities.iter().collect();
-        assert_eq!(prios, vec![&1, &2]);
+        let q = MockPacket::new(vec![2]);
+        let m = rvh.classify_and_count(&q, u64::MAX, now).unwrap();
+        assert_eq!((m.id, m.exceeded), (free, false));
+
+        // the bucket is kept when the rule is changed
+        rvh.get_mut(limited).unwrap().fields_mut().unwrap()[0] = 0x101;
+        let later = now + Duration::from_secs(100);
+        assert!(rvh.classify_and_count(&p, 101, later).unwrap().exceeded);
+        assert!(!rvh.classify_and_count(&p, 100, later).unwrap().exceeded);
+
+        assert!(rvh.set_rate_limit(limited, None));
+        assert!(!rvh.classify_and_count(&p, 1000, later).unwrap().exceeded);
+        assert!(rvh.remove(limited).is_ok());
+        assert!(!rvh.set_rate_limit(limited, Some(limit)));
     }
 
     #[test]
-    fn test_classifier_classifies_correctly() {
+    fn test_add_rules_sorts_tables_once() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 4)], vec![(4, 9)]].into_iter());
+        rvh.set_adaptive(Some(AdaptivePolicy {
+            max_bucket: 2,
+            merge_below: 0,
+            max_tables: 4,
+        }));
+        let results = rvh.add_rules(vec![
+            MockRule::new(vec![0b1], vec![0b1], 1),
+            MockRule::new(vec![0b1_0001], vec![0xff], 5),
+            MockRule::new(vec![0b0], vec![0b1], 1),
+            MockRule::new(vec![0b11], vec![0b11], 2),
+            MockRule::new(vec![0b111], vec![0b111], 3),
+        ]);
+        assert_eq!(results[2], Err(RvhError::DuplicatePriority));
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 4);
+
+        // the crowded bucket of the first table was split at the end
+        assert_eq!(rvh.table_count(), 3);
+        assert!(rvh.check_split().is_valid());
+        let classify = |fields| rvh.classify(&MockPacket::new(fields)).map(|r| r.priority());
+        assert_eq!(classify(vec![0b1_0001]), Some(5));
+        assert_eq!(classify(vec![0b111]), Some(3));
+        assert_eq!(classify(vec![0b1]), Some(1));
+    }
+
+    #[test]
+    fn test_updates_keep_tables_in_probe_order() {
+        let mut rvh = RVHClassifier::<MockRule>::new((0..8).map(|i| vec![(i * 4, i * 4 + 4)]));
+        let in_order = |rvh: &RVHClassifier<MockRule>| {
+            rvh.hash_maps.windows(2).all(|w| {
+                (w[0].enabled, w[0].highest_priority()) >= (w[1].enabled, w[1].highest_priority())
+            })
+        };
+
+        let mut ids = Vec::new();
+        for i in 0..64u32 {
+            let len = i * 7 % 32;
+            let rule = MockRule::new(vec![i], vec![fields::prefix_mask(len)], i * 13 % 61 + 1);
+            ids.extend(rvh.add_rule(rule).ok());
+            assert!(in_order(&rvh));
+            if i % 5 == 0 {
+                assert!(rvh.set_table_enabled((i / 5) as usize % 8, i % 2 == 0));
+                assert!(in_order(&rvh));
+            }
+        }
+        for id in ids.into_iter().step_by(2) {
+            assert!(rvh.remove(id).is_ok());
+            assert!(in_order(&rvh));
+        }
+    }
+
+    #[test]
+    fn test_edits_moving_rules_keep_tables_in_probe_order() {
         let mut rvh = RVHClassifier::<MockRule>::new(
-            vec![
-                vec![(0, 3)],
-                vec![(3, 6)],
-                vec![(6, 9)],
-            ]
-            .into_iter(),
+            vec![vec![(3, 4)], vec![(2, 3)], vec![(0, 2)]].into_iter(),
         );
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b001], vec![0b111], 10))
+            .is_ok());
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b110], vec![0b111], 4))
+            .is_ok());
+        let moved = rvh
+            .add_rule(MockRule::new(vec![0b10], vec![0b11], 8))
+            .unwrap();
+        assert!(rvh.add_rule(MockRule::new(vec![0b0], vec![0b1], 6)).is_ok());
+        assert!(rvh.add_rule(MockRule::new(vec![0], vec![0], 2)).is_ok());
+        let classify = |rvh: &RVHClassifier<MockRule>| {
+            rvh.classify(&MockPacket::new(vec![0b110]))
+                .map(|r| r.priority())
+        };
+        assert_eq!(classify(&rvh), Some(8));
 
-        let r11 = MockRule::new(vec![0b11], vec![0b11], 1);
-        let r12 = MockRule::new(vec![0b1], vec![0b1], 3);
-        rvh.add_rule(r11);
-        rvh.add_rule(r12);
+        // the table left behind is empty and has to move behind the one holding 6
+        *rvh.get_mut(moved).unwrap() = MockRule::new(vec![0b111], vec![0b111], 9);
+        assert_eq!(classify(&rvh), Some(6));
+    }
 
-        let r21 = MockRule::new(vec![0b100], vec![0b111], 4);
-        let r22 = MockRule::new(vec![0b101], vec![0b1_1111], 2);
-        rvh.add_rule(r21);
-        rvh.add_rule(r22);
+    #[test]
+    fn test_pooled_storage_classifies_the_same() {
+        let split = || (0..4).map(|i| vec![(i * 8, i * 8 + 8), (0, 4)]);
+        let mut nested = RVHClassifier::<MockRule>::new(split());
+        let mut pooled = RVHClassifier::<MockRule>::new(split());
+        pooled.set_pooled_storage(true);
 
-        let r31 = MockRule::new(vec![0b11_1001], vec![0b11_1111], 6);
-        let r32 = MockRule::new(vec![0b11_1100], vec![0b1111_1111], 5);
-        rvh.add_rule(r31);
-        rvh.add_rule(r32);
+        let mut ids = Vec::new();
+        for i in 0..300u32 {
+            let len = i * 7 % 32;
+            let fields = vec![i * 37 % 64, i % 3];
+            let rule = MockRule::new(fields, vec![fields::prefix_mask(len), 0b11], i + 1);
+            let id = nested.add_rule(rule.clone());
+            assert_eq!(pooled.add_rule(rule), id);
+            ids.extend(id.ok());
+            if i == 150 {
+                // switching moves the installed rules
+                pooled.set_pooled_storage(false);
+                pooled.set_pooled_storage(true);
+            }
+        }
+        for id in ids.iter().step_by(3) {
+            assert_eq!(nested.remove(*id), pooled.remove(*id));
+        }
+        if let Some(mut rule) = pooled.get_mut(ids[1]) {
+            rule.fields_mut().unwrap()[0] ^= 1;
+        }
+        if let Some(mut rule) = nested.get_mut(ids[1]) {
+            rule.fields_mut().unwrap()[0] ^= 1;
+        }
 
-        let p31 = MockPacket::new(vec![0b11_1001]); // matches r12, r31
-        let p32 = MockPacket::new(vec![0b11_1100]); // matches r21, r32
+        assert_eq!(pooled.len(), nested.len());
+        for a in 0..64 {
+            for b in 0..4 {
+                let p = MockPacket::new(vec![a, b]);
+                assert_eq!(pooled.classify(&p), nested.classify(&p));
+            }
+        }
+        assert_eq!(pooled.rule_set_hash(), nested.rule_set_hash());
+    }
 
-        let p12 = MockPacket::new(vec![0b00_101]); // matches r22, r12
-        let p21 = MockPacket::new(vec![0b00_100]); // matches r21
+    #[test]
+    fn test_hit_ordering_keeps_priorities() {
+        let split = || (0..4).map(|i| vec![(i * 3, i * 3 + 3)]);
+        let mut plain = RVHClassifier::<MockRule>::new(split());
+        let mut hot = RVHClassifier::<MockRule>::new(split());
+        hot.set_hit_ordering(true);
+        // longer prefixes have lower priorities, most packets only match the /1 of table 0
+        for i in 0..40u32 {
+            let len = 1 + i % 11;
+            let rule = MockRule::new(vec![i % 2], vec![fields::prefix_mask(len)], 100 - i);
+            assert_eq!(plain.add_rule(rule.clone()), hot.add_rule(rule));
+        }
+        let exact = MockRule::new(vec![0b1011], vec![0x7ff], 200);
+        assert!(hot.add_rule(exact.clone()).is_ok() && plain.add_rule(exact).is_ok());
 
-        let p_none = MockPacket::new(vec![0b0]);
+        let packets: Vec<_> = (0..4096).map(|a| MockPacket::new(vec![a])).collect();
+        for p in packets.iter() {
+            assert_eq!(hot.classify(p), plain.classify(p));
+        }
+        let hits: Vec<u64> = (0..4).map(|i| hot.table_hits(i).unwrap()).collect();
+        assert_eq!(hits.iter().sum::<u64>(), 4096);
+        assert!(hot.table_hits(4).is_none());
 
-        assert_eq!(rvh.classify(&p31).expect("should match").priority(), 6);
-        assert_eq!(rvh.classify(&p32).expect("should match").priority(), 5);
-        assert_eq!(rvh.classify(&p12).expect("should match").priority(), 3);
-        assert_eq!(rvh.classify(&p21).expect("should match").priority(), 4);
-        assert!(rvh.classify(&p_none).is_none());
+        hot.reorder_by_hits();
+        let order = hot.hit_order.clone().unwrap();
+        let hottest = hot.hash_maps[order[0]].index;
+        assert_eq!(hits[hottest], *hits.iter().max().unwrap());
+        assert_eq!(hot.table_hits(hottest), Some(hits[hottest] / 2));
+        for p in packets.iter() {
+            assert_eq!(hot.classify(p), plain.classify(p));
+        }
+
+        // the order stays with the tables when they are moved
+        let tables = |rvh: &RVHClassifier<MockRule>| -> Vec<usize> {
+            let order = rvh.hit_order.as_ref().unwrap();
+            order.iter().map(|&p| rvh.hash_maps[p].index).collect()
+        };
+        let before = tables(&hot);
+        let top = MockRule::new(vec![0b10_1011], vec![0xff], 250);
+        assert!(hot.add_rule(top.clone()).is_ok() && plain.add_rule(top).is_ok());
+        assert_eq!(hot.hash_maps[0].index, 2);
+        assert_eq!(tables(&hot), before);
+
+        // new tables are probed by priority until the next reorder
+        hot.set_auto_tables(true);
+        plain.set_auto_tables(true);
+        let deep = MockRule::new(vec![0b1011], vec![0x7fff], 300);
+        assert!(hot.add_rule(deep.clone()).is_ok() && plain.add_rule(deep).is_ok());
+        for p in packets.iter().step_by(7) {
+            assert_eq!(hot.classify(p), plain.classify(p));
+        }
+        hot.reorder_by_hits();
+        assert_eq!(hot.hit_order.as_ref().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_explain_follows_classify() {
+        let split = (0..3).map(|i| vec![(i * 4, i * 4 + 4), (0, 5)]);
+        let mut rvh = RVHClassifier::<MockRule>::new(split);
+        // a /2 above everything else, then /5s and /9s
+        rvh.add_rule(MockRule::new(vec![0b01, 3], vec![0b11, 0xf], 100))
+            .unwrap();
+        for i in 0..20u32 {
+            let mask = if i % 2 == 0 { 0x1f } else { 0x1ff };
+            rvh.add_rule(MockRule::new(vec![i * 5, i % 4], vec![mask, 0xf], i + 1))
+                .unwrap();
+        }
+
+        for a in 0..512 {
+            for b in 0..4 {
+                let p = MockPacket::new(vec![a, b]);
+                let explanation = rvh.classify_explain(&p);
+                assert_eq!(explanation.rule, rvh.classify(&p));
+                assert_eq!(explanation.tables.len(), 3);
+            }
+        }
+
+        // the table of the /2 matches first, the others can not do better
+        let p = MockPacket::new(vec![0b01, 3]);
+        let explanation = rvh.classify_explain(&p);
+        assert_eq!(explanation.rule.unwrap().priority(), 100);
+        assert!(matches!(
+            explanation.tables[0].probe,
+            Probe::Bucket {
+                compared: 1,
+                matched: Some(100),
+                ..
+            }
+        ));
+        assert!(explanation.tables[1..]
+            .iter()
+            .all(|t| t.probe == Probe::Skipped));
+        assert_eq!(explanation.compared(), 1);
+        assert!(explanation.to_string().contains("matched priority 100"));
+
+        let p = MockPacket::new(vec![45, 1]);
+        let table = rvh.classify_explain(&p).tables[1].table;
+        rvh.set_table_enabled(table, false);
+        let explanation = rvh.classify_explain(&p);
+        assert_eq!(explanation.rule, rvh.classify(&p));
+        assert_eq!(explanation.tables[2].probe, Probe::Disabled);
+
+        rvh.clear();
+        rvh.add_rule(MockRule::new(vec![45, 1], vec![0x1ff, 0xf], 1))
+            .unwrap();
+        rvh.set_prefilter(0, 9);
+        assert!(
+            !rvh.classify_explain(&MockPacket::new(vec![45, 0]))
+                .prefiltered
+        );
+        assert!(
+            rvh.classify_explain(&MockPacket::new(vec![300, 0]))
+                .prefiltered
+        );
+    }
+
+    #[test]
+    fn test_table_filters_classify_the_same() {
+        let split = || (0..6).map(|i| vec![(i * 5, i * 5 + 5), (0, 4)]);
+        let mut plain = RVHClassifier::<MockRule>::new(split());
+        let mut filtered = RVHClassifier::<MockRule>::new(split());
+        filtered.set_table_filters(true);
+
+        let mut ids = Vec::new();
+        for i in 0..400u32 {
+            let len = i * 7 % 30;
+            let fields = vec![i * 37 % 256, i % 3];
+            let rule = MockRule::new(fields, vec![fields::prefix_mask(len), 0b11], i + 1);
+            let id = plain.add_rule(rule.clone());
+            assert_eq!(filtered.add_rule(rule), id);
+            ids.extend(id.ok());
+        }
+        for id in ids.iter().step_by(3) {
+            assert_eq!(plain.remove(*id), filtered.remove(*id));
+        }
+        filtered.set_dictionary(1, true);
+        filtered.set_pooled_storage(true);
+        assert!(filtered.reseed_table(2, 7));
+        filtered.shrink_to_fit();
+
+        for a in 0..256 {
+            for b in 0..4 {
+                let p = MockPacket::new(vec![a, b]);
+                assert_eq!(filtered.classify(&p), plain.classify(&p));
+            }
+        }
+        let bytes = filtered.memory_usage().total_bytes();
+        filtered.set_table_filters(false);
+        assert!(filtered.memory_usage().total_bytes() < bytes);
+        filtered.set_pooled_storage(false);
+        filtered.shrink_to_fit();
+        plain.shrink_to_fit();
+        assert_eq!(filtered.rule_set_hash(), plain.rule_set_hash());
+    }
+
+    #[test]
+    fn test_prefilter_rejects_packets_without_rules() {
+        let split = || (0..3).map(|i| vec![(i * 6, i * 6 + 6), (0, 3)]);
+        let mut plain = RVHClassifier::<MockRule>::new(split());
+        let mut filtered = RVHClassifier::<MockRule>::new(split());
+        filtered.set_prefilter(0, 8);
+        let misses = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = misses.clone();
+        filtered.set_miss_hook(move |_| {
+            counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        });
+
+        // the filter grows with the rules
+        let mut ids = Vec::new();
+        for i in 0..100u32 {
+            let len = 8 + i % 10;
+            let rule = MockRule::new(
+                vec![i * 4 % 256, 0],
+                vec![fields::prefix_mask(len), 0],
+                i + 1,
+            );
+            let id = plain.add_rule(rule.clone());
+            assert_eq!(filtered.add_rule(rule), id);
+            ids.extend(id.ok());
+        }
+        for id in ids.iter().step_by(4) {
+            assert_eq!(plain.remove(*id), filtered.remove(*id));
+        }
+        *plain.get_mut(ids[1]).unwrap() = MockRule::new(vec![0x1f], vec![0xff], 200);
+        *filtered.get_mut(ids[1]).unwrap() = MockRule::new(vec![0x1f], vec![0xff], 200);
+
+        for a in 0..1024 {
+            let p = MockPacket::new(vec![a, 0]);
+            assert_eq!(filtered.classify(&p), plain.classify(&p));
+            assert_eq!(filtered.classify_first(&p), plain.classify_first(&p));
+        }
+        let missed = (0..1024)
+            .filter(|a| plain.classify(&MockPacket::new(vec![*a, 0])).is_none())
+            .count();
+        assert_eq!(
+            misses.load(std::sync::atomic::Ordering::Relaxed),
+            missed * 2
+        );
+
+        // a rule matching a shorter prefix lets every packet through
+        let p = MockPacket::new(vec![0b1110, 0]);
+        assert!(filtered.classify(&p).is_none());
+        let short = filtered
+            .add_rule(MockRule::new(vec![0b10], vec![0b11], 300))
+            .unwrap();
+        assert!(filtered
+            .add_rule(MockRule::new(vec![0b10], vec![0b11], 300))
+            .is_err());
+        assert!(filtered.may_match(&p));
+        assert!(filtered.remove(short).is_ok());
+        assert!(!filtered.may_match(&p));
+
+        filtered.clear();
+        assert!(!filtered.may_match(&MockPacket::new(vec![0x1f, 0])));
+        filtered.clear_prefilter();
+        assert!(filtered.may_match(&p));
+    }
+
+    #[test]
+    fn test_remove_by_priority() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3)], vec![(3, 6)]].into_iter());
+        let low = rvh
+            .add_rule(MockRule::new(vec![0b1], vec![0b1], 3))
+            .unwrap();
+        let high = rvh
+            .add_rule(MockRule::new(vec![0b101], vec![0b111], 3))
+            .unwrap();
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b110], vec![0b111], 9))
+            .is_ok());
+
+        // the second table is checked first, it has the higher priority rule
+        let removed = rvh.remove_by_priority(3).unwrap();
+        assert_eq!(removed.fields(), &[0b101]);
+        assert!(rvh.get(high).is_none());
+        assert!(rvh.remove_by_priority(3).is_some());
+        assert!(rvh.get(low).is_none());
+        assert!(rvh.remove_by_priority(3).is_none());
+        assert_eq!(rvh.len(), 1);
     }
 }
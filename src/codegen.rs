@@ -0,0 +1,331 @@
+use std::fmt::Write;
+
+use crate::classifier::RVHClassifier;
+use crate::error::RvhError;
+use crate::hash::{calc_hash, MixBuildHasher};
+use crate::range_vector_hash_map::rule_matches;
+use crate::types::*;
+
+// A rule of a `StaticClassifier`, `index` is its position in the rule set it was generated
+// from, f.e. to look up its action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaticRule<F: FieldType = Field> {
+    fields: &'static [F],
+    masks: &'static [F],
+    priority: Priority,
+    index: usize,
+}
+
+impl<F: FieldType> StaticRule<F> {
+    pub const fn new(
+        fields: &'static [F],
+        masks: &'static [F],
+        priority: Priority,
+        index: usize,
+    ) -> Self {
+        Self {
+            fields,
+            masks,
+            priority,
+            index,
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl<F: FieldType> Rule<F> for StaticRule<F> {
+    fn priority(&self) -> Priority {
+        self.priority
+    }
+    fn masks(&self) -> &[F] {
+        self.masks
+    }
+    fn fields(&self) -> &[F] {
+        self.fields
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StaticTable<F: FieldType = Field> {
+    highest_priority: Priority,
+    seed: u32,
+    masks: &'static [F],
+    // (hash, start, len) sorted by hash, the rules of a bucket by descending priority
+    buckets: &'static [(u64, usize, usize)],
+}
+
+impl<F: FieldType> StaticTable<F> {
+    pub const fn new(
+        highest_priority: Priority,
+        seed: u32,
+        masks: &'static [F],
+        buckets: &'static [(u64, usize, usize)],
+    ) -> Self {
+        Self {
+            highest_priority,
+            seed,
+            masks,
+            buckets,
+        }
+    }
+}
+
+// Classifier over a fixed rule set, initialized at compile time from the code `generate`
+// produces, f.e. in a build script of a firmware image. It does not allocate and needs no
+// setup, tables and buckets are laid out and hashed with `MixBuildHasher` in advance.
+#[derive(Debug, Clone, Copy)]
+pub struct StaticClassifier<F: FieldType = Field> {
+    // in probe order, by descending highest priority
+    tables: &'static [StaticTable<F>],
+    rules: &'static [StaticRule<F>],
+}
+
+impl<F: FieldType> StaticClassifier<F> {
+    pub const fn new(tables: &'static [StaticTable<F>], rules: &'static [StaticRule<F>]) -> Self {
+        Self { tables, rules }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn classify(&self, p: &impl Packet<F>) -> Option<&StaticRule<F>> {
+        let mut best: Option<&StaticRule<F>> = None;
+        for table in self.tables {
+            let best_prio = best.map_or(0, |r| r.priority);
+            if table.highest_priority < best_prio {
+                break;
+            }
+
+            let hash = calc_hash(
+                &MixBuildHasher,
+                table.masks,
+                table.seed,
+                &[],
+                p.fields().iter(),
+            );
+            let Some(Ok(i)) = hash.map(|h| table.buckets.binary_search_by_key(&h, |b| b.0)) else {
+                continue;
+            };
+            let (_, start, len) = table.buckets[i];
+            let matched = self.rules[start..start + len]
+                .iter()
+                .take_while(|r| r.priority > best_prio)
+                .find(|r| rule_matches(*r, p));
+            if matched.is_some() {
+                best = matched;
+            }
+        }
+
+        best
+    }
+}
+
+// Rules while planning the layout, remembering their position in the rule set.
+#[derive(Debug, Clone)]
+struct Planned<F> {
+    fields: Vec<F>,
+    masks: Vec<F>,
+    priority: Priority,
+    index: usize,
+}
+
+impl<F: FieldType> PartialEq for Planned<F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<F: FieldType> Rule<F> for Planned<F> {
+    fn priority(&self) -> Priority {
+        self.priority
+    }
+    fn masks(&self) -> &[F] {
+        &self.masks
+    }
+    fn fields(&self) -> &[F] {
+        &self.fields
+    }
+}
+
+// (hash, start, len) of a bucket
+type Span = (u64, usize, usize);
+
+// Tables in probe order and the rules they refer to, as laid out by `StaticClassifier`.
+struct Plan<F> {
+    // highest priority, seed, masks and buckets of every table
+    tables: Vec<(Priority, u32, Vec<F>, Vec<Span>)>,
+    rules: Vec<Planned<F>>,
+}
+
+fn plan<R: Rule<F>, F: FieldType>(
+    split: Vec<Vec<Range>>,
+    rules: &[R],
+) -> Result<Plan<F>, (usize, RvhError)> {
+    let mut classifier: RVHClassifier<Planned<F>, F, (), MixBuildHasher> =
+        RVHClassifier::with_hasher(split.into_iter(), MixBuildHasher);
+    let planned = rules.iter().enumerate().map(|(index, r)| Planned {
+        fields: r.fields().to_vec(),
+        masks: r.masks().to_vec(),
+        priority: r.priority(),
+        index,
+    });
+    if let Some((index, Err(e))) = classifier
+        .add_rules(planned)
+        .into_iter()
+        .enumerate()
+        .find(|(_, result)| result.is_err())
+    {
+        return Err((index, e));
+    }
+
+    let mut tables = Vec::new();
+    let mut planned = Vec::new();
+    for hm in classifier.into_tables() {
+        if hm.is_empty() {
+            continue;
+        }
+        let mut hashes: Vec<u64> = hm.hash_map.iter().map(|(hash, _)| hash).collect();
+        hashes.sort_unstable();
+
+        let mut buckets = Vec::new();
+        for hash in hashes {
+            let mut bucket: Vec<Planned<F>> =
+                hm.hash_map.get(hash).unwrap().iter().cloned().collect();
+            bucket.sort_by_key(|r| std::cmp::Reverse(r.priority));
+            buckets.push((hash, planned.len(), bucket.len()));
+            planned.extend(bucket);
+        }
+        tables.push((hm.highest_priority, hm.seed, hm.masks, buckets));
+    }
+
+    Ok(Plan {
+        tables,
+        rules: planned,
+    })
+}
+
+// Generates Rust code defining `pub static <name>: rvh::codegen::StaticClassifier<F>` for
+// `rules` with the given split, to be written to `OUT_DIR` by a build script and `include!`d.
+// Fails with the position of the first rule that could not be added.
+pub fn generate<R: Rule<F>, F: FieldType>(
+    name: &str,
+    split: Vec<Vec<Range>>,
+    rules: &[R],
+) -> Result<String, (usize, RvhError)> {
+    let Plan { tables, rules } = plan(split, rules)?;
+    let ty = std::any::type_name::<F>();
+
+    let mut out = String::new();
+    let _ = writeln!(out, "// generated by rvh::codegen::generate, do not edit");
+    let _ = writeln!(
+        out,
+        "pub static {}: rvh::codegen::StaticClassifier<{}> = rvh::codegen::StaticClassifier::new(",
+        name, ty
+    );
+    let _ = writeln!(out, "    &[");
+    for (highest_priority, seed, masks, buckets) in tables.iter() {
+        let _ = writeln!(
+            out,
+            "        rvh::codegen::StaticTable::new({}, {}, &{:?}, &{:?}),",
+            highest_priority, seed, masks, buckets
+        );
+    }
+    let _ = writeln!(out, "    ],");
+    let _ = writeln!(out, "    &[");
+    for r in rules.iter() {
+        let _ = writeln!(
+            out,
+            "        rvh::codegen::StaticRule::new(&{:?}, &{:?}, {}, {}),",
+            r.fields, r.masks, r.priority, r.index
+        );
+    }
+    let _ = writeln!(out, "    ],");
+    let _ = writeln!(out, ");");
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::mocks::{MockPacket, MockRule};
+
+    // Builds what the generated code would, with leaked instead of static arrays.
+    fn build(split: Vec<Vec<Range>>, rules: &[MockRule]) -> StaticClassifier {
+        let Plan {
+            tables,
+            rules: planned,
+        } = plan(split, rules).unwrap();
+        let tables: Vec<StaticTable> = tables
+            .into_iter()
+            .map(|(highest_priority, seed, masks, buckets)| {
+                StaticTable::new(
+                    highest_priority,
+                    seed,
+                    Box::leak(masks.into_boxed_slice()),
+                    Box::leak(buckets.into_boxed_slice()),
+                )
+            })
+            .collect();
+        let rules: Vec<StaticRule> = planned
+            .into_iter()
+            .map(|r| {
+                StaticRule::new(
+                    Box::leak(r.fields.into_boxed_slice()),
+                    Box::leak(r.masks.into_boxed_slice()),
+                    r.priority,
+                    r.index,
+                )
+            })
+            .collect();
+        StaticClassifier::new(
+            Box::leak(tables.into_boxed_slice()),
+            Box::leak(rules.into_boxed_slice()),
+        )
+    }
+
+    #[test]
+    fn test_static_classifier_matches_like_the_classifier() {
+        let split = vec![vec![(0, 4), (0, 9)], vec![(4, 9), (0, 9)]];
+        let rules: Vec<MockRule> = (1..=40)
+            .map(|i| {
+                let len = i % 9;
+                MockRule::new(vec![i * 7, i], vec![(1 << len) - 1, 0xf], i)
+            })
+            .collect();
+
+        let mut rvh = RVHClassifier::<MockRule>::new(split.clone().into_iter());
+        for rule in rules.iter() {
+            assert!(rvh.add_rule(rule.clone()).is_ok());
+        }
+        let fixed = build(split.clone(), &rules);
+        assert_eq!(fixed.len(), 40);
+
+        for a in 0..256 {
+            for b in 0..16 {
+                let p = MockPacket::new(vec![a, b]);
+                let matched = fixed.classify(&p).map(|r| rules[r.index()].priority());
+                assert_eq!(matched, rvh.classify(&p).map(|r| r.priority()));
+            }
+        }
+
+        let code = generate("POLICY", split.clone(), &rules).unwrap();
+        assert!(code.contains("pub static POLICY: rvh::codegen::StaticClassifier<u32>"));
+        assert!(code.contains("rvh::codegen::StaticRule::new(&[280, 40], &[15, 15], 40, 39),"));
+
+        // rules without a table are pointed out
+        let mut rules = rules;
+        rules.insert(3, MockRule::new(vec![0], vec![0x3ff], 41));
+        assert_eq!(
+            generate("POLICY", split, &rules),
+            Err((3, RvhError::NoMatchingTable))
+        );
+    }
+}
@@ -0,0 +1,319 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::classifier::RVHClassifier;
+use crate::telemetry::MemberStats;
+use crate::types::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    // the match with the highest priority across all classifiers
+    HighestPriority,
+    // the match of the first classifier, in the order they were added, that has one
+    FirstMatch,
+}
+
+impl MergePolicy {
+    pub fn merge<'a, R: Rule<F>, F: FieldType>(&self, matches: &[Option<&'a R>]) -> Option<&'a R> {
+        match self {
+            MergePolicy::HighestPriority => matches
+                .iter()
+                .flatten()
+                .copied()
+                // on equal priorities the earlier classifier wins
+                .fold(None, |best: Option<&R>, r| match best {
+                    Some(b) if b.priority() >= r.priority() => Some(b),
+                    _ => Some(r),
+                }),
+            MergePolicy::FirstMatch => matches.iter().flatten().copied().next(),
+        }
+    }
+}
+
+// Independent classifiers, f.e. for security, QoS and routing policy, queried with the same
+// packet in one call. Each one keeps its own split and rules. Members may also be the rule
+// sets of different tenants, queried one at a time with `classify_member`, see `stats` and
+// `MultiTenantClassifier` for many tenants.
+#[derive(Debug, Clone)]
+pub struct CompositeClassifier<R: Rule<F>, F: FieldType = Field> {
+    members: Vec<(String, RVHClassifier<R, F>, Counters)>,
+    policy: MergePolicy,
+}
+
+// Relaxed counters, lookups only take `&self`.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    lookups: AtomicU64,
+    hits: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn count<T>(&self, m: Option<T>) -> Option<T> {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        if m.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        m
+    }
+
+    pub(crate) fn stats(&self, rules: usize) -> MemberStats {
+        let lookups = self.lookups.load(Ordering::Relaxed);
+        let hits = self.hits.load(Ordering::Relaxed);
+        MemberStats {
+            lookups,
+            hits,
+            misses: lookups - hits,
+            rules,
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        self.lookups.store(0, Ordering::Relaxed);
+        self.hits.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Clone for Counters {
+    fn clone(&self) -> Self {
+        Self {
+            lookups: AtomicU64::new(self.lookups.load(Ordering::Relaxed)),
+            hits: AtomicU64::new(self.hits.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl<R: Rule<F>, F: FieldType> CompositeClassifier<R, F> {
+    pub fn new(policy: MergePolicy) -> Self {
+        Self {
+            members: Vec::new(),
+            policy,
+        }
+    }
+
+    // Fails if there already is a classifier with this name.
+    pub fn add(&mut self, name: impl Into<String>, classifier: RVHClassifier<R, F>) -> bool {
+        let name = name.into();
+        if self.members.iter().any(|(n, _, _)| *n == name) {
+            return false;
+        }
+
+        self.members.push((name, classifier, Counters::default()));
+        true
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<RVHClassifier<R, F>> {
+        let index = self.members.iter().position(|(n, _, _)| n == name)?;
+        Some(self.members.remove(index).1)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RVHClassifier<R, F>> {
+        self.members
+            .iter()
+            .find(|(n, _, _)| n == name)
+            .map(|(_, c, _)| c)
+    }
+
+    // For updating the rules of a single classifier.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut RVHClassifier<R, F>> {
+        self.members
+            .iter_mut()
+            .find(|(n, _, _)| n == name)
+            .map(|(_, c, _)| c)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.members.iter().map(|(n, _, _)| n.as_str())
+    }
+
+    // Lookups, hits and misses of a member since it was added or `reset_stats`, and its number
+    // of rules, f.e. for per tenant billing or quotas. None if there is no such member.
+    pub fn stats(&self, name: &str) -> Option<MemberStats> {
+        let (_, classifier, counters) = self.members.iter().find(|(n, _, _)| n == name)?;
+        Some(counters.stats(classifier.len()))
+    }
+
+    pub fn reset_stats(&self) {
+        for (_, _, counters) in self.members.iter() {
+            counters.reset();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub fn policy(&self) -> MergePolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: MergePolicy) {
+        self.policy = policy;
+    }
+
+    // The match of every classifier, in the order they were added.
+    pub fn classify_each(&self, p: &impl Packet<F>) -> Vec<Option<&R>> {
+        self.members
+            .iter()
+            .map(|(_, c, counters)| counters.count(c.classify(p)))
+            .collect()
+    }
+
+    // Classifies the packet with a single member, f.e. the classifier of the tenant the packet
+    // belongs to. None if there is no such member or no rule matches.
+    pub fn classify_member(&self, name: &str, p: &impl Packet<F>) -> Option<&R> {
+        let (_, classifier, counters) = self.members.iter().find(|(n, _, _)| n == name)?;
+        counters.count(classifier.classify(p))
+    }
+
+    pub fn classify(&self, p: &impl Packet<F>) -> Option<&R> {
+        self.policy.merge(&self.classify_each(p))
+    }
+
+    // Merges the matches of all classifiers with a custom policy, f.e. to combine the actions
+    // of several matching rules.
+    pub fn classify_with<'a, T>(
+        &'a self,
+        p: &impl Packet<F>,
+        merge: impl FnOnce(&[Option<&'a R>]) -> T,
+    ) -> T {
+        merge(&self.classify_each(p))
+    }
+
+    // Classifies a batch of packets, the fields of each packet are extracted once and shared
+    // by all classifiers.
+    pub fn classify_batch<P: Packet<F>>(&self, packets: &[P]) -> Vec<Option<&R>> {
+        let mut matches = Vec::with_capacity(self.members.len());
+        packets
+            .iter()
+            .map(|p| {
+                let fields = FieldsOf(p.fields());
+                matches.clear();
+                matches.extend(
+                    self.members
+                        .iter()
+                        .map(|(_, c, counters)| counters.count(c.classify(&fields))),
+                );
+                self.policy.merge(&matches)
+            })
+            .collect()
+    }
+}
+
+struct FieldsOf<'a, F>(&'a [F]);
+
+impl<'a, F: FieldType> Packet<F> for FieldsOf<'a, F> {
+    fn fields(&self) -> &[F] {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::mocks::{MockPacket, MockRule};
+
+    fn composite(policy: MergePolicy) -> CompositeClassifier<MockRule> {
+        let mut security = RVHClassifier::new(vec![vec![(0, 4)]].into_iter());
+        assert!(security
+            .add_rule(MockRule::new(vec![0b1], vec![0b1], 2))
+            .is_ok());
+
+        let mut qos = RVHClassifier::new(vec![vec![(0, 4)]].into_iter());
+        assert!(qos
+            .add_rule(MockRule::new(vec![0b11], vec![0b11], 5))
+            .is_ok());
+
+        let mut c = CompositeClassifier::new(policy);
+        assert!(c.add("security", security));
+        assert!(c.add("qos", qos));
+        assert!(!c.add("qos", RVHClassifier::new(vec![vec![(0, 4)]].into_iter())));
+        c
+    }
+
+    #[test]
+    fn test_merge_policies() {
+        let p = MockPacket::new(vec![0b11]);
+
+        let c = composite(MergePolicy::HighestPriority);
+        let each: Vec<_> = c
+            .classify_each(&p)
+            .iter()
+            .map(|m| m.map(|r| r.priority()))
+            .collect();
+        assert_eq!(each, vec![Some(2), Some(5)]);
+        assert_eq!(c.classify(&p).unwrap().priority(), 5);
+
+        let mut c = composite(MergePolicy::FirstMatch);
+        assert_eq!(c.classify(&p).unwrap().priority(), 2);
+        assert_eq!(
+            c.classify(&MockPacket::new(vec![0b10]))
+                .map(|r| r.priority()),
+            None
+        );
+
+        let matched = c.classify_with(&p, |matches| matches.iter().flatten().count());
+        assert_eq!(matched, 2);
+
+        assert!(c.remove("security").is_some());
+        assert_eq!(c.names().collect::<Vec<_>>(), vec!["qos"]);
+        assert!(c
+            .get_mut("qos")
+            .unwrap()
+            .remove_rule(&MockRule::new(vec![0b11], vec![0b11], 5))
+            .is_ok());
+        assert!(c.classify(&p).is_none());
+    }
+
+    #[test]
+    fn test_stats_per_member() {
+        let c = composite(MergePolicy::HighestPriority);
+        assert!(c.classify(&MockPacket::new(vec![0b11])).is_some());
+        assert!(c
+            .classify_member("security", &MockPacket::new(vec![0b1]))
+            .is_some());
+        assert!(c
+            .classify_member("qos", &MockPacket::new(vec![0b1]))
+            .is_none());
+        assert!(c
+            .classify_member("routing", &MockPacket::new(vec![0b1]))
+            .is_none());
+        c.classify_batch(&[MockPacket::new(vec![0b10])]);
+
+        let security = c.stats("security").unwrap();
+        assert_eq!(
+            security,
+            MemberStats {
+                lookups: 3,
+                hits: 2,
+                misses: 1,
+                rules: 1,
+            }
+        );
+        assert_eq!(c.stats("qos").unwrap().hits, 1);
+        assert_eq!(c.stats("qos").unwrap().misses, 2);
+        assert!(c.stats("routing").is_none());
+
+        c.reset_stats();
+        assert_eq!(c.clone().stats("qos").unwrap().lookups, 0);
+    }
+
+    #[test]
+    fn test_classify_batch() {
+        let c = composite(MergePolicy::HighestPriority);
+        let packets = vec![
+            MockPacket::new(vec![0b11]),
+            MockPacket::new(vec![0b01]),
+            MockPacket::new(vec![0b10]),
+        ];
+
+        let matches: Vec<_> = c
+            .classify_batch(&packets)
+            .iter()
+            .map(|m| m.map(|r| r.priority()))
+            .collect();
+        assert_eq!(matches, vec![Some(5), Some(2), None]);
+    }
+}
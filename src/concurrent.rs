@@ -0,0 +1,251 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+
+use crate::classifier::RVHClassifier;
+use crate::error::RvhError;
+use crate::frozen::FrozenRVHClassifier;
+use crate::rebuild::RebuildProgress;
+use crate::types::*;
+
+// A single writer updates a mutable classifier and publishes frozen snapshots of it, which any
+// number of `ConcurrentReader`s classify on without taking a lock. Updates become visible to
+// readers with the next `publish`, so a batch of updates is published at once.
+#[derive(Debug)]
+pub struct ConcurrentRVHClassifier<R: Rule<F>, F: FieldType = Field> {
+    writer: RVHClassifier<R, F>,
+    snapshot: Arc<ArcSwap<FrozenRVHClassifier<R, F>>>,
+    // true if there are updates not published yet
+    dirty: bool,
+}
+
+// Why `ConcurrentRVHClassifier::migrate_split` left the classifier as it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationError {
+    // number of rules the new split has no table for
+    Rejected(usize),
+    // index of the first packet of the sample the new classifier matched with another rule
+    Mismatch(usize),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Rejected(rules) => {
+                write!(f, "the new split has no table for {} rules", rules)
+            }
+            MigrationError::Mismatch(index) => {
+                write!(f, "sample packet {} is classified differently", index)
+            }
+        }
+    }
+}
+
+impl Error for MigrationError {}
+
+#[derive(Debug)]
+pub struct ConcurrentReader<R: Rule<F>, F: FieldType = Field> {
+    snapshot: Arc<ArcSwap<FrozenRVHClassifier<R, F>>>,
+}
+
+impl<R: Rule<F> + Clone, F: FieldType> ConcurrentRVHClassifier<R, F> {
+    pub fn new(classifier: RVHClassifier<R, F>) -> Self {
+        let snapshot = Arc::new(ArcSwap::from_pointee(classifier.clone().freeze()));
+
+        Self {
+            writer: classifier,
+            snapshot,
+            dirty: false,
+        }
+    }
+
+    pub fn reader(&self) -> ConcurrentReader<R, F> {
+        ConcurrentReader {
+            snapshot: self.snapshot.clone(),
+        }
+    }
+
+    pub fn add_rule(&mut self, rule: R) -> Result<RuleId, RvhError> {
+        let id = self.writer.add_rule(rule)?;
+        self.dirty = true;
+        Ok(id)
+    }
+
+    pub fn remove_rule(&mut self, rule: &R) -> Result<(), RvhError> {
+        self.writer.remove_rule(rule)?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: RuleId) -> Result<R, RvhError> {
+        let rule = self.writer.remove(id)?;
+        self.dirty = true;
+        Ok(rule)
+    }
+
+    // The classifier updates are applied to, including the ones not published yet.
+    pub fn writer(&self) -> &RVHClassifier<R, F> {
+        &self.writer
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    // Atomically replaces the snapshot readers classify on. Readers still holding the previous
+    // snapshot keep using it until they load the new one.
+    pub fn publish(&mut self) {
+        if !self.dirty {
+            return;
+        }
+
+        self.snapshot.store(Arc::new(self.writer.clone().freeze()));
+        self.dirty = false;
+    }
+
+    // Moves all rules to a classifier with the new split, `batch` rules at a time, while
+    // readers keep classifying on the published snapshot. `progress` is called after every
+    // batch. The new classifier has to match every packet of `sample` with the same rule as
+    // the current one, otherwise nothing changes. On success it is published at once, together
+    // with updates not published yet.
+    pub fn migrate_split(
+        &mut self,
+        split: Vec<Vec<Range>>,
+        batch: usize,
+        sample: &[impl Packet<F>],
+        mut progress: impl FnMut(RebuildProgress),
+    ) -> Result<(), MigrationError> {
+        let mut rebuild = self.writer.start_rebuild(split);
+        loop {
+            let step = rebuild.step(batch.max(1));
+            progress(step);
+            if step.inserted + rebuild.rejected().len() == step.total {
+                break;
+            }
+        }
+        if !rebuild.rejected().is_empty() {
+            return Err(MigrationError::Rejected(rebuild.rejected().len()));
+        }
+
+        let target = rebuild.target();
+        if let Some(index) = sample
+            .iter()
+            .position(|p| target.lookup_id(p) != self.writer.lookup_id(p))
+        {
+            return Err(MigrationError::Mismatch(index));
+        }
+
+        if self.writer.cutover(rebuild).is_err() {
+            unreachable!("a complete rebuild without rejected rules is cut over");
+        }
+        self.dirty = true;
+        self.publish();
+        Ok(())
+    }
+}
+
+impl<R: Rule<F>, F: FieldType> ConcurrentReader<R, F> {
+    // The current snapshot, which stays valid even if a newer one is published.
+    pub fn snapshot(&self) -> Arc<FrozenRVHClassifier<R, F>> {
+        self.snapshot.load_full()
+    }
+
+    // Classifies on the current snapshot and passes the match to `f`.
+    pub fn classify_with<T>(&self, p: &impl Packet<F>, f: impl FnOnce(Option<&R>) -> T) -> T {
+        f(self.snapshot.load().classify(p))
+    }
+}
+
+impl<R: Rule<F> + Clone, F: FieldType> ConcurrentReader<R, F> {
+    pub fn classify_owned(&self, p: &impl Packet<F>) -> Option<R> {
+        self.snapshot.load().classify_owned(p)
+    }
+}
+
+impl<R: Rule<F>, F: FieldType> Clone for ConcurrentReader<R, F> {
+    fn clone(&self) -> Self {
+        Self {
+            snapshot: self.snapshot.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::mocks::{MockPacket, MockRule};
+
+    #[test]
+    fn test_readers_see_published_updates() {
+        let rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3)], vec![(3, 6)]].into_iter());
+        let mut concurrent = ConcurrentRVHClassifier::new(rvh);
+        let reader = concurrent.reader();
+        let p = MockPacket::new(vec![0b101]);
+
+        let id = concurrent
+            .add_rule(MockRule::new(vec![0b1], vec![0b1], 1))
+            .unwrap();
+        assert!(concurrent.is_dirty());
+        assert!(reader.classify_owned(&p).is_none());
+
+        concurrent.publish();
+        assert!(!concurrent.is_dirty());
+        let before = reader.snapshot();
+        assert_eq!(reader.classify_owned(&p).unwrap().priority(), 1);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let reader = reader.clone();
+                let p = p.clone();
+                std::thread::spawn(move || reader.classify_with(&p, |r| r.is_some()))
+            })
+            .collect();
+        for h in handles {
+            assert!(h.join().unwrap());
+        }
+
+        assert!(concurrent.remove(id).is_ok());
+        assert!(concurrent
+            .add_rule(MockRule::new(vec![0b101], vec![0b111], 2))
+            .is_ok());
+        concurrent.publish();
+        assert_eq!(reader.classify_owned(&p).unwrap().priority(), 2);
+        assert_eq!(concurrent.writer().iter().count(), 1);
+
+        // snapshots taken earlier are not affected
+        assert_eq!(before.classify(&p).unwrap().priority(), 1);
+    }
+
+    #[test]
+    fn test_migrate_split_swaps_after_verifying() {
+        let split = vec![vec![(0, 9)]];
+        let mut rvh = RVHClassifier::<MockRule>::new(split.into_iter());
+        for prio in 1..=8 {
+            assert!(rvh
+                .add_rule(MockRule::new(vec![prio], vec![(1 << prio) - 1], prio))
+                .is_ok());
+        }
+        let mut concurrent = ConcurrentRVHClassifier::new(rvh);
+        let reader = concurrent.reader();
+        let sample: Vec<_> = (0..64).map(|f| MockPacket::new(vec![f])).collect();
+
+        // a split without a table for some of the rules is not migrated to
+        let result = concurrent.migrate_split(vec![vec![(0, 4)]], 3, &sample, |_| {});
+        assert_eq!(result, Err(MigrationError::Rejected(5)));
+        assert_eq!(concurrent.writer().table_count(), 1);
+
+        let mut batches = Vec::new();
+        let split = vec![vec![(0, 4)], vec![(4, 9)]];
+        let result = concurrent.migrate_split(split, 3, &sample, |p| batches.push(p.inserted));
+        assert!(result.is_ok());
+        assert_eq!(batches, vec![3, 6, 8]);
+        assert_eq!(concurrent.writer().table_count(), 2);
+        assert_eq!(reader.snapshot().len(), 8);
+        assert_eq!(
+            reader.classify_owned(&sample[0b1000]).unwrap().priority(),
+            8
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use std::hash::BuildHasher;
+
+use crate::classifier::RVHClassifier;
+use crate::range_vector_hash_map::is_match;
+use crate::types::*;
+
+// How the source rule set picks the rule for a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Semantics {
+    // the first matching rule in list order wins, as in an iptables chain
+    FirstMatch,
+    // the matching rule with the highest priority wins, as in an OVS flow table. Of several
+    // such rules the first one in list order is taken.
+    HighestPriority,
+}
+
+// A rule as written in the source rule set, before it was translated into rvh rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceRule<V, F: FieldType = Field> {
+    // (value, mask) per dimension, missing dimensions match anything
+    pub matches: Vec<(F, F)>,
+    // only used with `Semantics::HighestPriority`
+    pub priority: Priority,
+    pub verdict: V,
+}
+
+impl<V, F: FieldType> SourceRule<V, F> {
+    fn matches(&self, p: &impl Packet<F>) -> bool {
+        self.matches
+            .iter()
+            .enumerate()
+            .all(|(dim, &(value, mask))| {
+                p.fields()
+                    .get(dim)
+                    .is_some_and(|&f| is_match(f, value, mask))
+            })
+    }
+}
+
+// Reference interpreter of a source rule set, evaluating every rule on every packet.
+#[derive(Debug, Clone)]
+pub struct Reference<V, F: FieldType = Field> {
+    rules: Vec<SourceRule<V, F>>,
+    semantics: Semantics,
+    // verdict for packets no rule matches, f.e. the policy of a chain
+    default: V,
+}
+
+impl<V: Clone, F: FieldType> Reference<V, F> {
+    pub fn new(rules: Vec<SourceRule<V, F>>, semantics: Semantics, default: V) -> Self {
+        Self {
+            rules,
+            semantics,
+            default,
+        }
+    }
+
+    pub fn evaluate(&self, p: &impl Packet<F>) -> V {
+        let mut matching = self.rules.iter().filter(|r| r.matches(p));
+        let rule = match self.semantics {
+            Semantics::FirstMatch => matching.next(),
+            Semantics::HighestPriority => {
+                matching.fold(None, |best: Option<&SourceRule<V, F>>, r| match best {
+                    Some(b) if b.priority >= r.priority => Some(b),
+                    _ => Some(r),
+                })
+            }
+        };
+
+        rule.map_or_else(|| self.default.clone(), |r| r.verdict.clone())
+    }
+}
+
+// A packet of the sample the classifier decides differently than the reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch<V> {
+    // index of the packet in the sample
+    pub index: usize,
+    pub expected: V,
+    pub actual: V,
+}
+
+// Classifies every packet of `sample` with the translated rules in `classifier` and with the
+// `reference`, and reports the packets they disagree on. `verdict` maps a matching rule to
+// its verdict, packets without a match get the default verdict of the reference.
+pub fn cross_check<R, F, M, S, V, P>(
+    classifier: &RVHClassifier<R, F, M, S>,
+    reference: &Reference<V, F>,
+    verdict: impl Fn(&R) -> V,
+    sample: &[P],
+) -> Vec<Mismatch<V>>
+where
+    R: Rule<F>,
+    F: FieldType,
+    S: BuildHasher + Clone,
+    V: Clone + PartialEq,
+    P: Packet<F>,
+{
+    sample
+        .iter()
+        .enumerate()
+        .filter_map(|(index, p)| {
+            let expected = reference.evaluate(p);
+            let actual = classifier
+                .classify(p)
+                .map_or_else(|| reference.default.clone(), &verdict);
+
+            if actual == expected {
+                None
+            } else {
+                Some(Mismatch {
+                    index,
+                    expected,
+                    actual,
+                })
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::mocks::{MockPacket, MockRule};
+
+    fn chain() -> Vec<SourceRule<bool>> {
+        vec![
+            // drop 0b10, accept 0bx0
+            SourceRule {
+                matches: vec![(0b10, 0b11)],
+                priority: 0,
+                verdict: false,
+            },
+            SourceRule {
+                matches: vec![(0b0, 0b1)],
+                priority: 0,
+                verdict: true,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_translation_bugs_are_flagged() {
+        let reference = Reference::new(chain(), Semantics::FirstMatch, false);
+        let sample: Vec<_> = (0..4).map(|f| MockPacket::new(vec![f])).collect();
+
+        // priorities follow the chain order, the first rule is the most important one
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 33)]].into_iter());
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b10], vec![0b11], 2))
+            .is_ok());
+        assert!(rvh.add_rule(MockRule::new(vec![0b0], vec![0b1], 1)).is_ok());
+        let verdict = |r: &MockRule| r.priority() == 1;
+        assert!(cross_check(&rvh, &reference, verdict, &sample).is_empty());
+
+        // a translation reversing the priorities accepts 0b10
+        let mut reversed = RVHClassifier::<MockRule>::new(vec![vec![(0, 33)]].into_iter());
+        assert!(reversed
+            .add_rule(MockRule::new(vec![0b10], vec![0b11], 1))
+            .is_ok());
+        assert!(reversed
+            .add_rule(MockRule::new(vec![0b0], vec![0b1], 2))
+            .is_ok());
+        let verdict = |r: &MockRule| r.priority() == 2;
+        assert_eq!(
+            cross_check(&reversed, &reference, verdict, &sample),
+            vec![Mismatch {
+                index: 2,
+                expected: false,
+                actual: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_highest_priority_semantics() {
+        let mut rules = chain();
+        rules[1].priority = 5;
+        let reference = Reference::new(rules, Semantics::HighestPriority, false);
+
+        assert!(reference.evaluate(&MockPacket::new(vec![0b10])));
+        assert!(reference.evaluate(&MockPacket::new(vec![0b0])));
+        assert!(!reference.evaluate(&MockPacket::new(vec![0b1])));
+    }
+}
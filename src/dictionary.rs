@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+use crate::types::FieldType;
+
+// Small codes for the masked values of a dimension with few distinct values, hashed in place
+// of the values, see `RVHClassifier::set_dictionary`. Codes of values no rule uses anymore
+// are handed out again.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct Dictionary<F: FieldType> {
+    // code and number of rules per value
+    codes: HashMap<F, (u32, usize)>,
+    free: Vec<u32>,
+}
+
+impl<F: FieldType> Dictionary<F> {
+    pub fn new() -> Self {
+        Self {
+            codes: HashMap::new(),
+            free: Vec::new(),
+        }
+    }
+
+    // None if no rule uses the value, so no rule can match it either.
+    pub fn code(&self, value: F) -> Option<u32> {
+        self.codes.get(&value).map(|(code, _)| *code)
+    }
+
+    pub fn acquire(&mut self, value: F) -> u32 {
+        let next = self.codes.len() as u32;
+        let free = &mut self.free;
+        let entry = self
+            .codes
+            .entry(value)
+            .or_insert_with(|| (free.pop().unwrap_or(next), 0));
+        entry.1 += 1;
+        entry.0
+    }
+
+    pub fn release(&mut self, value: F) {
+        if let Some((code, count)) = self.codes.get_mut(&value) {
+            *count -= 1;
+            if *count == 0 {
+                self.free.push(*code);
+                self.codes.remove(&value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_are_reused() {
+        let mut dictionary = Dictionary::<u32>::new();
+        assert_eq!(dictionary.acquire(6), 0);
+        assert_eq!(dictionary.acquire(17), 1);
+        assert_eq!(dictionary.acquire(6), 0);
+
+        dictionary.release(6);
+        assert_eq!(dictionary.code(6), Some(0));
+        dictionary.release(6);
+        assert_eq!(dictionary.code(6), None);
+
+        assert_eq!(dictionary.acquire(1), 0);
+        assert_eq!(dictionary.acquire(47), 2);
+        assert_eq!(dictionary.code(17), Some(1));
+    }
+}
@@ -0,0 +1,47 @@
+use std::borrow::Cow;
+
+// Human-readable name and bit width of a dimension, used to describe tables and rules in
+// diagnostics.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dimension {
+    name: String,
+    width: u32,
+}
+
+impl Dimension {
+    pub fn new(name: impl Into<String>, width: u32) -> Self {
+        Self {
+            name: name.into(),
+            width,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+}
+
+// Name of the dimension at `index`, falls back to "field <index>" for unnamed dimensions.
+pub(crate) fn label(dimensions: &[Dimension], index: usize) -> Cow<'_, str> {
+    match dimensions.get(index) {
+        Some(d) => Cow::Borrowed(d.name()),
+        None => Cow::Owned(format!("field {}", index)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label_falls_back_to_index() {
+        let dims = vec![Dimension::new("src_ip", 32)];
+        assert_eq!(label(&dims, 0), "src_ip");
+        assert_eq!(label(&dims, 3), "field 3");
+    }
+}
@@ -0,0 +1,228 @@
+use std::hash::BuildHasher;
+
+use crate::classifier::RVHClassifier;
+use crate::range_vector_hash_map::is_match;
+use crate::types::*;
+
+// A packet two rule sets decide differently, with the verdict of either side, None if no
+// rule matches it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference<V, F = Field> {
+    // encoded fields, one per dimension
+    pub packet: Vec<F>,
+    pub left: Option<V>,
+    pub right: Option<V>,
+}
+
+// Decides whether two rule sets classify every packet with fields of the given `widths` alike,
+// i.e. the matching rules of highest priority have the same verdict, f.e. after merging or
+// splitting rules. Instead of enumerating packets the field space is cut into the regions the
+// prefixes of the rules induce, one dimension at a time, and every region is checked with a
+// single packet. A region is not cut any further once the rules deciding it on both sides
+// match all of it. Fails with a packet of the first region that is decided differently.
+pub fn check<'a, R, F, V>(
+    left: impl IntoIterator<Item = &'a R>,
+    right: impl IntoIterator<Item = &'a R>,
+    widths: &[u32],
+    verdict: impl Fn(&R) -> V,
+) -> Result<(), Difference<V, F>>
+where
+    R: Rule<F> + 'a,
+    F: FieldType,
+    V: PartialEq,
+{
+    // highest priority first, ties are broken by the order of the rules
+    let sorted = |rules: Vec<&'a R>| {
+        let mut rules: Vec<_> = rules.into_iter().filter(|r| r.priority() > 0).collect();
+        rules.sort_by_key(|r| std::cmp::Reverse(r.priority()));
+        rules
+    };
+    let check = Check {
+        widths,
+        verdict: &verdict,
+    };
+    let mut packet = vec![F::ZERO; widths.len()];
+    check.regions(
+        0,
+        &sorted(left.into_iter().collect()),
+        &sorted(right.into_iter().collect()),
+        &mut packet,
+    )
+}
+
+impl<R: Rule<F>, F: FieldType, M, S: BuildHasher + Clone> RVHClassifier<R, F, M, S> {
+    // See `equivalence::check`, rules of the same priority in different tables are compared in
+    // an unspecified order.
+    pub fn equivalent<M2, S2: BuildHasher + Clone, V: PartialEq>(
+        &self,
+        other: &RVHClassifier<R, F, M2, S2>,
+        widths: &[u32],
+        verdict: impl Fn(&R) -> V,
+    ) -> Result<(), Difference<V, F>> {
+        check(self.iter(), other.iter(), widths, verdict)
+    }
+}
+
+struct Check<'a, V, R> {
+    widths: &'a [u32],
+    verdict: &'a dyn Fn(&R) -> V,
+}
+
+impl<V: PartialEq, R> Check<'_, V, R> {
+    // `left` and `right` are the rules matching the region fixed by `packet[..dimension]`.
+    fn regions<'r, F: FieldType>(
+        &self,
+        dimension: usize,
+        left: &[&'r R],
+        right: &[&'r R],
+        packet: &mut Vec<F>,
+    ) -> Result<(), Difference<V, F>>
+    where
+        R: Rule<F>,
+    {
+        let decided = |rules: &[&R]| {
+            rules.first().is_none_or(|r| {
+                (dimension..self.widths.len()).all(|dim| field(*r, dim).1 == F::ZERO)
+            })
+        };
+        if decided(left) && decided(right) {
+            let left = left.first().map(|r| (self.verdict)(r));
+            let right = right.first().map(|r| (self.verdict)(r));
+            if left == right {
+                return Ok(());
+            }
+            return Err(Difference {
+                packet: packet.clone(),
+                left,
+                right,
+            });
+        }
+
+        let mut prefixes: Vec<(F, u32)> = left
+            .iter()
+            .chain(right.iter())
+            .map(|r| {
+                let (value, mask) = field(*r, dimension);
+                (value & mask, mask.count_ones())
+            })
+            .chain(std::iter::once((F::ZERO, 0)))
+            .collect();
+        prefixes.sort_by_key(|(_, len)| *len);
+        prefixes.dedup();
+
+        // every prefix minus the longer prefixes below it is a region
+        for &(value, len) in prefixes.iter() {
+            let below: Vec<(F, u32)> = prefixes
+                .iter()
+                .filter(|(v, l)| *l > len && is_match(*v, value, mask(len)))
+                .copied()
+                .collect();
+            let Some(representative) = representative(value, len, &below, self.widths[dimension])
+            else {
+                continue;
+            };
+
+            packet[dimension] = representative;
+            let matching = |rules: &[&'r R]| -> Vec<&'r R> {
+                rules
+                    .iter()
+                    .filter(|r| {
+                        let (value, mask) = field(**r, dimension);
+                        is_match(representative, value, mask)
+                    })
+                    .copied()
+                    .collect()
+            };
+            self.regions(dimension + 1, &matching(left), &matching(right), packet)?;
+        }
+        packet[dimension] = F::ZERO;
+
+        Ok(())
+    }
+}
+
+// Dimensions a rule does not have match anything.
+fn field<R: Rule<F>, F: FieldType>(rule: &R, dimension: usize) -> (F, F) {
+    match (rule.fields().get(dimension), rule.masks().get(dimension)) {
+        (Some(value), Some(mask)) => (*value, *mask),
+        _ => (F::ZERO, F::ZERO),
+    }
+}
+
+fn mask<F: FieldType>(len: u32) -> F {
+    if len >= F::BITS {
+        !F::ZERO
+    } else {
+        !(!F::ZERO << len)
+    }
+}
+
+// A value of the prefix that none of the longer prefixes `below` covers.
+fn representative<F: FieldType>(value: F, len: u32, below: &[(F, u32)], width: u32) -> Option<F> {
+    if below.is_empty() {
+        return Some(value);
+    }
+    if len >= width || below.iter().any(|(_, l)| *l == len) {
+        return None;
+    }
+
+    [F::ZERO, F::ONE << len].iter().find_map(|bit| {
+        let value = value | *bit;
+        let below: Vec<(F, u32)> = below
+            .iter()
+            .filter(|(v, _)| is_match(*v, value, mask(len + 1)))
+            .copied()
+            .collect();
+        representative(value, len + 1, &below, width)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+    use crate::rules::FiveTupleRule;
+    use crate::types::ActionRule;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_split_rules_are_equivalent() {
+        let widths: Vec<u32> = presets::five_tuple_dimensions()
+            .iter()
+            .map(|d| d.width())
+            .collect();
+        let net = |a, len| (Ipv4Addr::new(10, a, 0, 0), len);
+        let ssh = |priority, (addr, len)| {
+            FiveTupleRule::new(priority)
+                .src_prefix(addr, len)
+                .dst_port(22)
+                .with_action("accept")
+        };
+        let deny = FiveTupleRule::new(1).with_action("drop");
+
+        let before = vec![ssh(5, net(0, 8)), deny.clone()];
+        let after = vec![ssh(3, net(0, 9)), ssh(2, net(128, 9)), deny.clone()];
+        let verdict = |r: &FiveTupleRule<&'static str>| *r.action();
+        assert_eq!(check(&before, &after, &widths, verdict), Ok(()));
+        // redundant rules do not change anything either
+        let shadowed = vec![ssh(5, net(0, 8)), ssh(4, net(7, 16)), deny.clone()];
+        assert_eq!(check(&before, &shadowed, &widths, verdict), Ok(()));
+
+        let mut rvh = RVHClassifier::five_tuple();
+        let mut missing = RVHClassifier::five_tuple();
+        for rule in before.iter() {
+            assert!(rvh.add_rule(rule.clone()).is_ok());
+        }
+        for rule in after.iter().skip(1) {
+            assert!(missing.add_rule(rule.clone()).is_ok());
+        }
+        let difference = rvh.equivalent(&missing, &widths, verdict).unwrap_err();
+        assert_eq!(
+            (difference.left, difference.right),
+            (Some("accept"), Some("drop"))
+        );
+        let p = crate::generate::GeneratedPacket(difference.packet);
+        assert_eq!(*rvh.classify(&p).unwrap().action(), "accept");
+        assert_eq!(*missing.classify(&p).unwrap().action(), "drop");
+    }
+}
@@ -0,0 +1,59 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::telemetry::RejectionReason;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RvhError {
+    // another rule in the same table already uses the priority
+    DuplicatePriority,
+    // no table accepts the prefix lengths of the rule
+    NoMatchingTable,
+    // the rule has a different number of fields and masks
+    ArityMismatch { fields: usize, masks: usize },
+    // the mask of a dimension is not a right-aligned prefix
+    InvalidMask { dimension: usize },
+    // the priority is reserved for a priority band, or outside of the band the rule was
+    // added to
+    PriorityBand,
+    // the priority allocator has no priorities left
+    PrioritiesExhausted,
+    // the rule is not installed
+    NotFound,
+}
+
+impl RvhError {
+    // The category a rejected insertion is counted under, None for errors not caused by an
+    // insertion.
+    pub fn rejection_reason(&self) -> Option<RejectionReason> {
+        match self {
+            RvhError::DuplicatePriority => Some(RejectionReason::DuplicatePriority),
+            RvhError::NoMatchingTable => Some(RejectionReason::NoMatchingTable),
+            RvhError::ArityMismatch { .. }
+            | RvhError::InvalidMask { .. }
+            | RvhError::PriorityBand
+            | RvhError::PrioritiesExhausted => Some(RejectionReason::Validation),
+            RvhError::NotFound => None,
+        }
+    }
+}
+
+impl fmt::Display for RvhError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RvhError::DuplicatePriority => write!(f, "priority is already in use"),
+            RvhError::NoMatchingTable => write!(f, "no table accepts the prefix lengths"),
+            RvhError::ArityMismatch { fields, masks } => {
+                write!(f, "rule has {} fields but {} masks", fields, masks)
+            }
+            RvhError::InvalidMask { dimension } => {
+                write!(f, "mask of dimension {} is not a prefix", dimension)
+            }
+            RvhError::PriorityBand => write!(f, "priority violates a priority band"),
+            RvhError::PrioritiesExhausted => write!(f, "no priorities left"),
+            RvhError::NotFound => write!(f, "rule is not installed"),
+        }
+    }
+}
+
+impl Error for RvhError {}
@@ -0,0 +1,616 @@
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::fields::{
+    encode, encode_wide, BigEndianField, HostField, DSCP_WIDTH, IPV6_WIDTH, PORT_WIDTH,
+    PROTOCOL_WIDTH, TUNNEL_ID_WIDTH,
+};
+use crate::types::{Field, Packet, TryPacket};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FiveTuple {
+    fields: [Field; 6],
+    len: usize,
+}
+
+impl FiveTuple {
+    pub fn new(src: Ipv4Addr, dst: Ipv4Addr, src_port: u16, dst_port: u16, protocol: u8) -> Self {
+        Self {
+            fields: [
+                HostField::from(src).encode(),
+                HostField::from(dst).encode(),
+                HostField::new(u32::from(src_port), PORT_WIDTH).encode(),
+                HostField::new(u32::from(dst_port), PORT_WIDTH).encode(),
+                HostField::new(u32::from(protocol), PROTOCOL_WIDTH).encode(),
+                0,
+            ],
+            len: 5,
+        }
+    }
+
+    // Same as `new` from header bytes in network byte order, as they appear in the packet.
+    pub fn from_be_bytes(
+        src: [u8; 4],
+        dst: [u8; 4],
+        src_port: [u8; 2],
+        dst_port: [u8; 2],
+        protocol: u8,
+    ) -> Self {
+        Self {
+            fields: [
+                BigEndianField::from(src).encode(),
+                BigEndianField::from(dst).encode(),
+                BigEndianField::from(src_port).encode(),
+                BigEndianField::from(dst_port).encode(),
+                BigEndianField::from([protocol]).encode(),
+                0,
+            ],
+            len: 5,
+        }
+    }
+
+    // Adds the DSCP value as sixth field, required for classifiers built from
+    // `presets::five_tuple_dscp`.
+    pub fn with_dscp(mut self, dscp: u8) -> Self {
+        debug_assert!(dscp < 64);
+        self.fields[5] = encode(u32::from(dscp), DSCP_WIDTH);
+        self.len = 6;
+        self
+    }
+
+    // The DSCP occupies the upper six bits of the IPv4 ToS / IPv6 traffic class byte.
+    pub fn with_tos(self, tos: u8) -> Self {
+        self.with_dscp(tos >> 2)
+    }
+}
+
+impl Packet for FiveTuple {
+    fn fields(&self) -> &[Field] {
+        &self.fields[..self.len]
+    }
+}
+
+// IPv6 5-tuple with `u128` fields, matching the layout of `presets::ipv6_five_tuple`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv6FiveTuple {
+    fields: [u128; 5],
+}
+
+impl Ipv6FiveTuple {
+    pub fn new(src: Ipv6Addr, dst: Ipv6Addr, src_port: u16, dst_port: u16, protocol: u8) -> Self {
+        Self {
+            fields: [
+                encode_wide(u128::from(src), IPV6_WIDTH),
+                encode_wide(u128::from(dst), IPV6_WIDTH),
+                encode_wide(u128::from(src_port), PORT_WIDTH),
+                encode_wide(u128::from(dst_port), PORT_WIDTH),
+                encode_wide(u128::from(protocol), PROTOCOL_WIDTH),
+            ],
+        }
+    }
+}
+
+impl Packet<u128> for Ipv6FiveTuple {
+    fn fields(&self) -> &[u128] {
+        &self.fields
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tunnel {
+    Vxlan { vni: u32 },
+    Geneve { vni: u32 },
+    Gtp { teid: u32 },
+}
+
+impl Tunnel {
+    pub fn id(&self) -> u32 {
+        match *self {
+            Tunnel::Vxlan { vni } | Tunnel::Geneve { vni } => {
+                debug_assert!(vni < (1 << 24));
+                vni
+            }
+            Tunnel::Gtp { teid } => teid,
+        }
+    }
+}
+
+// Fields of encapsulated traffic: the outer tunnel id followed by the inner 5-tuple, matching
+// the layout of `presets::tunnel_five_tuple`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TunneledFiveTuple {
+    fields: [Field; 7],
+    len: usize,
+}
+
+impl TunneledFiveTuple {
+    pub fn new(tunnel: Tunnel, inner: FiveTuple) -> Self {
+        let mut fields = [0; 7];
+        fields[0] = encode(tunnel.id(), TUNNEL_ID_WIDTH);
+        fields[1..=inner.len].copy_from_slice(inner.fields());
+
+        Self {
+            fields,
+            len: inner.len + 1,
+        }
+    }
+}
+
+impl Packet for TunneledFiveTuple {
+    fn fields(&self) -> &[Field] {
+        &self.fields[..self.len]
+    }
+}
+
+// Why the 5-tuple of a packet could not be extracted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtractError {
+    // the header ends before the fields
+    Truncated,
+    NotIpv4,
+    // an IPv6 ethertype in front of a header of another version, see `parse::parse`
+    NotIpv6,
+    // only TCP, UDP and SCTP have ports
+    UnsupportedProtocol(u8),
+    // fragments after the first one carry no transport header
+    Fragment,
+    // the frame carries neither IPv4 nor IPv6, see `parse::parse`
+    UnsupportedEtherType(u16),
+    // an IPv4 header length below the minimum of 5 words
+    InvalidHeaderLength(u8),
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtractError::Truncated => write!(f, "truncated header"),
+            ExtractError::NotIpv4 => write!(f, "not an IPv4 packet"),
+            ExtractError::NotIpv6 => write!(f, "not an IPv6 packet"),
+            ExtractError::UnsupportedProtocol(p) => write!(f, "protocol {} has no ports", p),
+            ExtractError::Fragment => write!(f, "non-initial fragment"),
+            ExtractError::UnsupportedEtherType(t) => write!(f, "ethertype {:#06x} is not IP", t),
+            ExtractError::InvalidHeaderLength(ihl) => write!(f, "invalid header length {}", ihl),
+        }
+    }
+}
+
+impl std::error::Error for ExtractError {}
+
+// An IPv4 packet as received, starting with the IP header, see `RVHClassifier::classify_try`.
+// Also takes the IP header slice of other parsers, f.e. of an `etherparse::SlicedPacket`. There
+// is no etherparse feature yet, the crate is not available to this build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Bytes<'a>(pub &'a [u8]);
+
+impl TryPacket for Ipv4Bytes<'_> {
+    type Packet = FiveTuple;
+    type Error = ExtractError;
+
+    fn try_packet(&self) -> Result<FiveTuple, ExtractError> {
+        let bytes = self.0;
+        let byte = |i: usize| bytes.get(i).copied().ok_or(ExtractError::Truncated);
+        let array = |i: usize| {
+            let mut a = [0; 4];
+            a.copy_from_slice(bytes.get(i..i + 4).ok_or(ExtractError::Truncated)?);
+            Ok(a)
+        };
+
+        if byte(0)? >> 4 != 4 {
+            return Err(ExtractError::NotIpv4);
+        }
+        let ihl = byte(0)? & 0xf;
+        if ihl < 5 {
+            return Err(ExtractError::InvalidHeaderLength(ihl));
+        }
+        let header_len = usize::from(ihl) * 4;
+        let protocol = byte(9)?;
+        if !matches!(protocol, 6 | 17 | 132) {
+            return Err(ExtractError::UnsupportedProtocol(protocol));
+        }
+        if u16::from_be_bytes([byte(6)?, byte(7)?]) & 0x1fff != 0 {
+            return Err(ExtractError::Fragment);
+        }
+
+        let ports = array(header_len)?;
+        let tuple = FiveTuple::from_be_bytes(
+            array(12)?,
+            array(16)?,
+            [ports[0], ports[1]],
+            [ports[2], ports[3]],
+            protocol,
+        );
+        Ok(tuple.with_tos(byte(1)?))
+    }
+}
+
+// Frames and packets parsed by `pnet_packet`, f.e. from a capture, classified without
+// extracting the fields by hand. Only untagged Ethernet frames carrying IPv4 are followed, the
+// TCP, UDP or SCTP ports are read from the IP payload as with `Ipv4Bytes`. TCP and UDP views
+// on their own lack the IP header, see `Ipv4Transport`.
+#[cfg(feature = "pnet")]
+mod pnet {
+    use pnet_packet::ethernet::{EtherTypes, EthernetPacket};
+    use pnet_packet::ipv4::Ipv4Packet;
+    use pnet_packet::tcp::TcpPacket;
+    use pnet_packet::udp::UdpPacket;
+    use pnet_packet::Packet as _;
+
+    use super::*;
+
+    // A TCP or UDP view with the fields of the IPv4 header it was carried in, f.e. when the
+    // transport header was parsed on its own.
+    #[derive(Debug)]
+    pub struct Ipv4Transport<T> {
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        tos: u8,
+        header: T,
+    }
+
+    impl<T> Ipv4Transport<T> {
+        pub fn new(src: Ipv4Addr, dst: Ipv4Addr, tos: u8, header: T) -> Self {
+            Self {
+                src,
+                dst,
+                tos,
+                header,
+            }
+        }
+
+        pub fn header(&self) -> &T {
+            &self.header
+        }
+    }
+
+    impl TryPacket for Ipv4Transport<TcpPacket<'_>> {
+        type Packet = FiveTuple;
+        type Error = ExtractError;
+
+        fn try_packet(&self) -> Result<FiveTuple, ExtractError> {
+            let (sport, dport) = (self.header.get_source(), self.header.get_destination());
+            Ok(FiveTuple::new(self.src, self.dst, sport, dport, 6).with_tos(self.tos))
+        }
+    }
+
+    impl TryPacket for Ipv4Transport<UdpPacket<'_>> {
+        type Packet = FiveTuple;
+        type Error = ExtractError;
+
+        fn try_packet(&self) -> Result<FiveTuple, ExtractError> {
+            let (sport, dport) = (self.header.get_source(), self.header.get_destination());
+            Ok(FiveTuple::new(self.src, self.dst, sport, dport, 17).with_tos(self.tos))
+        }
+    }
+
+    impl TryPacket for Ipv4Packet<'_> {
+        type Packet = FiveTuple;
+        type Error = ExtractError;
+
+        fn try_packet(&self) -> Result<FiveTuple, ExtractError> {
+            Ipv4Bytes(self.packet()).try_packet()
+        }
+    }
+
+    impl TryPacket for EthernetPacket<'_> {
+        type Packet = FiveTuple;
+        type Error = ExtractError;
+
+        fn try_packet(&self) -> Result<FiveTuple, ExtractError> {
+            if self.get_ethertype() != EtherTypes::Ipv4 {
+                return Err(ExtractError::NotIpv4);
+            }
+            Ipv4Bytes(self.payload()).try_packet()
+        }
+    }
+}
+
+#[cfg(feature = "pnet")]
+pub use self::pnet::Ipv4Transport;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields;
+    use crate::presets;
+    use crate::types::mocks::MockRule;
+    use crate::types::Rule;
+    use crate::{RVHClassifier, TryMatch};
+
+    fn rule(parts: &[(Field, u32)], priority: u32) -> MockRule {
+        MockRule::new(
+            parts.iter().map(|p| p.0).collect(),
+            parts.iter().map(|p| p.1).collect(),
+            priority,
+        )
+    }
+
+    #[test]
+    fn test_five_tuple_fields() {
+        let t = FiveTuple::new(
+            Ipv4Addr::new(10, 0, 0, 1),
+            Ipv4Addr::new(192, 168, 1, 1),
+            1234,
+            443,
+            6,
+        );
+        assert_eq!(t.fields().len(), 5);
+        assert_eq!(t.with_dscp(46).fields().len(), 6);
+        assert_eq!(t.with_tos(46 << 2), t.with_dscp(46));
+
+        let wire = FiveTuple::from_be_bytes(
+            [10, 0, 0, 1],
+            [192, 168, 1, 1],
+            1234u16.to_be_bytes(),
+            443u16.to_be_bytes(),
+            6,
+        );
+        assert_eq!(wire, t);
+    }
+
+    #[test]
+    fn test_unparseable_packets_are_not_misses() {
+        let mut rvh = RVHClassifier::<MockRule>::new(presets::five_tuple().into_iter());
+        // any TCP traffic
+        let any_tcp = rule(
+            &[
+                fields::wildcard(),
+                fields::wildcard(),
+                fields::wildcard(),
+                fields::wildcard(),
+                fields::protocol(6),
+            ],
+            1,
+        );
+        assert!(rvh.add_rule(any_tcp).is_ok());
+
+        let mut header = vec![0x45, 46 << 2, 0, 48, 0, 0, 0x40, 0, 64, 6, 0, 0];
+        header.extend([10, 0, 0, 1, 192, 168, 1, 1]);
+        header.extend([0x04, 0xd2, 0x01, 0xbb]);
+        let expected = FiveTuple::new(
+            Ipv4Addr::new(10, 0, 0, 1),
+            Ipv4Addr::new(192, 168, 1, 1),
+            1234,
+            443,
+            6,
+        );
+        assert_eq!(Ipv4Bytes(&header).try_packet(), Ok(expected.with_dscp(46)));
+        assert_eq!(
+            rvh.classify_try(&Ipv4Bytes(&header))
+                .rule()
+                .unwrap()
+                .priority(),
+            1
+        );
+
+        let truncated = Ipv4Bytes(&header[..22]);
+        assert_eq!(
+            rvh.classify_try(&truncated),
+            TryMatch::Unparseable(ExtractError::Truncated)
+        );
+        let mut udp = header.clone();
+        udp[9] = 17;
+        assert_eq!(rvh.classify_try(&Ipv4Bytes(&udp)), TryMatch::Missed);
+        // without ports ICMP would have matched rules on port 0
+        let mut icmp = header.clone();
+        icmp[9] = 1;
+        assert_eq!(
+            rvh.classify_try(&Ipv4Bytes(&icmp)),
+            TryMatch::Unparseable(ExtractError::UnsupportedProtocol(1))
+        );
+        let mut fragment = header.clone();
+        fragment[7] = 1;
+        assert_eq!(
+            Ipv4Bytes(&fragment).try_packet(),
+            Err(ExtractError::Fragment)
+        );
+        assert_eq!(Ipv4Bytes(&[0x60]).try_packet(), Err(ExtractError::NotIpv4));
+        let mut short = header.clone();
+        short[0] = 0x44;
+        assert_eq!(
+            Ipv4Bytes(&short).try_packet(),
+            Err(ExtractError::InvalidHeaderLength(4))
+        );
+    }
+
+    #[cfg(feature = "pnet")]
+    #[test]
+    fn test_pnet_frames_are_classified() {
+        use pnet_packet::ethernet::EthernetPacket;
+        use pnet_packet::ipv4::Ipv4Packet;
+        use pnet_packet::tcp::TcpPacket;
+        use pnet_packet::udp::UdpPacket;
+
+        let mut rvh = RVHClassifier::<MockRule>::five_tuple();
+        let https = rule(
+            &[
+                fields::wildcard(),
+                fields::ipv4_prefix(Ipv4Addr::new(192, 168, 0, 0), 16),
+                fields::wildcard(),
+                fields::port(443),
+                fields::protocol(6),
+            ],
+            1,
+        );
+        assert!(rvh.add_rule(https).is_ok());
+
+        let mut frame = vec![0xff; 12];
+        frame.extend([0x08, 0x00]);
+        frame.extend([0x45, 0, 0, 48, 0, 0, 0x40, 0, 64, 6, 0, 0]);
+        frame.extend([10, 0, 0, 1, 192, 168, 1, 1]);
+        frame.extend([0x04, 0xd2, 0x01, 0xbb]);
+        // the rest of the TCP header
+        frame.extend([0; 16]);
+
+        let ethernet = EthernetPacket::new(&frame).unwrap();
+        assert_eq!(rvh.classify_try(&ethernet).rule().unwrap().priority(), 1);
+        let ip = Ipv4Packet::new(&frame[14..]).unwrap();
+        assert_eq!(ip.try_packet(), ethernet.try_packet());
+
+        // a TCP view parsed on its own, with the fields of its IP header
+        let tcp = TcpPacket::new(&frame[34..]).unwrap();
+        let tcp = Ipv4Transport::new(ip.get_source(), ip.get_destination(), 0, tcp);
+        assert_eq!(tcp.try_packet(), ethernet.try_packet());
+        let udp = UdpPacket::new(&frame[34..]).unwrap();
+        let udp = Ipv4Transport::new(ip.get_source(), ip.get_destination(), 0, udp);
+        assert_eq!(rvh.classify_try(&udp), TryMatch::Missed);
+
+        // IPv6 and VLAN tagged frames are not followed
+        frame[12..14].copy_from_slice(&[0x86, 0xdd]);
+        let ethernet = EthernetPacket::new(&frame).unwrap();
+        assert_eq!(
+            rvh.classify_try(&ethernet),
+            TryMatch::Unparseable(ExtractError::NotIpv4)
+        );
+    }
+
+    #[test]
+    fn test_security_and_qos_rules_share_classifier() {
+        let mut rvh = RVHClassifier::<MockRule>::new(presets::five_tuple_dscp().into_iter());
+
+        let security = rule(
+            &[
+                fields::ipv4_prefix(Ipv4Addr::new(10, 0, 0, 0), 8),
+                fields::wildcard(),
+                fields::wildcard(),
+                fields::port(22),
+                fields::protocol(6),
+            ],
+            10,
+        );
+        let qos = rule(
+            &[
+                fields::wildcard(),
+                fields::wildcard(),
+                fields::wildcard(),
+                fields::wildcard(),
+                fields::wildcard(),
+                fields::dscp(46),
+            ],
+            5,
+        );
+        assert!(rvh.add_rule(security).is_ok());
+        assert!(rvh.add_rule(qos).is_ok());
+
+        let ssh = FiveTuple::new(
+            Ipv4Addr::new(10, 1, 1, 1),
+            Ipv4Addr::new(10, 2, 2, 2),
+            40000,
+            22,
+            6,
+        );
+        let voice = FiveTuple::new(
+            Ipv4Addr::new(172, 16, 0, 1),
+            Ipv4Addr::new(10, 2, 2, 2),
+            5004,
+            5004,
+            17,
+        );
+
+        assert_eq!(rvh.classify(&ssh.with_dscp(46)).unwrap().priority(), 10);
+        assert_eq!(rvh.classify(&voice.with_dscp(46)).unwrap().priority(), 5);
+        assert!(rvh.classify(&voice.with_dscp(0)).is_none());
+    }
+
+    #[test]
+    fn test_tunneled_rules_match_on_outer_and_inner_headers() {
+        let mut rvh = RVHClassifier::<MockRule>::new(presets::tunnel_five_tuple().into_iter());
+
+        // tenant VNI 100 may reach 10.0.0.0/8 on port 80
+        let tenant = rule(
+            &[
+                fields::tunnel_id(100),
+                fields::wildcard(),
+                fields::ipv4_prefix(Ipv4Addr::new(10, 0, 0, 0), 8),
+                fields::wildcard(),
+                fields::port(80),
+                fields::protocol(6),
+            ],
+            20,
+        );
+        // any tunnel may reach the resolver
+        let dns = rule(
+            &[
+                fields::wildcard(),
+                fields::wildcard(),
+                fields::ipv4_prefix(Ipv4Addr::new(10, 0, 0, 53), 32),
+                fields::wildcard(),
+                fields::port(53),
+                fields::protocol(17),
+            ],
+            10,
+        );
+        assert!(rvh.add_rule(tenant).is_ok());
+        assert!(rvh.add_rule(dns).is_ok());
+
+        let web = FiveTuple::new(
+            Ipv4Addr::new(192, 168, 0, 1),
+            Ipv4Addr::new(10, 3, 3, 3),
+            50000,
+            80,
+            6,
+        );
+        let lookup = FiveTuple::new(
+            Ipv4Addr::new(192, 168, 0, 1),
+            Ipv4Addr::new(10, 0, 0, 53),
+            50000,
+            53,
+            17,
+        );
+
+        let vxlan = |inner| TunneledFiveTuple::new(Tunnel::Vxlan { vni: 100 }, inner);
+        let gtp = |inner| TunneledFiveTuple::new(Tunnel::Gtp { teid: 7 }, inner);
+
+        assert_eq!(rvh.classify(&vxlan(web)).unwrap().priority(), 20);
+        assert!(rvh.classify(&gtp(web)).is_none());
+        assert_eq!(rvh.classify(&gtp(lookup)).unwrap().priority(), 10);
+        assert_eq!(vxlan(web.with_dscp(1)).fields().len(), 7);
+    }
+
+    #[test]
+    fn test_ipv6_prefixes_match_across_all_bits() {
+        let mut rvh = RVHClassifier::<MockRule<u128>, u128>::ipv6_five_tuple();
+
+        let addr = |s: &str| s.parse::<Ipv6Addr>().unwrap();
+        let wide_rule = |parts: &[(u128, u128)], priority| {
+            MockRule::wide(
+                parts.iter().map(|p| p.0).collect(),
+                parts.iter().map(|p| p.1).collect(),
+                priority,
+            )
+        };
+
+        let site = wide_rule(&[fields::ipv6_prefix(addr("2001:db8:1::"), 48)], 1);
+        let host = wide_rule(
+            &[
+                fields::ipv6_prefix(addr("2001:db8:1::ffff"), 128),
+                (0, 0),
+                (0, 0),
+                fields::port_wide(22),
+                fields::protocol_wide(6),
+            ],
+            2,
+        );
+        // differs from the host only in the last bit
+        let neighbor = wide_rule(&[fields::ipv6_prefix(addr("2001:db8:1::fffe"), 127)], 3);
+        assert!(rvh.add_rule(site).is_ok());
+        assert!(rvh.add_rule(host).is_ok());
+        assert!(rvh.add_rule(neighbor.clone()).is_ok());
+
+        let flow = |src: &str, dport| {
+            Ipv6FiveTuple::new(addr(src), addr("2001:db8:2::1"), 40000, dport, 6)
+        };
+        let prio = |rvh: &RVHClassifier<MockRule<u128>, u128>, p: Ipv6FiveTuple| {
+            rvh.classify(&p).map(|r| r.priority())
+        };
+
+        assert_eq!(prio(&rvh, flow("2001:db8:1::1", 22)), Some(1));
+        assert_eq!(prio(&rvh, flow("2001:db8:1::ffff", 80)), Some(3));
+        assert_eq!(prio(&rvh, flow("2001:db8:1::fffe", 22)), Some(3));
+        assert_eq!(prio(&rvh, flow("2001:db8:1:0:1::ffff", 22)), Some(1));
+        assert_eq!(prio(&rvh, flow("2001:db8:2::1", 22)), None);
+
+        assert!(rvh.remove_rule(&neighbor).is_ok());
+        assert_eq!(prio(&rvh, flow("2001:db8:1::ffff", 22)), Some(2));
+        assert_eq!(prio(&rvh, flow("2001:db8:1::ffff", 80)), Some(1));
+    }
+}
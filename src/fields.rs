@@ -0,0 +1,495 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+#[cfg(feature = "ipnet")]
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+
+use crate::types::{Field, Mask, Range};
+
+pub const IPV4_WIDTH: u32 = 32;
+pub const IPV6_WIDTH: u32 = 128;
+pub const PORT_WIDTH: u32 = 16;
+pub const PROTOCOL_WIDTH: u32 = 8;
+pub const DSCP_WIDTH: u32 = 6;
+// VXLAN/Geneve VNIs are zero-extended so that they share a dimension with GTP TEIDs
+pub const TUNNEL_ID_WIDTH: u32 = 32;
+pub const VNI_WIDTH: u32 = 24;
+pub const MPLS_LABEL_WIDTH: u32 = 20;
+
+// Masks are right-aligned, i.e. a prefix of length `n` covers the lowest `n` bits of a field.
+// Header values are therefore stored bit-reversed within their width, so that the most
+// significant bit of the header ends up in bit 0 and header prefixes become mask prefixes.
+pub fn encode(value: u32, width: u32) -> Field {
+    debug_assert!(width > 0 && width <= 32);
+    value.reverse_bits() >> (32 - width)
+}
+
+// Header value in host byte order, f.e. a port as `u16` or an address converted with
+// `u32::from(Ipv4Addr)`. This is the representation `encode` expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HostField {
+    value: u32,
+    width: u32,
+}
+
+impl HostField {
+    pub fn new(value: u32, width: u32) -> Self {
+        debug_assert!(width > 0 && width <= 32);
+        debug_assert!(width == 32 || value < (1 << width));
+        Self { value, width }
+    }
+
+    pub fn value(&self) -> u32 {
+        self.value
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn encode(self) -> Field {
+        encode(self.value, self.width)
+    }
+
+    pub fn prefix(self, len: u32) -> (Field, Mask) {
+        prefix(self.value, self.width, len)
+    }
+
+    pub fn exact(self) -> (Field, Mask) {
+        exact(self.value, self.width)
+    }
+
+    // Only fields spanning whole bytes have a byte representation.
+    pub fn to_be(self) -> BigEndianField {
+        debug_assert_eq!(self.width % 8, 0);
+        let len = (self.width / 8) as usize;
+        let mut bytes = [0; 4];
+        bytes[..len].copy_from_slice(&self.value.to_be_bytes()[4 - len..]);
+        BigEndianField { bytes, len }
+    }
+}
+
+impl From<Ipv4Addr> for HostField {
+    fn from(addr: Ipv4Addr) -> Self {
+        Self::new(u32::from(addr), IPV4_WIDTH)
+    }
+}
+
+// Header value in network byte order, i.e. the bytes exactly as they appear in a packet. It
+// has to be converted with `to_host` before it can be encoded; reading header bytes directly
+// into a `u32` yields a byte-swapped value on little endian machines, which silently never
+// matches any rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BigEndianField {
+    bytes: [u8; 4],
+    len: usize,
+}
+
+impl BigEndianField {
+    // Takes one to four bytes, the width of the field is the number of bits given.
+    pub fn new(bytes: &[u8]) -> Option<Self> {
+        if bytes.is_empty() || bytes.len() > 4 {
+            return None;
+        }
+
+        let mut field = Self {
+            bytes: [0; 4],
+            len: bytes.len(),
+        };
+        field.bytes[..bytes.len()].copy_from_slice(bytes);
+        Some(field)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    pub fn to_host(self) -> HostField {
+        let value = self
+            .as_bytes()
+            .iter()
+            .fold(0u32, |v, &b| (v << 8) | u32::from(b));
+        HostField::new(value, self.len as u32 * 8)
+    }
+
+    pub fn encode(self) -> Field {
+        self.to_host().encode()
+    }
+}
+
+impl From<[u8; 1]> for BigEndianField {
+    fn from(bytes: [u8; 1]) -> Self {
+        Self::new(&bytes).unwrap()
+    }
+}
+
+impl From<[u8; 2]> for BigEndianField {
+    fn from(bytes: [u8; 2]) -> Self {
+        Self::new(&bytes).unwrap()
+    }
+}
+
+impl From<[u8; 4]> for BigEndianField {
+    fn from(bytes: [u8; 4]) -> Self {
+        Self::new(&bytes).unwrap()
+    }
+}
+
+pub fn prefix_mask(len: u32) -> Mask {
+    if len >= 32 {
+        !0
+    } else {
+        (1 << len) - 1
+    }
+}
+
+pub fn prefix(value: u32, width: u32, len: u32) -> (Field, Mask) {
+    debug_assert!(len <= width);
+    let mask = prefix_mask(len);
+    (encode(value, width) & mask, mask)
+}
+
+pub fn exact(value: u32, width: u32) -> (Field, Mask) {
+    prefix(value, width, width)
+}
+
+pub fn wildcard() -> (Field, Mask) {
+    (0, 0)
+}
+
+// Address and prefix length of an IPv4 network like `10.0.0.0/8`, an address without prefix
+// length is a /32. Usable in constants so that `rule!` rejects invalid networks at compile
+// time, panics on invalid input.
+pub const fn ipv4_cidr(text: &str) -> (u32, u32) {
+    let bytes = text.as_bytes();
+    let (mut i, mut addr, mut octet, mut digits, mut dots) = (0, 0u32, 0u32, 0, 0);
+    while i < bytes.len() && bytes[i] != b'/' {
+        match bytes[i] {
+            b'0'..=b'9' if digits < 3 => {
+                octet = octet * 10 + (bytes[i] - b'0') as u32;
+                digits += 1;
+            }
+            b'.' if digits > 0 && dots < 3 => {
+                addr = addr << 8 | octet;
+                (octet, digits, dots) = (0, 0, dots + 1);
+            }
+            _ => panic!("invalid IPv4 address"),
+        }
+        if octet > 255 {
+            panic!("invalid IPv4 address");
+        }
+        i += 1;
+    }
+    if digits == 0 || dots != 3 {
+        panic!("invalid IPv4 address");
+    }
+    addr = addr << 8 | octet;
+
+    if i == bytes.len() {
+        return (addr, IPV4_WIDTH);
+    }
+    let mut len = 0;
+    i += 1;
+    if i == bytes.len() || bytes.len() - i > 2 {
+        panic!("invalid prefix length");
+    }
+    while i < bytes.len() {
+        match bytes[i] {
+            b'0'..=b'9' => len = len * 10 + (bytes[i] - b'0') as u32,
+            _ => panic!("invalid prefix length"),
+        }
+        i += 1;
+    }
+    if len > IPV4_WIDTH {
+        panic!("invalid prefix length");
+    }
+    (addr, len)
+}
+
+pub fn ipv4_prefix(addr: Ipv4Addr, len: u32) -> (Field, Mask) {
+    prefix(u32::from(addr), IPV4_WIDTH, len)
+}
+
+#[cfg(feature = "ipnet")]
+pub fn ipv4_net(net: Ipv4Net) -> (Field, Mask) {
+    ipv4_prefix(net.network(), u32::from(net.prefix_len()))
+}
+
+pub fn port(port: u16) -> (Field, Mask) {
+    exact(u32::from(port), PORT_WIDTH)
+}
+
+pub fn protocol(protocol: u8) -> (Field, Mask) {
+    exact(u32::from(protocol), PROTOCOL_WIDTH)
+}
+
+pub fn dscp(dscp: u8) -> (Field, Mask) {
+    debug_assert!(dscp < 64);
+    exact(u32::from(dscp), DSCP_WIDTH)
+}
+
+pub fn tunnel_id(id: u32) -> (Field, Mask) {
+    exact(id, TUNNEL_ID_WIDTH)
+}
+
+pub fn vni(vni: u32) -> (Field, Mask) {
+    vni_prefix(vni, VNI_WIDTH)
+}
+
+pub fn vni_prefix(vni: u32, len: u32) -> (Field, Mask) {
+    debug_assert!(vni < (1 << VNI_WIDTH));
+    prefix(vni, VNI_WIDTH, len)
+}
+
+pub fn mpls_label(label: u32) -> (Field, Mask) {
+    mpls_label_prefix(label, MPLS_LABEL_WIDTH)
+}
+
+pub fn mpls_label_prefix(label: u32, len: u32) -> (Field, Mask) {
+    debug_assert!(label < (1 << MPLS_LABEL_WIDTH));
+    prefix(label, MPLS_LABEL_WIDTH, len)
+}
+
+// Counterparts of the helpers above for classifiers with `u128` fields, f.e. IPv6 5-tuples,
+// where every dimension uses the wide field type.
+pub fn encode_wide(value: u128, width: u32) -> u128 {
+    debug_assert!(width > 0 && width <= 128);
+    value.reverse_bits() >> (128 - width)
+}
+
+pub fn prefix_mask_wide(len: u32) -> u128 {
+    if len >= 128 {
+        !0
+    } else {
+        (1 << len) - 1
+    }
+}
+
+pub fn prefix_wide(value: u128, width: u32, len: u32) -> (u128, u128) {
+    debug_assert!(len <= width);
+    let mask = prefix_mask_wide(len);
+    (encode_wide(value, width) & mask, mask)
+}
+
+pub fn ipv6_prefix(addr: Ipv6Addr, len: u32) -> (u128, u128) {
+    prefix_wide(u128::from(addr), IPV6_WIDTH, len)
+}
+
+#[cfg(feature = "ipnet")]
+pub fn ipv6_net(net: Ipv6Net) -> (u128, u128) {
+    ipv6_prefix(net.network(), u32::from(net.prefix_len()))
+}
+
+// IPv4 addresses as IPv4-mapped IPv6 addresses, `::ffff:a.b.c.d`, so that both families can
+// share an address dimension of a `u128` classifier.
+pub fn ip_addr_wide(addr: IpAddr) -> (u128, u128) {
+    match addr {
+        IpAddr::V4(addr) => ipv6_prefix(addr.to_ipv6_mapped(), IPV6_WIDTH),
+        IpAddr::V6(addr) => ipv6_prefix(addr, IPV6_WIDTH),
+    }
+}
+
+#[cfg(feature = "ipnet")]
+pub fn ip_net_wide(net: IpNet) -> (u128, u128) {
+    match net {
+        IpNet::V4(net) => {
+            let len = IPV6_WIDTH - IPV4_WIDTH + u32::from(net.prefix_len());
+            ipv6_prefix(net.network().to_ipv6_mapped(), len)
+        }
+        IpNet::V6(net) => ipv6_net(net),
+    }
+}
+
+pub fn port_wide(port: u16) -> (u128, u128) {
+    prefix_wide(u128::from(port), PORT_WIDTH, PORT_WIDTH)
+}
+
+pub fn protocol_wide(protocol: u8) -> (u128, u128) {
+    prefix_wide(u128::from(protocol), PROTOCOL_WIDTH, PROTOCOL_WIDTH)
+}
+
+// Smallest set of prefixes covering the values `lo..=hi` of a field with the given width, as
+// `(value, prefix length)`, f.e. 1024-65535 of a port becomes six prefixes. Arbitrary ranges
+// need up to `2 * (width - 1)` of them.
+pub fn range_prefixes(lo: u32, hi: u32, width: u32) -> Vec<(u32, u32)> {
+    debug_assert!(lo <= hi && (width == 32 || hi < (1 << width)));
+    let (mut lo, hi) = (u64::from(lo), u64::from(hi));
+    let mut prefixes = Vec::new();
+    while lo <= hi {
+        // the largest block aligned at `lo` that does not reach beyond `hi`
+        let mut size = if lo == 0 {
+            1 << width
+        } else {
+            lo & lo.wrapping_neg()
+        };
+        while lo + size - 1 > hi {
+            size >>= 1;
+        }
+        prefixes.push((lo as u32, width - size.trailing_zeros()));
+        lo += size;
+    }
+
+    prefixes
+}
+
+// Range accepting every prefix length of a field with the given width.
+pub fn full_range(width: u32) -> Range {
+    (0, width + 1)
+}
+
+// Range accepting only exact matches on a field with the given width.
+pub fn exact_range(width: u32) -> Range {
+    (width, width + 1)
+}
+
+// A field of `width` bits only has prefix lengths `0..=width`. Ranges reaching beyond that
+// usually mean the dimension was configured as if it was a full 32 bit field.
+pub fn is_valid_range(range: Range, width: u32) -> bool {
+    range.0 < range.1 && range.1 <= width + 1
+}
+
+// Returns the `(table, dimension)` index of every range not valid for the given field widths.
+pub fn invalid_ranges(ranges: &[Vec<Range>], widths: &[u32]) -> Vec<(usize, usize)> {
+    let mut invalid = Vec::new();
+    for (table, r) in ranges.iter().enumerate() {
+        for (dim, (range, width)) in r.iter().zip(widths).enumerate() {
+            if !is_valid_range(*range, *width) {
+                invalid.push((table, dim));
+            }
+        }
+    }
+
+    invalid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_reverses_within_width() {
+        assert_eq!(encode(0b1000_0000, 8), 0b1);
+        assert_eq!(encode(0b0000_0001, 8), 0b1000_0000);
+        assert_eq!(encode(0x8000_0000, 32), 0b1);
+        assert_eq!(encode(0b10_0000, DSCP_WIDTH), 0b1);
+    }
+
+    #[test]
+    fn test_big_endian_fields_convert_to_host_order() {
+        let port = BigEndianField::from(443u16.to_be_bytes());
+        assert_eq!(port.to_host(), HostField::new(443, PORT_WIDTH));
+        assert_eq!(port.encode(), encode(443, PORT_WIDTH));
+        assert_eq!(HostField::new(443, PORT_WIDTH).to_be(), port);
+
+        let addr = Ipv4Addr::new(10, 1, 2, 3);
+        let wire = BigEndianField::from(addr.octets());
+        assert_eq!(wire.to_host(), HostField::from(addr));
+        assert_eq!(wire.as_bytes(), &[10, 1, 2, 3]);
+        assert_eq!(
+            wire.to_host().prefix(8),
+            ipv4_prefix(Ipv4Addr::new(10, 0, 0, 0), 8)
+        );
+
+        // three byte VNI
+        let vni = BigEndianField::new(&[0xab, 0xcd, 0xef]).unwrap();
+        assert_eq!(vni.to_host().value(), 0xab_cdef);
+        assert_eq!(vni.to_host().width(), VNI_WIDTH);
+
+        assert!(BigEndianField::new(&[]).is_none());
+        assert!(BigEndianField::new(&[0; 5]).is_none());
+    }
+
+    #[test]
+    fn test_prefix_mask() {
+        assert_eq!(prefix_mask(0), 0);
+        assert_eq!(prefix_mask(3), 0b111);
+        assert_eq!(prefix_mask(32), u32::MAX);
+    }
+
+    #[test]
+    fn test_ipv4_prefix_covers_network_bits() {
+        let (field, mask) = ipv4_prefix(Ipv4Addr::new(10, 0, 0, 0), 8);
+        assert_eq!(mask.count_ones(), 8);
+
+        let inside = encode(u32::from(Ipv4Addr::new(10, 1, 2, 3)), IPV4_WIDTH);
+        let outside = encode(u32::from(Ipv4Addr::new(11, 1, 2, 3)), IPV4_WIDTH);
+        assert_eq!(inside & mask, field);
+        assert_ne!(outside & mask, field);
+    }
+
+    #[test]
+    fn test_ipv6_prefix_covers_network_bits() {
+        let net: Ipv6Addr = "2001:db8:ffff::".parse().unwrap();
+        let (field, mask) = ipv6_prefix(net, 127);
+        assert_eq!(mask.count_ones(), 127);
+        assert_eq!(ipv6_prefix(net, 128).1, u128::MAX);
+
+        let inside: Ipv6Addr = "2001:db8:ffff::1".parse().unwrap();
+        let outside: Ipv6Addr = "2001:db8:ffff::2".parse().unwrap();
+        assert_eq!(encode_wide(u128::from(inside), IPV6_WIDTH) & mask, field);
+        assert_ne!(encode_wide(u128::from(outside), IPV6_WIDTH) & mask, field);
+
+        assert_eq!(port_wide(443).0, u128::from(port(443).0));
+        assert_eq!(protocol_wide(6).1.count_ones(), PROTOCOL_WIDTH);
+    }
+
+    #[test]
+    fn test_exact_helpers_use_full_width() {
+        assert_eq!(port(443).1.count_ones(), PORT_WIDTH);
+        assert_eq!(protocol(6).1.count_ones(), PROTOCOL_WIDTH);
+        assert_eq!(dscp(46).1.count_ones(), DSCP_WIDTH);
+        assert_eq!(tunnel_id(42).1.count_ones(), TUNNEL_ID_WIDTH);
+        assert_eq!(wildcard(), (0, 0));
+    }
+
+    #[test]
+    fn test_vni_and_mpls_label_helpers() {
+        assert_eq!(vni(0xab_cdef).1.count_ones(), VNI_WIDTH);
+        assert_eq!(mpls_label(0xf_ffff).1.count_ones(), MPLS_LABEL_WIDTH);
+
+        // the high bits of the label form the prefix
+        let (field, mask) = mpls_label_prefix(0x8_0000, 1);
+        assert_eq!((field, mask), (1, 1));
+        assert_eq!(encode(0xf_ffff, MPLS_LABEL_WIDTH) & mask, field);
+        assert_eq!(vni_prefix(0x80_0000, 1), (1, 1));
+    }
+
+    #[test]
+    fn test_range_validation_respects_field_width() {
+        assert!(is_valid_range(full_range(VNI_WIDTH), VNI_WIDTH));
+        assert!(is_valid_range(
+            exact_range(MPLS_LABEL_WIDTH),
+            MPLS_LABEL_WIDTH
+        ));
+        assert!(!is_valid_range((24, 33), VNI_WIDTH));
+        assert!(!is_valid_range((3, 3), VNI_WIDTH));
+
+        let ranges = vec![
+            vec![(0, 25), (0, 21)],
+            vec![(0, 33), (20, 21)],
+            vec![(24, 25), (16, 33)],
+        ];
+        assert_eq!(
+            invalid_ranges(&ranges, &[VNI_WIDTH, MPLS_LABEL_WIDTH]),
+            vec![(1, 0), (2, 1)]
+        );
+    }
+
+    #[test]
+    fn test_range_prefixes_cover_exactly_the_range() {
+        assert_eq!(range_prefixes(0, 65535, PORT_WIDTH), vec![(0, 0)]);
+        assert_eq!(range_prefixes(80, 80, PORT_WIDTH), vec![(80, 16)]);
+        assert_eq!(
+            range_prefixes(1024, 65535, PORT_WIDTH),
+            vec![
+                (1024, 6),
+                (2048, 5),
+                (4096, 4),
+                (8192, 3),
+                (16384, 2),
+                (32768, 1)
+            ]
+        );
+        assert_eq!(range_prefixes(1, 14, 4).len(), 6);
+        assert_eq!(range_prefixes(0, u32::MAX, 32), vec![(0, 0)]);
+    }
+}
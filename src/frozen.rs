@@ -0,0 +1,348 @@
+use std::hash::BuildHasher;
+
+use crate::bands::PriorityBands;
+use crate::classifier::RVHClassifier;
+use crate::dictionary::Dictionary;
+use crate::dimensions::Dimension;
+use crate::hash::{calc_hash, SipBuildHasher};
+use crate::perfect::PerfectMap;
+use crate::range_vector_hash_map::{self, is_match, RVHashMap};
+use crate::types::*;
+
+#[derive(Debug, Clone)]
+struct FrozenTable<F: FieldType> {
+    highest_priority: Priority,
+    seed: u32,
+    // (start, len) of the masks in the shared mask array
+    masks: (u32, u32),
+    // (start, len) of each bucket in the shared rule array, found with a single probe
+    buckets: PerfectMap,
+    // empty unless a dimension has a dictionary
+    dictionaries: Box<[Option<Dictionary<F>>]>,
+}
+
+// Immutable classifier produced by `RVHClassifier::freeze`. All rules live in one contiguous
+// array, grouped by table and bucket, with every bucket sorted by descending priority. The
+// masks of all tables share another array, so that the tables themselves stay small. Since it
+// can not be modified it may be shared between threads without any synchronization.
+#[derive(Debug, Clone)]
+pub struct FrozenRVHClassifier<R: Rule<F>, F: FieldType = Field, S = SipBuildHasher> {
+    tables: Box<[FrozenTable<F>]>,
+    masks: Box<[F]>,
+    rules: Box<[R]>,
+    // kept to restore the original classifier in `thaw`, in the original order
+    split: Box<[Vec<Range>]>,
+    // positions in `split` of disabled tables and their rules, kept for `thaw` only
+    disabled: Box<[usize]>,
+    disabled_rules: Box<[R]>,
+    bands: PriorityBands,
+    dimensions: Vec<Dimension>,
+    // kept for `thaw`
+    dictionaries: Box<[usize]>,
+    hasher: S,
+}
+
+// The compiled form of a classifier for read mostly deployments, the same as a frozen one.
+pub type CompiledRVHClassifier<R, F = Field, S = SipBuildHasher> = FrozenRVHClassifier<R, F, S>;
+
+impl<R: Rule<F>, F: FieldType, S: BuildHasher + Clone> FrozenRVHClassifier<R, F, S> {
+    // `hash_maps` has to be sorted by descending highest priority, and use `hasher`
+    pub(crate) fn from_hash_maps(
+        hash_maps: Vec<RVHashMap<R, F, S>>,
+        bands: PriorityBands,
+        dimensions: Vec<Dimension>,
+        dictionaries: Box<[usize]>,
+        hasher: S,
+    ) -> Self {
+        let mut tables = Vec::with_capacity(hash_maps.len());
+        let mut masks = Vec::new();
+        let mut rules = Vec::new();
+        let mut split = Vec::with_capacity(hash_maps.len());
+        let mut disabled_rules = Vec::new();
+
+        for hm in hash_maps {
+            split.push((hm.index, hm.ranges, hm.enabled));
+            if !hm.enabled {
+                disabled_rules.extend(hm.hash_map.into_rules());
+                continue;
+            }
+            if hm.priorities.is_empty() {
+                continue;
+            }
+
+            let mut buckets = Vec::with_capacity(hm.hash_map.len());
+            for (hash, mut bucket) in hm.hash_map.into_buckets() {
+                if bucket.is_empty() {
+                    continue;
+                }
+
+                bucket.sort_by_key(|r| std::cmp::Reverse(r.priority()));
+                buckets.push((hash, (rules.len() as u32, bucket.len() as u32)));
+                rules.extend(bucket);
+            }
+
+            tables.push(FrozenTable {
+                highest_priority: hm.highest_priority,
+                seed: hm.seed,
+                masks: (masks.len() as u32, hm.masks.len() as u32),
+                buckets: PerfectMap::new(&buckets),
+                dictionaries: hm.dictionaries.into_boxed_slice(),
+            });
+            masks.extend(hm.masks);
+        }
+
+        // `thaw` numbers the tables by their position in the split
+        split.sort_by_key(|(index, _, _)| *index);
+        let disabled = split
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, _, enabled))| !enabled)
+            .map(|(position, _)| position)
+            .collect();
+        Self {
+            tables: tables.into_boxed_slice(),
+            masks: masks.into_boxed_slice(),
+            rules: rules.into_boxed_slice(),
+            split: split.into_iter().map(|(_, ranges, _)| ranges).collect(),
+            disabled,
+            disabled_rules: disabled_rules.into_boxed_slice(),
+            bands,
+            dimensions,
+            dictionaries,
+            hasher,
+        }
+    }
+
+    // Rebuilds the mutable classifier, including empty tables, priority bands and dimension
+    // names.
+    pub fn thaw(self) -> RVHClassifier<R, F, (), S> {
+        let mut rules = self.rules.into_vec();
+        rules.extend(self.disabled_rules.into_vec());
+        let mut classifier =
+            RVHClassifier::from_parts(self.split.into_vec(), self.bands, rules, self.hasher);
+        classifier.set_dimensions(self.dimensions);
+        for dimension in self.dictionaries.iter() {
+            classifier.set_dictionary(*dimension, true);
+        }
+        for position in self.disabled.iter() {
+            classifier.set_table_enabled(*position, false);
+        }
+        classifier
+    }
+
+    pub fn dimensions(&self) -> &[Dimension] {
+        &self.dimensions
+    }
+
+    pub fn classify(&self, p: &impl Packet<F>) -> Option<&R> {
+        let mut highest_matching_priority = 0;
+        let mut best_match = None;
+
+        for table in self.tables.iter() {
+            if table.highest_priority < highest_matching_priority {
+                break;
+            }
+
+            let (start, len) = table.masks;
+            let hash = calc_hash(
+                &self.hasher,
+                &self.masks[start as usize..(start + len) as usize],
+                table.seed,
+                &table.dictionaries,
+                p.fields().iter(),
+            );
+            if let Some((start, len)) = hash.and_then(|hash| table.buckets.get(hash)) {
+                let bucket = &self.rules[start as usize..(start + len) as usize];
+
+                // buckets are sorted, so the first match is the best one and the scan can stop
+                // at the best match of the other tables
+                let matching_rule = bucket
+                    .iter()
+                    .take_while(|r| r.priority() > highest_matching_priority)
+                    .find(|r| {
+                        p.fields()
+                            .iter()
+                            .zip(r.fields().iter())
+                            .zip(r.masks())
+                            .all(|((&pf, &rf), &rm)| is_match(pf, rf, rm))
+                    });
+
+                if let Some(matching_rule) = matching_rule {
+                    highest_matching_priority = matching_rule.priority();
+                    best_match = Some(matching_rule);
+                }
+            }
+        }
+
+        best_match
+    }
+
+    // See `RVHClassifier::prewarm`.
+    pub fn prewarm(&self) -> usize {
+        std::hint::black_box(&self.masks);
+        for table in self.tables.iter() {
+            for bucket in table.buckets.values() {
+                std::hint::black_box(bucket);
+            }
+        }
+
+        self.rules.iter().for_each(range_vector_hash_map::touch);
+        self.rules.len()
+    }
+
+    pub fn prewarm_with<P: Packet<F>>(&self, sample: &[P]) -> usize {
+        for p in sample {
+            std::hint::black_box(self.classify(p));
+        }
+
+        self.prewarm()
+    }
+
+    // All rules that may match, grouped by table and bucket. Rules of disabled tables are left
+    // out, as by `len`.
+    pub fn iter(&self) -> impl Iterator<Item = &R> {
+        self.rules.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+impl<R: Rule<F> + Clone, F: FieldType, S: BuildHasher + Clone> FrozenRVHClassifier<R, F, S> {
+    pub fn classify_owned(&self, p: &impl Packet<F>) -> Option<R> {
+        self.classify(p).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CompiledRVHClassifier;
+    use crate::types::mocks::{MockPacket, MockRule};
+    use crate::types::Rule;
+    use crate::RVHClassifier;
+
+    fn classifier() -> RVHClassifier<MockRule> {
+        let mut rvh = RVHClassifier::<MockRule>::new(
+            vec![vec![(0, 3)], vec![(3, 6)], vec![(6, 9)], vec![(9, 12)]].into_iter(),
+        );
+
+        rvh.add_rule(MockRule::new(vec![0b11], vec![0b11], 1))
+            .unwrap();
+        rvh.add_rule(MockRule::new(vec![0b1], vec![0b1], 3))
+            .unwrap();
+        rvh.add_rule(MockRule::new(vec![0b100], vec![0b111], 4))
+            .unwrap();
+        rvh.add_rule(MockRule::new(vec![0b101], vec![0b1_1111], 2))
+            .unwrap();
+        rvh.add_rule(MockRule::new(vec![0b11_1001], vec![0b11_1111], 6))
+            .unwrap();
+        rvh.add_rule(MockRule::new(vec![0b11_1100], vec![0b1111_1111], 5))
+            .unwrap();
+        rvh
+    }
+
+    #[test]
+    fn test_frozen_classifier_classifies_like_the_original() {
+        let rvh = classifier();
+        let frozen = classifier().freeze();
+        assert_eq!(frozen.len(), 6);
+        assert_eq!(frozen.iter().count(), 6);
+        assert_eq!(
+            frozen
+                .classify_owned(&MockPacket::new(vec![0b11_1100]))
+                .map(|r| r.priority()),
+            Some(5)
+        );
+        // the empty table is dropped, the masks of the others are packed
+        assert_eq!(frozen.tables.len(), 3);
+        assert_eq!(frozen.masks.len(), 3);
+
+        for field in 0..=0b1111_1111 {
+            let p = MockPacket::new(vec![field]);
+            assert_eq!(
+                rvh.classify(&p).map(|r| r.priority()),
+                frozen.classify(&p).map(|r| r.priority())
+            );
+        }
+    }
+
+    #[test]
+    fn test_frozen_classifier_can_be_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+        let compiled: CompiledRVHClassifier<MockRule> = classifier().freeze();
+        let frozen = std::sync::Arc::new(compiled);
+        assert_send_sync(&frozen);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let frozen = frozen.clone();
+                std::thread::spawn(move || {
+                    frozen
+                        .classify(&MockPacket::new(vec![0b11_1001]))
+                        .map(|r| r.priority())
+                })
+            })
+            .collect();
+
+        for h in handles {
+            assert_eq!(h.join().unwrap(), Some(6));
+        }
+    }
+
+    #[test]
+    fn test_thaw_restores_a_mutable_classifier() {
+        let mut rvh = classifier();
+        assert!(rvh.declare_band("system", 100, 200));
+
+        let mut thawed = rvh.freeze().thaw();
+        assert_eq!(thawed.band("system").unwrap().low(), 100);
+
+        // the previously empty table is available again
+        assert!(thawed
+            .add_rule(MockRule::new(vec![0b11_1001], vec![0b1_1111_1111], 7))
+            .is_ok());
+        assert!(thawed
+            .add_rule(MockRule::new(vec![0b1], vec![0b1], 150))
+            .is_err());
+        assert!(thawed
+            .remove_rule(&MockRule::new(vec![0b11_1001], vec![0b11_1111], 6))
+            .is_ok());
+
+        let p = MockPacket::new(vec![0b11_1001]);
+        assert_eq!(thawed.classify(&p).unwrap().priority(), 7);
+
+        let refrozen = thawed.freeze();
+        assert_eq!(refrozen.len(), 6);
+        assert_eq!(refrozen.classify(&p).unwrap().priority(), 7);
+    }
+
+    #[test]
+    fn test_thaw_keeps_disabled_tables() {
+        let mut rvh = classifier();
+        rvh.set_auto_tables(true);
+        let wide = MockRule::new(vec![0b1], vec![0x1fff], 9);
+        assert!(rvh.add_rule(wide.clone()).is_ok());
+        assert!(rvh.set_table_enabled(4, false));
+        assert!(rvh.set_table_enabled(1, false));
+
+        let frozen = rvh.freeze();
+        // the rules of disabled tables can not match and are not counted
+        assert_eq!(frozen.len(), 4);
+        assert!(frozen.iter().all(|r| *r != wide));
+        assert!(frozen.classify(&MockPacket::new(vec![0b1])).is_some());
+
+        let thawed = frozen.thaw();
+        assert_eq!(thawed.iter().count(), 7);
+        let enabled: Vec<_> = (0..5)
+            .map(|i| thawed.is_table_enabled(i).unwrap())
+            .collect();
+        assert_eq!(enabled, vec![true, false, true, true, false]);
+        assert_eq!(thawed.iter_table(4).unwrap().next(), Some(&wide));
+    }
+}
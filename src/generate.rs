@@ -0,0 +1,262 @@
+use crate::fields::{self, IPV4_WIDTH, PORT_WIDTH, PROTOCOL_WIDTH};
+use crate::types::*;
+
+// Rule produced by a `Generator`, fields and masks are already encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeneratedRule {
+    pub fields: Vec<Field>,
+    pub masks: Vec<Mask>,
+    pub priority: Priority,
+}
+
+impl Rule for GeneratedRule {
+    fn priority(&self) -> Priority {
+        self.priority
+    }
+    fn masks(&self) -> &[Mask] {
+        &self.masks
+    }
+    fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeneratedPacket(pub Vec<Field>);
+
+impl Packet for GeneratedPacket {
+    fn fields(&self) -> &[Field] {
+        &self.0
+    }
+}
+
+// splitmix64, good enough for workloads and without a dependency
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    // uniform in `0.0..1.0`
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// A dimension of the generated rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSpec {
+    pub width: u32,
+    // `(prefix length, weight)`, lengths are drawn in proportion to their weight
+    pub prefix_lengths: Vec<(u32, u32)>,
+}
+
+impl FieldSpec {
+    // Half of the rules match any value, the other half one exact value.
+    pub fn new(width: u32) -> Self {
+        Self {
+            width,
+            prefix_lengths: vec![(0, 1), (width, 1)],
+        }
+    }
+
+    pub fn with_prefix_lengths(mut self, prefix_lengths: Vec<(u32, u32)>) -> Self {
+        debug_assert!(prefix_lengths.iter().all(|(len, _)| *len <= self.width));
+        debug_assert!(prefix_lengths.iter().any(|(_, weight)| *weight > 0));
+        self.prefix_lengths = prefix_lengths;
+        self
+    }
+
+    fn prefix_len(&self, rng: &mut Rng) -> u32 {
+        let total: u32 = self.prefix_lengths.iter().map(|(_, w)| w).sum();
+        let mut pick = rng.below(total.into()) as u32;
+        for &(len, weight) in self.prefix_lengths.iter() {
+            if pick < weight {
+                return len;
+            }
+            pick -= weight;
+        }
+        unreachable!()
+    }
+
+    fn random(&self, rng: &mut Rng) -> Field {
+        rng.next() as Field & fields::prefix_mask(self.width)
+    }
+}
+
+// Reproducible synthetic rule sets and traces for benches and tests, f.e.
+// `Generator::five_tuple(7).rules(10_000)`. Rules draw their values from a few networks per
+// dimension (see `with_locality`) to get the nesting and shared prefixes of real rule sets, and
+// traces consist of packets derived from those rules plus uniformly random background traffic.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    specs: Vec<FieldSpec>,
+    locality: usize,
+    hit_ratio: f64,
+    rng: Rng,
+    // values the rules of every dimension are derived from
+    bases: Vec<Vec<Field>>,
+}
+
+impl Generator {
+    pub fn new(seed: u64, specs: Vec<FieldSpec>) -> Self {
+        let mut generator = Self {
+            specs,
+            locality: 64,
+            hit_ratio: 0.9,
+            rng: Rng(seed),
+            bases: Vec::new(),
+        };
+        generator.draw_bases();
+        generator
+    }
+
+    // Matches the layout of `presets::five_tuple` with address prefixes skewed towards /24,
+    // as in firewall and ACL rule sets.
+    pub fn five_tuple(seed: u64) -> Self {
+        let address = FieldSpec::new(IPV4_WIDTH).with_prefix_lengths(vec![
+            (0, 2),
+            (8, 1),
+            (16, 2),
+            (24, 4),
+            (32, 3),
+        ]);
+        let port = FieldSpec::new(PORT_WIDTH).with_prefix_lengths(vec![(0, 6), (16, 4)]);
+        let protocol = FieldSpec::new(PROTOCOL_WIDTH).with_prefix_lengths(vec![(0, 1), (8, 3)]);
+        Self::new(
+            seed,
+            vec![address.clone(), address, port.clone(), port, protocol],
+        )
+    }
+
+    // Number of distinct values every dimension draws from, fewer means more rules sharing
+    // prefixes.
+    pub fn with_locality(mut self, locality: usize) -> Self {
+        debug_assert!(locality > 0);
+        self.locality = locality;
+        self.draw_bases();
+        self
+    }
+
+    // Share of trace packets derived from a rule, the rest is random and mostly unmatched.
+    pub fn with_hit_ratio(mut self, hit_ratio: f64) -> Self {
+        debug_assert!((0.0..=1.0).contains(&hit_ratio));
+        self.hit_ratio = hit_ratio;
+        self
+    }
+
+    fn draw_bases(&mut self) {
+        let Self {
+            specs,
+            rng,
+            locality,
+            ..
+        } = self;
+        self.bases = specs
+            .iter()
+            .map(|spec| (0..*locality).map(|_| spec.random(rng)).collect())
+            .collect();
+    }
+
+    // Rules with unique priorities, the first one being the highest.
+    pub fn rules(&mut self, n: usize) -> Vec<GeneratedRule> {
+        let Self {
+            specs, bases, rng, ..
+        } = self;
+        (0..n)
+            .map(|i| {
+                let (fields, masks) = specs
+                    .iter()
+                    .zip(bases.iter())
+                    .map(|(spec, bases)| {
+                        let base = bases[rng.below(bases.len() as u64) as usize];
+                        let mask = fields::prefix_mask(spec.prefix_len(rng));
+                        (base & mask, mask)
+                    })
+                    .unzip();
+                GeneratedRule {
+                    fields,
+                    masks,
+                    priority: (n - i) as Priority,
+                }
+            })
+            .collect()
+    }
+
+    // Packets hitting random rules of `rules` with the configured hit ratio, the bits a rule
+    // does not care about being random.
+    pub fn trace(&mut self, rules: &[GeneratedRule], n: usize) -> Vec<GeneratedPacket> {
+        let Self {
+            specs,
+            rng,
+            hit_ratio,
+            ..
+        } = self;
+        (0..n)
+            .map(|_| {
+                let rule = if !rules.is_empty() && rng.unit() < *hit_ratio {
+                    Some(&rules[rng.below(rules.len() as u64) as usize])
+                } else {
+                    None
+                };
+
+                let fields = specs
+                    .iter()
+                    .enumerate()
+                    .map(|(dim, spec)| {
+                        let random = spec.random(rng);
+                        match rule {
+                            Some(r) => r.fields[dim] | (random & !r.masks[dim]),
+                            None => random,
+                        }
+                    })
+                    .collect();
+                GeneratedPacket(fields)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::RVHClassifier;
+
+    #[test]
+    fn test_generated_traces_hit_generated_rules() {
+        let mut generator = Generator::five_tuple(7).with_hit_ratio(1.0);
+        let rules = generator.rules(1000);
+        assert_eq!(rules, Generator::five_tuple(7).rules(1000));
+        assert_ne!(rules, Generator::five_tuple(8).rules(1000));
+
+        let mut rvh = RVHClassifier::<GeneratedRule>::five_tuple();
+        for rule in rules.iter() {
+            assert!(rvh.add_rule(rule.clone()).is_ok());
+        }
+        let trace = generator.trace(&rules, 1000);
+        assert!(trace.iter().all(|p| rvh.classify(p).is_some()));
+
+        // fewer bases, more rules sharing a source prefix
+        let distinct = |rules: &[GeneratedRule]| {
+            let mut sources: Vec<_> = rules.iter().map(|r| r.fields[0]).collect();
+            sources.sort_unstable();
+            sources.dedup();
+            sources.len()
+        };
+        let local = Generator::five_tuple(7).with_locality(4).rules(1000);
+        assert!(distinct(&local) < distinct(&rules));
+        assert!(local
+            .iter()
+            .all(|r| r.masks[2] == 0 || r.masks[2] == 0xffff));
+    }
+}
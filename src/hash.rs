@@ -0,0 +1,196 @@
+use std::collections::hash_map::RandomState;
+// std's SipHasher is deprecated in favor of `DefaultHasher`, which can not be keyed
+#[allow(deprecated)]
+use std::hash::SipHasher;
+use std::hash::{BuildHasher, BuildHasherDefault, Hasher};
+
+use crate::dictionary::Dictionary;
+use crate::types::FieldType;
+
+// Fast hasher for tables that do not need to withstand crafted traffic. Every word is mixed in
+// with a multiply-rotate step as in FxHash, and the result goes through the finalizer of
+// MurmurHash3, so that masked fields only differing in a few bits still end up in different
+// buckets. It is not keyed, an attacker who knows the rules can craft colliding packets, see
+// `SipBuildHasher`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MixHasher {
+    hash: u64,
+}
+
+const K: u64 = 0x517c_c1b7_2722_0a95;
+
+impl Hasher for MixHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.write_u64(u64::from_le_bytes(word));
+        }
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ i).wrapping_mul(K);
+    }
+
+    fn finish(&self) -> u64 {
+        let mut h = self.hash;
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        h ^ (h >> 33)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MixBuildHasher;
+
+impl BuildHasher for MixBuildHasher {
+    type Hasher = MixHasher;
+
+    fn build_hasher(&self) -> MixHasher {
+        MixHasher::default()
+    }
+}
+
+// Default hasher of the tables, SipHash-2-4 with random keys drawn for every classifier, so
+// that flows can not be crafted to collide in one bucket. The keys are serialized with the
+// classifier, so that a restored classifier finds its rules again. `with_keys` gives a
+// deterministic hasher, f.e. for reproducible tests or a `static` classifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SipBuildHasher {
+    k0: u64,
+    k1: u64,
+}
+
+impl SipBuildHasher {
+    pub fn random() -> Self {
+        // every RandomState is seeded differently, from keys the OS provided
+        let state = RandomState::new();
+        Self::with_keys(state.hash_one(0u64), state.hash_one(1u64))
+    }
+
+    pub const fn with_keys(k0: u64, k1: u64) -> Self {
+        Self { k0, k1 }
+    }
+}
+
+impl Default for SipBuildHasher {
+    fn default() -> Self {
+        Self::random()
+    }
+}
+
+// `DefaultHasher` can not be built from the keys of `with_keys`, the deprecated hasher can
+#[allow(deprecated)]
+impl BuildHasher for SipBuildHasher {
+    type Hasher = SipHasher;
+
+    fn build_hasher(&self) -> SipHasher {
+        SipHasher::new_with_keys(self.k0, self.k1)
+    }
+}
+
+// Hasher of the maps from bucket hashes to buckets, their keys are hashes already.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct PassThrough(u64);
+
+impl Hasher for PassThrough {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = self.0 << 8 | u64::from(*b);
+        }
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.0 = i;
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+pub(crate) type Prehashed = BuildHasherDefault<PassThrough>;
+
+// Bucket of the masked `fields` in a table with `masks`, `seed` selects one of several hash
+// functions of the same hasher. Missing fields are hashed as zero, so that rules leaving out
+// trailing wildcards share the bucket of the packets they match. Values of dimensions with a
+// dictionary are hashed by their code, None if a value has none.
+pub(crate) fn calc_hash<'a, F: FieldType>(
+    hasher: &impl BuildHasher,
+    masks: &[F],
+    seed: u32,
+    dictionaries: &[Option<Dictionary<F>>],
+    mut fields: impl Iterator<Item = &'a F>,
+) -> Option<u64> {
+    let mut h = hasher.build_hasher();
+    h.write_u32(seed);
+    for (dim, m) in masks.iter().enumerate() {
+        let masked = fields.next().map_or(F::ZERO, |f| *f & *m);
+        match dictionaries.get(dim) {
+            Some(Some(dictionary)) => h.write_u32(dictionary.code(masked)?),
+            _ => {
+                for i in 0..F::WORDS {
+                    h.write_u32(masked.word(i));
+                }
+            }
+        }
+    }
+
+    Some(h.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_nearby_fields_are_spread() {
+        let hasher = MixBuildHasher;
+        let masks = [u32::MAX, u32::MAX];
+
+        // the XOR hash mapped all of these to a handful of buckets
+        let hashes: HashSet<u64> = (0..256u32)
+            .flat_map(|a| (0..4u32).map(move |b| [a, b ^ a]))
+            .map(|fields| calc_hash(&hasher, &masks, 0, &[], fields.iter()).unwrap())
+            .collect();
+        assert_eq!(hashes.len(), 1024);
+
+        assert_ne!(
+            calc_hash(&hasher, &masks, 0, &[], [1, 2].iter()).unwrap(),
+            calc_hash(&hasher, &masks, 1, &[], [1, 2].iter()).unwrap()
+        );
+        // bits outside the masks are ignored
+        assert_eq!(
+            calc_hash(&hasher, &[0xffu32], 0, &[], [0x1ff].iter()).unwrap(),
+            calc_hash(&hasher, &[0xffu32], 0, &[], [0xff].iter()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_classifiers_use_different_keys() {
+        let masks = [u32::MAX];
+        let hash = |hasher: SipBuildHasher| calc_hash(&hasher, &masks, 0, &[], [1].iter()).unwrap();
+
+        assert_ne!(
+            hash(SipBuildHasher::random()),
+            hash(SipBuildHasher::random())
+        );
+        assert_eq!(
+            hash(SipBuildHasher::with_keys(1, 2)),
+            hash(SipBuildHasher::with_keys(1, 2))
+        );
+        assert_ne!(
+            hash(SipBuildHasher::with_keys(1, 2)),
+            hash(SipBuildHasher::with_keys(2, 1))
+        );
+    }
+}
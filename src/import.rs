@@ -0,0 +1,253 @@
+use std::fmt;
+
+use crate::rules::FiveTupleRule;
+use crate::types::*;
+
+// A construct of an imported rule set rvh can not represent, f.e. a conntrack state match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unsupported {
+    // 1-based line of the rule in the source
+    pub line: usize,
+    // short name of the construct, f.e. "--ctstate"
+    pub construct: String,
+    // the whole line, for reporting
+    pub text: String,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}: unsupported {}: {}",
+            self.line, self.construct, self.text
+        )
+    }
+}
+
+// Result of importing a rule set. Rules with unsupported constructs are left out and listed
+// instead of failing the whole import, so that callers decide whether a partial rule set is
+// good enough.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportReport<R> {
+    pub rules: Vec<R>,
+    pub unsupported: Vec<Unsupported>,
+}
+
+impl<R> Default for ImportReport<R> {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            unsupported: Vec::new(),
+        }
+    }
+}
+
+impl<R> ImportReport<R> {
+    // True if every rule of the source was imported.
+    pub fn is_complete(&self) -> bool {
+        self.unsupported.is_empty()
+    }
+
+    // Records a rule that could not be imported.
+    pub fn skip(&mut self, line: usize, construct: impl Into<String>, text: impl Into<String>) {
+        self.unsupported.push(Unsupported {
+            line,
+            construct: construct.into(),
+            text: text.into(),
+        });
+    }
+
+    // Number of skipped rules per construct, most frequent first.
+    pub fn summary(&self) -> Vec<(&str, usize)> {
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        for u in self.unsupported.iter() {
+            match counts.iter_mut().find(|(c, _)| *c == u.construct) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((&u.construct, 1)),
+            }
+        }
+        counts.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+        counts
+    }
+}
+
+// Where a rule came from, for audited environments that have to trace every installed rule
+// back to its origin. Importers fill in the file and line, the rest is up to the caller.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Provenance {
+    pub comment: Option<String>,
+    pub source_file: Option<String>,
+    // 1-based line of the rule in `source_file`
+    pub line: Option<usize>,
+    pub author: Option<String>,
+}
+
+impl Provenance {
+    pub fn at(source_file: impl Into<String>, line: usize) -> Self {
+        Self {
+            source_file: Some(source_file.into()),
+            line: Some(line),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    pub fn with_author(mut self, author: impl Into<String>) -> Self {
+        self.author = Some(author.into());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+// f.e. `rules.v4:12 (alice): allow ssh`
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(file) = &self.source_file {
+            parts.push(match self.line {
+                Some(line) => format!("{}:{}", file, line),
+                None => file.clone(),
+            });
+        }
+        if let Some(author) = &self.author {
+            parts.push(format!("({})", author));
+        }
+        write!(f, "{}", parts.join(" "))?;
+        match &self.comment {
+            Some(comment) if parts.is_empty() => write!(f, "{}", comment),
+            Some(comment) => write!(f, ": {}", comment),
+            None => Ok(()),
+        }
+    }
+}
+
+// A rule with its provenance, which travels along wherever the rule goes, f.e. through the
+// serialized classifier or `changes_since`. Matches like the rule, rules only differing in
+// their provenance are not equal though.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Annotated<R> {
+    pub rule: R,
+    pub provenance: Provenance,
+}
+
+// The rule of the import and export formats, a five-tuple rule with its action, f.e. the
+// target of an iptables rule, and where it came from.
+pub type GenericRule<A = String> = Annotated<FiveTupleRule<A>>;
+
+impl<R> Annotated<R> {
+    pub fn new(rule: R, provenance: Provenance) -> Self {
+        Self { rule, provenance }
+    }
+}
+
+impl<R: Rule<F>, F: FieldType> Rule<F> for Annotated<R> {
+    fn priority(&self) -> Priority {
+        self.rule.priority()
+    }
+    fn masks(&self) -> &[F] {
+        self.rule.masks()
+    }
+    fn fields(&self) -> &[F] {
+        self.rule.fields()
+    }
+    fn fields_mut(&mut self) -> Option<&mut [F]> {
+        self.rule.fields_mut()
+    }
+}
+
+impl<R: ActionRule<F>, F: FieldType> ActionRule<F> for Annotated<R> {
+    type Action = R::Action;
+
+    fn action(&self) -> &R::Action {
+        self.rule.action()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skipped_rules_are_summarized() {
+        let mut report = ImportReport::<u32>::default();
+        assert!(report.is_complete());
+        report.rules.push(1);
+        report.skip(
+            2,
+            "--ctstate",
+            "-A INPUT -m conntrack --ctstate NEW -j ACCEPT",
+        );
+        report.skip(5, "-m limit", "-A INPUT -m limit --limit 5/s -j ACCEPT");
+        report.skip(
+            7,
+            "--ctstate",
+            "-A INPUT -m conntrack --ctstate INVALID -j DROP",
+        );
+
+        assert!(!report.is_complete());
+        assert_eq!(report.summary(), vec![("--ctstate", 2), ("-m limit", 1)]);
+        assert_eq!(
+            report.unsupported[1].to_string(),
+            "line 5: unsupported -m limit: -A INPUT -m limit --limit 5/s -j ACCEPT"
+        );
+    }
+
+    #[test]
+    fn test_generic_rules_keep_their_provenance() {
+        use crate::openflow;
+
+        let rule: GenericRule = Annotated::new(
+            FiveTupleRule::new(5)
+                .dst_port(22)
+                .protocol(6)
+                .with_action("ACCEPT".to_string()),
+            Provenance::at("rules.v4", 3).with_author("alice"),
+        );
+        let entry = openflow::export(&rule, rule.action().clone()).unwrap();
+        assert_eq!(entry.matches["tp_dst"], "22");
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&rule).unwrap();
+            assert_eq!(serde_json::from_str::<GenericRule>(&json).unwrap(), rule);
+        }
+    }
+
+    #[test]
+    fn test_provenance_travels_with_the_rule() {
+        use crate::classifier::RVHClassifier;
+        use crate::types::mocks::{MockPacket, MockRule};
+
+        let provenance = Provenance::at("rules.v4", 12)
+            .with_author("alice")
+            .with_comment("allow ssh");
+        assert_eq!(provenance.to_string(), "rules.v4:12 (alice): allow ssh");
+        assert_eq!(Provenance::default().with_comment("x").to_string(), "x");
+        assert!(Provenance::default().is_empty() && !provenance.is_empty());
+
+        let mut rvh = RVHClassifier::<Annotated<MockRule>>::new(vec![vec![(0, 4)]].into_iter());
+        let rule = MockRule::new(vec![0b101], vec![0b111], 2);
+        let id = rvh
+            .add_rule(Annotated::new(rule, provenance.clone()))
+            .unwrap();
+        let matched = rvh.classify(&MockPacket::new(vec![0b101])).unwrap();
+        assert_eq!(matched.provenance, provenance);
+        assert_eq!(rvh.get(id), Some(matched));
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&rvh).unwrap();
+            let restored: RVHClassifier<Annotated<MockRule>> = serde_json::from_str(&json).unwrap();
+            assert_eq!(restored.get(id).unwrap().provenance, provenance);
+        }
+    }
+}
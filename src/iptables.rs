@@ -0,0 +1,290 @@
+use crate::fields::{self, PORT_WIDTH};
+use crate::import::ImportReport;
+use crate::openflow;
+use crate::rules::FiveTupleRule;
+
+#[cfg(feature = "test-utils")]
+use crate::crossval::{Reference, Semantics, SourceRule};
+
+// A rule of an iptables chain, its action is the target of the rule, f.e. "ACCEPT". Rules with
+// port ranges expand into several rules sharing the target.
+pub type IptablesRule = FiveTupleRule<String>;
+
+// Targets ending the traversal of the chain. Others, f.e. LOG or a jump to a user defined
+// chain, go on with the following rules and have no verdict of their own.
+const VERDICTS: [&str; 3] = ["ACCEPT", "DROP", "REJECT"];
+
+// Match modules that only provide the options read below.
+const MODULES: [&str; 4] = ["tcp", "udp", "sctp", "comment"];
+
+// Reads the rules of a chain of the filter table from `iptables-save` output, f.e.
+// `-A INPUT -s 10.0.0.0/8 -p tcp -m tcp --dport 22 -j ACCEPT`. Port ranges like
+// `--dport 1024:65535` are expanded into prefixes. The first rule has the highest priority and
+// every rule gets a priority of its own. Rules with other options, f.e. interfaces, negations
+// or conntrack matches, are skipped with the first such option.
+pub fn parse(text: &str, chain: &str) -> ImportReport<IptablesRule> {
+    let mut report = ImportReport::default();
+    for (i, words) in lines(text) {
+        if words.len() < 2 || words[0] != "-A" || words[1] != chain {
+            continue;
+        }
+        match rule(&words[2..]) {
+            Ok(rules) => report.rules.extend(rules),
+            Err(construct) => report.skip(i + 1, construct, text.lines().nth(i).unwrap().trim()),
+        }
+    }
+
+    // rules of later lines are placed below the earlier ones
+    let count = report.rules.len();
+    report.rules = report
+        .rules
+        .into_iter()
+        .enumerate()
+        .map(|(i, rule)| rule.with_priority((count - i) as u32))
+        .collect();
+    report
+}
+
+// The policy of a built-in chain of the filter table, f.e. "DROP" for `:INPUT DROP [0:0]`.
+// None for user defined chains, which return to the calling chain instead.
+pub fn policy(text: &str, chain: &str) -> Option<String> {
+    lines(text)
+        .filter_map(|(_, words)| match words.as_slice() {
+            [name, policy, ..] if name.strip_prefix(':') == Some(chain) && *policy != "-" => {
+                Some(policy.to_string())
+            }
+            _ => None,
+        })
+        .next()
+}
+
+// The imported rules of a chain interpreted in chain order, as reference for
+// `crossval::cross_check`. Packets no rule matches get the policy of the chain.
+#[cfg(feature = "test-utils")]
+pub fn reference(report: &ImportReport<IptablesRule>, policy: &str) -> Reference<String> {
+    use crate::types::{ActionRule, Rule};
+
+    let rules = report
+        .rules
+        .iter()
+        .map(|r| SourceRule {
+            matches: r
+                .fields()
+                .iter()
+                .copied()
+                .zip(r.masks().iter().copied())
+                .collect(),
+            priority: r.priority(),
+            verdict: r.action().clone(),
+        })
+        .collect();
+    Reference::new(rules, Semantics::FirstMatch, policy.to_string())
+}
+
+// The words of the lines of the filter table with their 0-based line. Lines in front of the
+// first table are taken as part of it.
+fn lines(text: &str) -> impl Iterator<Item = (usize, Vec<&str>)> {
+    let mut filter = true;
+    text.lines().enumerate().filter_map(move |(i, line)| {
+        let line = line.trim();
+        if let Some(table) = line.strip_prefix('*') {
+            filter = table == "filter";
+        }
+        if !filter || line.is_empty() || line.starts_with(['#', '*']) || line == "COMMIT" {
+            return None;
+        }
+        Some((i, words(line)))
+    })
+}
+
+// Splits at whitespace, keeping quoted words like the text of `--comment "allow ssh"` whole.
+fn words(line: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        let (word, next) = match rest.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+        };
+        words.push(word);
+        rest = next.trim_start();
+    }
+    words
+}
+
+// Fails with the option that can not be represented.
+fn rule(options: &[&str]) -> Result<Vec<IptablesRule>, String> {
+    let mut rule = FiveTupleRule::new(0);
+    let (mut src_ports, mut dst_ports) = (vec![(0, 0)], vec![(0, 0)]);
+    let mut target = None;
+
+    // all supported options take a value, `!` and flags like `-f` are not supported
+    for pair in options.chunks(2) {
+        let (option, value) = match *pair {
+            [option, value] => (option, value),
+            [option] => return Err(option.to_string()),
+            _ => unreachable!(),
+        };
+        match option {
+            "-s" | "--source" => {
+                let (addr, len) = openflow::ipv4(value).ok_or(option)?;
+                rule = rule.src_prefix(addr, len);
+            }
+            "-d" | "--destination" => {
+                let (addr, len) = openflow::ipv4(value).ok_or(option)?;
+                rule = rule.dst_prefix(addr, len);
+            }
+            "-p" | "--protocol" => {
+                if let Some(protocol) = protocol(value).ok_or(option)? {
+                    rule = rule.protocol(protocol);
+                }
+            }
+            "-m" | "--match" if MODULES.contains(&value) => {}
+            "-m" | "--match" => return Err(format!("-m {}", value)),
+            "--sport" | "--source-port" => src_ports = ports(value).ok_or(option)?,
+            "--dport" | "--destination-port" => dst_ports = ports(value).ok_or(option)?,
+            "--comment" => {}
+            "-j" | "--jump" if VERDICTS.contains(&value) => target = Some(value),
+            "-j" | "--jump" => return Err(format!("-j {}", value)),
+            _ => return Err(option.to_string()),
+        }
+    }
+    let target = target.ok_or("no target")?;
+
+    let mut rules = Vec::with_capacity(src_ports.len() * dst_ports.len());
+    for &(src_port, src_port_len) in src_ports.iter() {
+        for &(dst_port, dst_port_len) in dst_ports.iter() {
+            rules.push(
+                rule.clone()
+                    .src_port_prefix(src_port as u16, src_port_len)
+                    .dst_port_prefix(dst_port as u16, dst_port_len)
+                    .with_action(target.to_string()),
+            );
+        }
+    }
+    Ok(rules)
+}
+
+// None for `all`, which matches any protocol.
+fn protocol(value: &str) -> Option<Option<u8>> {
+    Some(match value {
+        "all" => None,
+        "icmp" => Some(1),
+        "tcp" => Some(6),
+        "udp" => Some(17),
+        "sctp" => Some(132),
+        number => Some(number.parse().ok()?),
+    })
+}
+
+// f.e. `22`, `1024:65535` or `:1023`
+fn ports(value: &str) -> Option<Vec<(u32, u32)>> {
+    let (lo, hi) = value.split_once(':').unwrap_or((value, value));
+    let bound = |bound: &str, open: u16| match bound {
+        "" => Some(open),
+        bound => bound.parse().ok(),
+    };
+    let (lo, hi) = (bound(lo, 0)?, bound(hi, u16::MAX)?);
+    (lo <= hi).then(|| fields::range_prefixes(lo.into(), hi.into(), PORT_WIDTH))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::FiveTuple;
+    use crate::types::{ActionRule, Rule};
+    use crate::RVHClassifier;
+    use std::net::Ipv4Addr;
+
+    const SAVED: &str = "\
+# Generated by iptables-save
+*nat
+:PREROUTING ACCEPT [0:0]
+-A PREROUTING -p tcp -m tcp --dport 80 -j DNAT --to-destination 10.0.0.2
+COMMIT
+*filter
+:INPUT DROP [0:0]
+:FORWARD ACCEPT [0:0]
+:ssh - [0:0]
+-A INPUT -m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT
+-A INPUT -s 10.0.0.0/8 -p tcp -m tcp --dport 22 -m comment --comment \"admin ssh\" -j ACCEPT
+-A INPUT -i lo -j ACCEPT
+-A INPUT -s 10.1.0.0/16 -j DROP
+-A INPUT -p udp -m udp --sport 53 --dport 1024:65535 -j ACCEPT
+-A INPUT ! -s 192.168.0.0/16 -p tcp -j REJECT
+-A INPUT -p tcp -j LOG
+-A INPUT -p tcp -m tcp --dport 22 -j ssh
+-A FORWARD -j ACCEPT
+COMMIT
+";
+
+    #[test]
+    fn test_chain_is_imported_in_order() {
+        let report = parse(SAVED, "INPUT");
+        let skipped: Vec<_> = report
+            .unsupported
+            .iter()
+            .map(|u| (u.line, u.construct.as_str()))
+            .collect();
+        assert_eq!(
+            skipped,
+            vec![
+                (10, "-m conntrack"),
+                (12, "-i"),
+                (15, "!"),
+                (16, "-j LOG"),
+                (17, "-j ssh")
+            ]
+        );
+        assert_eq!(
+            report.unsupported[1].text,
+            "-A INPUT -i lo -j ACCEPT".to_string()
+        );
+        // the port range expands into six rules
+        assert_eq!(report.rules.len(), 8);
+        assert_eq!(report.rules[0].priority(), 8);
+        assert_eq!(policy(SAVED, "INPUT").as_deref(), Some("DROP"));
+        assert_eq!(policy(SAVED, "ssh"), None);
+        assert_eq!(policy(SAVED, "PREROUTING"), None);
+
+        let mut rvh = RVHClassifier::<IptablesRule>::five_tuple();
+        for rule in report.rules.iter().cloned() {
+            assert!(rvh.add_rule(rule).is_ok());
+        }
+        let verdict = |src: [u8; 4], sport, dport, protocol| {
+            let p = FiveTuple::new(
+                src.into(),
+                Ipv4Addr::new(10, 0, 0, 1),
+                sport,
+                dport,
+                protocol,
+            );
+            rvh.classify(&p).map(|r| r.action().as_str())
+        };
+        // the earlier ssh rule wins over the drop of its subnet
+        assert_eq!(verdict([10, 1, 2, 3], 40000, 22, 6), Some("ACCEPT"));
+        assert_eq!(verdict([10, 1, 2, 3], 40000, 23, 6), Some("DROP"));
+        assert_eq!(verdict([8, 8, 8, 8], 53, 40000, 17), Some("ACCEPT"));
+        assert_eq!(verdict([8, 8, 8, 8], 53, 1000, 17), None);
+
+        #[cfg(feature = "test-utils")]
+        {
+            use crate::crossval;
+
+            let reference = reference(&report, &policy(SAVED, "INPUT").unwrap());
+            let mut sample = Vec::new();
+            for net in 0..3 {
+                for protocol in [1, 6, 17] {
+                    for dport in [22, 23, 1000, 40000] {
+                        let src = Ipv4Addr::new(10, net, 0, 1);
+                        let dst = Ipv4Addr::new(10, 0, 0, 1);
+                        sample.push(FiveTuple::new(src, dst, 53, dport, protocol));
+                    }
+                }
+            }
+            let mismatches =
+                crossval::cross_check(&rvh, &reference, |r| r.action().clone(), &sample);
+            assert!(mismatches.is_empty());
+        }
+    }
+}
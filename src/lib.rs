@@ -1,13 +1,83 @@
+// lets `::rvh` paths of derived impls resolve within the crate
+extern crate self as rvh;
+
+pub mod adaptive;
+pub mod analysis;
+mod auto;
+pub mod bands;
+mod bloom;
+pub mod cache;
+mod changes;
+pub mod classbench;
 mod classifier;
+pub mod codegen;
+mod composite;
+#[cfg(feature = "concurrent")]
+mod concurrent;
+#[cfg(feature = "test-utils")]
+pub mod crossval;
+mod dictionary;
+pub mod dimensions;
+pub mod equivalence;
+mod error;
+pub mod extract;
+pub mod fields;
+mod frozen;
+pub mod generate;
+pub mod hash;
+pub mod import;
+pub mod iptables;
+mod linear;
+mod macros;
+pub mod minimize;
+mod offload;
+pub mod openflow;
+#[cfg(feature = "rayon")]
+mod parallel;
+pub mod parse;
+mod perfect;
+#[cfg(feature = "rate-limit")]
+pub mod police;
+mod pool;
+mod prefilter;
+pub mod presets;
 mod range_vector_hash_map;
+mod rebuild;
+pub mod replay;
+mod replicated;
+pub mod rules;
+#[cfg(feature = "simd")]
+mod simd;
+pub mod simulate;
+pub mod split;
+mod table;
+pub mod telemetry;
+mod tenants;
 pub mod types;
 
 pub mod prelude {
     pub use super::classifier::RVHClassifier;
+    pub use super::error::RvhError;
+    pub use super::frozen::{CompiledRVHClassifier, FrozenRVHClassifier};
     pub use super::types::*;
 }
 
-pub use classifier::RVHClassifier;
+pub use auto::{AutoClassifier, Backend, Classifier};
+pub use changes::Changes;
+pub use classifier::{
+    BudgetedMatch, Decision, Explanation, Probe, RVHClassifier, RuleMut, TableProbe, TryMatch,
+};
+pub use composite::{CompositeClassifier, MergePolicy};
+#[cfg(feature = "concurrent")]
+pub use concurrent::{ConcurrentRVHClassifier, ConcurrentReader, MigrationError};
+pub use error::RvhError;
+pub use frozen::{CompiledRVHClassifier, FrozenRVHClassifier};
+pub use linear::LinearClassifier;
+pub use offload::{OffloadSink, OffloadedClassifier};
+pub use rebuild::{Rebuild, RebuildProgress};
+pub use replicated::{ReplicaHandle, ReplicatedClassifier};
+pub use table::RVHTable;
+pub use tenants::MultiTenantClassifier;
 
 #[cfg(test)]
 mod tests {
@@ -15,4 +85,49 @@ mod tests {
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derived_rule() {
+        use crate::extract::FiveTuple;
+        use crate::prelude::*;
+        use std::net::Ipv4Addr;
+
+        #[derive(Debug, PartialEq, Rule)]
+        struct Acl<'a> {
+            name: &'a str,
+            #[rvh(field)]
+            fields: [Field; 5],
+            #[rvh(mask)]
+            masks: Vec<Mask>,
+            #[rvh(priority)]
+            priority: Priority,
+        }
+
+        #[derive(Debug, PartialEq, Rule)]
+        #[rvh(field_type = u128)]
+        struct Wide(
+            #[rvh(priority)] Priority,
+            #[rvh(field)] Vec<u128>,
+            #[rvh(mask)] Vec<u128>,
+        );
+        assert_eq!(Rule::<u128>::masks(&Wide(1, vec![7], vec![0])), &[0]);
+
+        let ssh = crate::rules::FiveTupleRule::new(5).dst_port(22);
+        let mut fields = [0; 5];
+        fields.copy_from_slice(ssh.fields());
+        let mut rvh = RVHClassifier::five_tuple();
+        let acl = |name, fields, masks: &[Mask], priority| Acl {
+            name,
+            fields,
+            masks: masks.to_vec(),
+            priority,
+        };
+        assert!(rvh.add_rule(acl("ssh", fields, ssh.masks(), 5)).is_ok());
+        assert!(rvh.add_rule(acl("any", [0; 5], &[0; 5], 1)).is_ok());
+
+        let p = |port| FiveTuple::new(Ipv4Addr::LOCALHOST, Ipv4Addr::LOCALHOST, 4000, port, 6);
+        assert_eq!(rvh.classify(&p(22)).unwrap().name, "ssh");
+        assert_eq!(rvh.classify(&p(80)).unwrap().name, "any");
+    }
 }
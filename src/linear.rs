@@ -0,0 +1,110 @@
+use crate::auto::Classifier;
+use crate::error::RvhError;
+use crate::range_vector_hash_map::{self, rule_matches};
+use crate::types::*;
+
+// Rules sorted by descending priority, compared one by one. Obviously correct, so it serves as
+// the baseline of benchmarks and as the oracle of differential tests against the classifiers
+// of this crate. Unlike the tables of `RVHClassifier` priorities have to be unique overall.
+#[derive(Debug, Clone)]
+pub struct LinearClassifier<R> {
+    rules: Vec<R>,
+}
+
+impl<R> Default for LinearClassifier<R> {
+    fn default() -> Self {
+        Self { rules: Vec::new() }
+    }
+}
+
+impl<R> LinearClassifier<R> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Rules in the order they are compared, highest priority first.
+    pub fn rules(&self) -> &[R] {
+        &self.rules
+    }
+}
+
+impl<R: Rule<F>, F: FieldType> Classifier<R, F> for LinearClassifier<R> {
+    fn insert(&mut self, mut rule: R) -> Result<(), RvhError> {
+        if rule.fields().len() != rule.masks().len() {
+            return Err(RvhError::ArityMismatch {
+                fields: rule.fields().len(),
+                masks: rule.masks().len(),
+            });
+        }
+        if let Some(dimension) = range_vector_hash_map::invalid_mask(&rule) {
+            return Err(RvhError::InvalidMask { dimension });
+        }
+
+        let index = self
+            .rules
+            .partition_point(|r| r.priority() > rule.priority());
+        if self
+            .rules
+            .get(index)
+            .is_some_and(|r| r.priority() == rule.priority())
+        {
+            return Err(RvhError::DuplicatePriority);
+        }
+
+        range_vector_hash_map::normalize(&mut rule);
+        self.rules.insert(index, rule);
+        Ok(())
+    }
+
+    fn remove_rule(&mut self, rule: &R) -> Result<(), RvhError> {
+        let index = self
+            .rules
+            .iter()
+            .position(|r| r == rule)
+            .ok_or(RvhError::NotFound)?;
+        self.rules.remove(index);
+        Ok(())
+    }
+
+    fn classify(&self, p: &impl Packet<F>) -> Option<&R> {
+        self.rules
+            .iter()
+            .find(|r| r.priority() > 0 && rule_matches(*r, p))
+    }
+
+    fn len(&self) -> usize {
+        self.rules.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::RVHClassifier;
+    use crate::generate::{GeneratedRule, Generator};
+
+    #[test]
+    fn test_rvh_agrees_with_linear_scan() {
+        for seed in 0..4 {
+            let mut generator = Generator::five_tuple(seed)
+                .with_locality(16)
+                .with_hit_ratio(0.7);
+            let rules = generator.rules(500);
+            let mut linear = LinearClassifier::new();
+            let mut rvh = RVHClassifier::<GeneratedRule>::five_tuple();
+            for rule in rules.iter() {
+                assert!(linear.insert(rule.clone()).is_ok());
+                assert!(rvh.add_rule(rule.clone()).is_ok());
+            }
+            // removals must not leave stale matches behind in either
+            for rule in rules.iter().step_by(7) {
+                assert!(linear.remove_rule(rule).is_ok());
+                assert!(rvh.remove_rule(rule).is_ok());
+            }
+
+            for p in generator.trace(&rules, 2000) {
+                assert_eq!(rvh.classify(&p), linear.classify(&p), "seed {}", seed);
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+// Builds a `rules::FiveTupleRule` from key value pairs, f.e.
+// `rule!(prio 100, src "10.0.0.0/8", dport 22, proto tcp => "accept")`. Keys are the same as
+// for the textual rules of `rules`, networks are checked at compile time with
+// `fields::ipv4_cidr`. The action after `=>` is optional.
+#[macro_export]
+macro_rules! rule {
+    (prio $priority:expr $(, $key:ident $value:tt)* $(,)? $(=> $action:expr)?) => {{
+        let rule = $crate::rules::FiveTupleRule::new($priority);
+        $(let rule = $crate::__rule_field!(rule, $key $value);)*
+        $(let rule = rule.with_action($action);)?
+        rule
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __rule_field {
+    ($rule:ident, src $net:literal) => {{
+        const NET: (u32, u32) = $crate::fields::ipv4_cidr($net);
+        $rule.src_prefix(::std::net::Ipv4Addr::from(NET.0), NET.1)
+    }};
+    ($rule:ident, dst $net:literal) => {{
+        const NET: (u32, u32) = $crate::fields::ipv4_cidr($net);
+        $rule.dst_prefix(::std::net::Ipv4Addr::from(NET.0), NET.1)
+    }};
+    ($rule:ident, sport $port:tt) => {
+        $rule.src_port($port)
+    };
+    ($rule:ident, dport $port:tt) => {
+        $rule.dst_port($port)
+    };
+    ($rule:ident, proto icmp) => {
+        $rule.protocol(1)
+    };
+    ($rule:ident, proto tcp) => {
+        $rule.protocol(6)
+    };
+    ($rule:ident, proto udp) => {
+        $rule.protocol(17)
+    };
+    ($rule:ident, proto sctp) => {
+        $rule.protocol(132)
+    };
+    ($rule:ident, proto $protocol:tt) => {
+        $rule.protocol($protocol)
+    };
+    ($rule:ident, dscp $dscp:tt) => {{
+        const _: () = assert!($dscp < 64, "invalid DSCP");
+        $rule.dscp($dscp)
+    }};
+}
+
+// Builds a classifier from rules in the syntax of `rule!`, one per braces, f.e.
+// `ruleset![{prio 20, dport 22 => "accept"}, {prio 10 => "drop"}]`. The classifier is a
+// `RVHClassifier::five_tuple` unless one is given in front, like
+// `ruleset![in RVHClassifier::new(ranges); {..}]`. Rules the classifier rejects, f.e. for
+// prefix lengths no table accepts, panic with their position.
+#[macro_export]
+macro_rules! ruleset {
+    (in $classifier:expr; $({ $($rule:tt)* }),* $(,)?) => {{
+        let mut classifier = $classifier;
+        let mut position = 0;
+        $(
+            position += 1;
+            if let Err(e) = classifier.add_rule($crate::rule!($($rule)*)) {
+                panic!("rule {} of the rule set is rejected: {}", position, e);
+            }
+        )*
+        let _ = position;
+        classifier
+    }};
+    ($({ $($rule:tt)* }),* $(,)?) => {
+        $crate::ruleset!(in $crate::RVHClassifier::five_tuple(); $({ $($rule)* }),*)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::extract::FiveTuple;
+    use crate::fields;
+    use crate::rules::FiveTupleRule;
+    use crate::types::{ActionRule, Rule};
+    use crate::RVHClassifier;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_rules_from_macros() {
+        let ssh = rule!(prio 100, src "10.0.0.0/8", dport 22, proto tcp => "accept");
+        assert_eq!(
+            ssh,
+            "src 10.0.0.0/8 dport 22 proto tcp prio 100"
+                .parse::<FiveTupleRule>()
+                .unwrap()
+                .with_action("accept")
+        );
+        assert_eq!(rule!(prio 1, dst "192.168.1.1", proto 47).priority(), 1);
+        assert_eq!(fields::ipv4_cidr("10.1.2.3/24"), (0x0a01_0203, 24));
+
+        let rvh = ruleset![
+            {prio 20, src "10.0.0.0/8", dport 22 => "accept"},
+            {prio 10, dscp 46 => "prioritize"},
+            {prio 1 => "drop"},
+        ];
+        assert_eq!(rvh.len(), 3);
+        let p = |src| FiveTuple::new(src, Ipv4Addr::new(1, 1, 1, 1), 5000, 22, 6).with_dscp(46);
+        assert_eq!(
+            *rvh.classify(&p(Ipv4Addr::new(10, 0, 0, 1)))
+                .unwrap()
+                .action(),
+            "accept"
+        );
+        assert_eq!(
+            *rvh.classify(&p(Ipv4Addr::new(11, 0, 0, 1)))
+                .unwrap()
+                .action(),
+            "prioritize"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "rule 2 of the rule set is rejected")]
+    fn test_rejected_rules_panic() {
+        let ranges = vec![vec![(8, 9), (0, 33), (0, 17), (0, 17), (0, 9)]];
+        let _ = ruleset![in RVHClassifier::<FiveTupleRule>::new(ranges.into_iter());
+            {prio 2, src "10.0.0.0/8"},
+            {prio 1, src "10.0.0.0/16"},
+        ];
+    }
+}
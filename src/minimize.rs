@@ -0,0 +1,173 @@
+use crate::analysis::overlap;
+use crate::equivalence;
+use crate::types::*;
+
+// Drops the rules that do not change the verdict of any packet with fields of the given
+// `widths`, f.e. rules shadowed by a higher priority rule or rules only matching packets that
+// lower priority rules with the same verdict would decide alike. Every removal is checked with
+// `equivalence::check` against the remaining rules, so the result classifies every packet as
+// the input did. Lower priority rules are looked at first.
+pub fn remove_redundant<R, F, V>(rules: Vec<R>, widths: &[u32], verdict: impl Fn(&R) -> V) -> Vec<R>
+where
+    R: Rule<F>,
+    F: FieldType,
+    V: PartialEq,
+{
+    let mut order: Vec<usize> = (0..rules.len()).collect();
+    order.sort_by_key(|i| rules[*i].priority());
+    let mut removed = vec![false; rules.len()];
+    for i in order {
+        // packets the rule does not match are decided alike either way
+        let relevant = |j: usize| j == i || (!removed[j] && overlap(&rules[i], &rules[j]));
+        let with: Vec<&R> = (0..rules.len())
+            .filter(|j| relevant(*j))
+            .map(|j| &rules[j])
+            .collect();
+        let without = (0..rules.len())
+            .filter(|j| *j != i && relevant(*j))
+            .map(|j| &rules[j]);
+        if equivalence::check(with, without, widths, &verdict).is_ok() {
+            removed[i] = true;
+        }
+    }
+
+    rules
+        .into_iter()
+        .zip(removed)
+        .filter(|(_, removed)| !removed)
+        .map(|(rule, _)| rule)
+        .collect()
+}
+
+// Repeatedly merges two rules with the same verdict that only differ in one dimension, where
+// their prefixes are siblings, f.e. 10.0.0.0/9 and 10.128.0.0/9 into 10.0.0.0/8. The merged
+// rule takes the place and priority of the higher priority one and is built by `with_field`
+// from it, replacing the field and mask of the given dimension. Like with `remove_redundant`
+// a merge is only done if the rule set stays equivalent, which rules with a priority in
+// between may prevent. Best followed by `remove_redundant`.
+pub fn merge_siblings<R, F, V>(
+    mut rules: Vec<R>,
+    widths: &[u32],
+    verdict: impl Fn(&R) -> V,
+    with_field: impl Fn(&R, usize, (F, F)) -> R,
+) -> Vec<R>
+where
+    R: Rule<F>,
+    F: FieldType,
+    V: PartialEq,
+{
+    'merged: loop {
+        for i in 0..rules.len() {
+            for j in i + 1..rules.len() {
+                let (a, b) = (&rules[i], &rules[j]);
+                let Some(dimension) = siblings(a, b) else {
+                    continue;
+                };
+                if verdict(a) != verdict(b) {
+                    continue;
+                }
+
+                let (keep, drop) = if b.priority() > a.priority() {
+                    (j, i)
+                } else {
+                    (i, j)
+                };
+                let mask = a.masks()[dimension];
+                let parent = mask ^ (F::ONE << (mask.count_ones() - 1));
+                let merged = with_field(
+                    &rules[keep],
+                    dimension,
+                    (a.fields()[dimension] & parent, parent),
+                );
+
+                let relevant = |k: usize| k == i || k == j || overlap(&merged, &rules[k]);
+                let before = (0..rules.len()).filter(|k| relevant(*k)).map(|k| &rules[k]);
+                let after = (0..rules.len())
+                    .filter(|k| *k != drop && relevant(*k))
+                    .map(|k| if k == keep { &merged } else { &rules[k] });
+                if equivalence::check(before, after, widths, &verdict).is_ok() {
+                    rules[keep] = merged;
+                    rules.remove(drop);
+                    continue 'merged;
+                }
+            }
+        }
+
+        return rules;
+    }
+}
+
+// The dimension in which the prefixes of the rules are siblings, if they agree on all others.
+fn siblings<R: Rule<F>, F: FieldType>(a: &R, b: &R) -> Option<usize> {
+    if a.masks() != b.masks() || a.fields().len() != b.fields().len() {
+        return None;
+    }
+
+    let mut differing = a
+        .fields()
+        .iter()
+        .zip(b.fields())
+        .zip(a.masks())
+        .enumerate()
+        .filter(|(_, ((fa, fb), m))| (**fa ^ **fb) & **m != F::ZERO);
+    let (dimension, ((fa, fb), mask)) = differing.next()?;
+    // the prefixes differ in their last bit only
+    let last = F::ONE << (mask.count_ones() - 1);
+    (differing.next().is_none() && (*fa ^ *fb) & *mask == last).then_some(dimension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+    use crate::rules::FiveTupleRule;
+    use crate::types::ActionRule;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_minimized_rules_stay_equivalent() {
+        let widths: Vec<u32> = presets::five_tuple_dimensions()
+            .iter()
+            .map(|d| d.width())
+            .collect();
+        let rule = |priority, [a, b]: [u8; 2], len, action| {
+            FiveTupleRule::new(priority)
+                .src_prefix(Ipv4Addr::new(a, b, 0, 0), len)
+                .with_action(action)
+        };
+        let verdict = |r: &FiveTupleRule<&'static str>| *r.action();
+        let with_field =
+            |r: &FiveTupleRule<&'static str>, dim, field| r.clone().with_field(dim, field);
+
+        let rules = vec![
+            rule(10, [10, 0], 9, "accept"),
+            rule(9, [10, 128], 9, "accept"),
+            // shadowed by the rules above
+            rule(8, [10, 1], 16, "drop"),
+            // decides like the rule covering it below
+            rule(7, [11, 200], 16, "log"),
+            rule(6, [11, 128], 10, "reject").dst_port(22),
+            rule(5, [11, 192], 10, "log"),
+            rule(1, [0, 0], 0, "drop"),
+        ];
+
+        let merged = merge_siblings(rules.clone(), &widths, verdict, with_field);
+        assert_eq!(merged.len(), rules.len() - 1);
+        assert_eq!(merged[0], rule(10, [10, 0], 8, "accept"));
+        let minimized = remove_redundant(merged, &widths, verdict);
+        let priorities: Vec<_> = minimized.iter().map(|r| r.priority()).collect();
+        assert_eq!(priorities, vec![10, 6, 5, 1]);
+        assert!(equivalence::check(&rules, &minimized, &widths, verdict).is_ok());
+
+        // a rule in between with another verdict blocks the merge
+        let blocked = vec![
+            rule(10, [10, 0], 9, "accept"),
+            rule(8, [10, 128], 9, "accept"),
+            rule(9, [10, 128], 10, "drop"),
+        ];
+        assert_eq!(
+            merge_siblings(blocked.clone(), &widths, verdict, with_field),
+            blocked
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::BTreeSet;
+
+use crate::classifier::RVHClassifier;
+use crate::error::RvhError;
+use crate::types::*;
+
+// Receives the rules of an `OffloadedClassifier`, f.e. to install them on a NIC or switch.
+pub trait OffloadSink<R: Rule<F>, F: FieldType = Field> {
+    // Whether the device can represent the rule at all, f.e. its number of fields and prefix
+    // lengths. Unsupported rules are only classified in software.
+    fn supports(&self, _rule: &R) -> bool {
+        true
+    }
+
+    // Returns false if the rule could not be installed, f.e. because the device is full.
+    fn install(&mut self, id: RuleId, rule: &R) -> bool;
+
+    // Only called for rules `install` succeeded for.
+    fn remove(&mut self, id: RuleId);
+}
+
+// Mirrors every insertion and removal to an `OffloadSink`. The software classifier always
+// holds all rules, packets the device does not match are classified with `classify`.
+#[derive(Debug)]
+pub struct OffloadedClassifier<R: Rule<F>, S, F: FieldType = Field> {
+    classifier: RVHClassifier<R, F>,
+    sink: S,
+    // rules installed on the device
+    offloaded: BTreeSet<RuleId>,
+}
+
+impl<R: Rule<F>, S: OffloadSink<R, F>, F: FieldType> OffloadedClassifier<R, S, F> {
+    // Installs the rules of `classifier` on the device.
+    pub fn new(classifier: RVHClassifier<R, F>, sink: S) -> Self {
+        let mut offloaded = Self {
+            classifier,
+            sink,
+            offloaded: BTreeSet::new(),
+        };
+        offloaded.retry();
+        offloaded
+    }
+
+    pub fn add_rule(&mut self, rule: R) -> Result<RuleId, RvhError> {
+        let id = self.classifier.add_rule(rule)?;
+        self.install(id);
+        Ok(id)
+    }
+
+    pub fn add_rule_in_band(&mut self, band: &str, rule: R) -> Result<RuleId, RvhError> {
+        let id = self.classifier.add_rule_in_band(band, rule)?;
+        self.install(id);
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, id: RuleId) -> Result<R, RvhError> {
+        let rule = self.classifier.remove(id)?;
+        if self.offloaded.remove(&id) {
+            self.sink.remove(id);
+        }
+        Ok(rule)
+    }
+
+    fn install(&mut self, id: RuleId) {
+        let rule = self.classifier.get(id).unwrap();
+        if self.sink.supports(rule) && self.sink.install(id, rule) {
+            self.offloaded.insert(id);
+        }
+    }
+
+    // Tries to install the rules that are only classified in software, f.e. after the device
+    // freed up space. Returns the number of rules installed.
+    pub fn retry(&mut self) -> usize {
+        let pending: Vec<RuleId> = self
+            .classifier
+            .iter_ids()
+            .map(|(id, _)| id)
+            .filter(|id| !self.offloaded.contains(id))
+            .collect();
+
+        let before = self.offloaded.len();
+        for id in pending {
+            self.install(id);
+        }
+        self.offloaded.len() - before
+    }
+
+    // Software fallback for packets the device did not match.
+    pub fn classify(&self, p: &impl Packet<F>) -> Option<&R> {
+        self.classifier.classify(p)
+    }
+
+    pub fn is_offloaded(&self, id: RuleId) -> bool {
+        self.offloaded.contains(&id)
+    }
+
+    // Number of rules installed on the device.
+    pub fn offloaded(&self) -> usize {
+        self.offloaded.len()
+    }
+
+    pub fn classifier(&self) -> &RVHClassifier<R, F> {
+        &self.classifier
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn sink_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    // Removes all rules from the device and returns the software classifier.
+    pub fn into_inner(mut self) -> (RVHClassifier<R, F>, S) {
+        for id in std::mem::take(&mut self.offloaded) {
+            self.sink.remove(id);
+        }
+        (self.classifier, self.sink)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::mocks::{MockPacket, MockRule};
+
+    // Device with room for `capacity` exact matches.
+    #[derive(Debug, Default)]
+    struct MockDevice {
+        capacity: usize,
+        rules: BTreeSet<RuleId>,
+    }
+
+    impl OffloadSink<MockRule> for MockDevice {
+        fn supports(&self, rule: &MockRule) -> bool {
+            rule.masks().iter().all(|m| *m == Mask::MAX)
+        }
+
+        fn install(&mut self, id: RuleId, _rule: &MockRule) -> bool {
+            self.rules.len() < self.capacity && self.rules.insert(id)
+        }
+
+        fn remove(&mut self, id: RuleId) {
+            assert!(self.rules.remove(&id));
+        }
+    }
+
+    #[test]
+    fn test_rules_are_mirrored_to_the_sink() {
+        let mut rvh = RVHClassifier::new(vec![vec![(0, 33)]].into_iter());
+        let first = rvh
+            .add_rule(MockRule::new(vec![1], vec![Mask::MAX], 1))
+            .unwrap();
+        let device = MockDevice {
+            capacity: 2,
+            ..MockDevice::default()
+        };
+
+        let mut offloaded = OffloadedClassifier::new(rvh, device);
+        assert!(offloaded.is_offloaded(first));
+
+        let prefix = offloaded
+            .add_rule(MockRule::new(vec![0b1], vec![0b1], 2))
+            .unwrap();
+        let second = offloaded
+            .add_rule(MockRule::new(vec![2], vec![Mask::MAX], 3))
+            .unwrap();
+        let third = offloaded
+            .add_rule(MockRule::new(vec![3], vec![Mask::MAX], 4))
+            .unwrap();
+        assert!(!offloaded.is_offloaded(prefix));
+        assert!(offloaded.is_offloaded(second));
+        assert!(!offloaded.is_offloaded(third));
+        assert_eq!(offloaded.sink().rules.len(), 2);
+
+        // rules the device does not hold are still classified in software
+        let p = MockPacket::new(vec![3]);
+        assert_eq!(offloaded.classify(&p).unwrap().priority(), 4);
+
+        assert!(offloaded.remove(first).is_ok());
+        assert_eq!(offloaded.retry(), 1);
+        assert!(offloaded.is_offloaded(third));
+
+        let (classifier, device) = offloaded.into_inner();
+        assert_eq!(classifier.len(), 3);
+        assert!(device.rules.is_empty());
+    }
+}
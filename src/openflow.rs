@@ -0,0 +1,437 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::Ipv4Addr;
+
+use crate::fields::{self, DSCP_WIDTH, IPV4_WIDTH, PORT_WIDTH, PROTOCOL_WIDTH};
+use crate::import::ImportReport;
+use crate::presets::{DSCP, DST_IP, DST_PORT, PROTOCOL, SRC_IP, SRC_PORT};
+use crate::rules::FiveTupleRule;
+use crate::types::{Priority, Rule};
+
+const ETH_TYPE_IPV4: u32 = 0x0800;
+
+// Flow statistics and timeouts of `ovs-ofctl dump-flows` output that do not affect matching.
+const FLOW_STATS: [&str; 10] = [
+    "cookie",
+    "duration",
+    "table",
+    "n_packets",
+    "n_bytes",
+    "idle_age",
+    "hard_age",
+    "idle_timeout",
+    "hard_timeout",
+    "importance",
+];
+
+// An imported flow, its action is the action list of the flow entry, f.e. "output:2".
+pub type FlowRule = FiveTupleRule<String>;
+
+// Flow entry of an OpenFlow 1.3 switch, f.e. one line of `ovs-ofctl dump-flows`. Match fields
+// are keyed by their ovs-ofctl name, the OXM names (`ipv4_src`, `tcp_dst`, ..) are accepted
+// on import as well. With the serde feature entries can be read from JSON, f.e.
+// `{"priority": 100, "match": {"ipv4_src": "10.0.0.0/8"}, "actions": "output:2"}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct FlowEntry {
+    pub priority: u16,
+    #[cfg_attr(feature = "serde", serde(rename = "match"))]
+    pub matches: BTreeMap<String, String>,
+    // empty drops the packet
+    pub actions: String,
+}
+
+// Same default priority as OpenFlow.
+impl Default for FlowEntry {
+    fn default() -> Self {
+        Self {
+            priority: 0x8000,
+            matches: BTreeMap::new(),
+            actions: String::new(),
+        }
+    }
+}
+
+impl FlowEntry {
+    // Reads one flow of `ovs-ofctl dump-flows` or `add-flow` syntax, f.e.
+    // `cookie=0x0, table=0, priority=100,tcp,nw_src=10.0.0.0/8,tp_dst=22 actions=output:2`.
+    // Protocol keywords like `tcp` are expanded to `dl_type` and `nw_proto`, flow statistics
+    // are dropped. Fails with the part that could not be read.
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let (fields, actions) = match line.find("actions=") {
+            Some(at) => (&line[..at], &line[at + "actions=".len()..]),
+            None => return Err(line.trim().to_string()),
+        };
+
+        let mut entry = Self {
+            actions: match actions.trim() {
+                "drop" => String::new(),
+                actions => actions.to_string(),
+            },
+            ..Self::default()
+        };
+        for field in fields.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            let (key, value) = match field.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => {
+                    let protocol = match field {
+                        "ip" => None,
+                        "icmp" => Some(1),
+                        "tcp" => Some(6),
+                        "udp" => Some(17),
+                        "sctp" => Some(132),
+                        // f.e. `arp` or `tcp6`, left for the import to reject
+                        _ => {
+                            entry.matches.insert(field.to_string(), String::new());
+                            continue;
+                        }
+                    };
+                    entry.set("dl_type", format!("{:#06x}", ETH_TYPE_IPV4));
+                    if let Some(protocol) = protocol {
+                        entry.set("nw_proto", protocol);
+                    }
+                    continue;
+                }
+            };
+            if key == "priority" {
+                entry.priority = value.parse().map_err(|_| field.to_string())?;
+            } else if !FLOW_STATS.contains(&key) {
+                entry.set(key, value);
+            }
+        }
+
+        Ok(entry)
+    }
+
+    fn set(&mut self, key: &str, value: impl ToString) {
+        self.matches.insert(key.to_string(), value.to_string());
+    }
+}
+
+// `ovs-ofctl add-flow` syntax, f.e. `priority=100,dl_type=0x0800,tp_dst=22 actions=drop`.
+impl fmt::Display for FlowEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "priority={}", self.priority)?;
+        for (key, value) in self.matches.iter() {
+            match value.as_str() {
+                "" => write!(f, ",{}", key)?,
+                value => write!(f, ",{}={}", key, value)?,
+            }
+        }
+        match self.actions.as_str() {
+            "" => write!(f, " actions=drop"),
+            actions => write!(f, " actions={}", actions),
+        }
+    }
+}
+
+// OpenFlow leaves the order of overlapping flows with the same priority undefined, rvh
+// priorities are unique within a table though. The OpenFlow priority therefore makes up the
+// upper 16 bits and flows of the same priority are ordered by their position below that, the
+// first one winning.
+pub fn priority(openflow_priority: u16, position: usize) -> Priority {
+    let position = position.min(0xfffe) as Priority;
+    (Priority::from(openflow_priority) << 16) | (0xffff - position)
+}
+
+// Inverse of `priority`.
+pub fn openflow_priority(priority: Priority) -> u16 {
+    (priority >> 16) as u16
+}
+
+// Flow entries in order, see `FlowEntry::parse`. Lines are 1-based positions of the entries.
+pub fn import(entries: &[FlowEntry]) -> ImportReport<FlowRule> {
+    let mut report = ImportReport::default();
+    let mut positions = BTreeMap::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let position = positions.entry(entry.priority).or_insert(0);
+        match rule(entry, priority(entry.priority, *position)) {
+            Ok(rule) => report.rules.push(rule),
+            Err(construct) => report.skip(i + 1, construct, entry.to_string()),
+        }
+        *position += 1;
+    }
+
+    report
+}
+
+// Output of `ovs-ofctl dump-flows`, lines without a flow like the reply header are ignored.
+pub fn import_dump(text: &str) -> ImportReport<FlowRule> {
+    let mut entries = Vec::new();
+    let mut lines = Vec::new();
+    let mut report = ImportReport::default();
+    for (i, line) in text.lines().enumerate() {
+        if !line.contains("actions=") {
+            continue;
+        }
+        match FlowEntry::parse(line) {
+            Ok(entry) => {
+                entries.push(entry);
+                lines.push(i + 1);
+            }
+            Err(construct) => report.skip(i + 1, construct, line.trim()),
+        }
+    }
+
+    let imported = import(&entries);
+    report.rules = imported.rules;
+    for mut unsupported in imported.unsupported {
+        unsupported.line = lines[unsupported.line - 1];
+        unsupported.text = text
+            .lines()
+            .nth(unsupported.line - 1)
+            .unwrap()
+            .trim()
+            .into();
+        report.unsupported.push(unsupported);
+    }
+    report.unsupported.sort_by_key(|u| u.line);
+    report
+}
+
+// Fails with the key of the match field that can not be represented.
+fn rule(entry: &FlowEntry, priority: Priority) -> Result<FlowRule, String> {
+    let mut rule = FiveTupleRule::new(priority);
+    for (key, value) in entry.matches.iter() {
+        let invalid = || format!("{}={}", key, value);
+        rule = match key.as_str() {
+            "dl_type" | "eth_type" => match number(value) {
+                Some(ETH_TYPE_IPV4) => rule,
+                _ => return Err(invalid()),
+            },
+            "nw_src" | "ipv4_src" => {
+                let (addr, len) = ipv4(value).ok_or_else(invalid)?;
+                rule.src_prefix(addr, len)
+            }
+            "nw_dst" | "ipv4_dst" => {
+                let (addr, len) = ipv4(value).ok_or_else(invalid)?;
+                rule.dst_prefix(addr, len)
+            }
+            "tp_src" | "tcp_src" | "udp_src" | "sctp_src" => {
+                let (port, len) = port(value).ok_or_else(invalid)?;
+                rule.src_port_prefix(port, len)
+            }
+            "tp_dst" | "tcp_dst" | "udp_dst" | "sctp_dst" => {
+                let (port, len) = port(value).ok_or_else(invalid)?;
+                rule.dst_port_prefix(port, len)
+            }
+            "nw_proto" | "ip_proto" => match number(value) {
+                Some(protocol) if protocol <= 0xff => rule.protocol(protocol as u8),
+                _ => return Err(invalid()),
+            },
+            "ip_dscp" => match number(value) {
+                Some(dscp) if dscp < 64 => rule.dscp(dscp as u8),
+                _ => return Err(invalid()),
+            },
+            // the whole TOS byte, ECN bits must not be matched
+            "nw_tos" => match number(value) {
+                Some(tos) if tos <= 0xff && tos & 3 == 0 => rule.dscp((tos >> 2) as u8),
+                _ => return Err(invalid()),
+            },
+            _ => return Err(key.clone()),
+        };
+    }
+
+    Ok(rule.with_action(entry.actions.clone()))
+}
+
+// Flow entry of a rule over the dimensions of `presets::five_tuple`, f.e. an imported one,
+// mapping the priority back with `openflow_priority`. None if a field has no OpenFlow match,
+// i.e. a protocol or DSCP that is not matched exactly.
+pub fn export<R: Rule>(rule: &R, actions: impl Into<String>) -> Option<FlowEntry> {
+    let (fields, masks) = (rule.fields(), rule.masks());
+    let decode = |dimension: usize, width: u32| {
+        let len = masks[dimension].count_ones();
+        (fields::encode(fields[dimension], width), len)
+    };
+
+    let mut entry = FlowEntry {
+        priority: openflow_priority(rule.priority()),
+        actions: actions.into(),
+        ..FlowEntry::default()
+    };
+    entry.set("dl_type", format!("{:#06x}", ETH_TYPE_IPV4));
+    for (dimension, key) in [(SRC_IP, "nw_src"), (DST_IP, "nw_dst")] {
+        match decode(dimension, IPV4_WIDTH) {
+            (_, 0) => {}
+            (addr, 32) => entry.set(key, Ipv4Addr::from(addr)),
+            (addr, len) => entry.set(key, format!("{}/{}", Ipv4Addr::from(addr), len)),
+        }
+    }
+    for (dimension, key) in [(SRC_PORT, "tp_src"), (DST_PORT, "tp_dst")] {
+        match decode(dimension, PORT_WIDTH) {
+            (_, 0) => {}
+            (port, 16) => entry.set(key, port),
+            (port, len) => {
+                let mask = 0xffff << (16 - len) & 0xffff;
+                entry.set(key, format!("{:#06x}/{:#06x}", port, mask));
+            }
+        }
+    }
+    match decode(PROTOCOL, PROTOCOL_WIDTH) {
+        (_, 0) => {}
+        (protocol, 8) => entry.set("nw_proto", protocol),
+        _ => return None,
+    }
+    if fields.len() > DSCP {
+        match decode(DSCP, DSCP_WIDTH) {
+            (_, 0) => {}
+            (dscp, 6) => entry.set("ip_dscp", dscp),
+            _ => return None,
+        }
+    }
+
+    Some(entry)
+}
+
+// Decimal or `0x` prefixed hexadecimal.
+fn number(value: &str) -> Option<u32> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+// A mask given as number, it has to be a prefix of `width` bits.
+fn prefix_len(mask: u32, width: u32) -> Option<u32> {
+    let mask = mask << (32 - width);
+    (mask.leading_ones() + mask.trailing_zeros() >= 32).then(|| mask.leading_ones())
+}
+
+// f.e. `10.0.0.1`, `10.0.0.0/8` or `10.0.0.0/255.0.0.0`
+pub(crate) fn ipv4(value: &str) -> Option<(Ipv4Addr, u32)> {
+    let (addr, len) = match value.split_once('/') {
+        None => return Some((value.parse().ok()?, IPV4_WIDTH)),
+        Some((addr, len)) => (addr.parse().ok()?, len),
+    };
+    let len = match len.parse::<Ipv4Addr>() {
+        Ok(mask) => prefix_len(mask.into(), IPV4_WIDTH)?,
+        Err(_) => len.parse().ok().filter(|len| *len <= IPV4_WIDTH)?,
+    };
+    Some((addr, len))
+}
+
+// f.e. `22` or `0x0400/0xfc00`
+fn port(value: &str) -> Option<(u16, u32)> {
+    let (port, len) = match value.split_once('/') {
+        None => (number(value)?, PORT_WIDTH),
+        Some((port, mask)) => (number(port)?, prefix_len(number(mask)?, PORT_WIDTH)?),
+    };
+    Some((u16::try_from(port).ok()?, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::FiveTuple;
+    use crate::presets;
+    use crate::types::ActionRule;
+    use crate::RVHClassifier;
+
+    const DUMP: &str = "\
+OFPST_FLOW reply (OF1.3) (xid=0x2):
+ cookie=0x0, duration=12.3s, table=0, n_packets=4, n_bytes=240, priority=200,tcp,nw_src=10.0.0.0/8,tp_dst=22 actions=output:2
+ cookie=0x0, duration=12.3s, table=0, n_packets=0, n_bytes=0, priority=200,udp,nw_dst=192.168.1.0/255.255.255.0,tp_dst=0x0400/0xfc00 actions=output:3
+ cookie=0x0, duration=12.3s, table=0, n_packets=0, n_bytes=0, priority=150,in_port=1,ip actions=drop
+ cookie=0x0, duration=12.3s, table=0, n_packets=9, n_bytes=540, priority=100,ip,nw_tos=184 actions=set_queue:1,NORMAL
+ cookie=0x0, duration=12.3s, table=0, n_packets=0, n_bytes=0, priority=0 actions=CONTROLLER:65535
+";
+
+    #[test]
+    fn test_dump_flows_round_trip() {
+        let report = import_dump(DUMP);
+        assert_eq!(report.rules.len(), 4);
+        assert_eq!(report.unsupported.len(), 1);
+        assert_eq!(report.unsupported[0].line, 4);
+        assert_eq!(report.unsupported[0].construct, "in_port");
+
+        let priorities: Vec<_> = report.rules.iter().map(|r| r.priority()).collect();
+        assert_eq!(
+            priorities,
+            vec![
+                priority(200, 0),
+                priority(200, 1),
+                priority(100, 0),
+                priority(0, 0)
+            ]
+        );
+        assert!(priorities.windows(2).all(|w| w[0] > w[1]));
+
+        let mut rvh = RVHClassifier::<FlowRule>::new(presets::five_tuple_dscp().into_iter());
+        for rule in report.rules.iter() {
+            assert!(rvh.add_rule(rule.clone()).is_ok());
+        }
+        // packets carry the DSCP for the sixth dimension
+        let packet = |dst: [u8; 4], src_port, dst_port, protocol, dscp| {
+            FiveTuple::new(
+                [10, 1, 1, 1].into(),
+                dst.into(),
+                src_port,
+                dst_port,
+                protocol,
+            )
+            .with_dscp(dscp)
+        };
+        let decide = |p: FiveTuple| rvh.decide(&p).unwrap().action;
+        assert_eq!(
+            decide(packet([192, 168, 1, 1], 50000, 22, 6, 46)),
+            "output:2"
+        );
+        assert_eq!(
+            decide(packet([192, 168, 1, 9], 53, 2000, 17, 0)),
+            "output:3"
+        );
+        assert_eq!(
+            decide(packet([192, 168, 1, 9], 53, 3000, 17, 0)),
+            "CONTROLLER:65535"
+        );
+        assert_eq!(
+            decide(packet([8, 8, 8, 8], 53, 53, 17, 46)),
+            "set_queue:1,NORMAL"
+        );
+
+        let exported: Vec<_> = report
+            .rules
+            .iter()
+            .map(|r| export(r, r.action().clone()).unwrap())
+            .collect();
+        assert_eq!(
+            exported[1].to_string(),
+            "priority=200,dl_type=0x0800,nw_dst=192.168.1.0/24,nw_proto=17,\
+             tp_dst=0x0400/0xfc00 actions=output:3"
+        );
+        assert_eq!(
+            exported[2].to_string(),
+            "priority=100,dl_type=0x0800,ip_dscp=46 actions=set_queue:1,NORMAL"
+        );
+        let reimported = import(&exported);
+        assert!(reimported.is_complete());
+        assert_eq!(reimported.rules, report.rules);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_flow_entries() {
+        let entries: Vec<FlowEntry> = serde_json::from_str(
+            r#"[
+                {"priority": 10, "match": {"eth_type": "2048", "ipv4_dst": "10.0.0.1", "tcp_dst": "80"}, "actions": "output:1"},
+                {"match": {"eth_type": "0x86dd"}}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(entries[1].priority, 0x8000);
+
+        let report = import(&entries);
+        let expected = FiveTupleRule::new(priority(10, 0))
+            .dst_prefix(Ipv4Addr::new(10, 0, 0, 1), 32)
+            .dst_port(80)
+            .with_action("output:1".to_string());
+        assert_eq!(report.rules, vec![expected]);
+        assert_eq!(report.unsupported[0].construct, "eth_type=0x86dd");
+    }
+}
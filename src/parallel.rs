@@ -0,0 +1,69 @@
+use std::hash::BuildHasher;
+
+use rayon::prelude::*;
+
+use crate::classifier::RVHClassifier;
+use crate::frozen::FrozenRVHClassifier;
+use crate::types::*;
+
+impl<R, F, M, S> RVHClassifier<R, F, M, S>
+where
+    R: Rule<F> + Sync,
+    F: FieldType + Sync,
+    M: Sync,
+    S: BuildHasher + Clone + Sync,
+{
+    // Classifies a batch of packets on the rayon thread pool, the matches are returned in the
+    // order of `packets`.
+    pub fn par_classify<P: Packet<F> + Sync>(&self, packets: &[P]) -> Vec<Option<&R>> {
+        packets.par_iter().map(|p| self.classify(p)).collect()
+    }
+}
+
+impl<R, F, S> FrozenRVHClassifier<R, F, S>
+where
+    R: Rule<F> + Sync,
+    F: FieldType + Sync,
+    S: BuildHasher + Clone + Sync,
+{
+    pub fn par_classify<P: Packet<F> + Sync>(&self, packets: &[P]) -> Vec<Option<&R>> {
+        packets.par_iter().map(|p| self.classify(p)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::mocks::{MockPacket, MockRule};
+    use crate::types::Rule;
+    use crate::RVHClassifier;
+
+    #[test]
+    fn test_par_classify_keeps_input_order() {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 3)], vec![(3, 6)]].into_iter());
+        assert!(rvh.add_rule(MockRule::new(vec![0b1], vec![0b1], 1)).is_ok());
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b101], vec![0b111], 2))
+            .is_ok());
+
+        let packets: Vec<_> = (0..1000).map(|f| MockPacket::new(vec![f])).collect();
+        let sequential: Vec<_> = packets
+            .iter()
+            .map(|p| rvh.classify(p).map(|r| r.priority()))
+            .collect();
+
+        let parallel: Vec<_> = rvh
+            .par_classify(&packets)
+            .iter()
+            .map(|m| m.map(|r| r.priority()))
+            .collect();
+        assert_eq!(parallel, sequential);
+
+        let frozen = rvh.freeze();
+        let parallel: Vec<_> = frozen
+            .par_classify(&packets)
+            .iter()
+            .map(|m| m.map(|r| r.priority()))
+            .collect();
+        assert_eq!(parallel, sequential);
+    }
+}
@@ -0,0 +1,275 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::extract::{ExtractError, FiveTuple};
+use crate::fields;
+use crate::types::{Packet, TryPacket};
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+
+// IPv6 extension headers skipped on the way to the transport header
+const HOP_BY_HOP: u8 = 0;
+const ROUTING: u8 = 43;
+const FRAGMENT: u8 = 44;
+const DESTINATION_OPTIONS: u8 = 60;
+
+// The fields of a frame parsed by `parse`, laid out as `presets::ipv6_five_tuple` for
+// classifiers with `u128` fields. IPv4 addresses are IPv4-mapped, see `fields::ip_addr_wide`,
+// so that rules of both families share one classifier. IPv4 packets can be classified with
+// `u32` fields through `five_tuple` as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParsedPacket {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub protocol: u8,
+    pub dscp: u8,
+    fields: [u128; 5],
+}
+
+impl ParsedPacket {
+    fn new(src: IpAddr, dst: IpAddr, ports: [u8; 4], protocol: u8, tos: u8) -> Self {
+        let src_port = u16::from_be_bytes([ports[0], ports[1]]);
+        let dst_port = u16::from_be_bytes([ports[2], ports[3]]);
+        Self {
+            src,
+            dst,
+            src_port,
+            dst_port,
+            protocol,
+            dscp: tos >> 2,
+            fields: [
+                fields::ip_addr_wide(src).0,
+                fields::ip_addr_wide(dst).0,
+                fields::port_wide(src_port).0,
+                fields::port_wide(dst_port).0,
+                fields::protocol_wide(protocol).0,
+            ],
+        }
+    }
+
+    // The 5-tuple with the DSCP for classifiers with `u32` fields, None for IPv6 packets.
+    pub fn five_tuple(&self) -> Option<FiveTuple> {
+        match (self.src, self.dst) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => Some(
+                FiveTuple::new(src, dst, self.src_port, self.dst_port, self.protocol)
+                    .with_dscp(self.dscp),
+            ),
+            _ => None,
+        }
+    }
+}
+
+impl Packet<u128> for ParsedPacket {
+    fn fields(&self) -> &[u128] {
+        &self.fields
+    }
+}
+
+// An Ethernet frame as received, see `parse`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a>(pub &'a [u8]);
+
+impl TryPacket<u128> for Frame<'_> {
+    type Packet = ParsedPacket;
+    type Error = ExtractError;
+
+    fn try_packet(&self) -> Result<ParsedPacket, ExtractError> {
+        parse(self.0)
+    }
+}
+
+// Extracts the 5-tuple of an Ethernet frame, following up to two VLAN tags, an IPv4 or IPv6
+// header and the IPv6 extension headers in front of the TCP, UDP or SCTP header. Packets
+// without ports fail like with `extract::Ipv4Bytes`.
+pub fn parse(frame: &[u8]) -> Result<ParsedPacket, ExtractError> {
+    let mut at = 12;
+    let mut ethertype = be16(frame, at)?;
+    for _ in 0..2 {
+        if ethertype != ETHERTYPE_VLAN && ethertype != ETHERTYPE_QINQ {
+            break;
+        }
+        at += 4;
+        ethertype = be16(frame, at)?;
+    }
+
+    let ip = &frame[at + 2..];
+    match ethertype {
+        ETHERTYPE_IPV4 => parse_ipv4(ip),
+        ETHERTYPE_IPV6 => parse_ipv6(ip),
+        _ => Err(ExtractError::UnsupportedEtherType(ethertype)),
+    }
+}
+
+// Same as `parse` for an IP packet without link layer header, f.e. from a raw IP capture.
+pub fn parse_ip(ip: &[u8]) -> Result<ParsedPacket, ExtractError> {
+    match byte(ip, 0)? >> 4 {
+        6 => parse_ipv6(ip),
+        _ => parse_ipv4(ip),
+    }
+}
+
+fn parse_ipv4(ip: &[u8]) -> Result<ParsedPacket, ExtractError> {
+    if byte(ip, 0)? >> 4 != 4 {
+        return Err(ExtractError::NotIpv4);
+    }
+    let ihl = byte(ip, 0)? & 0xf;
+    if ihl < 5 {
+        return Err(ExtractError::InvalidHeaderLength(ihl));
+    }
+    let header_len = usize::from(ihl) * 4;
+    if be16(ip, 6)? & 0x1fff != 0 {
+        return Err(ExtractError::Fragment);
+    }
+
+    let protocol = byte(ip, 9)?;
+    let src = Ipv4Addr::from(array::<4>(ip, 12)?);
+    let dst = Ipv4Addr::from(array::<4>(ip, 16)?);
+    let ports = ports(ip, header_len, protocol)?;
+    Ok(ParsedPacket::new(
+        src.into(),
+        dst.into(),
+        ports,
+        protocol,
+        byte(ip, 1)?,
+    ))
+}
+
+fn parse_ipv6(ip: &[u8]) -> Result<ParsedPacket, ExtractError> {
+    if byte(ip, 0)? >> 4 != 6 {
+        return Err(ExtractError::NotIpv6);
+    }
+    let tos = (be16(ip, 0)? >> 4) as u8;
+    let src = Ipv6Addr::from(array::<16>(ip, 8)?);
+    let dst = Ipv6Addr::from(array::<16>(ip, 24)?);
+
+    let mut next = byte(ip, 6)?;
+    let mut at = 40;
+    loop {
+        match next {
+            HOP_BY_HOP | ROUTING | DESTINATION_OPTIONS => {
+                let len = (usize::from(byte(ip, at + 1)?) + 1) * 8;
+                next = byte(ip, at)?;
+                at += len;
+            }
+            FRAGMENT => {
+                if be16(ip, at + 2)? & 0xfff8 != 0 {
+                    return Err(ExtractError::Fragment);
+                }
+                next = byte(ip, at)?;
+                at += 8;
+            }
+            _ => break,
+        }
+    }
+
+    let ports = ports(ip, at, next)?;
+    Ok(ParsedPacket::new(src.into(), dst.into(), ports, next, tos))
+}
+
+fn ports(ip: &[u8], at: usize, protocol: u8) -> Result<[u8; 4], ExtractError> {
+    if !matches!(protocol, 6 | 17 | 132) {
+        return Err(ExtractError::UnsupportedProtocol(protocol));
+    }
+    array(ip, at)
+}
+
+fn byte(bytes: &[u8], at: usize) -> Result<u8, ExtractError> {
+    bytes.get(at).copied().ok_or(ExtractError::Truncated)
+}
+
+fn be16(bytes: &[u8], at: usize) -> Result<u16, ExtractError> {
+    array(bytes, at).map(u16::from_be_bytes)
+}
+
+fn array<const N: usize>(bytes: &[u8], at: usize) -> Result<[u8; N], ExtractError> {
+    let mut a = [0; N];
+    a.copy_from_slice(bytes.get(at..at + N).ok_or(ExtractError::Truncated)?);
+    Ok(a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::{FiveTupleRule, Ipv6FiveTupleRule};
+    use crate::{RVHClassifier, TryMatch};
+
+    fn ethernet(ethertype: u16, vlans: usize) -> Vec<u8> {
+        let mut frame = vec![0xff; 12];
+        for _ in 0..vlans {
+            frame.extend([0x81, 0x00, 0x00, 0x64]);
+        }
+        frame.extend(ethertype.to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn test_frames_of_both_families_are_parsed() {
+        let mut frame = ethernet(ETHERTYPE_IPV4, 1);
+        frame.extend([0x46, 46 << 2, 0, 52, 0, 0, 0x40, 0, 64, 17, 0, 0]);
+        frame.extend([10, 0, 0, 1, 192, 168, 1, 1]);
+        // options
+        frame.extend([1, 1, 1, 0]);
+        frame.extend([0x00, 0x35, 0x30, 0x39]);
+
+        let parsed = parse(&frame).unwrap();
+        assert_eq!(parsed.dst, IpAddr::from([192, 168, 1, 1]));
+        assert_eq!((parsed.src_port, parsed.dst_port), (53, 12345));
+        assert_eq!((parsed.protocol, parsed.dscp), (17, 46));
+
+        let mut rvh = RVHClassifier::<FiveTupleRule>::five_tuple();
+        let dns = FiveTupleRule::new(1).src_port(53).protocol(17);
+        assert!(rvh.add_rule(dns).is_ok());
+        assert!(rvh.classify(&parsed.five_tuple().unwrap()).is_some());
+
+        let mut frame = ethernet(ETHERTYPE_IPV6, 0);
+        frame.extend([0x6b, 0x80, 0, 0, 0, 28, 0, 64]);
+        frame.extend("2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        frame.extend("2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        // hop-by-hop options, then an initial fragment
+        frame.extend([44, 0, 1, 4, 0, 0, 0, 0]);
+        frame.extend([6, 0, 0, 1, 0, 0, 0, 7]);
+        frame.extend([0xc3, 0x50, 0x01, 0xbb]);
+
+        let parsed = parse(&frame).unwrap();
+        assert_eq!(
+            (parsed.dst_port, parsed.protocol, parsed.dscp),
+            (443, 6, 46)
+        );
+        assert!(parsed.five_tuple().is_none());
+        assert_eq!(parse_ip(&frame[14..]), Ok(parsed));
+
+        let mut rvh = RVHClassifier::<Ipv6FiveTupleRule, u128>::ipv6_five_tuple();
+        let https = Ipv6FiveTupleRule::new(1).dst_port(443).protocol(6);
+        assert!(rvh.add_rule(https).is_ok());
+        assert!(rvh.classify_try(&Frame(&frame)).rule().is_some());
+
+        // later fragments and truncated headers have no ports
+        let at = frame.len() - 10;
+        frame[at] = 0x10;
+        assert_eq!(parse(&frame), Err(ExtractError::Fragment));
+        assert_eq!(
+            rvh.classify_try(&Frame(&frame[..60])),
+            TryMatch::Unparseable(ExtractError::Truncated)
+        );
+        assert_eq!(
+            parse(&ethernet(0x0806, 0)),
+            Err(ExtractError::UnsupportedEtherType(0x0806))
+        );
+
+        // the ports would be read from within the IP header
+        let mut frame = ethernet(ETHERTYPE_IPV4, 0);
+        frame.extend([0x44, 0, 0, 40, 0, 0, 0x40, 0, 64, 6, 0, 0]);
+        frame.extend([10, 0, 0, 1, 192, 168, 1, 1, 0x00, 0x35, 0x30, 0x39]);
+        assert_eq!(parse(&frame), Err(ExtractError::InvalidHeaderLength(4)));
+
+        // the version has to match the ethertype
+        let mut frame = ethernet(ETHERTYPE_IPV6, 0);
+        frame.extend([0x45, 0, 0, 40, 0, 0, 0x40, 0, 64, 6, 0, 0]);
+        frame.extend([10, 0, 0, 1, 192, 168, 1, 1, 0x00, 0x35, 0x30, 0x39]);
+        assert_eq!(parse(&frame), Err(ExtractError::NotIpv6));
+    }
+}
@@ -0,0 +1,124 @@
+// Perfect hash map from bucket hashes to buckets of a frozen table, built once by hash and
+// displace as in CHD. Keys are spread over groups of about `GROUP` keys, every group gets the
+// first displacement that moves all of its keys to free slots, largest groups first. A lookup
+// hashes once, reads the displacement of its group and compares the key of a single slot.
+#[derive(Debug, Clone)]
+pub(crate) struct PerfectMap {
+    displacements: Box<[u32]>,
+    // (key, start, len), empty slots have len 0
+    slots: Box<[(u64, u32, u32)]>,
+}
+
+const GROUP: usize = 4;
+// displacements tried per group before the map gets more slots
+const MAX_TRIES: u32 = 1 << 16;
+
+fn mix(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
+
+fn slot(key: u64, displacement: u32, len: usize) -> usize {
+    let d = u64::from(displacement).wrapping_add(1);
+    (mix(key ^ d.wrapping_mul(0x9e37_79b9_7f4a_7c15)) % len as u64) as usize
+}
+
+impl PerfectMap {
+    // `entries` maps distinct keys to (start, len) with len > 0. Uses one slot per key unless
+    // the displacement search of a group fails, then a few percent more, f.e. for tables with
+    // hundreds of thousands of buckets.
+    pub fn new(entries: &[(u64, (u32, u32))]) -> Self {
+        let mut len = entries.len();
+        loop {
+            if let Some(map) = Self::build(entries, len) {
+                return map;
+            }
+            len += len / 16 + 1;
+        }
+    }
+
+    fn build(entries: &[(u64, (u32, u32))], len: usize) -> Option<Self> {
+        let groups = entries.len() / GROUP + 1;
+        let mut members: Vec<Vec<usize>> = vec![Vec::new(); groups];
+        for (i, (key, _)) in entries.iter().enumerate() {
+            members[(mix(*key) % groups as u64) as usize].push(i);
+        }
+        let mut order: Vec<usize> = (0..groups).collect();
+        order.sort_by_key(|g| std::cmp::Reverse(members[*g].len()));
+
+        let mut displacements = vec![0; groups];
+        let mut slots = vec![(0, 0, 0); len.max(1)];
+        let mut taken: Vec<usize> = Vec::with_capacity(GROUP);
+        for g in order {
+            if members[g].is_empty() {
+                break;
+            }
+
+            let found = (0..MAX_TRIES).find(|&d| {
+                taken.clear();
+                for &i in members[g].iter() {
+                    let s = slot(entries[i].0, d, slots.len());
+                    if slots[s].2 != 0 || taken.contains(&s) {
+                        return false;
+                    }
+                    taken.push(s);
+                }
+                true
+            })?;
+
+            displacements[g] = found;
+            for (&i, &s) in members[g].iter().zip(taken.iter()) {
+                let (key, (start, len)) = entries[i];
+                slots[s] = (key, start, len);
+            }
+        }
+
+        Some(Self {
+            displacements: displacements.into_boxed_slice(),
+            slots: slots.into_boxed_slice(),
+        })
+    }
+
+    pub fn get(&self, key: u64) -> Option<(u32, u32)> {
+        let group = (mix(key) % self.displacements.len() as u64) as usize;
+        let (k, start, len) = self.slots[slot(key, self.displacements[group], self.slots.len())];
+        (k == key && len != 0).then_some((start, len))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.slots
+            .iter()
+            .filter(|(_, _, len)| *len != 0)
+            .map(|(_, start, len)| (*start, *len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_key_takes_one_slot() {
+        let entries: Vec<_> = (0..1000u64)
+            .map(|i| (mix(i), (i as u32, 1 + i as u32 % 3)))
+            .collect();
+        let map = PerfectMap::new(&entries);
+        assert_eq!(map.slots.len(), 1000);
+        assert_eq!(map.values().count(), 1000);
+
+        for (key, value) in entries.iter() {
+            assert_eq!(map.get(*key), Some(*value));
+        }
+        assert_eq!(map.get(mix(1000)), None);
+        assert_eq!(map.get(1), None);
+
+        // even keys that do not look like hashes at all
+        let entries: Vec<_> = (0..100).map(|i| (i, (0, 1))).collect();
+        let map = PerfectMap::new(&entries);
+        assert!(entries.iter().all(|(key, _)| map.get(*key).is_some()));
+        assert!(PerfectMap::new(&[]).get(0).is_none());
+    }
+}
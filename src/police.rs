@@ -0,0 +1,99 @@
+use std::time::Instant;
+
+use crate::types::RuleId;
+
+// Rate a rule may be matched at, see `RVHClassifier::set_rate_limit`. Every match costs the
+// `cost` passed to `classify_and_count`, f.e. 1 for packets or the length of the packet for
+// bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RateLimit {
+    // tokens added per second
+    pub rate: u64,
+    // tokens the bucket holds at most, and starts with
+    pub burst: u64,
+}
+
+// A match of `classify_and_count`. `exceeded` is set if the rule had not enough tokens left
+// for the cost of the packet, in which case no tokens were taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policed<'a, R> {
+    pub rule: &'a R,
+    pub id: RuleId,
+    pub exceeded: bool,
+}
+
+// Tokens are counted in billionths, so that refilling by the nanosecond does not lose the
+// fractions.
+const SCALE: u128 = 1_000_000_000;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct TokenBucket {
+    limit: RateLimit,
+    tokens: u128,
+    // the bucket is full until the first match, a restored bucket starts full again
+    #[cfg_attr(feature = "serde", serde(skip))]
+    last: Option<Instant>,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: u128::from(limit.burst) * SCALE,
+            last: None,
+        }
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    // Refills the bucket up to `now` and takes `cost` tokens, false if there are not enough.
+    pub fn take(&mut self, cost: u64, now: Instant) -> bool {
+        let capacity = u128::from(self.limit.burst) * SCALE;
+        if let Some(last) = self.last {
+            // a clock going backwards does not refill
+            let elapsed = now.saturating_duration_since(last).as_nanos();
+            self.tokens = capacity.min(
+                self.tokens
+                    .saturating_add(elapsed.saturating_mul(u128::from(self.limit.rate))),
+            );
+        }
+        self.last = Some(self.last.map_or(now, |last| last.max(now)));
+
+        let cost = u128::from(cost) * SCALE;
+        if cost > self.tokens {
+            return false;
+        }
+        self.tokens -= cost;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_bucket_refills_at_rate() {
+        let mut bucket = TokenBucket::new(RateLimit { rate: 10, burst: 2 });
+        let start = Instant::now();
+        assert!(bucket.take(1, start));
+        assert!(bucket.take(1, start));
+        assert!(!bucket.take(1, start));
+
+        // 10 tokens per second, one token after 100ms
+        assert!(!bucket.take(1, start + Duration::from_millis(50)));
+        assert!(bucket.take(1, start + Duration::from_millis(100)));
+        assert!(!bucket.take(1, start + Duration::from_millis(100)));
+
+        // no more than the burst
+        let later = start + Duration::from_secs(60);
+        assert!(!bucket.take(3, later));
+        assert!(bucket.take(2, later));
+        assert!(!bucket.take(1, start));
+    }
+}
@@ -0,0 +1,592 @@
+use std::collections::HashMap;
+use std::mem;
+
+use crate::hash::Prehashed;
+use crate::range_vector_hash_map::{rule_matches, same_key, Bucket};
+#[cfg(feature = "simd")]
+use crate::simd::{Heads, LANES};
+use crate::types::*;
+
+// The rules of a bucket of either storage, see `Buckets`. Like in `Bucket`, `runs[i]` is the
+// number of rules with the same fields and masks from position `i` on.
+pub(crate) struct BucketRef<'a, R> {
+    rules: &'a [R],
+    // indices into `rules` for pooled buckets, None if `rules` is the bucket
+    order: Option<&'a [u32]>,
+    runs: &'a [u32],
+    #[cfg(feature = "simd")]
+    heads: Option<&'a Heads>,
+}
+
+impl<R> Clone for BucketRef<'_, R> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<R> Copy for BucketRef<'_, R> {}
+
+impl<'a, R> BucketRef<'a, R> {
+    pub fn nested(rules: &'a [R], runs: &'a [u32]) -> Self {
+        Self {
+            rules,
+            order: None,
+            runs,
+            #[cfg(feature = "simd")]
+            heads: None,
+        }
+    }
+
+    #[cfg(feature = "simd")]
+    pub fn with_heads(self, heads: &'a Heads) -> Self {
+        Self {
+            heads: Some(heads),
+            ..self
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> &'a R {
+        match self.order {
+            Some(order) => &self.rules[order[index] as usize],
+            None => &self.rules[index],
+        }
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = &'a R> {
+        (0..self.len()).map(move |i| self.get(i))
+    }
+
+    // Start and length of the runs, by descending priority of their first rule.
+    pub fn runs(self) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let runs = self.runs;
+        let mut start = 0;
+        std::iter::from_fn(move || {
+            let len = *runs.get(start)? as usize;
+            start += len;
+            Some((start - len, len))
+        })
+    }
+
+    // Whether the first rule of the `i`th run, at `start`, matches. With the `simd` feature
+    // nested buckets compare their runs `LANES` at a time, `lanes` keeps the result for the
+    // runs of `i`, so runs have to be asked for in order.
+    #[inline]
+    pub fn run_matches<F: FieldType>(
+        &self,
+        i: usize,
+        start: usize,
+        packet: &impl Packet<F>,
+        lanes: &mut u32,
+    ) -> bool
+    where
+        R: Rule<F>,
+    {
+        #[cfg(feature = "simd")]
+        if let Some(heads) = self.heads {
+            if i.is_multiple_of(LANES) {
+                *lanes = heads.matches(packet.fields(), i / LANES);
+            }
+            return *lanes >> (i % LANES) & 1 == 1;
+        }
+
+        let _ = (i, lanes);
+        rule_matches(self.get(start), packet)
+    }
+}
+
+// Rules of a table in one contiguous pool instead of a vector per bucket, for rule sets large
+// enough that allocating and freeing the buckets fragments memory. `order` holds the indices of
+// the rules bucket after bucket, each bucket in a segment of a power of two slots, which it
+// moves out of once full. Freed segments are reused by buckets of the same capacity.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct Pool<R> {
+    rules: Vec<R>,
+    // position of every rule in `order`
+    positions: Vec<u32>,
+    order: Vec<u32>,
+    // same as in `Bucket`, parallel to `order`
+    runs: Vec<u32>,
+    // (start, len, capacity) of the segment of each bucket
+    spans: HashMap<u64, (u32, u32, u32), Prehashed>,
+    // starts of free segments by log2 of their capacity
+    free: Vec<Vec<u32>>,
+}
+
+impl<R> Default for Pool<R> {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            positions: Vec::new(),
+            order: Vec::new(),
+            runs: Vec::new(),
+            spans: HashMap::default(),
+            free: Vec::new(),
+        }
+    }
+}
+
+impl<R> Pool<R> {
+    fn get(&self, hash: u64) -> Option<BucketRef<'_, R>> {
+        let (start, len, _) = *self.spans.get(&hash)?;
+        let segment = start as usize..(start + len) as usize;
+        Some(BucketRef {
+            rules: &self.rules,
+            order: Some(&self.order[segment.clone()]),
+            runs: &self.runs[segment],
+            #[cfg(feature = "simd")]
+            heads: None,
+        })
+    }
+
+    // Lays out the buckets one after the other, each in a segment just large enough.
+    fn from_buckets<F: FieldType>(buckets: Vec<(u64, Vec<R>)>) -> Self
+    where
+        R: Rule<F>,
+    {
+        let mut pool = Self::default();
+        let rules = buckets.iter().map(|(_, bucket)| bucket.len()).sum();
+        pool.rules.reserve_exact(rules);
+        pool.positions.reserve_exact(rules);
+        pool.spans.reserve(buckets.len());
+        for (hash, bucket) in buckets.into_iter().filter(|(_, b)| !b.is_empty()) {
+            let len = bucket.len() as u32;
+            let start = pool.allocate(len.next_power_of_two());
+            for (p, rule) in bucket.into_iter().enumerate() {
+                pool.order[start as usize + p] = pool.rules.len() as u32;
+                pool.rules.push(rule);
+                pool.positions.push(0);
+            }
+            pool.spans
+                .insert(hash, (start, len, len.next_power_of_two()));
+            pool.arrange(start, len);
+        }
+
+        pool
+    }
+
+    fn insert<F: FieldType>(&mut self, hash: u64, rule: R)
+    where
+        R: Rule<F>,
+    {
+        let index = self.rules.len() as u32;
+        self.rules.push(rule);
+        self.positions.push(0);
+
+        let (mut start, len, mut capacity) = match self.spans.get(&hash) {
+            Some(span) => *span,
+            None => (self.allocate(1), 0, 1),
+        };
+        if len == capacity {
+            let moved = self.allocate(capacity * 2);
+            let (from, to) = (start as usize, moved as usize);
+            self.order.copy_within(from..from + len as usize, to);
+            self.release(start, capacity);
+            start = moved;
+            capacity *= 2;
+        }
+
+        self.order[(start + len) as usize] = index;
+        self.spans.insert(hash, (start, len + 1, capacity));
+        self.arrange(start, len + 1);
+    }
+
+    // Removes the rule at `index` of a bucket. The last rule of the pool takes its place.
+    fn remove<F: FieldType>(&mut self, hash: u64, index: usize) -> R
+    where
+        R: Rule<F>,
+    {
+        let (start, len, capacity) = self.spans[&hash];
+        let at = start as usize + index;
+        let removed = self.order[at] as usize;
+        self.order.copy_within(at + 1..(start + len) as usize, at);
+        if len == 1 {
+            self.spans.remove(&hash);
+            self.release(start, capacity);
+        } else {
+            self.spans.insert(hash, (start, len - 1, capacity));
+            self.arrange(start, len - 1);
+        }
+
+        let last = self.rules.len() - 1;
+        if removed != last {
+            self.order[self.positions[last] as usize] = removed as u32;
+        }
+        self.positions.swap_remove(removed);
+        self.rules.swap_remove(removed)
+    }
+
+    fn rule_mut(&mut self, hash: u64, index: usize) -> &mut R {
+        let (start, _, _) = self.spans[&hash];
+        &mut self.rules[self.order[start as usize + index] as usize]
+    }
+
+    // Sorts a segment into runs as in `Bucket`: rules by descending priority, then rules with
+    // the same key moved behind the highest priority one.
+    fn arrange<F: FieldType>(&mut self, start: u32, len: u32)
+    where
+        R: Rule<F>,
+    {
+        let segment = start as usize..(start + len) as usize;
+        let rules = &self.rules;
+        let order = &mut self.order[segment.clone()];
+        let runs = &mut self.runs[segment];
+        order.sort_unstable_by_key(|&i| std::cmp::Reverse(rules[i as usize].priority()));
+
+        let mut i = 0;
+        while i < order.len() {
+            let mut end = i + 1;
+            for j in i + 1..order.len() {
+                if same_key(&rules[order[j] as usize], &rules[order[i] as usize]) {
+                    order[end..=j].rotate_right(1);
+                    end += 1;
+                }
+            }
+            for (p, run) in runs[i..end].iter_mut().enumerate() {
+                *run = (end - i - p) as u32;
+            }
+            i = end;
+        }
+
+        for (p, &index) in order.iter().enumerate() {
+            self.positions[index as usize] = start + p as u32;
+        }
+    }
+
+    fn allocate(&mut self, capacity: u32) -> u32 {
+        let class = capacity.trailing_zeros() as usize;
+        if let Some(start) = self.free.get_mut(class).and_then(Vec::pop) {
+            return start;
+        }
+
+        let start = self.order.len() as u32;
+        self.order.resize(self.order.len() + capacity as usize, 0);
+        self.runs.resize(self.order.len(), 0);
+        start
+    }
+
+    fn release(&mut self, start: u32, capacity: u32) {
+        let class = capacity.trailing_zeros() as usize;
+        if self.free.len() <= class {
+            self.free.resize_with(class + 1, Vec::new);
+        }
+        self.free[class].push(start);
+    }
+
+    fn into_buckets(self) -> Vec<(u64, Vec<R>)> {
+        let Pool {
+            rules,
+            order,
+            spans,
+            ..
+        } = self;
+        let mut rules: Vec<Option<R>> = rules.into_iter().map(Some).collect();
+        spans
+            .iter()
+            .map(|(&hash, &(start, len, _))| {
+                let segment = &order[start as usize..(start + len) as usize];
+                let bucket = segment
+                    .iter()
+                    .map(|&i| rules[i as usize].take().unwrap())
+                    .collect();
+                (hash, bucket)
+            })
+            .collect()
+    }
+}
+
+// The buckets of a table, either a vector per bucket or all in one `Pool`, see
+// `RVHClassifier::set_pooled_storage`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) enum Buckets<R> {
+    // keyed by the hash of the masked fields, which is not hashed again
+    Nested(HashMap<u64, Bucket<R>, Prehashed>),
+    Pooled(Pool<R>),
+}
+
+impl<R> Default for Buckets<R> {
+    fn default() -> Self {
+        Buckets::Nested(HashMap::default())
+    }
+}
+
+impl<R> Buckets<R> {
+    pub fn get(&self, hash: u64) -> Option<BucketRef<'_, R>> {
+        match self {
+            Buckets::Nested(buckets) => buckets.get(&hash).map(Bucket::view),
+            Buckets::Pooled(pool) => pool.get(hash),
+        }
+    }
+
+    pub fn insert<F: FieldType>(&mut self, hash: u64, rule: R)
+    where
+        R: Rule<F>,
+    {
+        match self {
+            Buckets::Nested(buckets) => buckets.entry(hash).or_default().insert(rule),
+            Buckets::Pooled(pool) => pool.insert(hash, rule),
+        }
+    }
+
+    pub fn remove<F: FieldType>(&mut self, hash: u64, index: usize) -> R
+    where
+        R: Rule<F>,
+    {
+        match self {
+            Buckets::Nested(buckets) => buckets.get_mut(&hash).unwrap().remove(index),
+            Buckets::Pooled(pool) => pool.remove(hash, index),
+        }
+    }
+
+    // The guard of `RVHClassifier::get_mut` places a changed rule again before the bucket is
+    // used for lookups.
+    pub fn rule_mut(&mut self, hash: u64, index: usize) -> &mut R {
+        match self {
+            Buckets::Nested(buckets) => buckets.get_mut(&hash).unwrap().rule_mut(index),
+            Buckets::Pooled(pool) => pool.rule_mut(hash, index),
+        }
+    }
+
+    // Number of buckets.
+    pub fn len(&self) -> usize {
+        match self {
+            Buckets::Nested(buckets) => buckets.len(),
+            Buckets::Pooled(pool) => pool.spans.len(),
+        }
+    }
+
+    // Buckets the index from hashes to buckets has room for.
+    pub fn capacity(&self) -> usize {
+        match self {
+            Buckets::Nested(buckets) => buckets.capacity(),
+            Buckets::Pooled(pool) => pool.spans.capacity(),
+        }
+    }
+
+    // Estimated heap bytes of the index from hashes to buckets, of the bookkeeping of the
+    // buckets and of the rules. Hash maps take a control byte per entry.
+    pub fn heap_bytes(&self) -> (usize, usize, usize) {
+        match self {
+            Buckets::Nested(buckets) => {
+                let entry = mem::size_of::<(u64, Bucket<R>)>() + 1;
+                buckets.values().map(Bucket::heap_bytes).fold(
+                    (buckets.capacity() * entry, 0, 0),
+                    |(keys, runs, rules), (r, s)| (keys, runs + r, rules + s),
+                )
+            }
+            Buckets::Pooled(pool) => {
+                let entry = mem::size_of::<(u64, (u32, u32, u32))>() + 1;
+                let slots = pool.positions.capacity()
+                    + pool.order.capacity()
+                    + pool.runs.capacity()
+                    + pool.free.iter().map(Vec::capacity).sum::<usize>();
+                (
+                    pool.spans.capacity() * entry,
+                    slots * mem::size_of::<u32>(),
+                    pool.rules.capacity() * mem::size_of::<R>(),
+                )
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, BucketRef<'_, R>)> + '_ {
+        match self {
+            Buckets::Nested(buckets) => Either::Nested(buckets.iter().map(|(h, b)| (*h, b.view()))),
+            Buckets::Pooled(pool) => {
+                Either::Pooled(pool.spans.keys().map(move |h| (*h, pool.get(*h).unwrap())))
+            }
+        }
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = &R> + '_ {
+        match self {
+            Buckets::Nested(buckets) => Either::Nested(buckets.values().flat_map(|b| b.iter())),
+            Buckets::Pooled(pool) => Either::Pooled(pool.rules.iter()),
+        }
+    }
+
+    pub fn into_buckets(self) -> Vec<(u64, Vec<R>)> {
+        match self {
+            Buckets::Nested(buckets) => buckets
+                .into_iter()
+                .map(|(hash, bucket)| (hash, bucket.into_vec()))
+                .collect(),
+            Buckets::Pooled(pool) => pool.into_buckets(),
+        }
+    }
+
+    pub fn into_rules(self) -> Vec<R> {
+        match self {
+            Buckets::Nested(buckets) => buckets.into_values().flatten().collect(),
+            Buckets::Pooled(pool) => pool.rules,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        match self {
+            Buckets::Nested(buckets) => buckets.clear(),
+            Buckets::Pooled(pool) => *pool = Pool::default(),
+        }
+    }
+
+    // Removes all rules, keeping the storage.
+    pub fn drain(&mut self) -> Vec<R> {
+        let empty = match self {
+            Buckets::Nested(_) => Buckets::default(),
+            Buckets::Pooled(_) => Buckets::Pooled(Pool::default()),
+        };
+        std::mem::replace(self, empty).into_rules()
+    }
+
+    pub fn is_pooled(&self) -> bool {
+        matches!(self, Buckets::Pooled(_))
+    }
+
+    pub fn set_pooled<F: FieldType>(&mut self, pooled: bool)
+    where
+        R: Rule<F>,
+    {
+        if self.is_pooled() == pooled {
+            return;
+        }
+
+        let buckets = std::mem::take(self).into_buckets();
+        if pooled {
+            *self = Buckets::Pooled(Pool::from_buckets(buckets));
+            return;
+        }
+        for (hash, bucket) in buckets {
+            for rule in bucket {
+                self.insert(hash, rule);
+            }
+        }
+    }
+
+    // Makes room for `additional` rules, each in a bucket of its own at worst.
+    pub fn reserve(&mut self, additional: usize) {
+        match self {
+            Buckets::Nested(buckets) => buckets.reserve(additional),
+            Buckets::Pooled(pool) => {
+                pool.rules.reserve(additional);
+                pool.positions.reserve(additional);
+                pool.order.reserve(additional);
+                pool.runs.reserve(additional);
+                pool.spans.reserve(additional);
+            }
+        }
+    }
+
+    // Releases unused capacity. Pooled buckets are laid out again, without the segments freed
+    // by removals in between.
+    pub fn shrink_to_fit<F: FieldType>(&mut self)
+    where
+        R: Rule<F>,
+    {
+        match self {
+            Buckets::Nested(buckets) => {
+                buckets.shrink_to_fit();
+                buckets.values_mut().for_each(Bucket::shrink_to_fit);
+            }
+            Buckets::Pooled(pool) => {
+                let mut compacted = Pool::from_buckets(std::mem::take(pool).into_buckets());
+                compacted.order.shrink_to_fit();
+                compacted.runs.shrink_to_fit();
+                *pool = compacted;
+            }
+        }
+    }
+}
+
+// Iterator over either storage.
+enum Either<A, B> {
+    Nested(A),
+    Pooled(B),
+}
+
+impl<T, A: Iterator<Item = T>, B: Iterator<Item = T>> Iterator for Either<A, B> {
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<T> {
+        match self {
+            Either::Nested(a) => a.next(),
+            Either::Pooled(b) => b.next(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::mocks::MockRule;
+
+    #[test]
+    fn test_pooled_buckets_keep_runs_sorted() {
+        let mut pool = Buckets::Pooled(Pool::default());
+        let mut nested = Buckets::default();
+        let rule = |key: Field, priority| MockRule::new(vec![key], vec![0xff], priority);
+        let ops = [
+            (0, 1),
+            (1, 5),
+            (0, 3),
+            (2, 4),
+            (1, 2),
+            (0, 9),
+            (2, 8),
+            (0, 7),
+        ];
+        for &(key, priority) in ops.iter() {
+            // all keys share a bucket, the low bits select another one
+            pool.insert(u64::from(priority % 2), rule(key, priority));
+            nested.insert(u64::from(priority % 2), rule(key, priority));
+        }
+
+        let priorities = |buckets: &Buckets<MockRule>, hash| -> Vec<(Priority, u32)> {
+            let bucket = buckets.get(hash).unwrap();
+            (0..bucket.len())
+                .map(|i| (bucket.get(i).priority(), bucket.runs[i]))
+                .collect()
+        };
+        assert_eq!(
+            priorities(&pool, 1),
+            vec![(9, 4), (7, 3), (3, 2), (1, 1), (5, 1)]
+        );
+        for hash in 0..2 {
+            assert_eq!(priorities(&pool, hash), priorities(&nested, hash));
+        }
+
+        // removing moves the last rule of the pool into the hole
+        for index in [0, 2, 0] {
+            let a = pool.remove(1, index);
+            let b = nested.remove(1, index);
+            assert_eq!(a.priority(), b.priority());
+            for hash in 0..2 {
+                assert_eq!(priorities(&pool, hash), priorities(&nested, hash));
+            }
+        }
+        assert_eq!(pool.rules().count(), 5);
+        assert_eq!(pool.remove(1, 0).priority(), 5);
+        assert_eq!(pool.remove(1, 0).priority(), 3);
+        assert!(pool.get(1).is_none());
+
+        // an emptied segment is reused
+        let Buckets::Pooled(inner) = &pool else {
+            unreachable!();
+        };
+        let slots = inner.order.len();
+        pool.insert(3, rule(0, 11));
+        let Buckets::Pooled(inner) = &pool else {
+            unreachable!();
+        };
+        assert_eq!(inner.order.len(), slots);
+    }
+}
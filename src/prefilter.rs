@@ -0,0 +1,249 @@
+use std::mem;
+
+use crate::types::*;
+
+// Approximate membership filter over a coarse key of the rules, the masked field of one
+// dimension, f.e. the destination /16. Packets whose key no rule has can not match any rule
+// and are rejected before a table is probed. Rules matching a shorter prefix of the dimension
+// match any key, as long as there are some the filter lets every packet pass.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct Prefilter {
+    dimension: usize,
+    prefix_len: u32,
+    // rules matching any key
+    wildcards: usize,
+    keys: CuckooFilter,
+}
+
+impl Prefilter {
+    pub fn new(dimension: usize, prefix_len: u32, capacity: usize) -> Self {
+        Self {
+            dimension,
+            prefix_len,
+            wildcards: 0,
+            keys: CuckooFilter::new(capacity),
+        }
+    }
+
+    // An empty filter over the same key.
+    pub fn emptied(&self, capacity: usize) -> Self {
+        Self::new(self.dimension, self.prefix_len, capacity)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.keys.capacity()
+    }
+
+    pub fn heap_bytes(&self) -> usize {
+        self.keys.fingerprints.capacity() * mem::size_of::<[u16; SLOTS]>()
+            + self.keys.counts.capacity() * mem::size_of::<[u32; SLOTS]>()
+    }
+
+    // Fails if the filter is full, it has to be built again with more capacity then.
+    pub fn insert<F: FieldType>(&mut self, fields: &[F], masks: &[F]) -> bool {
+        match self.rule_key(fields, masks) {
+            Some(key) => self.keys.insert(key),
+            None => {
+                self.wildcards += 1;
+                true
+            }
+        }
+    }
+
+    pub fn remove<F: FieldType>(&mut self, fields: &[F], masks: &[F]) {
+        match self.rule_key(fields, masks) {
+            Some(key) => self.keys.remove(key),
+            None => self.wildcards -= 1,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.wildcards = 0;
+        self.keys = CuckooFilter::new(self.keys.capacity());
+    }
+
+    // False if no rule can match the packet, true does not mean that one does.
+    pub fn may_match<F: FieldType>(&self, packet: &[F]) -> bool {
+        if self.wildcards > 0 {
+            return true;
+        }
+        // dimensions a packet does not have are not compared
+        match packet.get(self.dimension) {
+            Some(field) => self.keys.contains(self.key(*field)),
+            None => true,
+        }
+    }
+
+    // None for rules matching any key.
+    fn rule_key<F: FieldType>(&self, fields: &[F], masks: &[F]) -> Option<u64> {
+        let mask = masks.get(self.dimension)?;
+        if mask.count_ones() < self.prefix_len {
+            return None;
+        }
+        Some(self.key(fields[self.dimension]))
+    }
+
+    fn key<F: FieldType>(&self, field: F) -> u64 {
+        let mask = if self.prefix_len >= F::BITS {
+            !F::ZERO
+        } else {
+            !(!F::ZERO << self.prefix_len)
+        };
+        let masked = field & mask;
+        mix((0..F::WORDS).fold(0, |h, w| {
+            (h ^ u64::from(masked.word(w))).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        }))
+    }
+}
+
+fn mix(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
+
+const SLOTS: usize = 4;
+// evictions tried before an insertion gives up
+const MAX_KICKS: usize = 500;
+
+// Cuckoo filter with 16 bit fingerprints and 4 slots per bucket. Keys of several rules share
+// one entry with a count, so that removing one of them keeps the key for the others. Keys with
+// equal fingerprints and buckets share an entry as well, which only adds false positives.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct CuckooFilter {
+    // 0 marks an empty slot
+    fingerprints: Vec<[u16; SLOTS]>,
+    counts: Vec<[u32; SLOTS]>,
+}
+
+impl CuckooFilter {
+    fn new(capacity: usize) -> Self {
+        // a power of two, so that the alternate bucket is a xor away
+        let buckets = capacity.div_ceil(SLOTS).next_power_of_two();
+        Self {
+            fingerprints: vec![[0; SLOTS]; buckets],
+            counts: vec![[0; SLOTS]; buckets],
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.fingerprints.len() * SLOTS
+    }
+
+    fn fingerprint(key: u64) -> u16 {
+        (key >> 48) as u16 | 1
+    }
+
+    fn alternate(&self, bucket: usize, fingerprint: u16) -> usize {
+        let mask = self.fingerprints.len() - 1;
+        bucket ^ (mix(u64::from(fingerprint)) as usize & mask)
+    }
+
+    fn buckets(&self, key: u64) -> (u16, usize, usize) {
+        let fingerprint = Self::fingerprint(key);
+        let first = key as usize & (self.fingerprints.len() - 1);
+        (fingerprint, first, self.alternate(first, fingerprint))
+    }
+
+    fn find(&self, key: u64) -> Option<(usize, usize)> {
+        let (fingerprint, first, second) = self.buckets(key);
+        [first, second].iter().find_map(|&b| {
+            let slot = self.fingerprints[b]
+                .iter()
+                .position(|f| *f == fingerprint)?;
+            Some((b, slot))
+        })
+    }
+
+    fn contains(&self, key: u64) -> bool {
+        self.find(key).is_some()
+    }
+
+    fn insert(&mut self, key: u64) -> bool {
+        if let Some((b, slot)) = self.find(key) {
+            self.counts[b][slot] += 1;
+            return true;
+        }
+
+        let (mut fingerprint, first, second) = self.buckets(key);
+        let mut count = 1;
+        let mut bucket = first;
+        for b in [first, second] {
+            if let Some(slot) = self.fingerprints[b].iter().position(|f| *f == 0) {
+                self.fingerprints[b][slot] = fingerprint;
+                self.counts[b][slot] = count;
+                return true;
+            }
+        }
+
+        // move entries to their alternate buckets until one finds a free slot
+        for kick in 0..MAX_KICKS {
+            let slot = kick % SLOTS;
+            std::mem::swap(&mut fingerprint, &mut self.fingerprints[bucket][slot]);
+            std::mem::swap(&mut count, &mut self.counts[bucket][slot]);
+            bucket = self.alternate(bucket, fingerprint);
+            if let Some(slot) = self.fingerprints[bucket].iter().position(|f| *f == 0) {
+                self.fingerprints[bucket][slot] = fingerprint;
+                self.counts[bucket][slot] = count;
+                return true;
+            }
+        }
+
+        // the entry left over is lost, the filter has to be built again
+        false
+    }
+
+    fn remove(&mut self, key: u64) {
+        if let Some((b, slot)) = self.find(key) {
+            self.counts[b][slot] -= 1;
+            if self.counts[b][slot] == 0 {
+                self.fingerprints[b][slot] = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_keeps_keys_until_their_last_rule_is_removed() {
+        // duplicate keys do not take more space, distinct ones fill up a small filter
+        let mut filter = Prefilter::new(1, 16, 8);
+        assert!(!(0..100).all(|k: Field| filter.insert(&[0, k], &[0, !0])));
+
+        let mut filter = Prefilter::new(1, 16, 256);
+        let rules: Vec<(Vec<Field>, Vec<Field>)> = (0..200)
+            .map(|i| (vec![0, (i % 50) | (i << 16)], vec![0, !0]))
+            .collect();
+        assert!(rules.iter().all(|(f, m)| filter.insert(f, m)));
+
+        // only the prefix of the key is compared
+        assert!(filter.may_match::<Field>(&[0, 7 | 0xffff_0000u32]));
+        assert!(filter.may_match::<Field>(&[0]));
+        let misses = (1000..2000)
+            .filter(|k| filter.may_match::<Field>(&[0, *k]))
+            .count();
+        assert!(misses < 10);
+
+        for (fields, masks) in rules.iter().filter(|(f, _)| f[1] & 0xffff == 7) {
+            assert!(filter.may_match::<Field>(&[0, 7]));
+            filter.remove(fields, masks);
+        }
+        assert!(!filter.may_match::<Field>(&[0, 7]));
+        assert!(filter.may_match::<Field>(&[0, 8]));
+
+        // rules matching shorter prefixes let everything pass
+        assert!(filter.insert::<Field>(&[0, 0], &[0, 0xff]));
+        assert!(filter.may_match::<Field>(&[0, 7]));
+        filter.remove::<Field>(&[0, 0], &[0, 0xff]);
+        assert!(!filter.may_match::<Field>(&[0, 7]));
+        filter.clear();
+        assert!(!filter.may_match::<Field>(&[0, 8]));
+    }
+}
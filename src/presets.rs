@@ -0,0 +1,215 @@
+use crate::dimensions::Dimension;
+use crate::fields::*;
+use crate::types::Range;
+
+pub const SRC_IP: usize = 0;
+pub const DST_IP: usize = 1;
+pub const SRC_PORT: usize = 2;
+pub const DST_PORT: usize = 3;
+pub const PROTOCOL: usize = 4;
+pub const DSCP: usize = 5;
+
+// Tunnel presets prepend the outer tunnel id, the inner 5-tuple dimensions are shifted by
+// `TUNNEL_INNER_OFFSET`, f.e. the inner destination port is `TUNNEL_INNER_OFFSET + DST_PORT`.
+pub const TUNNEL_ID: usize = 0;
+pub const TUNNEL_INNER_OFFSET: usize = 1;
+
+const IPV4_SPLIT: [Range; 4] = [(0, 8), (8, 16), (16, 24), (24, 33)];
+// up to provider allocations, up to site prefixes, up to subnets, subnets to hosts
+const IPV6_SPLIT: [Range; 4] = [(0, 32), (32, 48), (48, 64), (64, 129)];
+const PORT_RANGE: Range = (0, 17);
+const PROTOCOL_RANGE: Range = (0, 9);
+const DSCP_RANGE: Range = (0, 7);
+// either any tunnel or one specific tunnel
+const TUNNEL_ID_SPLIT: [Range; 2] = [(0, 32), (32, 33)];
+
+// Source and destination prefixes are split into four buckets each, ports, protocol and DSCP
+// are accepted with any prefix length and thus do not contribute to the hash.
+pub fn five_tuple() -> Vec<Vec<Range>> {
+    let mut ranges = Vec::new();
+    for src in IPV4_SPLIT.iter() {
+        for dst in IPV4_SPLIT.iter() {
+            ranges.push(vec![*src, *dst, PORT_RANGE, PORT_RANGE, PROTOCOL_RANGE]);
+        }
+    }
+
+    ranges
+}
+
+// IPv6 counterpart of `five_tuple` for classifiers with `u128` fields, using the same
+// dimension indices.
+pub fn ipv6_five_tuple() -> Vec<Vec<Range>> {
+    let mut ranges = Vec::new();
+    for src in IPV6_SPLIT.iter() {
+        for dst in IPV6_SPLIT.iter() {
+            ranges.push(vec![*src, *dst, PORT_RANGE, PORT_RANGE, PROTOCOL_RANGE]);
+        }
+    }
+
+    ranges
+}
+
+// Same split as `five_tuple` with DSCP as additional sixth dimension. Rules with only five
+// fields may still be inserted and do not care about the DSCP value of a packet.
+pub fn five_tuple_dscp() -> Vec<Vec<Range>> {
+    let mut ranges = five_tuple();
+    for r in ranges.iter_mut() {
+        r.push(DSCP_RANGE);
+    }
+
+    ranges
+}
+
+pub fn tunnel_five_tuple() -> Vec<Vec<Range>> {
+    with_tunnel_id(five_tuple())
+}
+
+pub fn tunnel_five_tuple_dscp() -> Vec<Vec<Range>> {
+    with_tunnel_id(five_tuple_dscp())
+}
+
+fn with_tunnel_id(inner: Vec<Vec<Range>>) -> Vec<Vec<Range>> {
+    let mut ranges = Vec::new();
+    for tunnel in TUNNEL_ID_SPLIT.iter() {
+        for r in inner.iter() {
+            let mut outer = vec![*tunnel];
+            outer.extend_from_slice(r);
+            ranges.push(outer);
+        }
+    }
+
+    ranges
+}
+
+// Names and widths of the dimensions of `five_tuple`.
+pub fn five_tuple_dimensions() -> Vec<Dimension> {
+    vec![
+        Dimension::new("src_ip", IPV4_WIDTH),
+        Dimension::new("dst_ip", IPV4_WIDTH),
+        Dimension::new("src_port", PORT_WIDTH),
+        Dimension::new("dst_port", PORT_WIDTH),
+        Dimension::new("protocol", PROTOCOL_WIDTH),
+    ]
+}
+
+pub fn ipv6_five_tuple_dimensions() -> Vec<Dimension> {
+    let mut dimensions = five_tuple_dimensions();
+    dimensions[SRC_IP] = Dimension::new("src_ip", IPV6_WIDTH);
+    dimensions[DST_IP] = Dimension::new("dst_ip", IPV6_WIDTH);
+    dimensions
+}
+
+pub fn five_tuple_dscp_dimensions() -> Vec<Dimension> {
+    let mut dimensions = five_tuple_dimensions();
+    dimensions.push(Dimension::new("dscp", DSCP_WIDTH));
+    dimensions
+}
+
+pub fn tunnel_five_tuple_dimensions() -> Vec<Dimension> {
+    with_tunnel_id_dimension(five_tuple_dimensions())
+}
+
+pub fn tunnel_five_tuple_dscp_dimensions() -> Vec<Dimension> {
+    with_tunnel_id_dimension(five_tuple_dscp_dimensions())
+}
+
+fn with_tunnel_id_dimension(inner: Vec<Dimension>) -> Vec<Dimension> {
+    let mut dimensions = vec![Dimension::new("tunnel_id", TUNNEL_ID_WIDTH)];
+    dimensions.extend(
+        inner
+            .into_iter()
+            .map(|d| Dimension::new(format!("inner_{}", d.name()), d.width())),
+    );
+    dimensions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accepting_tables(ranges: &[Vec<Range>], lengths: &[u32]) -> usize {
+        ranges
+            .iter()
+            .filter(|r| {
+                r.iter()
+                    .zip(lengths)
+                    .all(|((low, high), len)| len >= low && len < high)
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_five_tuple_accepts_every_prefix_combination_exactly_once() {
+        let ranges = five_tuple();
+        assert_eq!(ranges.len(), 16);
+
+        for src in 0..=32 {
+            for dst in 0..=32 {
+                assert_eq!(accepting_tables(&ranges, &[src, dst, 16, 0, 8]), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ipv6_five_tuple_accepts_every_prefix_combination_exactly_once() {
+        let ranges = ipv6_five_tuple();
+        assert_eq!(ranges.len(), 16);
+
+        for src in 0..=128 {
+            for dst in 0..=128 {
+                assert_eq!(accepting_tables(&ranges, &[src, dst, 0, 16, 8]), 1);
+            }
+        }
+
+        let widths: Vec<_> = ipv6_five_tuple_dimensions()
+            .iter()
+            .map(|d| d.width())
+            .collect();
+        assert!(invalid_ranges(&ranges, &widths).is_empty());
+    }
+
+    #[test]
+    fn test_five_tuple_dscp_adds_sixth_dimension() {
+        let ranges = five_tuple_dscp();
+        assert!(ranges.iter().all(|r| r.len() == 6 && r[DSCP] == DSCP_RANGE));
+
+        for dscp in 0..=6 {
+            assert_eq!(accepting_tables(&ranges, &[8, 24, 16, 16, 8, dscp]), 1);
+        }
+    }
+
+    #[test]
+    fn test_tunnel_five_tuple_spans_outer_and_inner_headers() {
+        let ranges = tunnel_five_tuple();
+        assert_eq!(ranges.len(), 32);
+        assert!(ranges.iter().all(|r| r.len() == 6));
+
+        for tunnel in &[0, 32] {
+            for src in 0..=32 {
+                assert_eq!(accepting_tables(&ranges, &[*tunnel, src, 32, 0, 16, 8]), 1);
+            }
+        }
+
+        assert!(tunnel_five_tuple_dscp().iter().all(|r| r.len() == 7));
+    }
+
+    #[test]
+    fn test_dimensions_match_presets() {
+        let widths = |dims: Vec<Dimension>| dims.iter().map(|d| d.width()).collect::<Vec<_>>();
+
+        assert!(invalid_ranges(&five_tuple(), &widths(five_tuple_dimensions())).is_empty());
+        assert!(invalid_ranges(
+            &tunnel_five_tuple_dscp(),
+            &widths(tunnel_five_tuple_dscp_dimensions())
+        )
+        .is_empty());
+
+        let dims = tunnel_five_tuple_dimensions();
+        assert_eq!(dims.len(), tunnel_five_tuple()[0].len());
+        assert_eq!(
+            dims[TUNNEL_INNER_OFFSET + DST_PORT].name(),
+            "inner_dst_port"
+        );
+        assert_eq!(five_tuple_dscp_dimensions()[DSCP].name(), "dscp");
+    }
+}
@@ -1,14 +1,24 @@
-use std::collections::{BTreeSet, HashMap};
-
+use std::collections::{BTreeMap, HashSet};
+use std::hash::BuildHasher;
+use std::mem;
+use std::ops::Deref;
+
+use crate::bloom::BucketFilter;
+use crate::dictionary::Dictionary;
+use crate::error::RvhError;
+use crate::hash::{self, SipBuildHasher};
+use crate::pool::{BucketRef, Buckets};
+#[cfg(feature = "simd")]
+use crate::simd::Heads;
+use crate::telemetry::{CollidingBucket, CollisionReport, Counter, TableMemory, TableStats};
 use crate::types::*;
 
-fn get_masks<'a, I: Iterator<Item = &'a Range>>(ranges: I) -> Vec<Mask> {
+fn get_masks<'a, F: FieldType, I: Iterator<Item = &'a Range>>(ranges: I) -> Vec<F> {
     ranges
         .map(|r| {
-            let mut m = 0;
+            let mut m = F::ZERO;
             for _ in 0..r.0 {
-                m <<= 1;
-                m |= 1;
+                m = (m << 1) | F::ONE;
             }
 
             m
@@ -17,29 +27,283 @@ fn get_masks<'a, I: Iterator<Item = &'a Range>>(ranges: I) -> Vec<Mask> {
 }
 
 #[inline]
-fn is_match(field1: Field, field2: Field, mask: Mask) -> bool {
-    ((field1 ^ field2) & mask) == 0
+pub(crate) fn is_match<F: FieldType>(field1: F, field2: F, mask: F) -> bool {
+    ((field1 ^ field2) & mask) == F::ZERO
+}
+
+pub(crate) fn rule_matches<R: Rule<F>, F: FieldType>(rule: &R, packet: &impl Packet<F>) -> bool {
+    packet
+        .fields()
+        .iter()
+        .zip(rule.fields().iter())
+        .zip(rule.masks())
+        .all(|((&pf, &rf), &rm)| is_match(pf, rf, rm))
+}
+
+// Prefix length of every dimension of right-aligned masks.
+pub(crate) fn prefix_lengths<F: FieldType>(masks: &[F]) -> Box<[u32]> {
+    masks.iter().map(|m| m.count_ones()).collect()
+}
+
+// Index of the first dimension whose mask is not a right-aligned prefix.
+pub(crate) fn invalid_mask<R: Rule<F>, F: FieldType>(rule: &R) -> Option<usize> {
+    rule.masks()
+        .iter()
+        .position(|m| m.count_ones() != m.trailing_ones())
+}
+
+// Clears the bits outside of the masks, if the rule allows it.
+pub(crate) fn normalize<R: Rule<F>, F: FieldType>(rule: &mut R) {
+    let masks = rule.masks().to_vec();
+    if let Some(fields) = rule.fields_mut() {
+        for (f, m) in fields.iter_mut().zip(masks) {
+            *f = *f & m;
+        }
+    }
 }
 
+// Reads the priority, fields and masks of a rule, see `RVHClassifier::prewarm`.
+pub(crate) fn touch<R: Rule<F>, F: FieldType>(rule: &R) {
+    let fold = |values: &[F]| values.iter().fold(F::ZERO, |acc, &v| acc ^ v);
+    std::hint::black_box((rule.priority(), fold(rule.fields()), fold(rule.masks())));
+}
+
+// Rules sharing a hash. Rules with the same fields and masks, f.e. several actions stacked on
+// the same match, are stored next to each other, highest priority first, so that a lookup
+// compares their fields only once. These runs are sorted by descending priority of their
+// first rule, so that a lookup can stop at the first match.
 #[derive(Debug, Clone)]
-pub(crate) struct RVHashMap<R: Rule> {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct Bucket<R> {
+    rules: Vec<R>,
+    // number of rules with the same fields and masks from each position on
+    runs: Vec<u32>,
+    // the first rules of the runs, compared several at once
+    #[cfg(feature = "simd")]
+    heads: Heads,
+}
+
+impl<R> Default for Bucket<R> {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            runs: Vec::new(),
+            #[cfg(feature = "simd")]
+            heads: Heads::default(),
+        }
+    }
+}
+
+impl<R> Bucket<R> {
+    pub fn insert<F: FieldType>(&mut self, rule: R)
+    where
+        R: Rule<F>,
+    {
+        let position = self.runs().position(|run| same_key(&run[0], &rule));
+        let mut run = match position {
+            Some(i) => self.take_run(i),
+            None => Vec::new(),
+        };
+        let index = run.partition_point(|r| r.priority() > rule.priority());
+        run.insert(index, rule);
+        self.place_run(run);
+    }
+
+    pub fn remove<F: FieldType>(&mut self, index: usize) -> R
+    where
+        R: Rule<F>,
+    {
+        // the rule may have been changed in place, its run is found by position
+        let mut start = 0;
+        let mut i = 0;
+        while start + self.runs[start] as usize <= index {
+            start += self.runs[start] as usize;
+            i += 1;
+        }
+
+        let mut run = self.take_run(i);
+        let rule = run.remove(index - start);
+        self.place_run(run);
+        rule
+    }
+
+    // Removes the `i`th run.
+    fn take_run<F: FieldType>(&mut self, i: usize) -> Vec<R>
+    where
+        R: Rule<F>,
+    {
+        let start: usize = self.runs().take(i).map(<[R]>::len).sum();
+        let len = self.runs[start] as usize;
+        let run = self.rules.drain(start..start + len).collect();
+        self.runs.drain(start..start + len);
+        run
+    }
+
+    // Inserts a run, which is sorted already, before the runs with a lower first priority.
+    fn place_run<F: FieldType>(&mut self, run: Vec<R>)
+    where
+        R: Rule<F>,
+    {
+        let Some(first) = run.first() else {
+            self.update_runs();
+            return;
+        };
+        let index: usize = self
+            .runs()
+            .take_while(|other| other[0].priority() > first.priority())
+            .map(<[R]>::len)
+            .sum();
+
+        self.rules.splice(index..index, run);
+        self.update_runs();
+    }
+
+    fn update_runs<F: FieldType>(&mut self)
+    where
+        R: Rule<F>,
+    {
+        self.runs.resize(self.rules.len(), 1);
+        for i in (0..self.rules.len()).rev() {
+            self.runs[i] = match self.rules.get(i + 1) {
+                Some(next) if same_key(&self.rules[i], next) => self.runs[i + 1] + 1,
+                _ => 1,
+            };
+        }
+
+        #[cfg(feature = "simd")]
+        {
+            self.heads = Heads::new(self.runs().map(|run| &run[0]));
+        }
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.rules.shrink_to_fit();
+        self.runs.shrink_to_fit();
+        #[cfg(feature = "simd")]
+        self.heads.shrink_to_fit();
+    }
+
+    // Heap bytes of the bookkeeping and of the rules.
+    pub fn heap_bytes(&self) -> (usize, usize) {
+        let runs = self.runs.capacity() * mem::size_of::<u32>();
+        #[cfg(feature = "simd")]
+        let runs = runs + self.heads.heap_bytes();
+        (runs, self.rules.capacity() * mem::size_of::<R>())
+    }
+
+    pub fn view(&self) -> BucketRef<'_, R> {
+        let bucket = BucketRef::nested(&self.rules, &self.runs);
+        #[cfg(feature = "simd")]
+        let bucket = bucket.with_heads(&self.heads);
+        bucket
+    }
+
+    // The rules with the same fields and masks, each highest priority first, by descending
+    // priority of their first rule.
+    fn runs(&self) -> impl Iterator<Item = &[R]> {
+        let mut start = 0;
+        std::iter::from_fn(move || {
+            let len = *self.runs.get(start)? as usize;
+            start += len;
+            Some(&self.rules[start - len..start])
+        })
+    }
+
+    // The guard of `RVHClassifier::get_mut` places a changed rule again before the bucket is
+    // used for lookups.
+    pub fn rule_mut(&mut self, index: usize) -> &mut R {
+        &mut self.rules[index]
+    }
+
+    pub fn into_vec(self) -> Vec<R> {
+        self.rules
+    }
+}
+
+impl<R> Deref for Bucket<R> {
+    type Target = [R];
+
+    fn deref(&self) -> &[R] {
+        &self.rules
+    }
+}
+
+impl<'a, R> IntoIterator for &'a Bucket<R> {
+    type Item = &'a R;
+    type IntoIter = std::slice::Iter<'a, R>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rules.iter()
+    }
+}
+
+impl<R> IntoIterator for Bucket<R> {
+    type Item = R;
+    type IntoIter = std::vec::IntoIter<R>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rules.into_iter()
+    }
+}
+
+pub(crate) fn same_key<R: Rule<F>, F: FieldType>(a: &R, b: &R) -> bool {
+    a.masks() == b.masks()
+        && a.fields()
+            .iter()
+            .zip(b.fields())
+            .zip(a.masks())
+            .all(|((&fa, &fb), &m)| is_match(fa, fb, m))
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct RVHashMap<R: Rule<F>, F: FieldType = Field, S = SipBuildHasher> {
+    // position of the range vector in the split, tables are reordered by priority
+    pub(crate) index: usize,
     pub(crate) highest_priority: Priority,
-    pub(crate) priorities: BTreeSet<Priority>,
-    pub(crate) masks: Vec<Mask>,
+    // the id of the rule with each priority, priorities are unique within a table
+    pub(crate) priorities: BTreeMap<Priority, RuleId>,
+    pub(crate) masks: Vec<F>,
     pub(crate) ranges: Vec<Range>,
-    pub(crate) hash_map: HashMap<u32, Vec<R>>,
+    // selects the hash function of the table, see `calc_hash`
+    pub(crate) seed: u32,
+    // the hasher of the classifier
+    pub(crate) hasher: S,
+    // per dimension, see `set_dictionary`
+    pub(crate) dictionaries: Vec<Option<Dictionary<F>>>,
+    // rules of a disabled table stay installed but do not match
+    pub(crate) enabled: bool,
+    pub(crate) hash_map: Buckets<R>,
+    // skips lookups of buckets that do not exist, see `set_filter`
+    pub(crate) filter: Option<BucketFilter>,
+    // best matches found in this table, see `RVHClassifier::set_hit_ordering`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) hits: Counter,
 }
 
-impl<R: Rule> RVHashMap<R> {
+impl<R: Rule<F>, F: FieldType, S: BuildHasher + Default> RVHashMap<R, F, S> {
     pub fn new(ranges: Vec<Range>) -> Self {
+        Self::with_hasher(ranges, S::default())
+    }
+}
+
+impl<R: Rule<F>, F: FieldType, S: BuildHasher> RVHashMap<R, F, S> {
+    pub fn with_hasher(ranges: Vec<Range>, hasher: S) -> Self {
         let masks = get_masks(ranges.iter()).into_iter().collect();
 
         Self {
+            index: 0,
             highest_priority: 0,
-            priorities: BTreeSet::new(),
+            priorities: BTreeMap::new(),
             masks,
             ranges,
-            hash_map: HashMap::new(),
+            seed: 0,
+            hasher,
+            dictionaries: Vec::new(),
+            enabled: true,
+            hash_map: Buckets::default(),
+            filter: None,
+            hits: Counter::default(),
         }
     }
 
@@ -47,80 +311,186 @@ impl<R: Rule> RVHashMap<R> {
         self.highest_priority
     }
 
+    pub fn len(&self) -> usize {
+        self.priorities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.priorities.is_empty()
+    }
+
+    // Removes all rules, keeping the ranges and the seed.
+    pub fn clear(&mut self) {
+        self.highest_priority = 0;
+        self.priorities.clear();
+        self.hash_map.clear();
+        self.refilter();
+        for dictionary in self.dictionaries.iter_mut().flatten() {
+            *dictionary = Dictionary::new();
+        }
+    }
+
     pub fn can_insert(&self, rule: &R) -> bool {
-        let rule_ranges = rule.masks().iter().map(|m| {
-            if cfg!(debug_assertions) {
-                // make sure masks are correctly right-aligned
-                let shift = 32 - m.count_ones();
-                debug_assert_eq!((m << shift) >> shift, *m);
-            }
+        self.accepts(rule.masks())
+    }
+
+    pub fn accepts(&self, masks: &[F]) -> bool {
+        self.fits(masks.iter().map(|m| {
+            // make sure masks are correctly right-aligned
+            debug_assert_eq!(m.count_ones(), m.trailing_ones());
 
             // we can simply count the bits to get the prefix length
             m.count_ones()
-        });
+        }))
+    }
+
+    // Same as `accepts` for the prefix lengths of the masks, see `prefix_lengths`.
+    pub fn accepts_prefixes(&self, prefixes: &[u32]) -> bool {
+        self.fits(prefixes.iter().copied())
+    }
 
+    fn fits(&self, prefixes: impl Iterator<Item = u32>) -> bool {
         self.ranges
             .iter()
-            .zip(rule_ranges)
+            .zip(prefixes)
             .all(|((r_low, r_high), r_rule)| r_rule >= *r_low && r_rule < *r_high)
     }
 
-    pub fn insert(&mut self, rule: R) -> bool {
-        if !self.priorities.insert(rule.priority()) {
+    // Returns the bucket the rule was stored in.
+    pub fn insert(&mut self, id: RuleId, rule: R) -> Result<u64, RvhError> {
+        if self.priorities.contains_key(&rule.priority()) {
             // We enforce unique priorities
-            return false;
+            return Err(RvhError::DuplicatePriority);
         }
+        self.priorities.insert(rule.priority(), id);
 
         if rule.priority() > self.highest_priority {
             self.highest_priority = rule.priority();
         }
 
-        let hash = self.calc_hash(rule.fields().iter());
-        if let Some(rule_list) = self.hash_map.get_mut(&hash) {
-            rule_list.push(rule);
-        } else {
-            self.hash_map.insert(hash, vec![rule]);
+        self.encode(&rule, |dictionary, value| {
+            dictionary.acquire(value);
+        });
+        let hash = self.calc_hash(rule.fields().iter()).unwrap();
+        let new_bucket = self.filter.is_some() && self.hash_map.get(hash).is_none();
+        self.hash_map.insert(hash, rule);
+        if new_bucket && self.filter.as_mut().is_some_and(|f| f.insert(hash)) {
+            self.refilter();
         }
 
-        true
+        Ok(hash)
     }
 
-    pub fn remove(&mut self, rule: &R) -> bool {
-        if !self.priorities.remove(&rule.priority()) {
-            return false;
+    pub fn remove(&mut self, rule: &R) -> Option<RuleId> {
+        if !self.priorities.contains_key(&rule.priority()) {
+            return None;
+        }
+
+        let hash = self.calc_hash(rule.fields().iter())?;
+        // the rule with the priority may not be equal to this one, f.e. by its action
+        let index = self.hash_map.get(hash)?.iter().position(|r| r == rule)?;
+        let (id, _) = self.take(hash, index, rule.priority());
+
+        Some(id)
+    }
+
+    // Removes the rule at `index` of a bucket, `priority` is the priority it was inserted with.
+    pub fn take(&mut self, bucket: u64, index: usize, priority: Priority) -> (RuleId, R) {
+        let id = self.priorities.remove(&priority).unwrap();
+
+        if priority == self.highest_priority {
+            self.highest_priority = self.priorities.last_key_value().map_or(0, |(p, _)| *p);
         }
 
-        if rule.priority() == self.highest_priority {
-            self.highest_priority = *self.priorities.iter().min().unwrap_or(&0);
+        let rule = self.hash_map.remove(bucket, index);
+        if let Some(filter) = self.filter.as_mut() {
+            if self.hash_map.get(bucket).is_none_or(|b| b.is_empty()) {
+                filter.remove(bucket);
+            }
+        }
+        self.encode(&rule, |dictionary, value| dictionary.release(value));
+        (id, rule)
+    }
+
+    // Position of the rule with `priority` within a bucket.
+    pub fn position(&self, bucket: u64, priority: Priority) -> Option<usize> {
+        self.hash_map
+            .get(bucket)?
+            .iter()
+            .position(|r| r.priority() == priority)
+    }
+
+    // The rule with the highest priority among those with exactly these masks and fields.
+    pub fn find(&self, fields: &[F], masks: &[F]) -> Option<&R> {
+        // installed rules only have prefix masks
+        let prefixes = masks.iter().all(|m| m.count_ones() == m.trailing_ones());
+        if fields.len() != masks.len() || !prefixes || !self.accepts(masks) {
+            return None;
         }
 
-        let hash = self.calc_hash(rule.fields().iter());
-        // since we added the priority, the rule should be present in the hash_map
-        let rule_list = self.hash_map.get_mut(&hash).unwrap();
-        let index = rule_list.iter().position(|r| r == rule).unwrap();
-        rule_list.swap_remove(index);
+        self.hash_map
+            .get(self.calc_hash(fields.iter())?)?
+            .iter()
+            .filter(|r| {
+                r.masks() == masks
+                    && r.fields()
+                        .iter()
+                        .zip(fields)
+                        .zip(masks)
+                        .all(|((a, b), m)| *a & *m == *b & *m)
+            })
+            .max_by_key(|r| r.priority())
+    }
+
+    pub fn contains(&self, rule: &R) -> bool {
+        self.priorities.contains_key(&rule.priority())
+            && self
+                .calc_hash(rule.fields().iter())
+                .and_then(|hash| self.hash_map.get(hash))
+                .is_some_and(|bucket| bucket.iter().any(|r| r == rule))
+    }
 
-        true
+    pub fn check_match(&self, packet: &impl Packet<F>) -> Option<&R> {
+        self.check_match_where(packet, |_| true)
     }
 
-    pub fn check_match(&self, packet: &impl Packet) -> Option<&R> {
-        let hash = self.calc_hash(packet.fields().iter());
+    // The bucket of the packet, the only rules of the table that may match it.
+    pub fn candidates(&self, packet: &impl Packet<F>) -> impl Iterator<Item = &R> {
+        self.calc_hash(packet.fields().iter())
+            .and_then(|hash| self.bucket(hash))
+            .into_iter()
+            .flat_map(BucketRef::iter)
+    }
 
-        if let Some(matching_rules) = self.hash_map.get(&hash) {
+    // Same as `check_match` but ignores rules for which `accept` returns false.
+    pub fn check_match_where(
+        &self,
+        packet: &impl Packet<F>,
+        accept: impl Fn(&R) -> bool,
+    ) -> Option<&R> {
+        let hash = self.calc_hash(packet.fields().iter())?;
+
+        if let Some(bucket) = self.bucket(hash) {
             let mut best_prio = 0;
             let mut best_match = None;
+            let mut lanes = 0;
+
+            for (i, (start, len)) in bucket.runs().enumerate() {
+                // the remaining runs start with lower priorities
+                if bucket.get(start).priority() <= best_prio {
+                    break;
+                }
+                // all rules of a run match if the first one does
+                if !bucket.run_matches(i, start, packet, &mut lanes) {
+                    continue;
+                }
 
-            for r in matching_rules.iter() {
-                if packet
-                    .fields()
-                    .iter()
-                    .zip(r.fields().iter())
-                    .zip(r.masks())
-                    .all(|((&pf, &rf), &rm)| is_match(pf, rf, rm))
-                    && r.priority() > best_prio
-                {
-                    best_prio = r.priority();
-                    best_match = Some(r);
+                let mut run = (start..start + len).map(|p| bucket.get(p));
+                if let Some(r) = run.find(|r| accept(r)) {
+                    if r.priority() > best_prio {
+                        best_prio = r.priority();
+                        best_match = Some(r);
+                    }
                 }
             }
 
@@ -130,25 +500,248 @@ impl<R: Rule> RVHashMap<R> {
         None
     }
 
-    fn calc_hash<'a>(&self, fields: impl Iterator<Item = &'a Field>) -> u32 {
-        // TODO: this can certainly be improved
+    // Same as `check_match` for rules with a priority above `above`, along with the hash of the
+    // bucket of the packet, if it has one, and the number of rules compared, one per run of
+    // rules with the same key.
+    pub fn check_match_traced(
+        &self,
+        packet: &impl Packet<F>,
+        above: Priority,
+    ) -> (Option<u64>, Option<&R>, usize) {
+        let Some((hash, bucket)) = self
+            .calc_hash(packet.fields().iter())
+            .and_then(|hash| Some((hash, self.bucket(hash)?)))
+        else {
+            return (None, None, 0);
+        };
+
+        let mut compared = 0;
+        let mut lanes = 0;
+        for (i, (start, _)) in bucket.runs().enumerate() {
+            if bucket.get(start).priority() <= above {
+                break;
+            }
+            compared += 1;
+            if bucket.run_matches(i, start, packet, &mut lanes) {
+                return (Some(hash), Some(bucket.get(start)), compared);
+            }
+        }
+
+        (Some(hash), None, compared)
+    }
 
-        let mut hash = 0;
-        let mut p = 1;
+    // Same as `check_match` but compares at most `budget` runs of the bucket, which is
+    // decreased accordingly. The returned flag is false if the bucket was not fully scanned.
+    pub fn check_match_bounded(
+        &self,
+        packet: &impl Packet<F>,
+        budget: &mut usize,
+    ) -> (Option<&R>, bool) {
+        let mut best_prio = 0;
+        let mut best_match = None;
+
+        let bucket = self
+            .calc_hash(packet.fields().iter())
+            .and_then(|hash| self.bucket(hash));
+        if let Some(bucket) = bucket {
+            let mut lanes = 0;
+            for (i, (start, _)) in bucket.runs().enumerate() {
+                let first = bucket.get(start);
+                if first.priority() <= best_prio {
+                    break;
+                }
+                if *budget == 0 {
+                    return (best_match, false);
+                }
+                *budget -= 1;
 
-        for (m, f) in self.masks.iter().zip(fields) {
-            hash ^= p | (f & m);
-            p ^= 1;
+                // without a filter the first rule of the first matching run is the best one
+                if bucket.run_matches(i, start, packet, &mut lanes) {
+                    best_prio = first.priority();
+                    best_match = Some(first);
+                }
+            }
+        }
+
+        (best_match, true)
+    }
+
+    // Rehashes all rules with the hash function selected by `seed`.
+    pub fn reseed(&mut self, seed: u32) {
+        self.seed = seed;
+
+        for rule in self.hash_map.drain() {
+            let hash = self.calc_hash(rule.fields().iter()).unwrap();
+            self.hash_map.insert(hash, rule);
+        }
+        self.refilter();
+    }
+
+    // Keeps a counting Bloom filter of the buckets, so that lookups of packets without a
+    // bucket mostly skip the buckets, for classifiers with many tables where most probes miss.
+    // Costs a byte per bucket or so.
+    pub fn set_filter(&mut self, enabled: bool) {
+        self.filter = enabled.then(|| BucketFilter::new(std::iter::empty()));
+        self.refilter();
+    }
+
+    // Builds the filter again for the current buckets.
+    fn refilter(&mut self) {
+        if self.filter.is_some() {
+            let hashes: Vec<u64> = self
+                .hash_map
+                .iter()
+                .filter(|(_, bucket)| !bucket.is_empty())
+                .map(|(hash, _)| hash)
+                .collect();
+            self.filter = Some(BucketFilter::new(hashes.into_iter()));
         }
+    }
 
-        hash
+    #[inline]
+    fn bucket(&self, hash: u64) -> Option<BucketRef<'_, R>> {
+        if self.filter.as_ref().is_some_and(|f| !f.may_contain(hash)) {
+            return None;
+        }
+        self.hash_map.get(hash)
+    }
+
+    // Hashes the values of `dimension` by their code in a dictionary instead of the values
+    // themselves, or stops doing so. Rehashes all rules.
+    pub fn set_dictionary(&mut self, dimension: usize, enabled: bool) {
+        if dimension >= self.masks.len() {
+            return;
+        }
+        if self.dictionaries.len() <= dimension {
+            self.dictionaries.resize_with(dimension + 1, || None);
+        }
+
+        let mut dictionary = enabled.then(Dictionary::new);
+        if let Some(dictionary) = dictionary.as_mut() {
+            for rule in self.hash_map.rules() {
+                dictionary.acquire(masked(rule, &self.masks, dimension));
+            }
+        }
+        self.dictionaries[dimension] = dictionary;
+        self.reseed(self.seed);
+    }
+
+    // Calls `f` with the dictionary and masked value of every dimension of the rule that has
+    // a dictionary.
+    fn encode(&mut self, rule: &R, mut f: impl FnMut(&mut Dictionary<F>, F)) {
+        for (dim, dictionary) in self.dictionaries.iter_mut().enumerate() {
+            if let Some(dictionary) = dictionary {
+                f(dictionary, masked(rule, &self.masks, dim));
+            }
+        }
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.hash_map.reserve(additional);
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.hash_map.shrink_to_fit();
+        self.refilter();
+    }
+
+    pub fn stats(&self) -> TableStats {
+        let mut stats = TableStats {
+            index: self.index,
+            capacity: self.hash_map.capacity(),
+            lowest_priority: self.priorities.keys().next().copied().unwrap_or(0),
+            highest_priority: self.highest_priority,
+            ..TableStats::default()
+        };
+        for (_, rules) in self.hash_map.iter().filter(|(_, rules)| !rules.is_empty()) {
+            stats.rules += rules.len();
+            stats.buckets += 1;
+            stats.largest_bucket = stats.largest_bucket.max(rules.len());
+            stats.collisions += self.keys(rules).len() - 1;
+        }
+
+        stats
+    }
+
+    // Bucket lengths and the `worst` buckets with the most distinct keys.
+    pub fn collisions(&self, worst: usize) -> CollisionReport<F> {
+        let mut histogram = Vec::new();
+        let mut colliding = Vec::new();
+        for (hash, rules) in self.hash_map.iter().filter(|(_, rules)| !rules.is_empty()) {
+            if histogram.len() <= rules.len() {
+                histogram.resize(rules.len() + 1, 0);
+            }
+            histogram[rules.len()] += 1;
+
+            let keys = self.keys(rules);
+            if keys.len() > 1 {
+                colliding.push(CollidingBucket {
+                    hash,
+                    rules: rules.len(),
+                    keys,
+                });
+            }
+        }
+        // ties by hash, so that reports of the same rules compare equal
+        colliding.sort_by_key(|b| (std::cmp::Reverse((b.keys.len(), b.rules)), b.hash));
+        colliding.truncate(worst);
+
+        CollisionReport {
+            index: self.index,
+            histogram,
+            worst: colliding,
+        }
+    }
+
+    // The distinct masked fields of the rules of a bucket, in bucket order. Rules with the same
+    // masked fields always share a bucket, only different ones are collisions.
+    fn keys(&self, rules: BucketRef<'_, R>) -> Vec<Vec<F>> {
+        let mut seen = HashSet::new();
+        rules
+            .iter()
+            .map(|r| {
+                (0..self.masks.len())
+                    .map(|dim| masked(r, &self.masks, dim))
+                    .collect()
+            })
+            .filter(|key: &Vec<F>| seen.insert(key.clone()))
+            .collect()
+    }
+
+    pub fn memory(&self) -> TableMemory {
+        let (key_bytes, bucket_bytes, rule_bytes) = self.hash_map.heap_bytes();
+        TableMemory {
+            index: self.index,
+            buckets: self.hash_map.len(),
+            rules: self.len(),
+            key_bytes: key_bytes
+                + self.priorities.len() * mem::size_of::<(Priority, RuleId)>()
+                + self.filter.as_ref().map_or(0, BucketFilter::heap_bytes),
+            bucket_bytes,
+            rule_bytes,
+        }
+    }
+
+    fn calc_hash<'a>(&self, fields: impl Iterator<Item = &'a F>) -> Option<u64> {
+        hash::calc_hash(
+            &self.hasher,
+            &self.masks,
+            self.seed,
+            &self.dictionaries,
+            fields,
+        )
     }
 }
 
+// The field of `dim` of the rule under the mask of its table, missing fields are zero.
+fn masked<R: Rule<F>, F: FieldType>(rule: &R, masks: &[F], dim: usize) -> F {
+    rule.fields().get(dim).map_or(F::ZERO, |f| *f & masks[dim])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::mocks::{MockPacket, MockRule};
+    use crate::types::mocks::{MockBuildHasher, MockPacket, MockRule};
 
     #[test]
     fn it_works() {
@@ -157,18 +750,21 @@ mod tests {
 
     #[test]
     fn test_is_match() {
-        assert!(is_match(0b1101, 0b0101, 0b0111));
-        assert!(is_match(0b0101, 0b1101, 0b0111));
+        assert!(is_match::<Field>(0b1101, 0b0101, 0b0111));
+        assert!(is_match::<Field>(0b0101, 0b1101, 0b0111));
 
-        assert!(!is_match(0b1111, 0b1101, 0b0111));
-        assert!(!is_match(0b1101, 0b1111, 0b0111));
+        assert!(!is_match::<Field>(0b1111, 0b1101, 0b0111));
+        assert!(!is_match::<Field>(0b1101, 0b1111, 0b0111));
     }
 
     #[test]
     fn test_get_mask() {
-        let ranges = vec![(3, 5), (6, 10), (1, 2), (0, 1)];
+        let ranges = [(3, 5), (6, 10), (1, 2), (0, 1)];
 
-        assert_eq!(get_masks(ranges.iter()), vec![0b111, 0b11_1111, 0b1, 0b0]);
+        assert_eq!(
+            get_masks::<Mask, _>(ranges.iter()),
+            vec![0b111, 0b11_1111, 0b1, 0b0]
+        );
     }
 
     #[test]
@@ -198,10 +794,90 @@ mod tests {
         let mut map: RVHashMap<MockRule> = RVHashMap::new(vec![(3, 5)]);
 
         let yes1 = MockRule::new(vec![0b101], vec![0b111], 1);
-        map.insert(yes1);
+        map.insert(RuleId(1), yes1).unwrap();
 
         let no1 = MockRule::new(vec![0b101], vec![0b111], 1);
-        assert!(!map.insert(no1))
+        assert_eq!(map.insert(RuleId(2), no1), Err(RvhError::DuplicatePriority));
+    }
+
+    #[test]
+    fn test_rules_with_the_same_key_share_a_run() {
+        let mut map: RVHashMap<MockRule> = RVHashMap::new(vec![(3, 4)]);
+        for (id, priority) in [3, 7, 5].iter().enumerate() {
+            let r = MockRule::new(vec![0b101], vec![0b111], *priority);
+            map.insert(RuleId(id as u64), r).unwrap();
+        }
+        // bits outside of the masks do not matter
+        let same = MockRule::new(vec![0b1101], vec![0b111], 4);
+        let bucket = map.insert(RuleId(3), same).unwrap();
+
+        let priorities: Vec<_> = map
+            .hash_map
+            .get(bucket)
+            .unwrap()
+            .iter()
+            .map(|r| r.priority())
+            .collect();
+        assert_eq!(priorities, vec![7, 5, 4, 3]);
+        let runs: Vec<_> = map
+            .hash_map
+            .get(bucket)
+            .unwrap()
+            .runs()
+            .map(|(_, len)| len)
+            .collect();
+        assert_eq!(runs, vec![4]);
+
+        let p = MockPacket::new(vec![0b101]);
+        assert_eq!(map.check_match(&p).unwrap().priority(), 7);
+        let r = map.check_match_where(&p, |r| r.priority() < 7).unwrap();
+        assert_eq!(r.priority(), 5);
+
+        let index = map.position(bucket, 7).unwrap();
+        map.take(bucket, index, 7);
+        assert_eq!(map.check_match(&p).unwrap().priority(), 5);
+        assert_eq!(map.hash_map.get(bucket).unwrap().runs().count(), 1);
+    }
+
+    #[test]
+    fn test_runs_are_sorted_by_priority() {
+        let mut map: RVHashMap<MockRule, Field, MockBuildHasher> =
+            RVHashMap::new(vec![(3, 4), (3, 4)]);
+        // both keys collide with the XOR hash
+        let a = |priority| MockRule::new(vec![0b10, 0b100], vec![0b111, 0b111], priority);
+        let b = |priority| MockRule::new(vec![0b100, 0b10], vec![0b111, 0b111], priority);
+        map.insert(RuleId(0), a(1)).unwrap();
+        map.insert(RuleId(1), b(5)).unwrap();
+        let bucket = map.insert(RuleId(2), a(3)).unwrap();
+
+        let priorities = |map: &RVHashMap<MockRule, Field, MockBuildHasher>| -> Vec<Priority> {
+            map.hash_map
+                .get(bucket)
+                .unwrap()
+                .iter()
+                .map(|r| r.priority())
+                .collect()
+        };
+        assert_eq!(priorities(&map), vec![5, 3, 1]);
+
+        // the first matching run ends the scan
+        let p = MockPacket::new(vec![0b100, 0b10]);
+        let mut budget = 1;
+        let (r, complete) = map.check_match_bounded(&p, &mut budget);
+        assert_eq!((r.unwrap().priority(), complete), (5, true));
+        let mut budget = 1;
+        let q = MockPacket::new(vec![0b10, 0b100]);
+        assert_eq!(map.check_match_bounded(&q, &mut budget), (None, false));
+
+        // a run moves when its first rule changes
+        let index = map.position(bucket, 5).unwrap();
+        map.take(bucket, index, 5);
+        map.insert(RuleId(3), b(2)).unwrap();
+        assert_eq!(priorities(&map), vec![3, 1, 2]);
+        map.insert(RuleId(4), b(7)).unwrap();
+        assert_eq!(priorities(&map), vec![7, 2, 3, 1]);
+        assert_eq!(map.check_match(&q).unwrap().priority(), 3);
+        assert_eq!(map.check_match(&p).unwrap().priority(), 7);
     }
 
     #[test]
@@ -209,15 +885,15 @@ mod tests {
         let mut map: RVHashMap<MockRule> = RVHashMap::new(vec![(3, 5)]);
 
         let r = MockRule::new(vec![0b101], vec![0b111], 1);
-        map.insert(r);
+        map.insert(RuleId(3), r).unwrap();
         assert_eq!(map.highest_priority(), 1);
 
         let r = MockRule::new(vec![0b101], vec![0b1111], 4);
-        map.insert(r);
+        map.insert(RuleId(4), r).unwrap();
         assert_eq!(map.highest_priority(), 4);
 
         let r = MockRule::new(vec![0b111], vec![0b111], 2);
-        map.insert(r);
+        map.insert(RuleId(5), r).unwrap();
         assert_eq!(map.highest_priority(), 4);
     }
 
@@ -229,15 +905,21 @@ mod tests {
         let r2 = MockRule::new(vec![0b11], vec![0b1111], 4);
         let r3 = MockRule::new(vec![0b1001], vec![0b1111], 6);
 
-        map.insert(r1.clone());
-        map.insert(r2.clone());
-        map.insert(r3.clone());
-
-        map.remove(&r2);
-        assert_eq!(map.highest_priority(), 6);
+        map.insert(RuleId(6), r1.clone()).unwrap();
+        map.insert(RuleId(7), r2.clone()).unwrap();
+        map.insert(RuleId(8), r3.clone()).unwrap();
 
         map.remove(&r3);
+        assert_eq!(map.highest_priority(), 4);
+
+        map.remove(&r2);
         assert_eq!(map.highest_priority(), 1);
+        assert_eq!(map.len(), 1);
+
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.highest_priority(), 0);
+        assert!(map.check_match(&MockPacket::new(vec![0b101])).is_none());
     }
 
     #[test]
@@ -248,9 +930,9 @@ mod tests {
         let r2 = MockRule::new(vec![0b1101], vec![0b1111], 4);
         let r3 = MockRule::new(vec![0b1001], vec![0b1111], 6);
 
-        map.insert(r1);
-        map.insert(r2);
-        map.insert(r3);
+        map.insert(RuleId(9), r1).unwrap();
+        map.insert(RuleId(10), r2).unwrap();
+        map.insert(RuleId(11), r3).unwrap();
 
         let p1 = MockPacket::new(vec![0b101]);
         let p2 = MockPacket::new(vec![0b1101]);
@@ -263,11 +945,50 @@ mod tests {
         assert!(map.check_match(&p4).is_none());
     }
 
+    #[test]
+    fn test_reseed_resolves_collisions() {
+        let mut map: RVHashMap<MockRule, Field, MockBuildHasher> =
+            RVHashMap::new(vec![(3, 4), (3, 4)]);
+        let r1 = MockRule::new(vec![0b10, 0b100], vec![0b111, 0b111], 1);
+        let r2 = MockRule::new(vec![0b100, 0b10], vec![0b111, 0b111], 2);
+        let r3 = MockRule::new(vec![0b10, 0b100], vec![0b1111, 0b111], 3);
+        map.insert(RuleId(0), r1.clone()).unwrap();
+        map.insert(RuleId(1), r2).unwrap();
+        map.insert(RuleId(2), r3).unwrap();
+
+        let stats = map.stats();
+        assert_eq!(stats.rules, 3);
+        assert_eq!(stats.buckets, 1);
+        assert_eq!(stats.largest_bucket, 3);
+        assert_eq!(stats.collisions, 1);
+        let report = map.collisions(5);
+        assert_eq!(report.histogram, vec![0, 0, 0, 1]);
+        assert_eq!(report.largest_bucket(), 3);
+        assert_eq!(report.worst.len(), 1);
+        assert_eq!(report.worst[0].rules, 3);
+        assert_eq!(
+            report.worst[0].keys,
+            vec![vec![0b10, 0b100], vec![0b100, 0b10]]
+        );
+
+        map.reseed(2);
+        let stats = map.stats();
+        assert_eq!(stats.buckets, 2);
+        assert_eq!(stats.collisions, 0);
+        let report = map.collisions(5);
+        assert_eq!(report.histogram, vec![0, 1, 1]);
+        assert!(report.worst.is_empty());
+
+        let p = MockPacket::new(vec![0b100, 0b10]);
+        assert_eq!(map.check_match(&p).unwrap().priority(), 2);
+        assert!(map.remove(&r1).is_some());
+    }
+
     #[test]
     fn test_rv_hash_map_check_match_on_multiple_fields() {
         let mut map: RVHashMap<MockRule> = RVHashMap::new(vec![(3, 5), (3, 5)]);
         let r1 = MockRule::new(vec![0b101, 0b1010], vec![0b111, 0b1111], 1);
-        map.insert(r1);
+        map.insert(RuleId(12), r1).unwrap();
 
         let p1 = MockPacket::new(vec![0b101, 0b1000]);
         let p2 = MockPacket::new(vec![0b100, 0b1010]);
@@ -0,0 +1,199 @@
+use std::hash::BuildHasher;
+
+use crate::classifier::RVHClassifier;
+use crate::error::RvhError;
+use crate::hash::SipBuildHasher;
+use crate::types::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RebuildProgress {
+    pub inserted: usize,
+    pub total: usize,
+}
+
+impl RebuildProgress {
+    pub fn is_complete(&self) -> bool {
+        self.inserted == self.total
+    }
+}
+
+// A new classifier with a different split, built step by step from a snapshot of the rules of
+// an existing one. The original keeps serving lookups in the meantime. Updates applied to the
+// original after the snapshot has been taken have to be mirrored through `add_rule` and
+// `remove_rule`. Once complete it replaces the original with `RVHClassifier::cutover`.
+#[derive(Debug, Clone)]
+pub struct Rebuild<R: Rule<F>, F: FieldType = Field, M = (), S = SipBuildHasher> {
+    target: Box<RVHClassifier<R, F, M, S>>,
+    pending: Vec<(RuleId, R)>,
+    rejected: Vec<R>,
+    inserted: usize,
+}
+
+impl<R: Rule<F>, F: FieldType, M, S: BuildHasher + Clone> Rebuild<R, F, M, S> {
+    pub(crate) fn new(target: RVHClassifier<R, F, M, S>, pending: Vec<(RuleId, R)>) -> Self {
+        Self {
+            target: Box::new(target),
+            pending,
+            rejected: Vec::new(),
+            inserted: 0,
+        }
+    }
+
+    // Moves up to `max_rules` rules into the new classifier.
+    pub fn step(&mut self, max_rules: usize) -> RebuildProgress {
+        for _ in 0..max_rules {
+            match self.pending.pop() {
+                Some((id, rule)) => self.insert(id, rule),
+                None => break,
+            }
+        }
+
+        self.progress()
+    }
+
+    pub fn progress(&self) -> RebuildProgress {
+        RebuildProgress {
+            inserted: self.inserted,
+            total: self.inserted + self.pending.len() + self.rejected.len(),
+        }
+    }
+
+    // Rules the new split has no table for. A rebuild with rejected rules can not be cut over.
+    pub fn rejected(&self) -> &[R] {
+        &self.rejected
+    }
+
+    // `id` is the id the original classifier assigned to the rule.
+    pub fn add_rule(&mut self, id: RuleId, rule: R) {
+        self.pending.push((id, rule));
+    }
+
+    pub fn remove_rule(&mut self, rule: &R) -> Result<(), RvhError> {
+        if let Some(index) = self.pending.iter().position(|(_, r)| r == rule) {
+            self.pending.swap_remove(index);
+            return Ok(());
+        }
+
+        if let Some(index) = self.rejected.iter().position(|r| r == rule) {
+            self.rejected.swap_remove(index);
+            return Ok(());
+        }
+
+        self.target.remove_rule(rule)?;
+        self.inserted -= 1;
+        Ok(())
+    }
+
+    fn insert(&mut self, id: RuleId, rule: R) {
+        if self.target.can_insert_rule(&rule) {
+            let inserted = self.target.insert_rule_with_id(id, rule);
+            debug_assert!(inserted);
+            self.inserted += 1;
+        } else {
+            self.rejected.push(rule);
+        }
+    }
+
+    #[cfg(feature = "concurrent")]
+    pub(crate) fn target(&self) -> &RVHClassifier<R, F, M, S> {
+        &self.target
+    }
+
+    pub(crate) fn into_target(self) -> Result<RVHClassifier<R, F, M, S>, Self> {
+        if !self.pending.is_empty() || !self.rejected.is_empty() {
+            return Err(self);
+        }
+
+        Ok(*self.target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::mocks::{MockPacket, MockRule};
+    use crate::types::{Field, Rule};
+    use crate::RVHClassifier;
+
+    fn classifier() -> RVHClassifier<MockRule> {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 9)]].into_iter());
+        for prio in 1..=8 {
+            assert!(rvh
+                .add_rule(MockRule::new(vec![prio], vec![(1 << prio) - 1], prio))
+                .is_ok());
+        }
+        rvh
+    }
+
+    #[test]
+    fn test_incremental_rebuild_and_cutover() {
+        let mut rvh = classifier();
+        let mut rebuild = rvh.start_rebuild(vec![vec![(0, 4)], vec![(4, 9)]]);
+        assert_eq!(rebuild.progress().total, 8);
+
+        let p = MockPacket::new(vec![0b1000]);
+        assert_eq!(rvh.classify(&p).unwrap().priority(), 8);
+
+        let progress = rebuild.step(3);
+        assert_eq!((progress.inserted, progress.total), (3, 8));
+        assert!(!progress.is_complete());
+
+        // the original keeps serving, updates are mirrored into the rebuild
+        let r9 = MockRule::new(vec![0b1000], vec![0b1111], 9);
+        let id = rvh.add_rule(r9.clone()).unwrap();
+        rebuild.add_rule(id, r9);
+        let r8 = MockRule::new(vec![8], vec![0b1111_1111], 8);
+        assert!(rvh.remove_rule(&r8).is_ok());
+        assert!(rebuild.remove_rule(&r8).is_ok());
+
+        let mut rebuild = rvh.cutover(rebuild).unwrap_err();
+        assert!(rebuild.step(100).is_complete());
+        assert!(rvh.cutover(rebuild).is_ok());
+
+        assert_eq!(rvh.classify(&p).unwrap().priority(), 9);
+        assert_eq!(rvh.iter().count(), 8);
+    }
+
+    #[test]
+    fn test_rebuild_keeps_rule_ids_and_metadata() {
+        let mut rvh =
+            RVHClassifier::<MockRule, Field, &str>::with_metadata(vec![vec![(0, 9)]].into_iter());
+        let r1 = rvh
+            .add_rule(MockRule::new(vec![0b1], vec![0b1], 1))
+            .unwrap();
+        assert!(rvh.set_meta(r1, "first"));
+
+        assert!(rvh.rebuild(vec![vec![(0, 4)], vec![(4, 9)]]).is_ok());
+        assert_eq!(rvh.get_meta(r1), Some(&"first"));
+
+        let r2 = rvh
+            .add_rule(MockRule::new(vec![0b10], vec![0b11], 2))
+            .unwrap();
+        assert_ne!(r1, r2);
+    }
+
+    #[test]
+    fn test_rebuild_rejects_rules_the_new_split_can_not_hold() {
+        let mut rvh = classifier();
+        let mut rebuild = rvh.start_rebuild(vec![vec![(0, 4)]]);
+
+        let progress = rebuild.step(100);
+        assert_eq!((progress.inserted, progress.total), (3, 8));
+        assert_eq!(rebuild.rejected().len(), 5);
+        assert!(rvh.cutover(rebuild).is_err());
+
+        // unchanged
+        assert_eq!(rvh.iter().count(), 8);
+    }
+
+    #[test]
+    fn test_rebuild_runs_to_completion() {
+        let mut rvh = classifier();
+        assert!(rvh.rebuild(vec![vec![(0, 2)], vec![(2, 9)]]).is_ok());
+        assert_eq!(
+            rvh.classify(&MockPacket::new(vec![0b11]))
+                .unwrap()
+                .priority(),
+            3
+        );
+    }
+}
@@ -0,0 +1,388 @@
+use std::convert::TryInto;
+
+use crate::classifier::RVHClassifier;
+use crate::error::RvhError;
+use crate::hash::SipBuildHasher;
+use crate::types::*;
+
+const MAGIC: &[u8; 4] = b"RVHT";
+const VERSION: u8 = 1;
+
+const ADD: u8 = 0;
+const REMOVE: u8 = 1;
+const CLASSIFY: u8 = 2;
+
+// An operation of a trace together with its outcome when it was recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op<F = Field> {
+    // id of the added rule, None if it was rejected
+    Add {
+        fields: Vec<F>,
+        masks: Vec<F>,
+        priority: Priority,
+        id: Option<RuleId>,
+    },
+    Remove {
+        id: RuleId,
+        removed: bool,
+    },
+    // fields of the packet, and the id of the rule it matched
+    Classify {
+        fields: Vec<F>,
+        matched: Option<RuleId>,
+    },
+}
+
+// Everything needed to rebuild a classifier operation by operation, see `Recorder` and
+// `replay`. Ids are assigned in order, so the same operations give the same ids.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace<F = Field> {
+    pub split: Vec<Vec<Range>>,
+    // keys of the hasher, so that rules end up in the same buckets
+    pub seed: u64,
+    pub ops: Vec<Op<F>>,
+}
+
+fn hasher(seed: u64) -> SipBuildHasher {
+    SipBuildHasher::with_keys(seed, seed.rotate_left(32) ^ 0x9e37_79b9_7f4a_7c15)
+}
+
+impl<F: FieldType> Trace<F> {
+    // Little endian binary encoding, fields take `F::WORDS` 32 bit words.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.push(F::WORDS as u8);
+        out.extend_from_slice(&self.seed.to_le_bytes());
+
+        put_u32(&mut out, self.split.len() as u32);
+        for ranges in self.split.iter() {
+            put_u32(&mut out, ranges.len() as u32);
+            for &(low, high) in ranges {
+                put_u32(&mut out, low);
+                put_u32(&mut out, high);
+            }
+        }
+
+        for op in self.ops.iter() {
+            match op {
+                Op::Add {
+                    fields,
+                    masks,
+                    priority,
+                    id,
+                } => {
+                    out.push(ADD);
+                    put_u32(&mut out, *priority);
+                    put_fields(&mut out, fields);
+                    put_fields(&mut out, masks);
+                    put_id(&mut out, *id);
+                }
+                Op::Remove { id, removed } => {
+                    out.push(REMOVE);
+                    out.extend_from_slice(&id.0.to_le_bytes());
+                    out.push(*removed as u8);
+                }
+                Op::Classify { fields, matched } => {
+                    out.push(CLASSIFY);
+                    put_fields(&mut out, fields);
+                    put_id(&mut out, *matched);
+                }
+            }
+        }
+
+        out
+    }
+
+    // None if `bytes` is not a trace of this field type.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut r = Reader(bytes);
+        if r.take(4)? != MAGIC || r.u8()? != VERSION || r.u8()? as usize != F::WORDS {
+            return None;
+        }
+        let seed = r.u64()?;
+
+        let tables = r.u32()?;
+        let mut split = Vec::new();
+        for _ in 0..tables {
+            let len = r.u32()?;
+            let ranges = (0..len)
+                .map(|_| Some((r.u32()?, r.u32()?)))
+                .collect::<Option<_>>()?;
+            split.push(ranges);
+        }
+
+        let mut ops = Vec::new();
+        while !r.0.is_empty() {
+            let op = match r.u8()? {
+                ADD => {
+                    let priority = r.u32()?;
+                    Op::Add {
+                        fields: r.fields()?,
+                        masks: r.fields()?,
+                        priority,
+                        id: r.id()?,
+                    }
+                }
+                REMOVE => Op::Remove {
+                    id: RuleId(r.u64()?),
+                    removed: r.u8()? != 0,
+                },
+                CLASSIFY => Op::Classify {
+                    fields: r.fields()?,
+                    matched: r.id()?,
+                },
+                _ => return None,
+            };
+            ops.push(op);
+        }
+
+        Some(Self { split, seed, ops })
+    }
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_fields<F: FieldType>(out: &mut Vec<u8>, fields: &[F]) {
+    put_u32(out, fields.len() as u32);
+    for f in fields {
+        for i in 0..F::WORDS {
+            put_u32(out, f.word(i));
+        }
+    }
+}
+
+// ids are stored plus one, zero for none
+fn put_id(out: &mut Vec<u8>, id: Option<RuleId>) {
+    out.extend_from_slice(&id.map_or(0, |id| id.0 + 1).to_le_bytes());
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn id(&mut self) -> Option<Option<RuleId>> {
+        Some(self.u64()?.checked_sub(1).map(RuleId))
+    }
+
+    fn fields<F: FieldType>(&mut self) -> Option<Vec<F>> {
+        let len = self.u32()?;
+        (0..len)
+            .map(|_| {
+                let mut value = F::ZERO;
+                for i in 0..F::WORDS as u32 {
+                    let word = self.u32()?;
+                    for bit in (0..32).filter(|bit| word >> bit & 1 == 1) {
+                        value = value | F::ONE << (32 * i + bit);
+                    }
+                }
+                Some(value)
+            })
+            .collect()
+    }
+}
+
+// Classifier recording every insertion, removal and classification into a trace, f.e. to
+// attach to a bug report. Only the operations done through the recorder are recorded.
+#[derive(Debug, Clone)]
+pub struct Recorder<R: Rule<F>, F: FieldType = Field> {
+    classifier: RVHClassifier<R, F>,
+    trace: Trace<F>,
+}
+
+impl<R: Rule<F>, F: FieldType> Recorder<R, F> {
+    // The hasher of the classifier is keyed by `seed` instead of random keys.
+    pub fn new(split: Vec<Vec<Range>>, seed: u64) -> Self {
+        Self {
+            classifier: RVHClassifier::with_hasher(split.clone().into_iter(), hasher(seed)),
+            trace: Trace {
+                split,
+                seed,
+                ops: Vec::new(),
+            },
+        }
+    }
+
+    pub fn add_rule(&mut self, rule: R) -> Result<RuleId, RvhError> {
+        let (fields, masks, priority) = (
+            rule.fields().to_vec(),
+            rule.masks().to_vec(),
+            rule.priority(),
+        );
+        let result = self.classifier.add_rule(rule);
+        self.trace.ops.push(Op::Add {
+            fields,
+            masks,
+            priority,
+            id: result.as_ref().ok().copied(),
+        });
+        result
+    }
+
+    pub fn remove(&mut self, id: RuleId) -> Result<R, RvhError> {
+        let result = self.classifier.remove(id);
+        self.trace.ops.push(Op::Remove {
+            id,
+            removed: result.is_ok(),
+        });
+        result
+    }
+
+    pub fn classify(&mut self, p: &impl Packet<F>) -> Option<&R> {
+        let matched = self.classifier.classify_id(p);
+        self.trace.ops.push(Op::Classify {
+            fields: p.fields().to_vec(),
+            matched,
+        });
+        self.classifier.get(matched?)
+    }
+
+    pub fn classifier(&self) -> &RVHClassifier<R, F> {
+        &self.classifier
+    }
+
+    pub fn trace(&self) -> &Trace<F> {
+        &self.trace
+    }
+
+    pub fn into_trace(self) -> Trace<F> {
+        self.trace
+    }
+}
+
+// A classifier rebuilt by `replay`.
+#[derive(Debug, Clone)]
+pub struct Replayed<R: Rule<F>, F: FieldType = Field> {
+    pub classifier: RVHClassifier<R, F>,
+    // index of the first operation with another outcome than recorded, f.e. after a fix
+    pub diverged: Option<usize>,
+}
+
+struct Fields<'a, F>(&'a [F]);
+
+impl<F: FieldType> Packet<F> for Fields<'_, F> {
+    fn fields(&self) -> &[F] {
+        self.0
+    }
+}
+
+// Runs the operations of `trace` on a new classifier, `make` builds a rule from its fields,
+// masks and priority. All operations are run even after one diverged.
+pub fn replay<R: Rule<F>, F: FieldType>(
+    trace: &Trace<F>,
+    make: impl Fn(Vec<F>, Vec<F>, Priority) -> R,
+) -> Replayed<R, F> {
+    let mut classifier =
+        RVHClassifier::with_hasher(trace.split.clone().into_iter(), hasher(trace.seed));
+    let mut diverged = None;
+
+    for (index, op) in trace.ops.iter().enumerate() {
+        let same = match op {
+            Op::Add {
+                fields,
+                masks,
+                priority,
+                id,
+            } => {
+                let rule = make(fields.clone(), masks.clone(), *priority);
+                classifier.add_rule(rule).ok() == *id
+            }
+            Op::Remove { id, removed } => classifier.remove(*id).is_ok() == *removed,
+            Op::Classify { fields, matched } => classifier.classify_id(&Fields(fields)) == *matched,
+        };
+        if !same && diverged.is_none() {
+            diverged = Some(index);
+        }
+    }
+
+    Replayed {
+        classifier,
+        diverged,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::mocks::{MockPacket, MockRule};
+
+    fn record() -> Recorder<MockRule> {
+        let mut recorder = Recorder::new(vec![vec![(0, 9)], vec![(9, 33)]], 7);
+        let id = recorder
+            .add_rule(MockRule::new(vec![0x12], vec![0xff], 2))
+            .unwrap();
+        assert!(recorder
+            .add_rule(MockRule::new(vec![0x2], vec![0xf], 1))
+            .is_ok());
+        assert!(recorder
+            .add_rule(MockRule::new(vec![0x2], vec![0xf], 1))
+            .is_err());
+        assert_eq!(
+            recorder
+                .classify(&MockPacket::new(vec![0x12]))
+                .unwrap()
+                .priority(),
+            2
+        );
+        assert!(recorder.remove(id).is_ok());
+        assert!(recorder.remove(id).is_err());
+        assert_eq!(
+            recorder
+                .classify(&MockPacket::new(vec![0x12]))
+                .unwrap()
+                .priority(),
+            1
+        );
+        assert!(recorder.classify(&MockPacket::new(vec![0x13])).is_none());
+        recorder
+    }
+
+    #[test]
+    fn test_replay_reproduces_the_classifier() {
+        let recorder = record();
+        let bytes = recorder.trace().to_bytes();
+        let trace = Trace::<Field>::from_bytes(&bytes).unwrap();
+        assert_eq!(&trace, recorder.trace());
+        assert!(Trace::<u128>::from_bytes(&bytes).is_none());
+        assert!(Trace::<Field>::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+
+        let replayed = replay(&trace, MockRule::new);
+        assert_eq!(replayed.diverged, None);
+        let classifier = replayed.classifier;
+        assert_eq!(classifier.len(), 1);
+        assert_eq!(
+            classifier.rule_set_hash(),
+            recorder.classifier().rule_set_hash()
+        );
+
+        // a replay diverging from the recording is pointed out
+        let mut trace = trace;
+        if let Op::Classify { matched, .. } = &mut trace.ops[3] {
+            *matched = None;
+        }
+        assert_eq!(replay(&trace, MockRule::new).diverged, Some(3));
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::classifier::RVHClassifier;
+use crate::error::RvhError;
+use crate::types::*;
+
+#[derive(Debug, Clone)]
+enum Operation<R> {
+    Add(R),
+    Remove(R),
+}
+
+#[derive(Debug)]
+struct LogState<R> {
+    // sequence number of `ops[0]`
+    base: u64,
+    ops: VecDeque<Operation<R>>,
+    // sequence number of the next operation each replica has to apply
+    cursors: Vec<u64>,
+}
+
+#[derive(Debug)]
+struct OperationLog<R> {
+    state: Mutex<LogState<R>>,
+    // sequence number of the next operation, allows replicas to check for updates without locking
+    head: AtomicU64,
+}
+
+impl<R> OperationLog<R> {
+    fn truncate(state: &mut LogState<R>) {
+        let min = state.cursors.iter().copied().min().unwrap_or(u64::MAX);
+        while state.base < min && state.ops.pop_front().is_some() {
+            state.base += 1;
+        }
+    }
+}
+
+// Keeps one copy of the classifier per worker. Updates are applied to a primary copy and
+// recorded in an operation log, from which every replica catches up through its own
+// `ReplicaHandle`, so workers never share the memory they classify on.
+#[derive(Debug)]
+pub struct ReplicatedClassifier<R: Rule<F>, F: FieldType = Field> {
+    primary: RVHClassifier<R, F>,
+    log: Arc<OperationLog<R>>,
+}
+
+#[derive(Debug)]
+pub struct ReplicaHandle<R: Rule<F>, F: FieldType = Field> {
+    replica: RVHClassifier<R, F>,
+    log: Arc<OperationLog<R>>,
+    index: usize,
+    applied: u64,
+}
+
+impl<R: Rule<F> + Clone, F: FieldType> ReplicatedClassifier<R, F> {
+    pub fn new(
+        classifier: RVHClassifier<R, F>,
+        replicas: usize,
+    ) -> (Self, Vec<ReplicaHandle<R, F>>) {
+        let log = Arc::new(OperationLog {
+            state: Mutex::new(LogState {
+                base: 0,
+                ops: VecDeque::new(),
+                cursors: vec![0; replicas],
+            }),
+            head: AtomicU64::new(0),
+        });
+
+        let handles = (0..replicas)
+            .map(|index| ReplicaHandle {
+                replica: classifier.clone(),
+                log: log.clone(),
+                index,
+                applied: 0,
+            })
+            .collect();
+
+        (
+            Self {
+                primary: classifier,
+                log,
+            },
+            handles,
+        )
+    }
+
+    // Replicas assign the same ids as the primary, since they apply the same updates in the
+    // same order.
+    pub fn add_rule(&mut self, rule: R) -> Result<RuleId, RvhError> {
+        let id = self.primary.add_rule(rule.clone())?;
+
+        self.publish(Operation::Add(rule));
+        Ok(id)
+    }
+
+    pub fn remove_rule(&mut self, rule: &R) -> Result<(), RvhError> {
+        self.primary.remove_rule(rule)?;
+
+        self.publish(Operation::Remove(rule.clone()));
+        Ok(())
+    }
+
+    pub fn primary(&self) -> &RVHClassifier<R, F> {
+        &self.primary
+    }
+
+    // Number of logged operations not yet applied by every replica.
+    pub fn pending(&self) -> usize {
+        self.log.state.lock().unwrap().ops.len()
+    }
+
+    fn publish(&mut self, op: Operation<R>) {
+        let mut state = self.log.state.lock().unwrap();
+        state.ops.push_back(op);
+        OperationLog::truncate(&mut state);
+        self.log
+            .head
+            .store(state.base + state.ops.len() as u64, Ordering::Release);
+    }
+}
+
+impl<R: Rule<F> + Clone, F: FieldType> ReplicaHandle<R, F> {
+    // Applies all updates published since the last call, returns how many were applied.
+    pub fn sync(&mut self) -> usize {
+        if self.log.head.load(Ordering::Acquire) == self.applied {
+            return 0;
+        }
+
+        let mut state = self.log.state.lock().unwrap();
+        let start = (self.applied - state.base) as usize;
+        let count = state.ops.len() - start;
+        for op in state.ops.iter().skip(start) {
+            match op {
+                Operation::Add(rule) => {
+                    let added = self.replica.add_rule(rule.clone());
+                    debug_assert!(added.is_ok());
+                }
+                Operation::Remove(rule) => {
+                    let removed = self.replica.remove_rule(rule);
+                    debug_assert!(removed.is_ok());
+                }
+            }
+        }
+
+        self.applied += count as u64;
+        state.cursors[self.index] = self.applied;
+        OperationLog::truncate(&mut state);
+
+        count
+    }
+
+    pub fn classify(&self, p: &impl Packet<F>) -> Option<&R> {
+        self.replica.classify(p)
+    }
+
+    pub fn classifier(&self) -> &RVHClassifier<R, F> {
+        &self.replica
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+#[cfg(all(feature = "numa", target_os = "linux"))]
+impl<R: Rule<F> + Clone, F: FieldType> ReplicaHandle<R, F> {
+    // Prefers memory of `node` for all further allocations of the calling thread and moves the
+    // replica there. Has to be called from the worker thread owning this handle, updates applied
+    // by `sync` afterwards are then allocated on the same node.
+    pub fn bind_to_node(&mut self, node: usize) -> std::io::Result<()> {
+        numa::prefer_node(node)?;
+        // the copy is allocated under the new policy
+        self.replica = self.replica.clone();
+        Ok(())
+    }
+
+    // Same as `bind_to_node` with the node of the CPU the calling thread currently runs on.
+    pub fn bind_to_local_node(&mut self) -> std::io::Result<usize> {
+        let node = numa::current_node()?;
+        self.bind_to_node(node)?;
+        Ok(node)
+    }
+}
+
+#[cfg(all(feature = "numa", target_os = "linux"))]
+mod numa {
+    use std::io;
+
+    const MPOL_PREFERRED: libc::c_int = 1;
+    const MAX_NODES: usize = 1024;
+    const BITS: usize = std::mem::size_of::<libc::c_ulong>() * 8;
+
+    pub fn prefer_node(node: usize) -> io::Result<()> {
+        if node >= MAX_NODES {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+
+        let mut mask = [0 as libc::c_ulong; MAX_NODES / BITS];
+        mask[node / BITS] |= 1 << (node % BITS);
+
+        // the kernel ignores the last bit of `maxnode`
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_set_mempolicy,
+                MPOL_PREFERRED,
+                mask.as_ptr(),
+                MAX_NODES + 1,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    pub fn current_node() -> io::Result<usize> {
+        let mut cpu: libc::c_uint = 0;
+        let mut node: libc::c_uint = 0;
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_getcpu,
+                &mut cpu as *mut libc::c_uint,
+                &mut node as *mut libc::c_uint,
+                std::ptr::null_mut::<libc::c_void>(),
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(node as usize)
+    }
+}
+
+impl<R: Rule<F>, F: FieldType> Drop for ReplicaHandle<R, F> {
+    fn drop(&mut self) {
+        // a dropped replica must not hold back truncation of the log
+        if let Ok(mut state) = self.log.state.lock() {
+            state.cursors[self.index] = u64::MAX;
+            OperationLog::truncate(&mut state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::mocks::{MockPacket, MockRule};
+
+    fn classifier() -> RVHClassifier<MockRule> {
+        RVHClassifier::new(vec![vec![(0, 3)], vec![(3, 6)]].into_iter())
+    }
+
+    #[test]
+    fn test_replicas_apply_updates_on_sync() {
+        let (mut rc, mut handles) = ReplicatedClassifier::new(classifier(), 2);
+
+        let r1 = MockRule::new(vec![0b1], vec![0b1], 1);
+        let r2 = MockRule::new(vec![0b101], vec![0b111], 2);
+        assert!(rc.add_rule(r1.clone()).is_ok());
+        assert!(rc.add_rule(r2.clone()).is_ok());
+        assert!(rc.add_rule(r2.clone()).is_err());
+        assert_eq!(rc.pending(), 2);
+
+        let p = MockPacket::new(vec![0b101]);
+        assert!(handles[0].classify(&p).is_none());
+
+        assert_eq!(handles[0].sync(), 2);
+        assert_eq!(handles[0].sync(), 0);
+        assert_eq!(handles[0].classify(&p).unwrap().priority(), 2);
+        assert_eq!(rc.pending(), 2);
+
+        assert!(rc.remove_rule(&r2).is_ok());
+        assert_eq!(handles[1].sync(), 3);
+        assert_eq!(handles[1].classify(&p).unwrap().priority(), 1);
+        // the first replica has yet to apply the removal
+        assert_eq!(rc.pending(), 1);
+
+        assert_eq!(handles[0].sync(), 1);
+        assert_eq!(rc.pending(), 0);
+        assert_eq!(rc.primary().classify(&p).unwrap().priority(), 1);
+    }
+
+    #[test]
+    fn test_replicas_run_on_worker_threads() {
+        let (mut rc, handles) = ReplicatedClassifier::new(classifier(), 4);
+        for prio in 1..=4 {
+            assert!(rc
+                .add_rule(MockRule::new(vec![prio], vec![0b111], prio))
+                .is_ok());
+        }
+
+        let workers: Vec<_> = handles
+            .into_iter()
+            .map(|mut handle| {
+                std::thread::spawn(move || {
+                    handle.sync();
+                    let prio = handle.index() as u32 + 1;
+                    handle
+                        .classify(&MockPacket::new(vec![prio]))
+                        .map(|r| r.priority())
+                })
+            })
+            .collect();
+
+        for (i, w) in workers.into_iter().enumerate() {
+            assert_eq!(w.join().unwrap(), Some(i as u32 + 1));
+        }
+
+        // all handles are gone, nothing is retained
+        assert!(rc.add_rule(MockRule::new(vec![0], vec![0b111], 5)).is_ok());
+        assert_eq!(rc.pending(), 0);
+    }
+
+    #[cfg(all(feature = "numa", target_os = "linux"))]
+    #[test]
+    fn test_replicas_bind_to_their_local_node() {
+        let (mut rc, handles) = ReplicatedClassifier::new(classifier(), 2);
+        assert!(rc.add_rule(MockRule::new(vec![0b1], vec![0b1], 1)).is_ok());
+
+        let workers: Vec<_> = handles
+            .into_iter()
+            .map(|mut handle| {
+                std::thread::spawn(move || {
+                    handle.bind_to_local_node().unwrap();
+                    assert!(handle.bind_to_node(usize::MAX).is_err());
+                    handle.sync();
+                    handle
+                        .classify(&MockPacket::new(vec![0b1]))
+                        .map(|r| r.priority())
+                })
+            })
+            .collect();
+
+        for w in workers {
+            assert_eq!(w.join().unwrap(), Some(1));
+        }
+    }
+}
@@ -0,0 +1,541 @@
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+#[cfg(feature = "ipnet")]
+use ipnet::{Ipv4Net, Ipv6Net};
+
+use crate::dimensions::Dimension;
+use crate::fields;
+use crate::presets::{self, DSCP, DST_IP, DST_PORT, PROTOCOL, SRC_IP, SRC_PORT};
+use crate::types::*;
+
+// Rule over the dimensions of `presets::five_tuple`, built up one header field at a time, f.e.
+// `FiveTupleRule::new(10).dst_net("10.0.0.0/8".parse()?).dst_port(22)`. Fields that are not
+// set match any value. Setting the DSCP adds the sixth dimension of `presets::five_tuple_dscp`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FiveTupleRule<A = ()> {
+    fields: [Field; 6],
+    masks: [Mask; 6],
+    len: usize,
+    priority: Priority,
+    action: A,
+}
+
+impl FiveTupleRule {
+    pub fn new(priority: Priority) -> Self {
+        Self {
+            fields: [0; 6],
+            masks: [0; 6],
+            len: 5,
+            priority,
+            action: (),
+        }
+    }
+}
+
+impl<A> FiveTupleRule<A> {
+    pub fn with_action<B>(self, action: B) -> FiveTupleRule<B> {
+        FiveTupleRule {
+            fields: self.fields,
+            masks: self.masks,
+            len: self.len,
+            priority: self.priority,
+            action,
+        }
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn src_prefix(self, addr: Ipv4Addr, len: u32) -> Self {
+        self.set(SRC_IP, fields::ipv4_prefix(addr, len))
+    }
+
+    pub fn dst_prefix(self, addr: Ipv4Addr, len: u32) -> Self {
+        self.set(DST_IP, fields::ipv4_prefix(addr, len))
+    }
+
+    #[cfg(feature = "ipnet")]
+    pub fn src_net(self, net: Ipv4Net) -> Self {
+        self.set(SRC_IP, fields::ipv4_net(net))
+    }
+
+    #[cfg(feature = "ipnet")]
+    pub fn dst_net(self, net: Ipv4Net) -> Self {
+        self.set(DST_IP, fields::ipv4_net(net))
+    }
+
+    pub fn src_port(self, port: u16) -> Self {
+        self.set(SRC_PORT, fields::port(port))
+    }
+
+    pub fn dst_port(self, port: u16) -> Self {
+        self.set(DST_PORT, fields::port(port))
+    }
+
+    // Port ranges aligned to a power of two, f.e. `src_port_prefix(0x400, 6)` for 1024-2047.
+    pub fn src_port_prefix(self, port: u16, len: u32) -> Self {
+        self.set(
+            SRC_PORT,
+            fields::prefix(port.into(), fields::PORT_WIDTH, len),
+        )
+    }
+
+    pub fn dst_port_prefix(self, port: u16, len: u32) -> Self {
+        self.set(
+            DST_PORT,
+            fields::prefix(port.into(), fields::PORT_WIDTH, len),
+        )
+    }
+
+    pub fn protocol(self, protocol: u8) -> Self {
+        self.set(PROTOCOL, fields::protocol(protocol))
+    }
+
+    pub fn dscp(mut self, dscp: u8) -> Self {
+        self.len = 6;
+        self.set(DSCP, fields::dscp(dscp))
+    }
+
+    // Encoded field and mask of one of the dimensions, f.e. from `fields::prefix`.
+    pub fn with_field(mut self, dimension: usize, field: (Field, Mask)) -> Self {
+        debug_assert!(dimension <= DSCP);
+        self.len = self.len.max(dimension + 1);
+        self.set(dimension, field)
+    }
+
+    fn set(mut self, dimension: usize, (field, mask): (Field, Mask)) -> Self {
+        self.fields[dimension] = field;
+        self.masks[dimension] = mask;
+        self
+    }
+}
+
+impl<A: PartialEq> Rule for FiveTupleRule<A> {
+    fn priority(&self) -> Priority {
+        self.priority
+    }
+    fn masks(&self) -> &[Field] {
+        &self.masks[..self.len]
+    }
+    fn fields(&self) -> &[Field] {
+        &self.fields[..self.len]
+    }
+}
+
+impl<A: Clone + PartialEq> ActionRule for FiveTupleRule<A> {
+    type Action = A;
+
+    fn action(&self) -> &A {
+        &self.action
+    }
+}
+
+// IPv6 counterpart of `FiveTupleRule` over the dimensions of `presets::ipv6_five_tuple`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ipv6FiveTupleRule<A = ()> {
+    fields: [u128; 5],
+    masks: [u128; 5],
+    priority: Priority,
+    action: A,
+}
+
+impl Ipv6FiveTupleRule {
+    pub fn new(priority: Priority) -> Self {
+        Self {
+            fields: [0; 5],
+            masks: [0; 5],
+            priority,
+            action: (),
+        }
+    }
+}
+
+impl<A> Ipv6FiveTupleRule<A> {
+    pub fn with_action<B>(self, action: B) -> Ipv6FiveTupleRule<B> {
+        Ipv6FiveTupleRule {
+            fields: self.fields,
+            masks: self.masks,
+            priority: self.priority,
+            action,
+        }
+    }
+
+    pub fn src_prefix(self, addr: Ipv6Addr, len: u32) -> Self {
+        self.set(SRC_IP, fields::ipv6_prefix(addr, len))
+    }
+
+    pub fn dst_prefix(self, addr: Ipv6Addr, len: u32) -> Self {
+        self.set(DST_IP, fields::ipv6_prefix(addr, len))
+    }
+
+    #[cfg(feature = "ipnet")]
+    pub fn src_net(self, net: Ipv6Net) -> Self {
+        self.set(SRC_IP, fields::ipv6_net(net))
+    }
+
+    #[cfg(feature = "ipnet")]
+    pub fn dst_net(self, net: Ipv6Net) -> Self {
+        self.set(DST_IP, fields::ipv6_net(net))
+    }
+
+    pub fn src_port(self, port: u16) -> Self {
+        self.set(SRC_PORT, fields::port_wide(port))
+    }
+
+    pub fn dst_port(self, port: u16) -> Self {
+        self.set(DST_PORT, fields::port_wide(port))
+    }
+
+    pub fn protocol(self, protocol: u8) -> Self {
+        self.set(PROTOCOL, fields::protocol_wide(protocol))
+    }
+
+    fn set(mut self, dimension: usize, (field, mask): (u128, u128)) -> Self {
+        self.fields[dimension] = field;
+        self.masks[dimension] = mask;
+        self
+    }
+}
+
+impl<A: PartialEq> Rule<u128> for Ipv6FiveTupleRule<A> {
+    fn priority(&self) -> Priority {
+        self.priority
+    }
+    fn masks(&self) -> &[u128] {
+        &self.masks
+    }
+    fn fields(&self) -> &[u128] {
+        &self.fields
+    }
+}
+
+impl<A: Clone + PartialEq> ActionRule<u128> for Ipv6FiveTupleRule<A> {
+    type Action = A;
+
+    fn action(&self) -> &A {
+        &self.action
+    }
+}
+
+// A part of a textual rule that could not be read, f.e. "dport 70000" or "prio" if the rule
+// has no priority.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseRuleError {
+    pub construct: String,
+}
+
+impl ParseRuleError {
+    fn new(construct: impl Into<String>) -> Self {
+        Self {
+            construct: construct.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParseRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid rule: {}", self.construct)
+    }
+}
+
+impl Error for ParseRuleError {}
+
+// Key value pairs of a textual rule, f.e.
+// `src 10.0.0.0/8 dst 192.168.1.0/24 dport 443 proto tcp prio 100`. Keys are the names of the
+// dimensions in `presets`, f.e. `dst_port`, or their short forms `src` and `dst` with an
+// optional prefix length, `sport`, `dport` and `proto` by name or number, plus `dscp` and the
+// required `prio`. Fields that are left out match anything.
+fn pairs(text: &str) -> Result<Vec<(&str, &str)>, ParseRuleError> {
+    let mut pairs = Vec::new();
+    let mut words = text.split_whitespace();
+    while let Some(key) = words.next() {
+        let value = words.next().ok_or_else(|| ParseRuleError::new(key))?;
+        pairs.push((key, value));
+    }
+
+    Ok(pairs)
+}
+
+// Dimension named by a key, short forms are resolved to the names of the registry first.
+fn dimension(key: &str, dimensions: &[Dimension]) -> Result<usize, ParseRuleError> {
+    let name = match key {
+        "src" => "src_ip",
+        "dst" => "dst_ip",
+        "sport" => "src_port",
+        "dport" => "dst_port",
+        "proto" => "protocol",
+        _ => key,
+    };
+    dimensions
+        .iter()
+        .position(|d| d.name() == name)
+        .ok_or_else(|| ParseRuleError::new(key))
+}
+
+// The priority given by `prio`, else `default`.
+fn priority(pairs: &[(&str, &str)], default: Option<Priority>) -> Result<Priority, ParseRuleError> {
+    match pairs.iter().rev().find(|(key, _)| *key == "prio") {
+        Some((key, value)) => number(key, value),
+        None => default.ok_or_else(|| ParseRuleError::new("prio")),
+    }
+}
+
+fn number<T: FromStr>(key: &str, value: &str) -> Result<T, ParseRuleError> {
+    value
+        .parse()
+        .map_err(|_| ParseRuleError::new(format!("{} {}", key, value)))
+}
+
+fn protocol(key: &str, value: &str) -> Result<u8, ParseRuleError> {
+    match value {
+        "icmp" => Ok(1),
+        "tcp" => Ok(6),
+        "udp" => Ok(17),
+        "sctp" => Ok(132),
+        _ => number(key, value),
+    }
+}
+
+// An address with an optional prefix length, the full address if it has none.
+fn net(key: &str, value: &str) -> Result<(IpAddr, u32), ParseRuleError> {
+    let invalid = || ParseRuleError::new(format!("{} {}", key, value));
+    let (addr, len) = match value.split_once('/') {
+        Some((addr, len)) => (addr, Some(number(key, len)?)),
+        None => (value, None),
+    };
+    let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+    let width = if addr.is_ipv4() { 32 } else { 128 };
+    match len.unwrap_or(width) {
+        len if len <= width => Ok((addr, len)),
+        _ => Err(invalid()),
+    }
+}
+
+impl FiveTupleRule {
+    // Same as parsing the rule, see `pairs`, with a priority for rules without `prio`.
+    pub fn parse_with_priority(text: &str, priority: Priority) -> Result<Self, ParseRuleError> {
+        Self::parse(text, Some(priority))
+    }
+
+    fn parse(text: &str, default: Option<Priority>) -> Result<Self, ParseRuleError> {
+        let pairs = pairs(text)?;
+        let dimensions = presets::five_tuple_dscp_dimensions();
+        let mut rule = FiveTupleRule::new(priority(&pairs, default)?);
+        for (key, value) in pairs.into_iter().filter(|(key, _)| *key != "prio") {
+            let ipv4 = || match net(key, value)? {
+                (IpAddr::V4(addr), len) => Ok((addr, len)),
+                _ => Err(ParseRuleError::new(format!("{} {}", key, value))),
+            };
+            rule = match dimension(key, &dimensions)? {
+                SRC_IP => {
+                    let (addr, len) = ipv4()?;
+                    rule.src_prefix(addr, len)
+                }
+                DST_IP => {
+                    let (addr, len) = ipv4()?;
+                    rule.dst_prefix(addr, len)
+                }
+                SRC_PORT => rule.src_port(number(key, value)?),
+                DST_PORT => rule.dst_port(number(key, value)?),
+                PROTOCOL => rule.protocol(protocol(key, value)?),
+                _ => match number(key, value)? {
+                    dscp if dscp < 64 => rule.dscp(dscp),
+                    _ => return Err(ParseRuleError::new(format!("{} {}", key, value))),
+                },
+            };
+        }
+
+        Ok(rule)
+    }
+}
+
+impl FromStr for FiveTupleRule {
+    type Err = ParseRuleError;
+
+    // See `pairs` for the syntax.
+    fn from_str(text: &str) -> Result<Self, ParseRuleError> {
+        Self::parse(text, None)
+    }
+}
+
+impl Ipv6FiveTupleRule {
+    // Same as parsing the rule with a priority for rules without `prio`.
+    pub fn parse_with_priority(text: &str, priority: Priority) -> Result<Self, ParseRuleError> {
+        Self::parse(text, Some(priority))
+    }
+
+    fn parse(text: &str, default: Option<Priority>) -> Result<Self, ParseRuleError> {
+        let pairs = pairs(text)?;
+        let dimensions = presets::ipv6_five_tuple_dimensions();
+        let mut rule = Ipv6FiveTupleRule::new(priority(&pairs, default)?);
+        for (key, value) in pairs.into_iter().filter(|(key, _)| *key != "prio") {
+            let ipv6 = || {
+                net(key, value).map(|net| match net {
+                    (IpAddr::V4(addr), len) => (addr.to_ipv6_mapped(), 96 + len),
+                    (IpAddr::V6(addr), len) => (addr, len),
+                })
+            };
+            rule = match dimension(key, &dimensions)? {
+                SRC_IP => {
+                    let (addr, len) = ipv6()?;
+                    rule.src_prefix(addr, len)
+                }
+                DST_IP => {
+                    let (addr, len) = ipv6()?;
+                    rule.dst_prefix(addr, len)
+                }
+                SRC_PORT => rule.src_port(number(key, value)?),
+                DST_PORT => rule.dst_port(number(key, value)?),
+                _ => rule.protocol(protocol(key, value)?),
+            };
+        }
+
+        Ok(rule)
+    }
+}
+
+impl FromStr for Ipv6FiveTupleRule {
+    type Err = ParseRuleError;
+
+    // Same syntax as for `FiveTupleRule` without DSCP. IPv4 networks are matched as
+    // IPv4-mapped addresses, see `fields::ip_addr_wide`.
+    fn from_str(text: &str) -> Result<Self, ParseRuleError> {
+        Self::parse(text, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::{FiveTuple, Ipv6FiveTuple};
+    use crate::{RVHClassifier, RvhError};
+
+    #[test]
+    fn test_rules_match_the_packets_of_their_fields() {
+        let mut rvh = RVHClassifier::<FiveTupleRule<&str>>::five_tuple();
+        let ssh = FiveTupleRule::new(20)
+            .dst_prefix(Ipv4Addr::new(10, 0, 0, 0), 8)
+            .dst_port(22)
+            .protocol(6)
+            .with_action("accept");
+        let deny = FiveTupleRule::new(10)
+            .src_prefix(Ipv4Addr::new(192, 168, 0, 0), 16)
+            .with_action("drop");
+        let ssh = rvh.add_rule(ssh).unwrap();
+        let deny = rvh.add_rule(deny).unwrap();
+
+        let src = Ipv4Addr::new(192, 168, 1, 1);
+        let p = FiveTuple::new(src, Ipv4Addr::new(10, 1, 2, 3), 40000, 22, 6);
+        assert_eq!(rvh.decide(&p).unwrap().rule, ssh);
+        let p = FiveTuple::new(src, Ipv4Addr::new(11, 1, 2, 3), 40000, 22, 6);
+        assert_eq!(rvh.decide(&p).unwrap().action, "drop");
+        assert_eq!(rvh.decide(&p).unwrap().rule, deny);
+
+        // same priority and fields, another action
+        let other = FiveTupleRule::new(20)
+            .dst_prefix(Ipv4Addr::new(10, 0, 0, 0), 8)
+            .dst_port(22)
+            .protocol(6)
+            .with_action("drop");
+        assert_eq!(rvh.remove_rule(&other), Err(RvhError::NotFound));
+        let p = FiveTuple::new(src, Ipv4Addr::new(10, 1, 2, 3), 40000, 22, 6);
+        assert_eq!(rvh.decide(&p).unwrap().rule, ssh);
+
+        let mut rvh = RVHClassifier::<Ipv6FiveTupleRule, u128>::ipv6_five_tuple();
+        let prefix: Ipv6Addr = "2001:db8::".parse().unwrap();
+        assert!(rvh
+            .add_rule(
+                Ipv6FiveTupleRule::new(5)
+                    .dst_prefix(prefix, 32)
+                    .dst_port(443)
+            )
+            .is_ok());
+        let p = Ipv6FiveTuple::new(
+            "fe80::1".parse().unwrap(),
+            "2001:db8::7".parse().unwrap(),
+            50000,
+            443,
+            6,
+        );
+        assert_eq!(rvh.classify(&p).unwrap().priority(), 5);
+    }
+
+    #[cfg(feature = "ipnet")]
+    #[test]
+    fn test_nets_are_prefixes() {
+        let net: Ipv4Net = "10.1.2.3/8".parse().unwrap();
+        let rule = FiveTupleRule::new(1).dst_net(net);
+        let same = FiveTupleRule::new(1).dst_prefix(Ipv4Addr::new(10, 0, 0, 0), 8);
+        assert_eq!(rule, same);
+
+        let net: ipnet::IpNet = "10.0.0.0/8".parse().unwrap();
+        let (field, mask) = fields::ip_net_wide(net);
+        assert_eq!(mask.count_ones(), 104);
+        let (host, _) = fields::ip_addr_wide("10.9.8.7".parse().unwrap());
+        assert_eq!(host & mask, field);
+        let (host, _) = fields::ip_addr_wide("11.9.8.7".parse().unwrap());
+        assert_ne!(host & mask, field);
+    }
+
+    #[test]
+    fn test_rules_are_parsed_from_text() {
+        let rule: FiveTupleRule = "src 10.0.0.0/8 dst 192.168.1.0/24 dport 443 proto tcp prio 100"
+            .parse()
+            .unwrap();
+        let expected = FiveTupleRule::new(100)
+            .src_prefix(Ipv4Addr::new(10, 0, 0, 0), 8)
+            .dst_prefix(Ipv4Addr::new(192, 168, 1, 0), 24)
+            .dst_port(443)
+            .protocol(6);
+        assert_eq!(rule, expected);
+        assert_eq!(
+            "prio 7 dscp 46 src 10.0.0.1".parse(),
+            Ok(FiveTupleRule::new(7)
+                .dscp(46)
+                .src_prefix(Ipv4Addr::new(10, 0, 0, 1), 32))
+        );
+
+        let invalid = |text: &str| text.parse::<FiveTupleRule>().unwrap_err().to_string();
+        assert_eq!(invalid("dport 443"), "invalid rule: prio");
+        assert_eq!(invalid("dport 70000 prio 1"), "invalid rule: dport 70000");
+        assert_eq!(
+            invalid("src 10.0.0.0/33 prio 1"),
+            "invalid rule: src 10.0.0.0/33"
+        );
+        assert_eq!(
+            invalid("src 2001:db8::/32 prio 1"),
+            "invalid rule: src 2001:db8::/32"
+        );
+        assert_eq!(invalid("ttl 3 prio 1"), "invalid rule: ttl");
+        assert_eq!(invalid("prio 1 sport"), "invalid rule: sport");
+        // the names of the dimensions work as keys too
+        assert_eq!(
+            "dst_port 443 protocol tcp prio 100".parse(),
+            Ok(FiveTupleRule::new(100).dst_port(443).protocol(6))
+        );
+        assert_eq!(
+            FiveTupleRule::parse_with_priority("dport 443", 5),
+            Ok(FiveTupleRule::new(5).dst_port(443))
+        );
+
+        let rule: Ipv6FiveTupleRule = "src 10.0.0.0/8 dst 2001:db8::/32 proto udp prio 3"
+            .parse()
+            .unwrap();
+        let expected = Ipv6FiveTupleRule::new(3)
+            .src_prefix(Ipv4Addr::new(10, 0, 0, 0).to_ipv6_mapped(), 104)
+            .dst_prefix("2001:db8::".parse().unwrap(), 32)
+            .protocol(17);
+        assert_eq!(rule, expected);
+        assert_eq!(
+            "dscp 46 prio 1".parse::<Ipv6FiveTupleRule>(),
+            Err(ParseRuleError::new("dscp"))
+        );
+    }
+}
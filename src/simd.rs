@@ -0,0 +1,156 @@
+use crate::types::*;
+
+// runs compared at once
+pub(crate) const LANES: usize = 4;
+
+// Masked fields and masks of the first rule of every run of a bucket, laid out word by word
+// with one lane per run, so that a lookup compares a packet with `LANES` runs at once. Lanes
+// past the last run have all masks zero.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct Heads {
+    // lanes per word, the number of runs rounded up to `LANES`
+    stride: usize,
+    // per dimension and 32 bit word of a field
+    fields: Vec<u32>,
+    masks: Vec<u32>,
+}
+
+impl Heads {
+    pub fn new<'a, R: Rule<F> + 'a, F: FieldType>(heads: impl Iterator<Item = &'a R>) -> Self {
+        let heads: Vec<&R> = heads.collect();
+        let stride = heads.len().div_ceil(LANES) * LANES;
+        let dims = heads.iter().map(|r| r.masks().len()).max().unwrap_or(0);
+        let mut fields = vec![0; dims * F::WORDS * stride];
+        let mut masks = vec![0; dims * F::WORDS * stride];
+
+        for (lane, rule) in heads.iter().enumerate() {
+            // dimensions without a field or a mask are not compared, as in `rule_matches`
+            for (d, (&f, &m)) in rule.fields().iter().zip(rule.masks()).enumerate() {
+                for w in 0..F::WORDS {
+                    let at = (d * F::WORDS + w) * stride + lane;
+                    fields[at] = (f & m).word(w);
+                    masks[at] = m.word(w);
+                }
+            }
+        }
+
+        Self {
+            stride,
+            fields,
+            masks,
+        }
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.fields.shrink_to_fit();
+        self.masks.shrink_to_fit();
+    }
+
+    pub fn heap_bytes(&self) -> usize {
+        (self.fields.capacity() + self.masks.capacity()) * std::mem::size_of::<u32>()
+    }
+
+    // Bit `i` is set if the first rule of run `chunk * LANES + i` matches `packet`.
+    #[inline]
+    pub fn matches<F: FieldType>(&self, packet: &[F], chunk: usize) -> u32 {
+        let rows = (self.fields.len() / self.stride.max(1)).min(packet.len() * F::WORDS);
+        let word = |row: usize| packet[row / F::WORDS].word(row % F::WORDS);
+        compare(self, rows, chunk * LANES, word)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[inline]
+fn compare(heads: &Heads, rows: usize, base: usize, word: impl Fn(usize) -> u32) -> u32 {
+    use std::arch::x86_64::*;
+
+    let mut acc = unsafe { _mm_set1_epi32(-1) };
+    for row in 0..rows {
+        let at = row * heads.stride + base;
+        let fields = &heads.fields[at..at + LANES];
+        let masks = &heads.masks[at..at + LANES];
+        // SSE2 is available on every x86_64, the slices hold `LANES` words
+        unsafe {
+            let p = _mm_set1_epi32(word(row) as i32);
+            let f = _mm_loadu_si128(fields.as_ptr() as *const __m128i);
+            let m = _mm_loadu_si128(masks.as_ptr() as *const __m128i);
+            acc = _mm_and_si128(acc, _mm_cmpeq_epi32(_mm_and_si128(p, m), f));
+        }
+    }
+    unsafe { _mm_movemask_ps(_mm_castsi128_ps(acc)) as u32 }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+#[inline]
+fn compare(heads: &Heads, rows: usize, base: usize, word: impl Fn(usize) -> u32) -> u32 {
+    compare_scalar(heads, rows, base, word)
+}
+
+#[cfg(any(test, not(target_arch = "x86_64")))]
+fn compare_scalar(heads: &Heads, rows: usize, base: usize, word: impl Fn(usize) -> u32) -> u32 {
+    let mut acc = [true; LANES];
+    for row in 0..rows {
+        let at = row * heads.stride + base;
+        let p = word(row);
+        for (lane, matches) in acc.iter_mut().enumerate() {
+            *matches &= p & heads.masks[at + lane] == heads.fields[at + lane];
+        }
+    }
+    acc.iter()
+        .enumerate()
+        .fold(0, |bits, (lane, &matches)| bits | (matches as u32) << lane)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::range_vector_hash_map::rule_matches;
+    use crate::types::mocks::{MockPacket, MockRule};
+
+    #[test]
+    fn test_lanes_match_like_rules() {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let rules: Vec<MockRule> = (0..11)
+            .map(|i| {
+                let fields = (0..3).map(|_| next() as Field & 0x3f).collect();
+                let masks = (0..3).map(|_| (1 << (next() % 6)) - 1).collect();
+                MockRule::new(fields, masks, i)
+            })
+            .collect();
+        // packets with fewer dimensions only compare those
+        let packets: Vec<MockPacket> = (0..500)
+            .map(|i| MockPacket::new((0..3 - i % 2).map(|_| next() as Field & 0x1f).collect()))
+            .collect();
+
+        let heads = Heads::new(rules.iter());
+        for packet in packets.iter() {
+            let fields = packet.fields();
+            for chunk in 0..3 {
+                let bits = heads.matches(fields, chunk);
+                let rows = heads.fields.len() / heads.stride;
+                let rows = rows.min(fields.len() * Field::WORDS);
+                let scalar = compare_scalar(&heads, rows, chunk * LANES, |row| fields[row]);
+                assert_eq!(bits, scalar);
+
+                for (lane, rule) in rules.iter().enumerate().skip(chunk * LANES).take(LANES) {
+                    let bit = bits >> (lane - chunk * LANES) & 1 == 1;
+                    assert_eq!(bit, rule_matches(rule, packet));
+                }
+            }
+        }
+
+        // wide fields compare every word
+        let rule = MockRule::<u128>::wide(vec![1 << 64], vec![!0 >> 1], 1);
+        let heads = Heads::new(std::iter::once(&rule));
+        assert_eq!(heads.matches(&[1u128 << 64], 0) & 1, 1);
+        assert_eq!(heads.matches(&[1u128], 0) & 1, 0);
+    }
+}
@@ -0,0 +1,153 @@
+use std::hash::BuildHasher;
+
+use crate::classifier::RVHClassifier;
+use crate::range_vector_hash_map::rule_matches;
+use crate::types::*;
+
+// A packet of a sample whose classification would change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reclassification<'a, R> {
+    // index of the packet in the sample
+    pub index: usize,
+    pub before: Option<&'a R>,
+    pub after: Option<&'a R>,
+}
+
+impl<R: Rule<F>, F: FieldType, M, S: BuildHasher + Clone> RVHClassifier<R, F, M, S> {
+    // Reports which packets of `sample` would be classified differently if `rule` was added
+    // through `add_rule`, without adding it. Returns None if the rule would be rejected.
+    pub fn what_if_add<'a, P: Packet<F>>(
+        &'a self,
+        rule: &'a R,
+        sample: &[P],
+    ) -> Option<Vec<Reclassification<'a, R>>> {
+        if self.band_of(rule.priority()).is_some() || !self.can_insert_rule(rule) {
+            return None;
+        }
+
+        let changes = sample
+            .iter()
+            .enumerate()
+            .filter_map(|(index, p)| {
+                let before = self.lookup(p);
+                let current = before.map_or(0, |r| r.priority());
+                if rule.priority() > current && rule_matches(rule, &self.transform(p)) {
+                    Some(Reclassification {
+                        index,
+                        before,
+                        after: Some(rule),
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        Some(changes)
+    }
+
+    // Reports which packets of `sample` would be classified differently if `rule` was removed,
+    // without removing it. Returns None if the rule is not installed.
+    pub fn what_if_remove<'a, P: Packet<F>>(
+        &'a self,
+        rule: &R,
+        sample: &[P],
+    ) -> Option<Vec<Reclassification<'a, R>>> {
+        if !self.contains_rule(rule) {
+            return None;
+        }
+
+        let changes = sample
+            .iter()
+            .enumerate()
+            .filter_map(|(index, p)| {
+                let before = self.lookup(p).filter(|r| *r == rule)?;
+                Some(Reclassification {
+                    index,
+                    before: Some(before),
+                    after: self.lookup_where(p, |r| r != rule),
+                })
+            })
+            .collect();
+
+        Some(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::mocks::{MockPacket, MockRule};
+    use crate::types::Rule;
+    use crate::RVHClassifier;
+
+    fn classifier() -> RVHClassifier<MockRule> {
+        let mut rvh = RVHClassifier::<MockRule>::new(vec![vec![(0, 2)], vec![(2, 4)]].into_iter());
+        assert!(rvh.add_rule(MockRule::new(vec![0b1], vec![0b1], 1)).is_ok());
+        assert!(rvh
+            .add_rule(MockRule::new(vec![0b101], vec![0b111], 5))
+            .is_ok());
+        rvh
+    }
+
+    fn sample() -> Vec<MockPacket> {
+        vec![
+            MockPacket::new(vec![0b001]),
+            MockPacket::new(vec![0b101]),
+            MockPacket::new(vec![0b011]),
+            MockPacket::new(vec![0b010]),
+        ]
+    }
+
+    #[test]
+    fn test_what_if_add_reports_packets_the_rule_would_take_over() {
+        let rvh = classifier();
+
+        let r = MockRule::new(vec![0b01], vec![0b11], 3);
+        let changes = rvh.what_if_add(&r, &sample()).unwrap();
+        let indices: Vec<_> = changes.iter().map(|c| c.index).collect();
+        // the second packet keeps matching the rule with priority 5
+        assert_eq!(indices, vec![0]);
+        assert_eq!(changes[0].before.unwrap().priority(), 1);
+        assert_eq!(changes[0].after.unwrap().priority(), 3);
+
+        // nothing was added
+        assert_eq!(rvh.iter().count(), 2);
+
+        let duplicate = MockRule::new(vec![0b11], vec![0b111], 5);
+        assert!(rvh.what_if_add(&duplicate, &sample()).is_none());
+
+        // the rule is matched against the transformed packets, as by `classify`
+        let mut rvh = classifier();
+        rvh.set_transform(0, |f| f ^ 0b10);
+        let changes = rvh.what_if_add(&r, &sample()).unwrap();
+        let indices: Vec<_> = changes.iter().map(|c| c.index).collect();
+        assert_eq!(indices, vec![2]);
+    }
+
+    #[test]
+    fn test_what_if_remove_reports_the_fallback_classification() {
+        let mut rvh = classifier();
+        // simulations are not traffic
+        rvh.set_hit_ordering(true);
+        rvh.set_miss_hook(|_| panic!("simulated packets are not misses"));
+
+        let r5 = MockRule::new(vec![0b101], vec![0b111], 5);
+        let changes = rvh.what_if_remove(&r5, &sample()).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].index, 1);
+        assert_eq!(changes[0].after.unwrap().priority(), 1);
+
+        let r1 = MockRule::new(vec![0b1], vec![0b1], 1);
+        let changes = rvh.what_if_remove(&r1, &sample()).unwrap();
+        let indices: Vec<_> = changes.iter().map(|c| c.index).collect();
+        assert_eq!(indices, vec![0, 2]);
+        assert!(changes.iter().all(|c| c.after.is_none()));
+
+        let missing = MockRule::new(vec![0b1], vec![0b1], 7);
+        assert!(rvh.what_if_remove(&missing, &sample()).is_none());
+        assert!(rvh.what_if_add(&missing, &sample()).is_some());
+        rvh.prewarm_with(&sample());
+        assert_eq!(rvh.table_hits(0), Some(0));
+        assert_eq!(rvh.table_hits(1), Some(0));
+    }
+}
@@ -0,0 +1,152 @@
+use crate::types::Range;
+
+// Prefix lengths accepted by two tables. Which of them a rule with these prefix lengths ends up
+// in depends on the current order of the tables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overlap {
+    pub tables: (usize, usize),
+    pub ranges: Vec<Range>,
+}
+
+// Result of `analyze`. Every gap and overlap is a range of prefix lengths per dimension, gaps
+// are disjoint.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SplitReport {
+    // prefix lengths no table accepts, rules with them are rejected
+    pub gaps: Vec<Vec<Range>>,
+    pub overlaps: Vec<Overlap>,
+}
+
+impl SplitReport {
+    pub fn is_valid(&self) -> bool {
+        self.gaps.is_empty() && self.overlaps.is_empty()
+    }
+}
+
+// Checks that every combination of prefix lengths up to the field `widths` is accepted by
+// exactly one table. A table without a range for a dimension accepts any prefix length in it.
+pub fn analyze(split: &[Vec<Range>], widths: &[u32]) -> SplitReport {
+    let tables: Vec<Vec<Range>> = split
+        .iter()
+        .map(|ranges| {
+            widths
+                .iter()
+                .enumerate()
+                .map(|(dim, width)| ranges.get(dim).copied().unwrap_or((0, width + 1)))
+                .collect()
+        })
+        .collect();
+
+    let mut gaps = vec![widths.iter().map(|width| (0, width + 1)).collect()];
+    for table in tables.iter() {
+        gaps = gaps
+            .into_iter()
+            .flat_map(|gap| subtract(gap, table))
+            .collect();
+    }
+
+    let mut overlaps = Vec::new();
+    for (i, a) in tables.iter().enumerate() {
+        for (j, b) in tables.iter().enumerate().skip(i + 1) {
+            if let Some(ranges) = intersect(a, b) {
+                overlaps.push(Overlap {
+                    tables: (i, j),
+                    ranges,
+                });
+            }
+        }
+    }
+
+    SplitReport { gaps, overlaps }
+}
+
+fn intersect(a: &[Range], b: &[Range]) -> Option<Vec<Range>> {
+    a.iter()
+        .zip(b)
+        .map(|(&(a_low, a_high), &(b_low, b_high))| {
+            let range = (a_low.max(b_low), a_high.min(b_high));
+            if range.0 < range.1 {
+                Some(range)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// Splits the part of `a` not covered by `b` into disjoint pieces.
+fn subtract(a: Vec<Range>, b: &[Range]) -> Vec<Vec<Range>> {
+    if intersect(&a, b).is_none() {
+        return vec![a];
+    }
+
+    let mut pieces = Vec::new();
+    let mut rest = a;
+    for (dim, &(b_low, b_high)) in b.iter().enumerate() {
+        let (low, high) = rest[dim];
+        if low < b_low {
+            let mut piece = rest.clone();
+            piece[dim] = (low, b_low);
+            pieces.push(piece);
+        }
+        if b_high < high {
+            let mut piece = rest.clone();
+            piece[dim] = (b_high, high);
+            pieces.push(piece);
+        }
+        rest[dim] = (low.max(b_low), high.min(b_high));
+    }
+
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+
+    #[test]
+    fn test_presets_are_valid() {
+        let widths = |dims: Vec<crate::dimensions::Dimension>| {
+            dims.iter().map(|d| d.width()).collect::<Vec<_>>()
+        };
+
+        let report = analyze(
+            &presets::five_tuple(),
+            &widths(presets::five_tuple_dimensions()),
+        );
+        assert!(report.is_valid());
+        assert!(analyze(
+            &presets::tunnel_five_tuple_dscp(),
+            &widths(presets::tunnel_five_tuple_dscp_dimensions())
+        )
+        .is_valid());
+    }
+
+    #[test]
+    fn test_gaps_and_overlaps_are_reported() {
+        let split = vec![
+            vec![(0, 4), (0, 9)],
+            vec![(4, 8), (0, 5)],
+            vec![(6, 9), (3, 9)],
+        ];
+        let report = analyze(&split, &[8, 8]);
+
+        assert_eq!(
+            report.gaps,
+            vec![vec![(8, 9), (0, 3)], vec![(4, 6), (5, 9)]]
+        );
+        assert_eq!(
+            report.overlaps,
+            vec![Overlap {
+                tables: (1, 2),
+                ranges: vec![(6, 8), (3, 5)],
+            }]
+        );
+        assert!(!report.is_valid());
+
+        // a missing range accepts every prefix length
+        assert!(analyze(&[vec![(0, 9)]], &[8, 16]).is_valid());
+        assert_eq!(analyze(&[], &[8]).gaps, vec![vec![(0, 9)]]);
+    }
+}
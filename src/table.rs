@@ -0,0 +1,137 @@
+use crate::error::RvhError;
+use crate::range_vector_hash_map::{self, RVHashMap};
+use crate::types::*;
+
+// A single range-vector hash table, for rule sets whose prefix lengths all fall into one range
+// vector. Classification is a single hash lookup, without the table ordering of the classifier.
+#[derive(Debug, Clone)]
+pub struct RVHTable<R: Rule<F>, F: FieldType = Field> {
+    table: RVHashMap<R, F>,
+    next_id: u64,
+}
+
+impl<R: Rule<F>, F: FieldType> RVHTable<R, F> {
+    pub fn new(ranges: Vec<Range>) -> Self {
+        Self {
+            table: RVHashMap::new(ranges),
+            next_id: 0,
+        }
+    }
+
+    pub fn ranges(&self) -> &[Range] {
+        &self.table.ranges
+    }
+
+    // Whether the prefix lengths of the rule fit the ranges of the table.
+    pub fn accepts(&self, rule: &R) -> bool {
+        rule.fields().len() == rule.masks().len()
+            && range_vector_hash_map::invalid_mask(rule).is_none()
+            && self.table.can_insert(rule)
+    }
+
+    // Same checks and canonical form as `RVHClassifier::add_rule`, fails with
+    // `RvhError::NoMatchingTable` if the table does not accept the prefix lengths.
+    pub fn insert(&mut self, mut rule: R) -> Result<RuleId, RvhError> {
+        if rule.fields().len() != rule.masks().len() {
+            return Err(RvhError::ArityMismatch {
+                fields: rule.fields().len(),
+                masks: rule.masks().len(),
+            });
+        }
+        if let Some(dimension) = range_vector_hash_map::invalid_mask(&rule) {
+            return Err(RvhError::InvalidMask { dimension });
+        }
+        if !self.table.can_insert(&rule) {
+            return Err(RvhError::NoMatchingTable);
+        }
+
+        range_vector_hash_map::normalize(&mut rule);
+        let id = RuleId(self.next_id);
+        self.table.insert(id, rule)?;
+        self.next_id += 1;
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, rule: &R) -> Result<RuleId, RvhError> {
+        self.table.remove(rule).ok_or(RvhError::NotFound)
+    }
+
+    pub fn contains(&self, rule: &R) -> bool {
+        self.table.contains(rule)
+    }
+
+    pub fn classify(&self, p: &impl Packet<F>) -> Option<&R> {
+        self.table.check_match(p)
+    }
+
+    pub fn highest_priority(&self) -> Priority {
+        self.table.highest_priority()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &R> {
+        self.table.hash_map.rules()
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.table.clear();
+    }
+
+    pub(crate) fn into_rules(self) -> impl Iterator<Item = R> {
+        self.table.hash_map.into_rules().into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::mocks::{MockPacket, MockRule};
+
+    #[test]
+    fn test_single_table() {
+        let mut table = RVHTable::<MockRule>::new(vec![(2, 5), (0, 9)]);
+        assert_eq!(table.ranges(), &[(2, 5), (0, 9)][..]);
+
+        let r1 = MockRule::new(vec![0b11, 0], vec![0b11, 0], 1);
+        let r2 = MockRule::new(vec![0b1011, 0b1], vec![0b1111, 0b1], 2);
+        assert!(table.accepts(&r1));
+        assert!(table.insert(r1.clone()).is_ok());
+        assert!(table.insert(r2).is_ok());
+        assert_eq!(
+            table.insert(MockRule::new(vec![0b1, 0], vec![0b1, 0], 3)),
+            Err(RvhError::NoMatchingTable)
+        );
+        assert_eq!(
+            table.insert(MockRule::new(vec![0b1, 0], vec![0b11, 0], 2)),
+            Err(RvhError::DuplicatePriority)
+        );
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.highest_priority(), 2);
+
+        let p = MockPacket::new(vec![0b1011, 0b1]);
+        assert_eq!(table.classify(&p).unwrap().priority(), 2);
+        assert_eq!(
+            table
+                .classify(&MockPacket::new(vec![0b0111, 0b1]))
+                .unwrap()
+                .priority(),
+            1
+        );
+
+        assert!(table.remove(&r1).is_ok());
+        assert_eq!(table.remove(&r1), Err(RvhError::NotFound));
+        assert!(!table.contains(&r1));
+        assert_eq!(table.iter().count(), 1);
+
+        table.clear();
+        assert!(table.is_empty());
+        assert!(table.classify(&p).is_none());
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::types::{Field, Priority};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RejectionReason {
+    // another rule in the same table already uses the priority
+    DuplicatePriority,
+    // no table accepts the prefix lengths of the rule
+    NoMatchingTable,
+    // the rule violates a constraint of the classifier, e.g. a priority band
+    Validation,
+}
+
+// Counts of rejected insertions since `window_start`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectionStats {
+    pub duplicate_priority: u64,
+    pub no_matching_table: u64,
+    pub validation: u64,
+    // None until the first rejection for stats created by `unstarted`
+    window_start: Option<Instant>,
+}
+
+impl RejectionStats {
+    pub(crate) fn new() -> Self {
+        Self {
+            window_start: Some(Instant::now()),
+            ..Self::unstarted()
+        }
+    }
+
+    // Stats whose window starts with the first recorded rejection, for const contexts.
+    pub(crate) const fn unstarted() -> Self {
+        Self {
+            duplicate_priority: 0,
+            no_matching_table: 0,
+            validation: 0,
+            window_start: None,
+        }
+    }
+
+    pub(crate) fn record(&mut self, reason: RejectionReason) {
+        self.window_start.get_or_insert_with(Instant::now);
+
+        let counter = match reason {
+            RejectionReason::DuplicatePriority => &mut self.duplicate_priority,
+            RejectionReason::NoMatchingTable => &mut self.no_matching_table,
+            RejectionReason::Validation => &mut self.validation,
+        };
+        *counter += 1;
+    }
+
+    pub fn count(&self, reason: RejectionReason) -> u64 {
+        match reason {
+            RejectionReason::DuplicatePriority => self.duplicate_priority,
+            RejectionReason::NoMatchingTable => self.no_matching_table,
+            RejectionReason::Validation => self.validation,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.duplicate_priority + self.no_matching_table + self.validation
+    }
+
+    // A window without any rejection that was never started starts now.
+    pub fn window_start(&self) -> Instant {
+        self.window_start.unwrap_or_else(Instant::now)
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window_start
+            .map_or(Duration::ZERO, |start| start.elapsed())
+    }
+}
+
+// Bucket usage of a table. Rules with different masked fields sharing a bucket count as
+// collisions, they lengthen the scan of that bucket for no reason.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TableStats {
+    pub index: usize,
+    pub rules: usize,
+    // non-empty buckets
+    pub buckets: usize,
+    // buckets the table has room for before its index grows
+    pub capacity: usize,
+    pub largest_bucket: usize,
+    pub collisions: usize,
+    // zero for empty tables
+    pub lowest_priority: Priority,
+    pub highest_priority: Priority,
+}
+
+impl TableStats {
+    // Share of the room for buckets that is used.
+    pub fn load_factor(&self) -> f64 {
+        if self.capacity == 0 {
+            return 0.0;
+        }
+        self.buckets as f64 / self.capacity as f64
+    }
+
+    // Mean number of rules per non-empty bucket, the number of rules compared by an average
+    // probe that finds a bucket.
+    pub fn mean_bucket(&self) -> f64 {
+        if self.buckets == 0 {
+            return 0.0;
+        }
+        self.rules as f64 / self.buckets as f64
+    }
+
+    // Share of rules sharing their bucket with rules of different masked fields.
+    pub fn collision_rate(&self) -> f64 {
+        if self.rules == 0 {
+            return 0.0;
+        }
+        self.collisions as f64 / self.rules as f64
+    }
+}
+
+// Bucket lengths of a table and its buckets shared by the most rules with different masked
+// fields, see `RVHClassifier::collisions`, f.e. to compare hashers or seeds on a rule set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollisionReport<F = Field> {
+    pub index: usize,
+    // `histogram[n]` is the number of buckets with `n` rules
+    pub histogram: Vec<usize>,
+    // most distinct keys first
+    pub worst: Vec<CollidingBucket<F>>,
+}
+
+impl<F> CollisionReport<F> {
+    // Longest bucket, the most rules a probe of the table may compare.
+    pub fn largest_bucket(&self) -> usize {
+        self.histogram.len().saturating_sub(1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollidingBucket<F = Field> {
+    pub hash: u64,
+    pub rules: usize,
+    // the distinct fields of the rules under the masks of the table
+    pub keys: Vec<Vec<F>>,
+}
+
+// Relaxed counter bumped by lookups, which only take `&self`. Clones start with the count of
+// the original.
+#[derive(Debug, Default)]
+pub(crate) struct Counter(AtomicU64);
+
+impl Counter {
+    #[inline]
+    pub fn bump(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn halve(&mut self) {
+        *self.0.get_mut() /= 2;
+    }
+}
+
+impl Clone for Counter {
+    fn clone(&self) -> Self {
+        Self(AtomicU64::new(self.get()))
+    }
+}
+
+// Estimated heap memory of a table, see `RVHClassifier::memory_usage`. Memory owned by the
+// rules themselves, f.e. the vectors of `MockRule`, is not known and not counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TableMemory {
+    // position of the range vector in the split
+    pub index: usize,
+    pub buckets: usize,
+    pub rules: usize,
+    // index from bucket hashes and priorities to the rules
+    pub key_bytes: usize,
+    // per bucket bookkeeping, f.e. the runs of rules with equal fields
+    pub bucket_bytes: usize,
+    pub rule_bytes: usize,
+}
+
+impl TableMemory {
+    pub fn bytes(&self) -> usize {
+        self.key_bytes + self.bucket_bytes + self.rule_bytes
+    }
+}
+
+// Estimated heap memory of a classifier, for sizing deployments and spotting tables that grow
+// out of proportion. Capacity that was allocated but is not used yet is counted.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MemoryUsage {
+    // in probe order
+    pub tables: Vec<TableMemory>,
+    pub rules: usize,
+    // ids, metadata and prefix lengths of the installed rules
+    pub slot_bytes: usize,
+    pub prefilter_bytes: usize,
+}
+
+impl MemoryUsage {
+    pub fn total_bytes(&self) -> usize {
+        self.tables.iter().map(TableMemory::bytes).sum::<usize>()
+            + self.slot_bytes
+            + self.prefilter_bytes
+    }
+
+    // The table taking the most memory.
+    pub fn largest_table(&self) -> Option<&TableMemory> {
+        self.tables.iter().max_by_key(|t| t.bytes())
+    }
+}
+
+// Lookups of a member of a `CompositeClassifier` or a tenant of a `MultiTenantClassifier` since
+// it was added or the stats were reset. Every packet classified by the composite counts as a
+// lookup of every member it was passed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemberStats {
+    pub lookups: u64,
+    pub hits: u64,
+    pub misses: u64,
+    // installed rules at the time the stats were taken
+    pub rules: usize,
+}
+
+// Classification times sampled by `RVHClassifier::classify_sampled`, attributed to the table of
+// the matching rule. Misses are kept apart, they search every table that could still match.
+#[derive(Debug, Clone)]
+pub struct LatencySampler {
+    every: u64,
+    counter: u64,
+    capacity: usize,
+    tables: HashMap<usize, LatencySamples>,
+    misses: LatencySamples,
+}
+
+impl LatencySampler {
+    // Samples every `every`th classification and keeps the latest `capacity` samples per
+    // table.
+    pub fn new(every: u64, capacity: usize) -> Self {
+        Self {
+            every: every.max(1),
+            counter: 0,
+            capacity,
+            tables: HashMap::new(),
+            misses: LatencySamples::new(capacity),
+        }
+    }
+
+    pub(crate) fn should_sample(&mut self) -> bool {
+        self.counter += 1;
+        self.counter.is_multiple_of(self.every)
+    }
+
+    // `table` is the index of the table of the matching rule, None for a miss.
+    pub(crate) fn record(&mut self, table: Option<usize>, elapsed: Duration) {
+        let capacity = self.capacity;
+        let samples = match table {
+            Some(index) => self
+                .tables
+                .entry(index)
+                .or_insert_with(|| LatencySamples::new(capacity)),
+            None => &mut self.misses,
+        };
+        samples.push(elapsed);
+    }
+
+    pub fn table(&self, index: usize) -> Option<&LatencySamples> {
+        self.tables.get(&index)
+    }
+
+    // Tables with at least one sample, in no particular order.
+    pub fn tables(&self) -> impl Iterator<Item = (usize, &LatencySamples)> {
+        self.tables.iter().map(|(index, s)| (*index, s))
+    }
+
+    pub fn misses(&self) -> &LatencySamples {
+        &self.misses
+    }
+
+    pub fn clear(&mut self) {
+        self.tables.clear();
+        self.misses = LatencySamples::new(self.capacity);
+    }
+}
+
+// The latest samples of a table, older ones are overwritten.
+#[derive(Debug, Clone)]
+pub struct LatencySamples {
+    samples: Vec<Duration>,
+    capacity: usize,
+    // position of the oldest sample once the buffer is full
+    next: usize,
+}
+
+impl LatencySamples {
+    fn new(capacity: usize) -> Self {
+        Self {
+            samples: Vec::new(),
+            capacity,
+            next: 0,
+        }
+    }
+
+    fn push(&mut self, sample: Duration) {
+        if self.samples.len() < self.capacity {
+            self.samples.push(sample);
+        } else if self.capacity > 0 {
+            self.samples[self.next] = sample;
+            self.next = (self.next + 1) % self.capacity;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    // Nearest-rank percentile, `p` in 0..=100. None if there are no samples.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let mut sorted = self.samples.clone();
+        sorted.sort_unstable();
+        let rank = (p.clamp(0.0, 100.0) / 100.0 * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.saturating_sub(1)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_counts_per_reason() {
+        let mut stats = RejectionStats::new();
+        stats.record(RejectionReason::DuplicatePriority);
+        stats.record(RejectionReason::Validation);
+        stats.record(RejectionReason::Validation);
+
+        assert_eq!(stats.count(RejectionReason::DuplicatePriority), 1);
+        assert_eq!(stats.count(RejectionReason::NoMatchingTable), 0);
+        assert_eq!(stats.count(RejectionReason::Validation), 2);
+        assert_eq!(stats.total(), 3);
+        assert!(stats.window_start() <= Instant::now());
+
+        let mut lazy = RejectionStats::unstarted();
+        assert_eq!(lazy.window(), Duration::ZERO);
+        lazy.record(RejectionReason::NoMatchingTable);
+        assert!(lazy.window_start() <= Instant::now());
+        assert_eq!(lazy.total(), 1);
+    }
+
+    #[test]
+    fn test_latency_percentiles_keep_the_latest_samples() {
+        let mut sampler = LatencySampler::new(2, 4);
+        assert!(!sampler.should_sample());
+        assert!(sampler.should_sample());
+
+        for ms in 1..=6 {
+            sampler.record(Some(3), Duration::from_millis(ms));
+        }
+        sampler.record(None, Duration::from_millis(9));
+
+        // 1 and 2 were overwritten
+        let table = sampler.table(3).unwrap();
+        assert_eq!(table.len(), 4);
+        assert_eq!(table.percentile(0.0), Some(Duration::from_millis(3)));
+        assert_eq!(table.percentile(50.0), Some(Duration::from_millis(4)));
+        assert_eq!(table.percentile(99.0), Some(Duration::from_millis(6)));
+        assert_eq!(
+            sampler.misses().percentile(50.0),
+            Some(Duration::from_millis(9))
+        );
+        assert!(sampler.table(0).is_none());
+
+        sampler.clear();
+        assert_eq!(sampler.tables().count(), 0);
+        assert!(sampler.misses().percentile(50.0).is_none());
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use crate::classifier::RVHClassifier;
+use crate::composite::Counters;
+use crate::error::RvhError;
+use crate::telemetry::MemberStats;
+use crate::types::*;
+
+// The rule sets of many tenants, f.e. the customers of a multi-tenant gateway. A packet is only
+// ever classified with the rules of the tenant it belongs to, so tenants can not match each
+// other's traffic, even with overlapping addresses. Lookups, hits and misses are counted per
+// tenant, see `stats`.
+#[derive(Debug, Clone)]
+pub struct MultiTenantClassifier<R: Rule<F>, F: FieldType = Field> {
+    tenants: HashMap<String, (RVHClassifier<R, F>, Counters)>,
+}
+
+impl<R: Rule<F>, F: FieldType> Default for MultiTenantClassifier<R, F> {
+    fn default() -> Self {
+        Self {
+            tenants: HashMap::new(),
+        }
+    }
+}
+
+impl<R: Rule<F>, F: FieldType> MultiTenantClassifier<R, F> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Fails if there already is a tenant with this name.
+    pub fn add_tenant(&mut self, name: impl Into<String>, classifier: RVHClassifier<R, F>) -> bool {
+        let name = name.into();
+        if self.tenants.contains_key(&name) {
+            return false;
+        }
+
+        self.tenants.insert(name, (classifier, Counters::default()));
+        true
+    }
+
+    pub fn remove_tenant(&mut self, name: &str) -> Option<RVHClassifier<R, F>> {
+        self.tenants.remove(name).map(|(c, _)| c)
+    }
+
+    // Fails with `NotFound` if there is no such tenant.
+    pub fn add_rule(&mut self, tenant: &str, rule: R) -> Result<RuleId, RvhError> {
+        match self.tenants.get_mut(tenant) {
+            Some((classifier, _)) => classifier.add_rule(rule),
+            None => Err(RvhError::NotFound),
+        }
+    }
+
+    pub fn get(&self, tenant: &str) -> Option<&RVHClassifier<R, F>> {
+        self.tenants.get(tenant).map(|(c, _)| c)
+    }
+
+    // For updating the rules of a single tenant.
+    pub fn get_mut(&mut self, tenant: &str) -> Option<&mut RVHClassifier<R, F>> {
+        self.tenants.get_mut(tenant).map(|(c, _)| c)
+    }
+
+    // In no particular order.
+    pub fn tenants(&self) -> impl Iterator<Item = &str> {
+        self.tenants.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tenants.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tenants.is_empty()
+    }
+
+    // None if there is no such tenant or none of its rules matches. Packets of unknown tenants
+    // are not counted.
+    pub fn classify(&self, tenant: &str, p: &impl Packet<F>) -> Option<&R> {
+        let (classifier, counters) = self.tenants.get(tenant)?;
+        counters.count(classifier.classify(p))
+    }
+
+    // Lookups, hits and misses of a tenant since it was added or `reset_stats`, and its number
+    // of rules, f.e. for billing or quotas. None if there is no such tenant.
+    pub fn stats(&self, tenant: &str) -> Option<MemberStats> {
+        let (classifier, counters) = self.tenants.get(tenant)?;
+        Some(counters.stats(classifier.len()))
+    }
+
+    // The stats of every tenant, in no particular order.
+    pub fn all_stats(&self) -> impl Iterator<Item = (&str, MemberStats)> {
+        self.tenants
+            .iter()
+            .map(|(name, (classifier, counters))| (name.as_str(), counters.stats(classifier.len())))
+    }
+
+    pub fn reset_stats(&self) {
+        for (_, counters) in self.tenants.values() {
+            counters.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::mocks::{MockPacket, MockRule};
+
+    #[test]
+    fn test_tenants_are_isolated_and_counted_apart() {
+        let mut tenants = MultiTenantClassifier::<MockRule>::new();
+        let split = || vec![vec![(0, 4)]].into_iter();
+        assert!(tenants.add_tenant("acme", RVHClassifier::new(split())));
+        assert!(tenants.add_tenant("globex", RVHClassifier::new(split())));
+        assert!(!tenants.add_tenant("acme", RVHClassifier::new(split())));
+
+        // the same rule priority and overlapping rules in both tenants
+        let acme = MockRule::new(vec![0b1], vec![0b1], 2);
+        assert!(tenants.add_rule("acme", acme).is_ok());
+        let globex = MockRule::new(vec![0b11], vec![0b11], 2);
+        assert!(tenants.add_rule("globex", globex.clone()).is_ok());
+        assert_eq!(tenants.add_rule("initech", globex), Err(RvhError::NotFound));
+
+        let p = MockPacket::new(vec![0b01]);
+        assert!(tenants.classify("acme", &p).is_some());
+        assert!(tenants.classify("globex", &p).is_none());
+        assert!(tenants.classify("initech", &p).is_none());
+        assert!(tenants
+            .classify("globex", &MockPacket::new(vec![0b11]))
+            .is_some());
+
+        assert_eq!(
+            tenants.stats("globex").unwrap(),
+            MemberStats {
+                lookups: 2,
+                hits: 1,
+                misses: 1,
+                rules: 1,
+            }
+        );
+        assert_eq!(tenants.stats("acme").unwrap().hits, 1);
+        assert!(tenants.stats("initech").is_none());
+        let lookups: u64 = tenants.all_stats().map(|(_, s)| s.lookups).sum();
+        assert_eq!(lookups, 3);
+
+        tenants.reset_stats();
+        assert_eq!(tenants.stats("acme").unwrap().lookups, 0);
+        assert!(tenants.remove_tenant("acme").is_some());
+        assert_eq!(tenants.tenants().collect::<Vec<_>>(), vec!["globex"]);
+    }
+}
@@ -1,30 +1,130 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::ops::{BitAnd, BitOr, BitXor, Not, Shl};
+
+// `#[derive(Rule)]`, see the rvh-derive crate
+#[cfg(feature = "derive")]
+pub use rvh_derive::Rule;
+
 pub type Range = (u32, u32);
+// default field type, see `FieldType` for wider fields
 pub type Mask = u32;
 pub type Field = u32;
 pub type Priority = u32;
 
-pub trait Rule: PartialEq {
+// Integer type of fields and masks. Wider types allow f.e. IPv6 addresses or MAC addresses in
+// a single dimension, prefix lengths in ranges then go up to `BITS`.
+pub trait FieldType:
+    'static
+    + Copy
+    + Eq
+    + Hash
+    + Debug
+    + Default
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + BitXor<Output = Self>
+    + Not<Output = Self>
+    + Shl<u32, Output = Self>
+{
+    const BITS: u32;
+    const ZERO: Self;
+    const ONE: Self;
+    // number of 32 bit words, see `word`
+    const WORDS: usize;
+
+    fn count_ones(self) -> u32;
+    fn trailing_ones(self) -> u32;
+    // The `index`th 32 bit word, starting with the least significant one.
+    fn word(self, index: usize) -> u32;
+}
+
+macro_rules! impl_field_type {
+    ($($t:ty),*) => {
+        $(
+            impl FieldType for $t {
+                const BITS: u32 = <$t>::BITS;
+                const ZERO: Self = 0;
+                const ONE: Self = 1;
+                const WORDS: usize = (<$t>::BITS / 32) as usize;
+
+                fn count_ones(self) -> u32 {
+                    <$t>::count_ones(self)
+                }
+
+                fn trailing_ones(self) -> u32 {
+                    <$t>::trailing_ones(self)
+                }
+
+                fn word(self, index: usize) -> u32 {
+                    debug_assert!(index < Self::WORDS);
+                    (self >> (32 * index as u32)) as u32
+                }
+            }
+        )*
+    };
+}
+
+impl_field_type!(u32, u64, u128);
+
+// Opaque handle of an installed rule, unique within the classifier that assigned it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RuleId(pub(crate) u64);
+
+pub trait Rule<F: FieldType = Field>: PartialEq {
     fn priority(&self) -> Priority;
-    fn masks(&self) -> &[Mask];
-    fn fields(&self) -> &[Field];
+    fn masks(&self) -> &[F];
+    fn fields(&self) -> &[F];
+
+    // Rules providing mutable access to their fields are stored in canonical form, with all bits
+    // outside of the masks cleared.
+    fn fields_mut(&mut self) -> Option<&mut [F]> {
+        None
+    }
 }
-pub trait Packet {
-    fn fields(&self) -> &[Field];
+// Rule carrying the action to take for matching packets, see `RVHClassifier::decide`.
+pub trait ActionRule<F: FieldType = Field>: Rule<F> {
+    type Action: Clone;
+
+    fn action(&self) -> &Self::Action;
+}
+
+pub trait Packet<F: FieldType = Field> {
+    fn fields(&self) -> &[F];
+}
+
+// Source of a packet whose fields can not always be extracted, f.e. a truncated header or a
+// protocol without ports. Extractors fail instead of making up fields, which could match
+// wildcard rules, see `RVHClassifier::classify_try`.
+pub trait TryPacket<F: FieldType = Field> {
+    type Packet: Packet<F>;
+    type Error;
+
+    fn try_packet(&self) -> Result<Self::Packet, Self::Error>;
 }
 
 #[cfg(test)]
 pub(crate) mod mocks {
     use super::*;
+    use std::hash::{BuildHasherDefault, Hasher};
 
     #[derive(Debug, Clone)]
-    pub struct MockRule {
-        fields: Vec<Field>,
-        masks: Vec<Mask>,
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct MockRule<F = Field> {
+        fields: Vec<F>,
+        masks: Vec<F>,
         priority: Priority,
     }
 
     impl MockRule {
         pub fn new(fields: Vec<Field>, masks: Vec<Mask>, priority: Priority) -> Self {
+            MockRule::wide(fields, masks, priority)
+        }
+    }
+
+    impl<F: FieldType> MockRule<F> {
+        pub fn wide(fields: Vec<F>, masks: Vec<F>, priority: Priority) -> Self {
             Self {
                 fields,
                 masks,
@@ -33,38 +133,79 @@ pub(crate) mod mocks {
         }
     }
 
-    impl Rule for MockRule {
-        fn fields(&self) -> &[Field] {
+    impl<F: FieldType> Rule<F> for MockRule<F> {
+        fn fields(&self) -> &[F] {
             &self.fields
         }
-        fn masks(&self) -> &[Mask] {
+        fn masks(&self) -> &[F] {
             &self.masks
         }
         fn priority(&self) -> Priority {
             self.priority
         }
+        fn fields_mut(&mut self) -> Option<&mut [F]> {
+            Some(&mut self.fields)
+        }
     }
 
-    impl PartialEq for MockRule {
+    impl<F: FieldType> PartialEq for MockRule<F> {
         fn eq(&self, other: &Self) -> bool {
             self.priority() == other.priority()
         }
     }
 
     #[derive(Debug, Clone)]
-    pub struct MockPacket {
-        fields: Vec<Field>,
+    pub struct MockPacket<F = Field> {
+        fields: Vec<F>,
     }
 
     impl MockPacket {
         pub fn new(fields: Vec<Field>) -> Self {
+            MockPacket::wide(fields)
+        }
+    }
+
+    impl<F: FieldType> MockPacket<F> {
+        pub fn wide(fields: Vec<F>) -> Self {
             Self { fields }
         }
     }
 
-    impl Packet for MockPacket {
-        fn fields(&self) -> &[Field] {
+    impl<F: FieldType> Packet<F> for MockPacket<F> {
+        fn fields(&self) -> &[F] {
             &self.fields
         }
     }
+
+    // Multiplies and XORs the words, with the seed, which is written first, selecting the
+    // multiplier. With seed 0 fields collide that only differ in their order.
+    #[derive(Debug, Default)]
+    pub struct XorHasher {
+        k: Option<u64>,
+        hash: u64,
+    }
+
+    impl Hasher for XorHasher {
+        // other integers and byte strings are folded in as words
+        fn write(&mut self, bytes: &[u8]) {
+            for chunk in bytes.chunks(4) {
+                let mut word = [0; 4];
+                word[..chunk.len()].copy_from_slice(chunk);
+                self.write_u32(u32::from_ne_bytes(word));
+            }
+        }
+
+        fn write_u32(&mut self, i: u32) {
+            match self.k {
+                None => self.k = Some((i as u64) << 1 | 1),
+                Some(k) => self.hash = self.hash.wrapping_mul(k) ^ i as u64,
+            }
+        }
+
+        fn finish(&self) -> u64 {
+            self.hash
+        }
+    }
+
+    pub type MockBuildHasher = BuildHasherDefault<XorHasher>;
 }